@@ -0,0 +1,241 @@
+use crate::error::MonitorError;
+use crate::store::{get_swaps, SwapQuery};
+use crate::{CombinedLog, IN_MEMORY_PATH};
+use ethers::core::types::Address;
+use eyre::{eyre, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use rusqlite::{Connection, OpenFlags};
+use serde_json::json;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// The most swaps `GET /swaps` returns at once.
+pub const MAX_PAGE_SIZE: u64 = 1000;
+
+/// Reads the `pool`, `from_block`, `to_block`, `limit` and `offset` parameters
+/// of a `GET /swaps` query string. Unknown parameters are rejected, so a typo
+/// does not silently return every swap.
+fn parse_swap_query(query: Option<&str>) -> Result<SwapQuery, String> {
+    let mut swap_query = SwapQuery::default();
+    for pair in query.unwrap_or_default().split('&') {
+        if pair.is_empty() {
+            continue;
+        }
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|e| format!("invalid {} '{}': {}", name, value, e))
+        };
+        match name {
+            "pool" => {
+                let pool = Address::from_str(value)
+                    .map_err(|e| format!("invalid pool '{}': {}", value, e))?;
+                swap_query.pool = Some(pool);
+            }
+            "from_block" => swap_query.from_block = Some(number()?),
+            "to_block" => swap_query.to_block = Some(number()?),
+            "limit" => swap_query.limit = number()?,
+            "offset" => swap_query.offset = number()?,
+            _ => return Err(format!("unknown parameter '{}'", name)),
+        }
+    }
+    if !(1..=MAX_PAGE_SIZE).contains(&swap_query.limit) {
+        return Err(format!("limit must be between 1 and {}", MAX_PAGE_SIZE));
+    }
+    Ok(swap_query)
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        // The data is public chain data, so pages on any origin may read it
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Reads the swaps of `query` from a read-only connection opened for the
+/// request, so requests never wait for the monitor's connection.
+async fn query_swaps(db_path: Arc<str>, query: SwapQuery) -> Result<Vec<CombinedLog>> {
+    let swaps = tokio::task::spawn_blocking(move || {
+        let conn = Connection::open_with_flags(&*db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        get_swaps(&conn, &query)
+    })
+    .await??;
+    Ok(swaps)
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    db_path: Arc<str>,
+) -> Result<Response<Body>, Infallible> {
+    if (request.method(), request.uri().path()) != (&Method::GET, "/swaps") {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap());
+    }
+    let query = match parse_swap_query(request.uri().query()) {
+        Ok(query) => query,
+        Err(message) => {
+            return Ok(json_response(
+                StatusCode::BAD_REQUEST,
+                json!({ "error": message }),
+            ))
+        }
+    };
+    let (limit, offset) = (query.limit, query.offset);
+    let response = match query_swaps(db_path, query).await {
+        Ok(swaps) => json_response(
+            StatusCode::OK,
+            json!({
+                "swaps": swaps.iter().map(CombinedLog::to_json).collect::<Vec<_>>(),
+                "limit": limit,
+                "offset": offset,
+            }),
+        ),
+        Err(e) => {
+            warn!(error = %e, "failed to query swaps");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "error": "failed to query the database" }),
+            )
+        }
+    };
+    Ok(response)
+}
+
+/// Serves the swaps stored in the SQLite database at `db_path` on
+/// `GET /swaps` at `addr` in a background task and returns the bound address,
+/// which tells the port when `addr` uses port 0.
+///
+/// The query parameters `pool`, `from_block` and `to_block` filter the swaps,
+/// see `SwapQuery`, and `limit`, 100 by default and at most `MAX_PAGE_SIZE`,
+/// and `offset` page through them. The response is a JSON object with the
+/// `swaps`, as the objects of `CombinedLog::to_json` in chain order, and the
+/// `limit` and `offset` used.
+pub fn spawn_api_server(addr: SocketAddr, db_path: &str) -> Result<(SocketAddr, JoinHandle<()>)> {
+    if db_path == IN_MEMORY_PATH || db_path.starts_with("postgres") {
+        return Err(MonitorError::InvalidConfig(format!(
+            "the query server needs a SQLite database file, not '{}'",
+            db_path
+        ))
+        .into());
+    }
+    let db_path: Arc<str> = Arc::from(db_path);
+    let server = Server::try_bind(&addr)
+        .map_err(|e| eyre!("failed to bind query server to {}: {}", addr, e))?
+        .serve(make_service_fn(move |_| {
+            let db_path = db_path.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    handle_request(request, db_path.clone())
+                }))
+            }
+        }));
+    let local_addr = server.local_addr();
+    info!(addr = %local_addr, "serving stored swaps");
+    let handle = tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!(error = %e, "query server failed");
+        }
+    });
+    Ok((local_addr, handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{SqliteStore, SwapStore};
+    use crate::tests::create_test_combined_log;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_parse_swap_query() {
+        assert_eq!(parse_swap_query(None).unwrap(), SwapQuery::default());
+        let pool = Address::from_low_u64_be(1);
+        assert_eq!(
+            parse_swap_query(Some(&format!(
+                "pool={:#x}&from_block=10&to_block=20&limit=5&offset=15",
+                pool
+            )))
+            .unwrap(),
+            SwapQuery {
+                pool: Some(pool),
+                from_block: Some(10),
+                to_block: Some(20),
+                limit: 5,
+                offset: 15,
+            }
+        );
+
+        for invalid in [
+            "pool=0xzz",
+            "from_block=-1",
+            "limit=0",
+            "limit=1001",
+            "block=1",
+        ] {
+            assert!(parse_swap_query(Some(invalid)).is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_swaps_endpoint() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir.path().join("swaps.db");
+        let db_path = db_path.to_str().unwrap();
+        let mut store = SqliteStore::open(db_path).unwrap();
+        let swap = create_test_combined_log();
+        for log_index in 0..3 {
+            let swap = CombinedLog {
+                log_index: Some(log_index),
+                ..swap.clone()
+            };
+            store.insert(&swap.into()).await.unwrap();
+        }
+
+        let (addr, server) = spawn_api_server(([127, 0, 0, 1], 0).into(), db_path).unwrap();
+        let get = |query: String| async move {
+            let response = reqwest::get(format!("http://{}{}", addr, query))
+                .await
+                .unwrap();
+            let status = response.status();
+            (status, response.json::<serde_json::Value>().await.unwrap())
+        };
+
+        let (status, body) = get(format!("/swaps?pool={:#x}&limit=2&offset=1", swap.pool)).await;
+        assert_eq!(status, StatusCode::OK);
+        let log_indexes: Vec<_> = body["swaps"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|swap| swap["log_index"].as_u64().unwrap())
+            .collect();
+        assert_eq!(log_indexes, vec![1, 2]);
+        assert_eq!(body["limit"], 2);
+        assert_eq!(body["offset"], 1);
+
+        let (_, body) = get(format!("/swaps?pool={:#x}", Address::zero())).await;
+        assert_eq!(body["swaps"], json!([]));
+
+        let (status, body) = get("/swaps?limit=abc".to_string()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("invalid limit"));
+
+        let response = reqwest::get(format!("http://{}/other", addr))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        assert!(spawn_api_server(([127, 0, 0, 1], 0).into(), IN_MEMORY_PATH).is_err());
+        server.abort();
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{CombinedLog, PoolMetadata, SwapSink};
+use eyre::{eyre, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+/// Messages buffered for each client. A client that falls further behind skips
+/// the oldest ones instead of slowing down the monitor.
+const CLIENT_BUFFER: usize = 256;
+
+/// Sends swaps to the clients connected to a broadcast server, each as the JSON
+/// object of `CombinedLog::to_json` in a text message.
+#[derive(Clone)]
+pub struct SwapBroadcaster {
+    sender: broadcast::Sender<String>,
+}
+
+impl SwapBroadcaster {
+    pub fn send(&self, combined_log: &CombinedLog) {
+        // Sending only fails when no client is connected
+        let _ = self.sender.send(combined_log.to_json().to_string());
+    }
+
+    /// The number of connected clients.
+    pub fn client_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+impl SwapSink for SwapBroadcaster {
+    fn handle(&mut self, swap: &CombinedLog, _metadata: Option<&PoolMetadata>) -> Result<()> {
+        self.send(swap);
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "broadcast"
+    }
+}
+
+/// Accepts WebSocket clients at `addr` in a background task and returns the
+/// bound address, which tells the port when `addr` uses port 0, and the
+/// broadcaster that sends swaps to the clients.
+///
+/// Clients only receive swaps; messages they send are ignored. A client that
+/// disconnects or fails is dropped without affecting the others.
+pub async fn spawn_broadcast_server(
+    addr: SocketAddr,
+) -> Result<(SocketAddr, SwapBroadcaster, JoinHandle<()>)> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| eyre!("failed to bind broadcast server to {}: {}", addr, e))?;
+    let local_addr = listener.local_addr()?;
+    let (sender, _) = broadcast::channel(CLIENT_BUFFER);
+    let broadcaster = SwapBroadcaster {
+        sender: sender.clone(),
+    };
+    info!(addr = %local_addr, "broadcasting swaps");
+    // Aborting the task drops the last sender, which ends the client tasks
+    let handle = tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    tokio::spawn(serve_client(stream, peer, sender.subscribe()));
+                }
+                Err(e) => warn!(error = %e, "failed to accept broadcast client"),
+            }
+        }
+    });
+    Ok((local_addr, broadcaster, handle))
+}
+
+async fn serve_client(stream: TcpStream, peer: SocketAddr, mut swaps: broadcast::Receiver<String>) {
+    let socket = match tokio_tungstenite::accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!(%peer, error = %e, "broadcast client handshake failed");
+            return;
+        }
+    };
+    info!(%peer, "broadcast client connected");
+    let (mut write, mut read) = socket.split();
+    loop {
+        tokio::select! {
+            swap = swaps.recv() => match swap {
+                Ok(message) => {
+                    if write.send(Message::Text(message)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(%peer, skipped, "broadcast client fell behind, skipping swaps");
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    let _ = write.send(Message::Close(None)).await;
+                    break;
+                }
+            },
+            // Reading answers pings and notices when the client goes away
+            message = read.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    info!(%peer, "broadcast client disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::create_test_combined_log;
+    use std::time::Duration;
+
+    async fn next_swap<S>(client: &mut S) -> serde_json::Value
+    where
+        S: futures_util::Stream<Item = tokio_tungstenite::tungstenite::Result<Message>> + Unpin,
+    {
+        let message = tokio::time::timeout(Duration::from_secs(5), client.next())
+            .await
+            .expect("no swap was broadcast")
+            .unwrap()
+            .unwrap();
+        serde_json::from_str(&message.into_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_server() {
+        let (addr, broadcaster, server) = spawn_broadcast_server(([127, 0, 0, 1], 0).into())
+            .await
+            .unwrap();
+        let url = format!("ws://{}", addr);
+        let (mut first, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (mut second, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        assert_eq!(broadcaster.client_count(), 2);
+
+        let swap = create_test_combined_log();
+        broadcaster.send(&swap);
+        for client in [&mut first, &mut second] {
+            let json = next_swap(client).await;
+            assert_eq!(json["tx_hash"], format!("{:#x}", swap.tx_hash));
+        }
+
+        // A client going away does not affect the others
+        first.close(None).await.unwrap();
+        drop(first);
+        broadcaster.send(&swap);
+        assert_eq!(next_swap(&mut second).await["tick"], swap.data.tick);
+        server.abort();
+    }
+}
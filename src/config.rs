@@ -0,0 +1,201 @@
+use crate::{parse_pool_address, TokenDecimals};
+use ethers::core::types::Address;
+use eyre::{bail, eyre, Result};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Settings read from a TOML file, for monitoring several pools without
+/// listing them in the environment:
+///
+/// ```toml
+/// provider_urls = ["wss://eth-mainnet.example/ws"]
+/// db_path = "swaps.db"
+///
+/// [[pools]]
+/// address = "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640"
+/// token0_decimals = 6
+/// token1_decimals = 18
+///
+/// [[pools]]
+/// address = "0xcbcdf9626bc03e24f779434178a73a0b4bad62ed"
+/// db_path = "link-weth.db"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Provider URLs, tried in order as `PROVIDER_URL` does.
+    #[serde(default)]
+    pub provider_urls: Vec<String>,
+    /// SQLite database path, `:memory:` or a `postgres://` URL.
+    pub db_path: Option<String>,
+    pub pools: Vec<PoolConfig>,
+}
+
+/// A pool to monitor.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoolConfig {
+    pub address: String,
+    /// Decimals of the pool's tokens, given together. When unset, they are read
+    /// from the token contracts on startup.
+    pub token0_decimals: Option<u8>,
+    pub token1_decimals: Option<u8>,
+    /// A database of its own for the pool's events and metadata, in the forms
+    /// of `Config::db_path`. When unset, the pool uses the main database.
+    pub db_path: Option<String>,
+}
+
+impl Config {
+    /// Reads and validates the config file at `path`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| eyre!("failed to read config file '{}': {}", path.display(), e))?;
+        Self::parse(&contents).map_err(|e| eyre!("invalid config file '{}': {}", path.display(), e))
+    }
+
+    /// Parses and validates the contents of a config file.
+    pub fn parse(contents: &str) -> Result<Self> {
+        let config: Config = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.pools.is_empty() {
+            bail!("no pools configured");
+        }
+        let mut seen = HashSet::new();
+        for pool in &self.pools {
+            let address = parse_pool_address(&pool.address)?;
+            if !seen.insert(address) {
+                bail!("pool {:#x} is configured twice", address);
+            }
+            if pool.token0_decimals.is_some() != pool.token1_decimals.is_some() {
+                bail!(
+                    "pool {:#x} needs both token0_decimals and token1_decimals, or neither",
+                    address
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The addresses of the configured pools, as `run` takes them.
+    pub fn pool_addresses(&self) -> Vec<String> {
+        self.pools.iter().map(|pool| pool.address.clone()).collect()
+    }
+
+    /// The decimals of the pools that configure them, for
+    /// `MonitorOptions::token_decimals`.
+    pub fn token_decimals(&self) -> HashMap<Address, TokenDecimals> {
+        self.pools
+            .iter()
+            .filter_map(|pool| {
+                let decimals = TokenDecimals {
+                    decimals0: pool.token0_decimals?,
+                    decimals1: pool.token1_decimals?,
+                };
+                // Validated when the config was parsed
+                let address = parse_pool_address(&pool.address).ok()?;
+                Some((address, decimals))
+            })
+            .collect()
+    }
+
+    /// The databases of the pools that have one of their own, for
+    /// `open_sharded_store`.
+    pub fn pool_db_paths(&self) -> HashMap<Address, String> {
+        self.pools
+            .iter()
+            .filter_map(|pool| {
+                let db_path = pool.db_path.clone()?;
+                // Validated when the config was parsed
+                let address = parse_pool_address(&pool.address).ok()?;
+                Some((address, db_path))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(
+            r#"
+            provider_urls = ["wss://one.example", "wss://two.example"]
+            db_path = "swaps.db"
+
+            [[pools]]
+            address = "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640"
+            token0_decimals = 6
+            token1_decimals = 18
+
+            [[pools]]
+            address = "0xcbcdf9626bc03e24f779434178a73a0b4bad62ed"
+            db_path = "link-weth.db"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.provider_urls.len(), 2);
+        assert_eq!(config.db_path.as_deref(), Some("swaps.db"));
+        assert_eq!(
+            config.pool_addresses(),
+            vec![
+                "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640",
+                "0xcbcdf9626bc03e24f779434178a73a0b4bad62ed"
+            ]
+        );
+        let pool = parse_pool_address("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640").unwrap();
+        assert_eq!(
+            config.token_decimals(),
+            HashMap::from([(
+                pool,
+                TokenDecimals {
+                    decimals0: 6,
+                    decimals1: 18
+                }
+            )])
+        );
+        let link_weth = parse_pool_address("0xcbcdf9626bc03e24f779434178a73a0b4bad62ed").unwrap();
+        assert_eq!(
+            config.pool_db_paths(),
+            HashMap::from([(link_weth, "link-weth.db".to_string())])
+        );
+
+        // The provider and database may come from the environment instead
+        let config =
+            Config::parse("[[pools]]\naddress = \"0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640\"")
+                .unwrap();
+        assert!(config.provider_urls.is_empty());
+        assert_eq!(config.db_path, None);
+    }
+
+    #[test]
+    fn test_parse_config_rejects_invalid_pools() {
+        let pool = "address = \"0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640\"";
+        for (contents, error) in [
+            ("pools = []".to_string(), "no pools configured"),
+            (
+                "[[pools]]\naddress = \"0x1234\"".to_string(),
+                "invalid pool address",
+            ),
+            (
+                format!("[[pools]]\n{pool}\n[[pools]]\n{pool}"),
+                "configured twice",
+            ),
+            (
+                format!("[[pools]]\n{pool}\ntoken0_decimals = 6"),
+                "needs both",
+            ),
+            (format!("[[pools]]\n{pool}\ndecimals = 6"), "unknown field"),
+        ] {
+            let e = Config::parse(&contents).unwrap_err();
+            assert!(e.to_string().contains(error), "{contents}: {e}");
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use crate::PoolEvent;
+use ethers::providers::Middleware;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+/// Events waiting until `confirmations` blocks were mined on top of theirs,
+/// see `MonitorOptions::confirmations`. With no confirmations every event is
+/// ready as soon as it is pushed.
+pub(crate) struct PendingEvents {
+    confirmations: u64,
+    /// The highest block known to be on chain.
+    head: Option<u64>,
+    /// In the order they arrived, which is block order.
+    events: VecDeque<PoolEvent>,
+    /// Chain heads read by `spawn_head_watch`, so events of a quiet pool are
+    /// confirmed without waiting for a later event.
+    heads: Option<watch::Receiver<u64>>,
+}
+
+impl PendingEvents {
+    pub(crate) fn new(confirmations: u64) -> Self {
+        PendingEvents {
+            confirmations,
+            head: None,
+            events: VecDeque::new(),
+            heads: None,
+        }
+    }
+
+    pub(crate) fn watch_heads(&mut self, heads: watch::Receiver<u64>) {
+        self.heads = Some(heads);
+    }
+
+    /// Completes with the next head read by the head watch, or never without
+    /// one.
+    pub(crate) async fn next_head(&mut self) -> u64 {
+        if let Some(heads) = self.heads.as_mut() {
+            if heads.changed().await.is_ok() {
+                return *heads.borrow_and_update();
+            }
+        }
+        std::future::pending().await
+    }
+
+    /// Raises the known head to `head`.
+    pub(crate) fn observe_head(&mut self, head: u64) {
+        self.head = Some(self.head.map_or(head, |known| known.max(head)));
+    }
+
+    /// Buffers `event` until it is confirmed, unless it was retracted.
+    ///
+    /// A retracted event that is still buffered is dropped together with its
+    /// buffered copy, since it was never stored. Otherwise it is returned, as
+    /// its stored row has to be deleted. An event of a block that replaced a
+    /// buffered one, with the same number but another hash, drops the buffered
+    /// events of that block and the later ones, which were orphaned; this
+    /// catches reorgs that the provider does not report as retracted logs.
+    pub(crate) fn push(&mut self, event: PoolEvent) -> Option<PoolEvent> {
+        if event.removed() {
+            let buffered = self.events.iter().position(|pending| {
+                pending.tx_hash() == event.tx_hash() && pending.log_index() == event.log_index()
+            });
+            return match buffered {
+                Some(position) => {
+                    self.events.remove(position);
+                    None
+                }
+                None => Some(event),
+            };
+        }
+        if let (Some(block_number), Some(block_hash)) = (event.block_number(), event.block_hash()) {
+            let before = self.events.len();
+            self.events.retain(|pending| {
+                pending.block_number().is_none_or(|pending_block| {
+                    pending_block < block_number
+                        || (pending_block == block_number
+                            && pending.block_hash().is_none_or(|hash| hash == block_hash))
+                })
+            });
+            let orphaned = before - self.events.len();
+            if orphaned > 0 {
+                warn!(
+                    orphaned,
+                    block_number, "dropping buffered events of blocks replaced by a reorg"
+                );
+            }
+            self.observe_head(block_number);
+        }
+        self.events.push_back(event);
+        None
+    }
+
+    /// Removes and returns the buffered events that have `confirmations`
+    /// blocks on top of theirs, in the order they arrived. Events without a
+    /// block number cannot be confirmed and are returned right away.
+    pub(crate) fn take_confirmed(&mut self) -> Vec<PoolEvent> {
+        let mut confirmed = Vec::new();
+        while let Some(event) = self.events.front() {
+            let ready = event.block_number().is_none_or(|block_number| {
+                self.head
+                    .is_some_and(|head| block_number.saturating_add(self.confirmations) <= head)
+            });
+            if !ready {
+                break;
+            }
+            confirmed.extend(self.events.pop_front());
+        }
+        confirmed
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Drops the events that are still waiting for confirmations when the
+    /// monitor stops. They lie past the stored checkpoint, so the backfill on
+    /// the next start fetches them again.
+    pub(crate) fn drop_unconfirmed(&mut self) {
+        if !self.is_empty() {
+            info!(
+                events = self.len(),
+                "not storing events that are not confirmed yet"
+            );
+            self.events.clear();
+        }
+    }
+}
+
+/// Reads the block number from `client` every `interval` and sends it to the
+/// returned receiver. Failed reads are logged and retried on the next tick.
+pub(crate) fn spawn_head_watch<M: Middleware + 'static>(
+    client: Arc<M>,
+    interval: Duration,
+) -> (watch::Receiver<u64>, JoinHandle<()>) {
+    let (sender, receiver) = watch::channel(0);
+    let handle = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval.max(Duration::from_millis(1)));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match client.get_block_number().await {
+                Ok(head) => {
+                    sender.send_replace(head.as_u64());
+                }
+                Err(e) => warn!(error = %e, "failed to read the chain head"),
+            }
+        }
+    });
+    (receiver, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::swap_at;
+    use crate::CombinedLog;
+    use ethers::core::types::{H256, U64};
+    use ethers::providers::Provider;
+
+    fn block_numbers(events: &[PoolEvent]) -> Vec<Option<u64>> {
+        events.iter().map(PoolEvent::block_number).collect()
+    }
+
+    #[test]
+    fn test_pending_events_wait_for_confirmations() {
+        let mut pending = PendingEvents::new(2);
+        assert!(pending.push(swap_at(100, 0).into()).is_none());
+        assert!(pending.push(swap_at(101, 0).into()).is_none());
+        assert!(pending.take_confirmed().is_empty());
+
+        pending.observe_head(102);
+        assert_eq!(block_numbers(&pending.take_confirmed()), vec![Some(100)]);
+        assert_eq!(pending.len(), 1);
+        // A later event confirms the earlier ones as well
+        pending.push(swap_at(103, 0).into());
+        assert_eq!(block_numbers(&pending.take_confirmed()), vec![Some(101)]);
+
+        let mut immediate = PendingEvents::new(0);
+        immediate.push(swap_at(100, 0).into());
+        assert_eq!(immediate.take_confirmed().len(), 1);
+        assert!(immediate.is_empty());
+    }
+
+    #[test]
+    fn test_pending_events_drop_orphaned_events() {
+        let mut pending = PendingEvents::new(3);
+        pending.push(swap_at(100, 0).into());
+        pending.push(swap_at(101, 0).into());
+        pending.push(swap_at(101, 1).into());
+
+        // A retracted log takes its buffered copy with it
+        let removed = CombinedLog {
+            removed: true,
+            ..swap_at(101, 1)
+        };
+        assert!(pending.push(removed.clone().into()).is_none());
+        assert_eq!(pending.len(), 2);
+        // Retracting a log that is no longer buffered deletes the stored row
+        assert_eq!(pending.push(removed.clone().into()), Some(removed.into()));
+
+        // Another block 101 replaces the buffered one
+        let replacement = CombinedLog {
+            block_hash: Some(H256::repeat_byte(0xbb)),
+            ..swap_at(101, 4)
+        };
+        pending.push(replacement.clone().into());
+        pending.observe_head(105);
+        assert_eq!(
+            pending.take_confirmed(),
+            vec![swap_at(100, 0).into(), replacement.into()]
+        );
+    }
+
+    #[test]
+    fn test_drop_unconfirmed() {
+        let mut pending = PendingEvents::new(1);
+        pending.push(swap_at(100, 0).into());
+        pending.drop_unconfirmed();
+        pending.observe_head(200);
+        assert!(pending.take_confirmed().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_head_watch() {
+        let (client, mock) = Provider::mocked();
+        mock.push(U64::from(17_500_000)).unwrap();
+        let (receiver, handle) = spawn_head_watch(Arc::new(client), Duration::from_secs(60));
+        let mut pending = PendingEvents::new(1);
+        pending.watch_heads(receiver);
+        assert_eq!(pending.next_head().await, 17_500_000);
+        handle.abort();
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{CombinedLog, PoolMetadata, SwapSink};
+use eyre::{eyre, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Column names of the CSV file. They mirror the `logs` table, followed by the
+/// `removed` flag since rows cannot be deleted from the file after a reorg.
+const CSV_HEADER: &str = "tx_hash,pool_address,sender_address,receiver_address,amount0,amount1,sqrt_price,liquidity,tick,block_number,block_hash,timestamp,price,log_index,removed";
+
+/// Appends swaps to a CSV file, one line per swap.
+///
+/// Amounts, `sqrt_price` and `liquidity` are written as full-precision decimal
+/// strings. Retracted swaps are appended as well, with `removed` set to `true`.
+pub struct CsvSink {
+    writer: BufWriter<File>,
+}
+
+impl CsvSink {
+    /// Opens `path` for appending, creating it if needed. The header row is
+    /// only written when the file is empty.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| eyre!("failed to open CSV file '{}': {}", path.display(), e))?;
+        let is_empty = file.metadata()?.len() == 0;
+        let mut writer = BufWriter::new(file);
+        if is_empty {
+            writeln!(writer, "{}", CSV_HEADER)?;
+        }
+        Ok(CsvSink { writer })
+    }
+
+    pub fn write(&mut self, combined_log: &CombinedLog) -> Result<()> {
+        // None of the fields can contain a comma or a quote, so no escaping is needed
+        writeln!(
+            self.writer,
+            "{:#x},{:#x},{:#x},{:#x},{},{},{},{},{},{},{},{},{},{},{}",
+            combined_log.tx_hash,
+            combined_log.pool,
+            combined_log.sender,
+            combined_log.receiver,
+            combined_log.data.amount0,
+            combined_log.data.amount1,
+            combined_log.data.sqrt_price,
+            combined_log.data.liquidity,
+            combined_log.data.tick,
+            optional(combined_log.block_number),
+            optional(combined_log.block_hash.map(|hash| format!("{:#x}", hash))),
+            optional(combined_log.timestamp),
+            optional(combined_log.price),
+            optional(combined_log.log_index),
+            combined_log.removed,
+        )?;
+        Ok(())
+    }
+
+    /// Writes the buffered lines to the file.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl SwapSink for CsvSink {
+    fn handle(&mut self, swap: &CombinedLog, _metadata: Option<&PoolMetadata>) -> Result<()> {
+        self.write(swap)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        CsvSink::flush(self)
+    }
+
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::create_test_combined_log;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_csv_sink_appends_rows() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let csv_path = temp_dir.path().join("swaps.csv");
+
+        let mut sink = CsvSink::open(&csv_path).unwrap();
+        sink.write(&create_test_combined_log()).unwrap();
+        sink.flush().unwrap();
+        drop(sink);
+
+        // Reopening appends without repeating the header
+        let mut sink = CsvSink::open(&csv_path).unwrap();
+        let mut removed = create_test_combined_log();
+        removed.removed = true;
+        removed.block_hash = None;
+        sink.write(&removed).unwrap();
+        sink.flush().unwrap();
+
+        let contents = fs::read_to_string(&csv_path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            "0xe92955b4c46b38de18c1cdd58b06d49d45d6f9ca0906a86918f4cf20650683b4,\
+             0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640,\
+             0xe592427a0aece92de3edee1f18e0157c05861564,\
+             0x4b7d6c3cea01f4d54a9cad6587da106ea39da1e6,\
+             -263120000,162381653432074306,1967716719848838692609454179917707,\
+             32607304702662909871,202411,17500000,\
+             0x2f2a9c0e1f5d6a3c4b8e7d9f0a1b2c3d4e5f60718293a4b5c6d7e8f901234567,\
+             1687000000,,42,false"
+        );
+        assert!(lines[2].ends_with(",17500000,,1687000000,,42,true"));
+        assert_eq!(lines[2].split(',').count(), CSV_HEADER.split(',').count());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::error::MonitorError;
+use crate::METRICS;
+use ethers::core::types::Address;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// An exponential moving average of the swap price of each pool, for a price
+/// readout that single-swap spikes barely move.
+///
+/// Set it as `MonitorOptions::price_ema` and keep a clone to read the current
+/// values with `get` while `run` updates them. Every stored swap with a `price`
+/// moves the average of its pool by `alpha` of the way towards that price, so
+/// an `alpha` of 0.1 weighs the latest swap by a tenth, and 1 follows the last
+/// price exactly. The values are also served as the `pool_price_ema` gauge.
+#[derive(Debug, Clone)]
+pub struct PriceEma {
+    alpha: f64,
+    prices: Arc<RwLock<HashMap<Address, f64>>>,
+}
+
+impl PriceEma {
+    /// Fails unless `alpha` is above 0 and at most 1.
+    pub fn new(alpha: f64) -> Result<Self, MonitorError> {
+        if !(alpha > 0.0 && alpha <= 1.0) {
+            return Err(MonitorError::InvalidConfig(format!(
+                "the price EMA smoothing factor must be above 0 and at most 1, got {}",
+                alpha
+            )));
+        }
+        Ok(PriceEma {
+            alpha,
+            prices: Arc::default(),
+        })
+    }
+
+    /// The average price of `pool`, token0 in units of token1, unless none of
+    /// its swaps had a price yet.
+    pub fn get(&self, pool: Address) -> Option<f64> {
+        self.prices.read().unwrap().get(&pool).copied()
+    }
+
+    /// Moves the average of `pool` towards `price` and returns it. The first
+    /// price of a pool starts its average.
+    pub(crate) fn update(&self, pool: Address, price: f64) -> f64 {
+        let mut prices = self.prices.write().unwrap();
+        let average = match prices.get(&pool) {
+            Some(average) => average + self.alpha * (price - average),
+            None => price,
+        };
+        prices.insert(pool, average);
+        METRICS.record_price_ema(pool, average);
+        average
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_price_ema() {
+        let pool = Address::from_low_u64_be(1);
+        let ema = PriceEma::new(0.5).unwrap();
+        assert_eq!(ema.get(pool), None);
+        assert_eq!(ema.update(pool, 100.0), 100.0);
+        assert_eq!(ema.update(pool, 200.0), 150.0);
+        // A clone reads the same averages
+        assert_eq!(ema.clone().update(pool, 50.0), 100.0);
+        assert_eq!(ema.get(pool), Some(100.0));
+        assert_eq!(ema.get(Address::zero()), None);
+
+        // A single spike moves a slow average only a little
+        let slow = PriceEma::new(0.1).unwrap();
+        slow.update(pool, 100.0);
+        assert!((slow.update(pool, 1_000.0) - 190.0).abs() < 1e-9);
+
+        for alpha in [0.0, -0.5, 1.5, f64::NAN] {
+            assert!(matches!(
+                PriceEma::new(alpha),
+                Err(MonitorError::InvalidConfig(_))
+            ));
+        }
+        assert!(PriceEma::new(1.0).is_ok());
+    }
+}
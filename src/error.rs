@@ -0,0 +1,95 @@
+use ethers::core::abi::AbiError;
+use std::error::Error;
+use std::fmt;
+
+/// The kinds of failure of the monitor, so that library users can handle a
+/// decode failure differently from a database failure.
+///
+/// `run` and the other entry points return an `eyre::Report`, which wraps a
+/// `MonitorError` where one applies and gives it back through `downcast_ref`.
+#[derive(Debug)]
+pub enum MonitorError {
+    /// The provider could not be reached, or a request to it failed.
+    ProviderConnect(String),
+    /// A log, a contract call result or a stored row could not be decoded.
+    Decode(String),
+    /// Reading from or writing to the database failed.
+    Database(String),
+    /// A pool or token address is not a valid hex address.
+    InvalidAddress(String),
+    /// An option or argument has an invalid value, such as a provider URL
+    /// with an unsupported scheme.
+    InvalidConfig(String),
+}
+
+impl fmt::Display for MonitorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MonitorError::ProviderConnect(message)
+            | MonitorError::Decode(message)
+            | MonitorError::Database(message)
+            | MonitorError::InvalidAddress(message)
+            | MonitorError::InvalidConfig(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for MonitorError {}
+
+/// Joins the messages of `error` and its sources, which driver errors such as
+/// `tokio_postgres::Error` keep their details in.
+fn describe(error: &dyn Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message = format!("{}: {}", message, error);
+        source = error.source();
+    }
+    message
+}
+
+impl From<AbiError> for MonitorError {
+    fn from(e: AbiError) -> Self {
+        MonitorError::Decode(describe(&e))
+    }
+}
+
+impl From<ethers::core::abi::Error> for MonitorError {
+    fn from(e: ethers::core::abi::Error) -> Self {
+        MonitorError::Decode(describe(&e))
+    }
+}
+
+impl From<rusqlite::Error> for MonitorError {
+    fn from(e: rusqlite::Error) -> Self {
+        MonitorError::Database(describe(&e))
+    }
+}
+
+impl From<tokio_postgres::Error> for MonitorError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        MonitorError::Database(describe(&e))
+    }
+}
+
+pub(crate) type Result<T, E = MonitorError> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_database_error_keeps_details() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let error = MonitorError::from(conn.execute("SELECT * FROM missing", []).unwrap_err());
+        assert!(matches!(error, MonitorError::Database(_)));
+        assert!(error.to_string().contains("no such table: missing"));
+
+        // The kind survives the conversion to eyre at the edge
+        let report = eyre::Report::new(error);
+        assert!(matches!(
+            report.downcast_ref::<MonitorError>(),
+            Some(MonitorError::Database(_))
+        ));
+    }
+}
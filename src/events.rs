@@ -0,0 +1,729 @@
+use crate::error::{MonitorError, Result};
+use crate::CombinedLog;
+use ethers::core::{
+    abi::{AbiDecode, Event, HumanReadableParser, LogParam, RawLog, Token},
+    types::{Address, Log, H256, I256, U256},
+    utils::{hex, keccak256},
+};
+use serde_json::json;
+
+/// Signature of the Uniswap V3 `Swap` event.
+pub const SWAP_EVENT: &str = "Swap(address,address,int256,int256,uint160,uint128,int24)";
+/// Signature of the `Swap` event of Uniswap V3 forks such as PancakeSwap V3,
+/// which also report the protocol fees taken from the swap.
+pub const PANCAKE_SWAP_EVENT: &str =
+    "Swap(address,address,int256,int256,uint160,uint128,int24,uint128,uint128)";
+/// Signature of the Uniswap V3 `Mint` event, emitted when liquidity is added.
+pub const MINT_EVENT: &str = "Mint(address,address,int24,int24,uint128,uint256,uint256)";
+/// Signature of the Uniswap V3 `Burn` event, emitted when liquidity is removed.
+pub const BURN_EVENT: &str = "Burn(address,int24,int24,uint128,uint256,uint256)";
+
+/// Signature of the Uniswap V2 `Swap` event.
+pub const V2_SWAP_EVENT: &str = "Swap(address,uint256,uint256,uint256,uint256,address)";
+
+/// Returns the `topics[0]` value of logs emitted for `signature`.
+pub fn event_topic(signature: &str) -> H256 {
+    H256::from(keccak256(signature.as_bytes()))
+}
+
+/// A decoded pool `Mint` event.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MintLog {
+    pub tx_hash: H256,
+    pub pool: Address,
+    /// The account that called `mint`, usually the position manager.
+    pub sender: Address,
+    /// The owner of the position the liquidity was added to.
+    pub owner: Address,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    /// Liquidity added to the position.
+    pub amount: u128,
+    /// Amount of token0 paid into the pool.
+    pub amount0: U256,
+    /// Amount of token1 paid into the pool.
+    pub amount1: U256,
+    pub block_number: Option<u64>,
+    pub block_hash: Option<H256>,
+    pub timestamp: Option<u64>,
+    pub log_index: Option<u64>,
+    /// Set when the provider retracted the log because of a chain reorganization.
+    pub removed: bool,
+}
+
+/// A decoded pool `Burn` event.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BurnLog {
+    pub tx_hash: H256,
+    pub pool: Address,
+    /// The owner of the position the liquidity was removed from.
+    pub owner: Address,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    /// Liquidity removed from the position.
+    pub amount: u128,
+    /// Amount of token0 owed to the position.
+    pub amount0: U256,
+    /// Amount of token1 owed to the position.
+    pub amount1: U256,
+    pub block_number: Option<u64>,
+    pub block_hash: Option<H256>,
+    pub timestamp: Option<u64>,
+    pub log_index: Option<u64>,
+    /// Set when the provider retracted the log because of a chain reorganization.
+    pub removed: bool,
+}
+
+impl MintLog {
+    /// Returns the mint as a JSON object, formatted like `CombinedLog::to_json`.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "event": "mint",
+            "tx_hash": format!("{:#x}", self.tx_hash),
+            "pool": format!("{:#x}", self.pool),
+            "sender": format!("{:#x}", self.sender),
+            "owner": format!("{:#x}", self.owner),
+            "tick_lower": self.tick_lower,
+            "tick_upper": self.tick_upper,
+            "amount": self.amount.to_string(),
+            "amount0": self.amount0.to_string(),
+            "amount1": self.amount1.to_string(),
+            "block_number": self.block_number,
+            "block_hash": self.block_hash.map(|hash| format!("{:#x}", hash)),
+            "timestamp": self.timestamp,
+            "log_index": self.log_index,
+            "removed": self.removed,
+        })
+    }
+}
+
+impl BurnLog {
+    /// Returns the burn as a JSON object, formatted like `CombinedLog::to_json`.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "event": "burn",
+            "tx_hash": format!("{:#x}", self.tx_hash),
+            "pool": format!("{:#x}", self.pool),
+            "owner": format!("{:#x}", self.owner),
+            "tick_lower": self.tick_lower,
+            "tick_upper": self.tick_upper,
+            "amount": self.amount.to_string(),
+            "amount0": self.amount0.to_string(),
+            "amount1": self.amount1.to_string(),
+            "block_number": self.block_number,
+            "block_hash": self.block_hash.map(|hash| format!("{:#x}", hash)),
+            "timestamp": self.timestamp,
+            "log_index": self.log_index,
+            "removed": self.removed,
+        })
+    }
+}
+
+/// The non-indexed fields of a Uniswap V2 `Swap` event. A V2 pair reports the
+/// amounts paid in and taken out separately instead of signed deltas.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct V2LogData {
+    pub amount0_in: U256,
+    pub amount1_in: U256,
+    pub amount0_out: U256,
+    pub amount1_out: U256,
+}
+
+/// A decoded Uniswap V2 swap together with the transaction, pair and block it
+/// came from.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct V2SwapLog {
+    pub tx_hash: H256,
+    pub pool: Address,
+    pub sender: Address,
+    pub to: Address,
+    pub data: V2LogData,
+    pub block_number: Option<u64>,
+    pub block_hash: Option<H256>,
+    pub timestamp: Option<u64>,
+    pub log_index: Option<u64>,
+    /// Set when the provider retracted the log because of a chain reorganization.
+    pub removed: bool,
+}
+
+impl V2SwapLog {
+    /// Returns the swap as a JSON object, formatted like `CombinedLog::to_json`.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "event": "v2_swap",
+            "tx_hash": format!("{:#x}", self.tx_hash),
+            "pool": format!("{:#x}", self.pool),
+            "sender": format!("{:#x}", self.sender),
+            "to": format!("{:#x}", self.to),
+            "amount0_in": self.data.amount0_in.to_string(),
+            "amount1_in": self.data.amount1_in.to_string(),
+            "amount0_out": self.data.amount0_out.to_string(),
+            "amount1_out": self.data.amount1_out.to_string(),
+            "block_number": self.block_number,
+            "block_hash": self.block_hash.map(|hash| format!("{:#x}", hash)),
+            "timestamp": self.timestamp,
+            "log_index": self.log_index,
+            "removed": self.removed,
+        })
+    }
+}
+
+/// A log of the event given by `MonitorOptions::custom_event`, decoded from
+/// its ABI rather than as one of the typed Uniswap events.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CustomLog {
+    pub tx_hash: H256,
+    /// The contract that emitted the log.
+    pub contract: Address,
+    /// The name of the event, such as `Transfer`.
+    pub name: String,
+    /// The parameters in the order of the signature, indexed ones included.
+    /// Indexed strings, bytes and arrays only appear as the hash in their topic.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_params"))]
+    pub params: Vec<LogParam>,
+    pub block_number: Option<u64>,
+    pub block_hash: Option<H256>,
+    pub timestamp: Option<u64>,
+    pub log_index: Option<u64>,
+    /// Set when the provider retracted the log because of a chain reorganization.
+    pub removed: bool,
+}
+
+impl CustomLog {
+    /// The parameters as a JSON object keyed by name, or by position for
+    /// unnamed ones. Integers are decimal strings, as elsewhere, and bytes are
+    /// `0x`-prefixed hex.
+    pub fn params_json(&self) -> serde_json::Value {
+        params_json(&self.params)
+    }
+
+    /// Returns the log as a JSON object, formatted like `CombinedLog::to_json`.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "event": "custom",
+            "name": self.name,
+            "tx_hash": format!("{:#x}", self.tx_hash),
+            "contract": format!("{:#x}", self.contract),
+            "params": self.params_json(),
+            "block_number": self.block_number,
+            "block_hash": self.block_hash.map(|hash| format!("{:#x}", hash)),
+            "timestamp": self.timestamp,
+            "log_index": self.log_index,
+            "removed": self.removed,
+        })
+    }
+}
+
+fn params_json(params: &[LogParam]) -> serde_json::Value {
+    params
+        .iter()
+        .enumerate()
+        .map(|(position, param)| {
+            let name = match param.name.as_str() {
+                "" => position.to_string(),
+                name => name.to_string(),
+            };
+            (name, token_json(&param.value))
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+#[cfg(feature = "serde")]
+fn serialize_params<S: serde::Serializer>(
+    params: &[LogParam],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serde::Serialize::serialize(&params_json(params), serializer)
+}
+
+fn token_json(token: &Token) -> serde_json::Value {
+    match token {
+        Token::Address(address) => json!(format!("{:#x}", address)),
+        Token::Uint(value) => json!(value.to_string()),
+        Token::Int(value) => json!(I256::from_raw(*value).to_string()),
+        Token::Bool(value) => json!(value),
+        Token::String(value) => json!(value),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => {
+            json!(format!("0x{}", hex::encode(bytes)))
+        }
+        Token::Array(tokens) | Token::FixedArray(tokens) | Token::Tuple(tokens) => {
+            tokens.iter().map(token_json).collect()
+        }
+    }
+}
+
+/// Parses a human-readable event signature for `MonitorOptions::custom_event`,
+/// such as `Transfer(address indexed from, address indexed to, uint256 value)`.
+/// A leading `event` keyword is optional. Indexed parameters must be marked, as
+/// they are read from the topics; anonymous events are rejected, since their
+/// logs cannot be told apart by topic.
+pub fn parse_event_signature(signature: &str) -> Result<Event> {
+    let signature = signature.trim();
+    let event = HumanReadableParser::parse_event(signature).map_err(|e| {
+        MonitorError::InvalidConfig(format!("invalid event signature '{}': {}", signature, e))
+    })?;
+    if event.anonymous {
+        return Err(MonitorError::InvalidConfig(format!(
+            "anonymous event '{}' cannot be monitored",
+            signature
+        )));
+    }
+    Ok(event)
+}
+
+/// A decoded event of one of the monitored pools.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum PoolEvent {
+    Swap(Box<CombinedLog>),
+    Mint(MintLog),
+    Burn(BurnLog),
+    V2Swap(V2SwapLog),
+    Custom(CustomLog),
+}
+
+impl PoolEvent {
+    pub fn tx_hash(&self) -> H256 {
+        match self {
+            PoolEvent::Swap(swap) => swap.tx_hash,
+            PoolEvent::Mint(mint) => mint.tx_hash,
+            PoolEvent::Burn(burn) => burn.tx_hash,
+            PoolEvent::V2Swap(swap) => swap.tx_hash,
+            PoolEvent::Custom(log) => log.tx_hash,
+        }
+    }
+
+    /// The pool that emitted the event, or the contract for a custom event.
+    pub fn pool(&self) -> Address {
+        match self {
+            PoolEvent::Swap(swap) => swap.pool,
+            PoolEvent::Mint(mint) => mint.pool,
+            PoolEvent::Burn(burn) => burn.pool,
+            PoolEvent::V2Swap(swap) => swap.pool,
+            PoolEvent::Custom(log) => log.contract,
+        }
+    }
+
+    pub fn block_number(&self) -> Option<u64> {
+        match self {
+            PoolEvent::Swap(swap) => swap.block_number,
+            PoolEvent::Mint(mint) => mint.block_number,
+            PoolEvent::Burn(burn) => burn.block_number,
+            PoolEvent::V2Swap(swap) => swap.block_number,
+            PoolEvent::Custom(log) => log.block_number,
+        }
+    }
+
+    pub fn block_hash(&self) -> Option<H256> {
+        match self {
+            PoolEvent::Swap(swap) => swap.block_hash,
+            PoolEvent::Mint(mint) => mint.block_hash,
+            PoolEvent::Burn(burn) => burn.block_hash,
+            PoolEvent::V2Swap(swap) => swap.block_hash,
+            PoolEvent::Custom(log) => log.block_hash,
+        }
+    }
+
+    pub fn log_index(&self) -> Option<u64> {
+        match self {
+            PoolEvent::Swap(swap) => swap.log_index,
+            PoolEvent::Mint(mint) => mint.log_index,
+            PoolEvent::Burn(burn) => burn.log_index,
+            PoolEvent::V2Swap(swap) => swap.log_index,
+            PoolEvent::Custom(log) => log.log_index,
+        }
+    }
+
+    /// Whether the provider retracted the log because of a chain reorganization.
+    pub fn removed(&self) -> bool {
+        match self {
+            PoolEvent::Swap(swap) => swap.removed,
+            PoolEvent::Mint(mint) => mint.removed,
+            PoolEvent::Burn(burn) => burn.removed,
+            PoolEvent::V2Swap(swap) => swap.removed,
+            PoolEvent::Custom(log) => log.removed,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            PoolEvent::Swap(swap) => swap.to_json(),
+            PoolEvent::Mint(mint) => mint.to_json(),
+            PoolEvent::Burn(burn) => burn.to_json(),
+            PoolEvent::V2Swap(swap) => swap.to_json(),
+            PoolEvent::Custom(log) => log.to_json(),
+        }
+    }
+}
+
+impl From<CombinedLog> for PoolEvent {
+    fn from(swap: CombinedLog) -> Self {
+        PoolEvent::Swap(Box::new(swap))
+    }
+}
+
+pub(crate) fn topic(log: &Log, index: usize) -> Result<H256> {
+    log.topics.get(index).copied().ok_or_else(|| {
+        MonitorError::Decode(format!(
+            "log {:?} has {} topics, expected at least {}",
+            log.transaction_hash,
+            log.topics.len(),
+            index + 1
+        ))
+    })
+}
+
+/// Decodes an indexed `int24` topic, which is sign-extended to 32 bytes.
+fn decode_tick_topic(log: &Log, index: usize) -> Result<i32> {
+    Ok(i32::decode(topic(log, index)?.as_bytes())?)
+}
+
+pub(crate) fn decode_mint(log: &Log, timestamp: Option<u64>) -> Result<MintLog> {
+    let (sender, amount, amount0, amount1): (Address, u128, U256, U256) =
+        AbiDecode::decode(&log.data)?;
+    Ok(MintLog {
+        tx_hash: log.transaction_hash.unwrap_or_default(),
+        pool: log.address,
+        sender,
+        owner: Address::from(topic(log, 1)?),
+        tick_lower: decode_tick_topic(log, 2)?,
+        tick_upper: decode_tick_topic(log, 3)?,
+        amount,
+        amount0,
+        amount1,
+        block_number: log.block_number.map(|n| n.as_u64()),
+        block_hash: log.block_hash,
+        timestamp,
+        log_index: log.log_index.map(|i| i.as_u64()),
+        removed: log.removed.unwrap_or(false),
+    })
+}
+
+pub(crate) fn decode_burn(log: &Log, timestamp: Option<u64>) -> Result<BurnLog> {
+    let (amount, amount0, amount1): (u128, U256, U256) = AbiDecode::decode(&log.data)?;
+    Ok(BurnLog {
+        tx_hash: log.transaction_hash.unwrap_or_default(),
+        pool: log.address,
+        owner: Address::from(topic(log, 1)?),
+        tick_lower: decode_tick_topic(log, 2)?,
+        tick_upper: decode_tick_topic(log, 3)?,
+        amount,
+        amount0,
+        amount1,
+        block_number: log.block_number.map(|n| n.as_u64()),
+        block_hash: log.block_hash,
+        timestamp,
+        log_index: log.log_index.map(|i| i.as_u64()),
+        removed: log.removed.unwrap_or(false),
+    })
+}
+
+pub(crate) fn decode_v2_log_data(data: &[u8]) -> Result<V2LogData> {
+    let (amount0_in, amount1_in, amount0_out, amount1_out): (U256, U256, U256, U256) =
+        AbiDecode::decode(data)?;
+    Ok(V2LogData {
+        amount0_in,
+        amount1_in,
+        amount0_out,
+        amount1_out,
+    })
+}
+
+pub(crate) fn decode_v2_swap(log: &Log, timestamp: Option<u64>) -> Result<V2SwapLog> {
+    Ok(V2SwapLog {
+        tx_hash: log.transaction_hash.unwrap_or_default(),
+        pool: log.address,
+        sender: Address::from(topic(log, 1)?),
+        to: Address::from(topic(log, 2)?),
+        data: decode_v2_log_data(&log.data)?,
+        block_number: log.block_number.map(|n| n.as_u64()),
+        block_hash: log.block_hash,
+        timestamp,
+        log_index: log.log_index.map(|i| i.as_u64()),
+        removed: log.removed.unwrap_or(false),
+    })
+}
+
+pub(crate) fn decode_custom(log: &Log, event: &Event, timestamp: Option<u64>) -> Result<CustomLog> {
+    let decoded = event.parse_log(RawLog {
+        topics: log.topics.clone(),
+        data: log.data.to_vec(),
+    })?;
+    Ok(CustomLog {
+        tx_hash: log.transaction_hash.unwrap_or_default(),
+        contract: log.address,
+        name: event.name.clone(),
+        params: decoded.params,
+        block_number: log.block_number.map(|n| n.as_u64()),
+        block_hash: log.block_hash,
+        timestamp,
+        log_index: log.log_index.map(|i| i.as_u64()),
+        removed: log.removed.unwrap_or(false),
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ethers::abi::encode;
+    use ethers::types::Bytes;
+    use std::str::FromStr;
+
+    pub(crate) const TRADE_EVENT: &str =
+        "event Trade(address indexed trader, int256 delta, bytes4 market, bool)";
+
+    const POSITION_MANAGER: &str = "0xc36442b4a4522e871399cd717abdd847ab11fe88";
+    const POOL: &str = "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640";
+    const V2_PAIR: &str = "0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc";
+    const OWNER_TOPIC: &str = "0x000000000000000000000000c36442b4a4522e871399cd717abdd847ab11fe88";
+
+    // A Mint of the USDC/WETH pool through the position manager, ticks 201800..203200
+    pub(crate) fn create_test_mint_log() -> Log {
+        Log {
+            address: Address::from_str(POOL).unwrap(),
+            transaction_hash: Some(
+                H256::from_str(
+                    "0x5d2a5e5b7d8b1f0cbd5a6b5c2c3e4f6a7b8c9d0e1f2a3b4c5d6e7f8091a2b3c4",
+                )
+                .unwrap(),
+            ),
+            topics: vec![
+                event_topic(MINT_EVENT),
+                H256::from_str(OWNER_TOPIC).unwrap(),
+                H256::from_str(
+                    "0x0000000000000000000000000000000000000000000000000000000000031448",
+                )
+                .unwrap(),
+                H256::from_str(
+                    "0x00000000000000000000000000000000000000000000000000000000000319c0",
+                )
+                .unwrap(),
+            ],
+            data: Bytes::from_str("0x000000000000000000000000c36442b4a4522e871399cd717abdd847ab11fe8800000000000000000000000000000000000000000000005248d95d8e821c000000000000000000000000000000000000000000000000000000000000b2d05e0000000000000000000000000000000000000000000000000016c63f455ee48481").unwrap(),
+            block_number: Some(17_500_001u64.into()),
+            log_index: Some(7u64.into()),
+            ..Default::default()
+        }
+    }
+
+    // A Trade of a non-Uniswap contract, see `TRADE_EVENT`
+    pub(crate) fn create_test_custom_log() -> Log {
+        Log {
+            address: Address::from_low_u64_be(0xc0de),
+            transaction_hash: Some(H256::from_low_u64_be(42)),
+            topics: vec![
+                parse_event_signature(TRADE_EVENT).unwrap().signature(),
+                H256::from_str(OWNER_TOPIC).unwrap(),
+            ],
+            data: Bytes::from(encode(&[
+                Token::Int(I256::from(-1_500).into_raw()),
+                Token::FixedBytes(b"ETH1".to_vec()),
+                Token::Bool(true),
+            ])),
+            block_number: Some(17_500_004u64.into()),
+            log_index: Some(2u64.into()),
+            ..Default::default()
+        }
+    }
+
+    // A full-range Burn, ticks -887270..887270
+    pub(crate) fn create_test_burn_log() -> Log {
+        Log {
+            address: Address::from_str(POOL).unwrap(),
+            transaction_hash: Some(
+                H256::from_str(
+                    "0x9f3c1b2a4d5e6f708192a3b4c5d6e7f8091a2b3c4d5e6f708192a3b4c5d6e7f8",
+                )
+                .unwrap(),
+            ),
+            topics: vec![
+                event_topic(BURN_EVENT),
+                H256::from_str(OWNER_TOPIC).unwrap(),
+                H256::from_str(
+                    "0xfffffffffffffffffffffffffffffffffffffffffffffffffffffffffff2761a",
+                )
+                .unwrap(),
+                H256::from_str(
+                    "0x00000000000000000000000000000000000000000000000000000000000d89e6",
+                )
+                .unwrap(),
+            ],
+            data: Bytes::from_str("0x00000000000000000000000000000000000000000000005248d95d8e821c000000000000000000000000000000000000000000000000000000000000b2d05dff00000000000000000000000000000000000000000000000016c63f455ee48480").unwrap(),
+            block_number: Some(17_500_002u64.into()),
+            log_index: Some(3u64.into()),
+            ..Default::default()
+        }
+    }
+
+    // A V2 USDC/WETH swap of 1 WETH for 1850.123456 USDC through the V2 router
+    pub(crate) fn create_test_v2_swap_log() -> Log {
+        Log {
+            address: Address::from_str(V2_PAIR).unwrap(),
+            transaction_hash: Some(
+                H256::from_str(
+                    "0x3b8e1c2d4f5a6b7c8d9e0f1a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d5e",
+                )
+                .unwrap(),
+            ),
+            topics: vec![
+                event_topic(V2_SWAP_EVENT),
+                H256::from_str(
+                    "0x0000000000000000000000007a250d5630b4cf539739df2c5dacb4c659f2488d",
+                )
+                .unwrap(),
+                H256::from_str(
+                    "0x0000000000000000000000004b7d6c3cea01f4d54a9cad6587da106ea39da1e6",
+                )
+                .unwrap(),
+            ],
+            data: Bytes::from_str("0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000de0b6b3a7640000000000000000000000000000000000000000000000000000000000006e46a4c00000000000000000000000000000000000000000000000000000000000000000").unwrap(),
+            block_number: Some(17_500_003u64.into()),
+            log_index: Some(11u64.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_event_topics() {
+        assert_eq!(
+            event_topic(SWAP_EVENT),
+            H256::from_str("0xc42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67")
+                .unwrap()
+        );
+        assert_eq!(
+            event_topic(MINT_EVENT),
+            H256::from_str("0x7a53080ba414158be7ec69b987b5fb7d07dee101fe85488f0853ae16239d0bde")
+                .unwrap()
+        );
+        assert_eq!(
+            event_topic(V2_SWAP_EVENT),
+            H256::from_str("0xd78ad95fa46c994b6551d0da85fc275fe613ce37657fb8d5e3d130840159d822")
+                .unwrap()
+        );
+        assert_eq!(
+            event_topic(BURN_EVENT),
+            H256::from_str("0x0c396cd989a39f4459b5fa1aed6a9a8dcdbc45908acfd67e028cd568da98982c")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decode_mint() {
+        let mint = decode_mint(&create_test_mint_log(), Some(1_687_000_012)).unwrap();
+        let position_manager = Address::from_str(POSITION_MANAGER).unwrap();
+        assert_eq!(mint.pool, Address::from_str(POOL).unwrap());
+        assert_eq!(mint.sender, position_manager);
+        assert_eq!(mint.owner, position_manager);
+        assert_eq!(mint.tick_lower, 201_800);
+        assert_eq!(mint.tick_upper, 203_200);
+        assert_eq!(mint.amount, 1_517_882_343_751_509_868_544);
+        assert_eq!(mint.amount0, U256::from(3_000_000_000u64));
+        assert_eq!(mint.amount1, U256::from(1_641_068_681_400_452_225u64));
+        assert_eq!(mint.block_number, Some(17_500_001));
+        assert_eq!(mint.timestamp, Some(1_687_000_012));
+        assert_eq!(mint.log_index, Some(7));
+        assert!(!mint.removed);
+    }
+
+    #[test]
+    fn test_decode_burn() {
+        let burn = decode_burn(&create_test_burn_log(), None).unwrap();
+        assert_eq!(burn.owner, Address::from_str(POSITION_MANAGER).unwrap());
+        // Negative ticks are sign-extended in the topic
+        assert_eq!(burn.tick_lower, -887_270);
+        assert_eq!(burn.tick_upper, 887_270);
+        assert_eq!(burn.amount, 1_517_882_343_751_509_868_544);
+        assert_eq!(burn.amount0, U256::from(2_999_999_999u64));
+        assert_eq!(burn.amount1, U256::from(1_641_068_681_400_452_224u64));
+        assert_eq!(burn.log_index, Some(3));
+    }
+
+    #[test]
+    fn test_decode_mint_without_tick_topics() {
+        let mut log = create_test_mint_log();
+        log.topics.truncate(2);
+        assert!(decode_mint(&log, None).is_err());
+    }
+
+    #[test]
+    fn test_decode_v2_swap() {
+        let swap = decode_v2_swap(&create_test_v2_swap_log(), None).unwrap();
+        assert_eq!(swap.pool, Address::from_str(V2_PAIR).unwrap());
+        assert_eq!(
+            swap.sender,
+            Address::from_str("0x7a250d5630b4cf539739df2c5dacb4c659f2488d").unwrap()
+        );
+        assert_eq!(
+            swap.to,
+            Address::from_str("0x4b7d6c3cea01f4d54a9cad6587da106ea39da1e6").unwrap()
+        );
+        assert_eq!(
+            swap.data,
+            V2LogData {
+                amount0_in: U256::zero(),
+                amount1_in: U256::exp10(18),
+                amount0_out: U256::from(1_850_123_456u64),
+                amount1_out: U256::zero(),
+            }
+        );
+        assert_eq!(swap.log_index, Some(11));
+    }
+
+    #[test]
+    fn test_parse_event_signature() {
+        let event = parse_event_signature(TRADE_EVENT).unwrap();
+        assert_eq!(event.name, "Trade");
+        assert!(event.inputs[0].indexed);
+        // The keyword is optional
+        let transfer =
+            parse_event_signature("Transfer(address indexed from, address indexed to, uint256)")
+                .unwrap();
+        assert_eq!(
+            transfer.signature(),
+            event_topic("Transfer(address,address,uint256)")
+        );
+
+        for signature in ["Transfer(address", "event Ping() anonymous"] {
+            assert!(matches!(
+                parse_event_signature(signature),
+                Err(MonitorError::InvalidConfig(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decode_custom() {
+        let event = parse_event_signature(TRADE_EVENT).unwrap();
+        let log = decode_custom(&create_test_custom_log(), &event, Some(1_687_000_048)).unwrap();
+        assert_eq!(log.contract, Address::from_low_u64_be(0xc0de));
+        assert_eq!(log.name, "Trade");
+        assert_eq!(log.params.len(), 4);
+        assert_eq!(log.block_number, Some(17_500_004));
+        assert_eq!(log.timestamp, Some(1_687_000_048));
+        assert_eq!(
+            log.params_json(),
+            json!({
+                "trader": POSITION_MANAGER,
+                "delta": "-1500",
+                "market": "0x45544831",
+                // Unnamed parameters are keyed by position
+                "3": true,
+            })
+        );
+        assert_eq!(log.to_json()["event"], "custom");
+
+        // A log of a different shape does not decode
+        let mut truncated = create_test_custom_log();
+        truncated.data = Bytes::from(vec![0; 32]);
+        assert!(matches!(
+            decode_custom(&truncated, &event, None),
+            Err(MonitorError::Decode(_))
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::retry::backoff_delay;
+use crate::{CombinedLog, PoolMetadata, SwapSink};
+use eyre::{eyre, Result};
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+/// Time librdkafka has to deliver a message, its own retries included, before
+/// reporting a delivery error.
+const KAFKA_DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time a message may wait for room in the producer's queue.
+const KAFKA_QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Attempts made to deliver one swap before it is dropped.
+const KAFKA_SEND_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry of a failed delivery, doubled for each further one.
+const KAFKA_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Swaps that may be waiting for delivery at once. While a broker is down more
+/// are dropped, so the payloads waiting to retry do not pile up in memory.
+const KAFKA_MAX_IN_FLIGHT: usize = 10_000;
+
+/// Time given to the messages still queued on shutdown.
+const KAFKA_CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings for publishing swaps to a Kafka topic.
+#[derive(Debug, Clone)]
+pub struct KafkaConfig {
+    /// Comma-separated `host:port` list of bootstrap brokers.
+    pub brokers: String,
+    pub topic: String,
+}
+
+/// Publishes swaps to a Kafka topic, each as the JSON object of
+/// `CombinedLog::to_json` keyed by the pool address, so the swaps of a pool
+/// land in one partition.
+///
+/// Delivery runs on a spawned task, so an unreachable broker never holds up
+/// log processing. A failed delivery is retried with backoff and the swap is
+/// dropped with a warning once `KAFKA_SEND_ATTEMPTS` attempts have failed, or
+/// right away while `KAFKA_MAX_IN_FLIGHT` swaps are waiting for delivery.
+/// Retried swaps are published after the swaps sent in the meantime, so the
+/// order of a pool's swaps within its partition is only kept while deliveries
+/// succeed. Retracted swaps are published as well, with `removed` set to
+/// `true`.
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+    in_flight: Arc<Semaphore>,
+}
+
+impl KafkaSink {
+    /// Creates the producer. It connects to the brokers in the background, so
+    /// this does not fail when they are unreachable.
+    pub fn new(config: &KafkaConfig) -> Result<Self> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set(
+                "message.timeout.ms",
+                KAFKA_DELIVERY_TIMEOUT.as_millis().to_string(),
+            )
+            .create()
+            .map_err(|e| eyre!("failed to create Kafka producer: {}", e))?;
+        Ok(KafkaSink {
+            producer,
+            topic: config.topic.clone(),
+            in_flight: Arc::new(Semaphore::new(KAFKA_MAX_IN_FLIGHT)),
+        })
+    }
+
+    /// Queues the swap for delivery and returns whether it was, which it is
+    /// not while `KAFKA_MAX_IN_FLIGHT` swaps are waiting.
+    pub fn send(&self, combined_log: &CombinedLog) -> bool {
+        let Ok(permit) = self.in_flight.clone().try_acquire_owned() else {
+            warn!(
+                pool = %format!("{:#x}", combined_log.pool),
+                "too many swaps waiting for Kafka, dropping this one"
+            );
+            return false;
+        };
+        let (key, payload) = message(combined_log);
+        let producer = self.producer.clone();
+        let topic = self.topic.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            for attempt in 1..=KAFKA_SEND_ATTEMPTS {
+                let record = FutureRecord::to(&topic).key(&key).payload(&payload);
+                match producer.send(record, KAFKA_QUEUE_TIMEOUT).await {
+                    Ok(_) => return,
+                    Err((e, _)) if attempt < KAFKA_SEND_ATTEMPTS => {
+                        let delay = backoff_delay(KAFKA_RETRY_DELAY, attempt);
+                        warn!(error = %e, attempt, ?delay, "failed to publish swap to Kafka, retrying");
+                        tokio::time::sleep(delay).await;
+                    }
+                    Err((e, _)) => {
+                        warn!(error = %e, pool = %key, "failed to publish swap to Kafka, dropping it");
+                    }
+                }
+            }
+        });
+        true
+    }
+
+    /// Waits up to `KAFKA_CLOSE_TIMEOUT` for the queued messages to be
+    /// delivered. Swaps waiting to retry a failed delivery are not.
+    pub fn close(&self) {
+        if let Err(e) = self.producer.flush(KAFKA_CLOSE_TIMEOUT) {
+            warn!(error = %e, "failed to deliver the queued swaps to Kafka");
+        }
+    }
+}
+
+impl SwapSink for KafkaSink {
+    fn handle(&mut self, swap: &CombinedLog, _metadata: Option<&PoolMetadata>) -> Result<()> {
+        self.send(swap);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        KafkaSink::close(self);
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+}
+
+/// The key and payload of the message of a swap.
+fn message(combined_log: &CombinedLog) -> (String, String) {
+    (
+        format!("{:#x}", combined_log.pool),
+        combined_log.to_json().to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::create_test_combined_log;
+
+    #[test]
+    fn test_kafka_message() {
+        let swap = create_test_combined_log();
+        let (key, payload) = message(&swap);
+        assert_eq!(key, format!("{:#x}", swap.pool));
+        let json: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(json["pool"], key);
+        assert_eq!(json["tx_hash"], format!("{:#x}", swap.tx_hash));
+    }
+
+    #[tokio::test]
+    async fn test_kafka_sink_does_not_block_without_broker() {
+        let config = KafkaConfig {
+            brokers: "127.0.0.1:1".to_string(),
+            topic: "swaps".to_string(),
+        };
+        let mut sink = KafkaSink::new(&config).unwrap();
+        // Delivery waits in the background, so sending returns immediately
+        assert!(sink.send(&create_test_combined_log()));
+
+        // Once the deliveries in flight reach the bound, swaps are dropped
+        sink.in_flight = Arc::new(Semaphore::new(1));
+        assert!(sink.send(&create_test_combined_log()));
+        assert!(!sink.send(&create_test_combined_log()));
+    }
+}
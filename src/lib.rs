@@ -1,318 +1,5903 @@
 use ethers::{
     core::{
-        abi::AbiDecode,
-        types::{Address, Filter, Log, H256, I256, U256},
+        abi::{AbiDecode, Event, EventExt},
+        types::{Address, Bytes, Filter, Log, H256, I256, U256},
+        utils::hex,
     },
-    providers::{Middleware, Provider, StreamExt, Ws},
+    providers::{Authorization, ConnectionDetails, Http, Middleware, Provider, StreamExt, Ws},
 };
-use eyre::Result;
-use rusqlite::{params, Connection};
+use eyre::{bail, Result};
+use futures_util::{future, Future, Stream};
+use serde_json::json;
 use std::cmp::PartialEq;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info, warn};
 
+mod api;
+mod broadcast;
+mod config;
+mod confirmations;
+mod csv_sink;
+mod ema;
+mod error;
+mod events;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod metadata;
+mod metrics;
+mod monitor_config;
+mod oracle;
+mod retry;
+mod sink;
+mod source;
+mod store;
+mod verify;
+mod webhook;
+
+pub use api::{spawn_api_server, MAX_PAGE_SIZE};
+pub use broadcast::{spawn_broadcast_server, SwapBroadcaster};
+pub use config::{Config, PoolConfig};
+use confirmations::{spawn_head_watch, PendingEvents};
+pub use csv_sink::CsvSink;
+pub use ema::PriceEma;
+pub use error::MonitorError;
+pub use events::{
+    event_topic, parse_event_signature, BurnLog, CustomLog, MintLog, PoolEvent, V2LogData,
+    V2SwapLog, BURN_EVENT, MINT_EVENT, PANCAKE_SWAP_EVENT, SWAP_EVENT, V2_SWAP_EVENT,
+};
+#[cfg(feature = "kafka")]
+pub use kafka::{KafkaConfig, KafkaSink};
+pub use metadata::{
+    fetch_pool_address, fetch_pool_metadata, resolve_pool_addresses, PoolMetadata, TokenMetadata,
+    TokenPair, UNISWAP_V3_FACTORY,
+};
+pub use metrics::{spawn_metrics_server, Metrics, METRICS};
+pub use monitor_config::MonitorConfig;
+pub use oracle::{spawn_price_oracle, UsdPrices, PRICE_REFRESH_INTERVAL};
+use retry::{jittered, retry, PROVIDER_CALL_ATTEMPTS, PROVIDER_RETRY_DELAY};
+pub use sink::{StdoutSink, SwapSink};
+use source::{HeadStream, LogSource, LogStream};
+pub use store::{
+    aggregate_volume, database_stats, find_block_gaps, get_swaps, get_swaps_by_block_range,
+    get_swaps_by_sender, get_swaps_by_tx_hash, get_swaps_since, liquidity_series, net_volume,
+    open_sharded_store, open_store, print_database_stats, reprocess_all, BackgroundStore, BlockGap,
+    DatabaseStats, DurabilityMode, NullStore, PostgresStore, ReprocessStats, ShardedStore,
+    SqliteStore, SwapQuery, SwapStore, TableName, VolumeBucket, DEFAULT_SWAP_TABLE, DOWNTIME_SECS,
+    IN_MEMORY_PATH,
+};
+#[cfg(feature = "serde")]
+pub use store::{export_json, import_json};
+pub use verify::{verify_swap, verify_swaps, SwapMismatch};
+pub use webhook::{usd_value, Webhook, WebhookConfig};
+
+/// Number of decoded events buffered between the subscription task and the consumer.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// The Uniswap protocol version of the monitored pools, which decides the
+/// events `run` subscribes to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PoolVersion {
+    /// Only swaps are stored for V2 pairs.
+    V2,
+    #[default]
+    V3,
+}
+
+impl PoolVersion {
+    /// The kinds of events stored for pools of this version.
+    fn event_kinds(self) -> &'static [EventKind] {
+        match self {
+            PoolVersion::V2 => &[EventKind::Swap],
+            PoolVersion::V3 => &[EventKind::Swap, EventKind::Mint, EventKind::Burn],
+        }
+    }
+
+    /// The signatures of the events of `kind`, including the `Swap` variant of
+    /// forks for V3 pools. Empty when `kind` is not stored for this version.
+    fn signatures(self, kind: EventKind) -> &'static [&'static str] {
+        match (self, kind) {
+            (PoolVersion::V2, EventKind::Swap) => &[V2_SWAP_EVENT],
+            (PoolVersion::V2, _) => &[],
+            (PoolVersion::V3, EventKind::Swap) => &[SWAP_EVENT, PANCAKE_SWAP_EVENT],
+            (PoolVersion::V3, EventKind::Mint) => &[MINT_EVENT],
+            (PoolVersion::V3, EventKind::Burn) => &[BURN_EVENT],
+        }
+    }
+}
+
+impl FromStr for PoolVersion {
+    type Err = eyre::Report;
+
+    fn from_str(version: &str) -> Result<Self> {
+        match version {
+            "v2" => Ok(PoolVersion::V2),
+            "v3" => Ok(PoolVersion::V3),
+            _ => bail!("invalid pool version '{}': expected 'v2' or 'v3'", version),
+        }
+    }
+}
+
+/// A kind of pool event, see `MonitorOptions::events`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    Swap,
+    /// Liquidity added to a V3 pool.
+    Mint,
+    /// Liquidity removed from a V3 pool.
+    Burn,
+}
+
+impl EventKind {
+    fn name(self) -> &'static str {
+        match self {
+            EventKind::Swap => "swap",
+            EventKind::Mint => "mint",
+            EventKind::Burn => "burn",
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = eyre::Report;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        match kind {
+            "swap" => Ok(EventKind::Swap),
+            "mint" => Ok(EventKind::Mint),
+            "burn" => Ok(EventKind::Burn),
+            _ => bail!(
+                "invalid event '{}': expected 'swap', 'mint' or 'burn'",
+                kind
+            ),
+        }
+    }
+}
+
+/// The non-indexed fields of a Uniswap V3 `Swap` event.
 #[derive(Debug, Clone, PartialEq)]
-struct LogData {
-    amount0: I256,
-    amount1: I256,
-    sqrt_price: U256,
-    liquidity: u128,
-    tick: i32,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogData {
+    pub amount0: I256,
+    pub amount1: I256,
+    pub sqrt_price: U256,
+    pub liquidity: u128,
+    pub tick: i32,
+    /// Protocol fees in token0 reported by forks such as PancakeSwap V3, see
+    /// `PANCAKE_SWAP_EVENT`; `None` for Uniswap pools.
+    pub protocol_fees_token0: Option<u128>,
+    /// Protocol fees in token1, like `protocol_fees_token0`.
+    pub protocol_fees_token1: Option<u128>,
+}
+
+/// Which way a swap traded, going by the signs of its amounts: the token paid
+/// into the pool has a positive amount and the one taken out a negative one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SwapDirection {
+    /// token0 was sold for token1.
+    #[cfg_attr(feature = "serde", serde(rename = "token0->token1"))]
+    ZeroForOne,
+    /// token1 was sold for token0.
+    #[cfg_attr(feature = "serde", serde(rename = "token1->token0"))]
+    OneForZero,
+}
+
+impl SwapDirection {
+    /// The direction of a swap of `amount0` and `amount1`, or `None` unless
+    /// exactly one of them is positive and the other negative.
+    pub fn from_amounts(amount0: I256, amount1: I256) -> Option<Self> {
+        if amount0.is_positive() && amount1.is_negative() {
+            Some(SwapDirection::ZeroForOne)
+        } else if amount0.is_negative() && amount1.is_positive() {
+            Some(SwapDirection::OneForZero)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SwapDirection::ZeroForOne => "token0->token1",
+            SwapDirection::OneForZero => "token1->token0",
+        }
+    }
 }
 
-struct CombinedLog {
-    tx_hash: H256,
-    sender: Address,
-    receiver: Address,
-    data: LogData,
+impl FromStr for SwapDirection {
+    type Err = eyre::Report;
+
+    fn from_str(direction: &str) -> Result<Self> {
+        match direction {
+            "token0->token1" => Ok(SwapDirection::ZeroForOne),
+            "token1->token0" => Ok(SwapDirection::OneForZero),
+            _ => bail!(
+                "invalid swap direction '{}': expected 'token0->token1' or 'token1->token0'",
+                direction
+            ),
+        }
+    }
+}
+
+/// A decoded swap together with the transaction, pool and block it came from.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CombinedLog {
+    pub tx_hash: H256,
+    pub pool: Address,
+    pub sender: Address,
+    pub receiver: Address,
+    pub data: LogData,
+    pub block_number: Option<u64>,
+    pub block_hash: Option<H256>,
+    pub timestamp: Option<u64>,
+    pub price: Option<f64>,
+    /// The price implied by the tick, `1.0001^tick` adjusted for token decimals.
+    /// It rounds down to the tick boundary, so it trails `price` by less than
+    /// one basis point.
+    pub tick_price: Option<f64>,
+    /// `amount0` divided by 10^decimals0, when the pool's token decimals are known.
+    pub amount0_normalized: Option<f64>,
+    /// `amount1` divided by 10^decimals1, when the pool's token decimals are known.
+    pub amount1_normalized: Option<f64>,
+    /// The value of the swap in dollars at an oracle price of one of its
+    /// tokens, see `spawn_price_oracle`.
+    pub usd_value: Option<f64>,
+    /// Which token was sold for which, derived from the signs of the amounts
+    /// when the swap is stored; `None` when they have the same sign.
+    pub direction: Option<SwapDirection>,
+    /// The position of the log among all logs of its block, which orders
+    /// swaps of the same block and transaction.
+    pub log_index: Option<u64>,
+    /// The position of the swap's transaction in its block.
+    pub transaction_index: Option<u64>,
+    /// The gas used by the swap's whole transaction, with
+    /// `MonitorOptions::fetch_gas`. `None` while it has no receipt.
+    pub gas_used: Option<u64>,
+    /// The price the transaction paid per unit of gas in wei, base fee and
+    /// priority fee together, with `MonitorOptions::fetch_gas`.
+    pub effective_gas_price: Option<U256>,
+    /// The `MonitorOptions::source_tag` of the run that stored the swap, which
+    /// tells apart the monitors writing to a shared database.
+    pub source: Option<String>,
+    /// Set when the provider retracted the log because of a chain reorganization.
+    pub removed: bool,
+    /// The data of the log as received, stored so that swaps can be decoded
+    /// again after a decoder fix, see `reprocess_all`.
+    pub raw_data: Option<Bytes>,
 }
 
 impl CombinedLog {
-    fn new(tx_hash: Option<H256>, sender: Address, receiver: Address, data: LogData) -> Self {
+    fn new(
+        tx_hash: Option<H256>,
+        pool: Address,
+        sender: Address,
+        receiver: Address,
+        data: LogData,
+    ) -> Self {
         CombinedLog {
             tx_hash: tx_hash.unwrap_or_default(),
+            pool,
             sender,
             receiver,
             data,
+            block_number: None,
+            block_hash: None,
+            timestamp: None,
+            price: None,
+            tick_price: None,
+            amount0_normalized: None,
+            amount1_normalized: None,
+            usd_value: None,
+            direction: None,
+            log_index: None,
+            transaction_index: None,
+            gas_used: None,
+            effective_gas_price: None,
+            source: None,
+            removed: false,
+            raw_data: None,
         }
     }
-}
 
-fn initialize_database(db_path: &str) -> Result<Connection> {
-    let conn = Connection::open(db_path)?;
+    /// Amount of token0 moved by the swap; negative when it left the pool.
+    pub fn amount0(&self) -> I256 {
+        self.data.amount0
+    }
+
+    /// Amount of token1 moved by the swap; negative when it left the pool.
+    pub fn amount1(&self) -> I256 {
+        self.data.amount1
+    }
 
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS logs (
-        tx_hash TEXT,
-        sender_address TEXT,
-        receiver_address TEXT,
-        amount0 TEXT,
-        amount1 TEXT,  
-        sqrt_price TEXT,
-        liquidity TEXT,
-        tick INTEGER
-      )",
-        [],
-    )?;
+    /// The pool's `sqrtPriceX96` after the swap.
+    pub fn sqrt_price(&self) -> U256 {
+        self.data.sqrt_price
+    }
+
+    /// The pool's in-range liquidity after the swap.
+    pub fn liquidity(&self) -> u128 {
+        self.data.liquidity
+    }
+
+    /// The pool's tick after the swap.
+    pub fn tick(&self) -> i32 {
+        self.data.tick
+    }
+
+    /// Returns the swap as a JSON object with hex hashes and addresses. Amounts,
+    /// `sqrt_price` and `liquidity` are decimal strings, since they can exceed
+    /// the integer precision of a JSON number.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "event": "swap",
+            "tx_hash": format!("{:#x}", self.tx_hash),
+            "pool": format!("{:#x}", self.pool),
+            "sender": format!("{:#x}", self.sender),
+            "receiver": format!("{:#x}", self.receiver),
+            "amount0": self.data.amount0.to_string(),
+            "amount1": self.data.amount1.to_string(),
+            "sqrt_price": self.data.sqrt_price.to_string(),
+            "liquidity": self.data.liquidity.to_string(),
+            "tick": self.data.tick,
+            "protocol_fees_token0": self.data.protocol_fees_token0.map(|fees| fees.to_string()),
+            "protocol_fees_token1": self.data.protocol_fees_token1.map(|fees| fees.to_string()),
+            "block_number": self.block_number,
+            "block_hash": self.block_hash.map(|hash| format!("{:#x}", hash)),
+            "timestamp": self.timestamp,
+            "price": self.price,
+            "tick_price": self.tick_price,
+            "amount0_normalized": self.amount0_normalized,
+            "amount1_normalized": self.amount1_normalized,
+            "usd_value": self.usd_value,
+            "direction": self.direction.map(SwapDirection::as_str),
+            "log_index": self.log_index,
+            "transaction_index": self.transaction_index,
+            "gas_used": self.gas_used,
+            "effective_gas_price": self.effective_gas_price.map(|price| price.to_string()),
+            "source": self.source,
+            "removed": self.removed,
+        })
+    }
+
+    fn with_block(mut self, block_number: Option<u64>, block_hash: Option<H256>) -> Self {
+        self.block_number = block_number;
+        self.block_hash = block_hash;
+        self
+    }
+
+    fn with_timestamp(mut self, timestamp: Option<u64>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    fn with_price(mut self, price: Option<f64>, tick_price: Option<f64>) -> Self {
+        self.price = price;
+        self.tick_price = tick_price;
+        self
+    }
 
-    Ok(conn)
+    fn with_normalized_amounts(mut self, decimals: &TokenDecimals) -> Self {
+        self.amount0_normalized = Some(to_token_units(self.data.amount0, decimals.decimals0));
+        self.amount1_normalized = Some(to_token_units(self.data.amount1, decimals.decimals1));
+        self
+    }
+
+    fn with_log_index(mut self, log_index: Option<u64>, removed: bool) -> Self {
+        self.log_index = log_index;
+        self.removed = removed;
+        self
+    }
+
+    fn with_transaction_index(mut self, transaction_index: Option<u64>) -> Self {
+        self.transaction_index = transaction_index;
+        self
+    }
+
+    fn with_raw_data(mut self, raw_data: Bytes) -> Self {
+        self.raw_data = Some(raw_data);
+        self
+    }
 }
 
-fn create_pool_filter(contract_address: &str) -> Filter {
-    let pool_address = Address::from_str(contract_address).unwrap();
-    Filter::new()
-        .address(vec![pool_address])
-        .event("Swap(address,address,int256,int256,uint160,uint128,int24)")
+/// Decimals of a pool's token0 and token1, needed to turn raw amounts and
+/// prices into human-readable units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenDecimals {
+    pub decimals0: u8,
+    pub decimals1: u8,
 }
 
-fn print_log(combined_log: &CombinedLog, log_data: &LogData) {
-    println!(
-        "new | tx_hash: {:?}, sender: {:?}, receiver: {:?}, amount0: {:?}, amount1: {:?}, sqrt_price: {:?}, liquidity: {:?}, tick: {:?}",
-        combined_log.tx_hash,
-        combined_log.sender,
-        combined_log.receiver,
-        log_data.amount0,
-        log_data.amount1,
-        log_data.sqrt_price,
-        log_data.liquidity,
-        log_data.tick
-    );
+fn u256_to_f64(value: U256) -> f64 {
+    value
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |acc, limb| acc * 2f64.powi(64) + *limb as f64)
 }
 
-async fn connect_to_provider(provider_ws: &str) -> Result<Arc<Provider<Ws>>> {
-    let provider = Provider::<Ws>::connect(provider_ws).await?;
-    Ok(Arc::new(provider))
+/// The magnitude of a raw token amount. Unlike `I256::abs`, which overflows on
+/// `I256::MIN`, this maps it to 2^255, so a buggy or malicious pool emitting
+/// that amount cannot make filtering or normalization panic.
+pub fn abs_u256(amount: I256) -> U256 {
+    amount.unsigned_abs()
 }
 
-async fn process_log(log: Log, conn: &Connection) -> Result<()> {
-    let log_data = decode_log_data(&log.data)?;
-    let combined_log = CombinedLog::new(
-        log.transaction_hash,
-        Address::from(log.topics[1]),
-        Address::from(log.topics[2]),
-        log_data.clone(),
-    );
-    insert_log(&conn, &combined_log)?;
-    print_log(&combined_log, &log_data);
-    Ok(())
+/// A raw token amount as an `i128`, or `None` when it does not fit, unlike
+/// `I256::low_i128`, which silently truncates.
+pub fn try_to_i128(amount: I256) -> Option<i128> {
+    i128::try_from(amount).ok()
 }
 
-async fn handle_logs(
-    client: Arc<Provider<Ws>>,
-    conn: &Connection,
-    pool_filter: &Filter,
-) -> Result<()> {
-    let mut stream = client.subscribe_logs(pool_filter).await?;
-    while let Some(log) = stream.next().await {
-        process_log(log, conn).await?;
+/// Like `try_to_i128`, for unsigned values such as `sqrt_price`.
+pub fn try_to_u128(value: U256) -> Option<u128> {
+    u128::try_from(value).ok()
+}
+
+/// Divides a raw token amount by 10^`decimals`.
+///
+/// The magnitude is converted through `f64`, which covers the whole `I256`
+/// range, so large amounts lose precision instead of overflowing.
+pub fn to_token_units(amount: I256, decimals: u8) -> f64 {
+    let magnitude = u256_to_f64(abs_u256(amount)) / 10f64.powi(decimals as i32);
+    if amount.is_negative() {
+        -magnitude
+    } else {
+        magnitude
     }
-    Ok(())
 }
 
-fn decode_log_data(data: &[u8]) -> Result<LogData> {
-    let (amount0, amount1, sqrt_price, liquidity, tick): (I256, I256, U256, u128, i32) =
-        AbiDecode::decode(data)?;
+/// Divides a raw token amount by 10^`decimals` exactly, as a decimal string
+/// such as `-263.12`, for when the precision lost by `to_token_units` matters.
+///
+/// Trailing zeros of the fraction are dropped, and the decimal point with
+/// them for whole amounts.
+pub fn to_token_units_decimal(amount: I256, decimals: u8) -> String {
+    let digits = abs_u256(amount).to_string();
+    let decimals = decimals as usize;
+    let digits = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    let sign = if amount.is_negative() { "-" } else { "" };
+    if fraction.is_empty() {
+        format!("{}{}", sign, whole)
+    } else {
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
 
-    Ok(LogData {
-        amount0,
-        amount1,
-        sqrt_price,
-        liquidity,
-        tick,
-    })
+/// Converts a pool's `sqrtPriceX96` into the price of token0 denominated in
+/// token1, adjusted for the tokens' decimals.
+///
+/// `sqrtPriceX96` fits in 160 bits, so after dividing by 2^96 the square root is
+/// at most 2^64 and squaring it stays well inside the range of `f64`.
+pub fn price_from_sqrt_price(sqrt_price: U256, decimals0: u8, decimals1: u8) -> f64 {
+    let sqrt_ratio = u256_to_f64(sqrt_price) / 2f64.powi(96);
+    sqrt_ratio * sqrt_ratio * 10f64.powi(decimals0 as i32 - decimals1 as i32)
 }
 
-fn insert_log(conn: &Connection, combined_log: &CombinedLog) -> Result<()> {
-    conn.execute(
-        "INSERT INTO logs (tx_hash, sender_address, receiver_address, amount0, amount1, sqrt_price, liquidity, tick)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-        params![
-            format!("{:#x}", combined_log.tx_hash),
-            format!("{:#x}", combined_log.sender),
-            format!("{:#x}", combined_log.receiver),
-            combined_log.data.amount0.to_string(),
-            combined_log.data.amount1.to_string(),
-            combined_log.data.sqrt_price.to_string(),
-            combined_log.data.liquidity.to_string(),
-            combined_log.data.tick,
-        ],
-    )?;
-    Ok(())
+/// Converts a pool tick into the price of token0 denominated in token1,
+/// adjusted for the tokens' decimals.
+///
+/// Ticks range over ±887272, so `1.0001^tick` stays between about 3e-39 and
+/// 3e38 and neither end underflows or overflows `f64`.
+pub fn price_from_tick(tick: i32, decimals0: u8, decimals1: u8) -> f64 {
+    1.0001f64.powi(tick) * 10f64.powi(decimals0 as i32 - decimals1 as i32)
+}
+
+/// The gas used by a transaction and its effective gas price in wei, from its
+/// receipt.
+type TransactionGas = (Option<u64>, Option<U256>);
+
+/// Remembers the timestamp of the most recently fetched block and the gas of
+/// the most recently fetched receipt, so consecutive swaps from the same block
+/// only cost one `get_block` round trip, and those of the same transaction one
+/// `get_transaction_receipt`.
+#[derive(Debug, Default)]
+struct LookupCache {
+    last: Option<(u64, u64)>,
+    last_receipt: Option<(H256, TransactionGas)>,
 }
 
-pub async fn run(provider_ws: &str, contract_address: &str, db_path: &str) -> eyre::Result<()> {
-    let conn = initialize_database(db_path)?;
-    let pool_filter = create_pool_filter(contract_address);
-    let client = connect_to_provider(provider_ws).await?;
+impl LookupCache {
+    async fn timestamp<M: Middleware>(
+        &mut self,
+        client: &M,
+        block_number: u64,
+    ) -> Result<Option<u64>, MonitorError> {
+        if let Some((cached_number, timestamp)) = self.last {
+            if cached_number == block_number {
+                return Ok(Some(timestamp));
+            }
+        }
+
+        let block = retry(PROVIDER_CALL_ATTEMPTS, PROVIDER_RETRY_DELAY, || {
+            client.get_block(block_number)
+        })
+        .await
+        .map_err(|e| {
+            MonitorError::ProviderConnect(format!("failed to fetch block {}: {}", block_number, e))
+        })?;
+        Ok(block.map(|block| {
+            let timestamp = block.timestamp.as_u64();
+            self.last = Some((block_number, timestamp));
+            timestamp
+        }))
+    }
 
-    handle_logs(client, &conn, &pool_filter).await?;
+    /// The gas of transaction `tx_hash`, or `None`s while it has no receipt,
+    /// such as a pending transaction.
+    async fn gas<M: Middleware>(
+        &mut self,
+        client: &M,
+        tx_hash: H256,
+    ) -> Result<TransactionGas, MonitorError> {
+        if let Some((cached_hash, gas)) = self.last_receipt {
+            if cached_hash == tx_hash {
+                return Ok(gas);
+            }
+        }
 
-    Ok(())
+        let receipt = retry(PROVIDER_CALL_ATTEMPTS, PROVIDER_RETRY_DELAY, || {
+            client.get_transaction_receipt(tx_hash)
+        })
+        .await
+        .map_err(|e| {
+            MonitorError::ProviderConnect(format!(
+                "failed to fetch the receipt of {:#x}: {}",
+                tx_hash, e
+            ))
+        })?;
+        let gas = receipt.map_or((None, None), |receipt| {
+            (
+                receipt.gas_used.map(|gas_used| gas_used.low_u64()),
+                receipt.effective_gas_price,
+            )
+        });
+        self.last_receipt = Some((tx_hash, gas));
+        Ok(gas)
+    }
 }
 
-#[cfg(test)]
-mod tests {
+fn parse_pool_address(contract_address: &str) -> Result<Address, MonitorError> {
+    Address::from_str(contract_address).map_err(|e| {
+        MonitorError::InvalidAddress(format!(
+            "invalid pool address '{}': {}",
+            contract_address, e
+        ))
+    })
+}
 
-    use super::*;
-    use ethers::types::Bytes;
-    use std::path::Path;
-    use tempdir::TempDir;
+/// Creates a filter for the logs of the given pools that match any of the
+/// event signatures in `events`, from `from_block` up to `to_block` when they
+/// are set.
+///
+/// The live subscription leaves both unset, while `get_logs` calls for
+/// historical blocks constrain the same filter to their range.
+fn create_pool_filter(
+    contract_addresses: &[String],
+    events: &[impl AsRef<str>],
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+) -> Result<Filter, MonitorError> {
+    if contract_addresses.is_empty() {
+        return Err(MonitorError::InvalidConfig(
+            "no pool addresses given".to_string(),
+        ));
+    }
+    let pool_addresses = contract_addresses
+        .iter()
+        .map(|address| parse_pool_address(address))
+        .collect::<Result<Vec<Address>, MonitorError>>()?;
+    let mut filter = Filter::new()
+        .address(pool_addresses)
+        .events(events.iter().map(AsRef::as_ref));
+    if let Some(from_block) = from_block {
+        filter = filter.from_block(from_block);
+    }
+    if let Some(to_block) = to_block {
+        filter = filter.to_block(to_block);
+    }
+    Ok(filter)
+}
 
-    struct TestTransactionValues {
-        tx_hash: &'static str,
-        sender: &'static str,
-        receiver: &'static str,
-        data: &'static str,
-        topic0: &'static str,
-        topic1: &'static str,
-        topic2: &'static str,
-        ammount0: &'static str,
-        ammount1: &'static str,
-        sqrt_price: &'static str,
-        liquidity: &'static str,
-        tick: i32,
+/// How swaps are written to stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable, one `new | ...` line per swap.
+    #[default]
+    Pretty,
+    /// One JSON object per line, see `CombinedLog::to_json`.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = eyre::Report;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!(
+                "invalid log format '{}': expected 'pretty' or 'json'",
+                format
+            ),
+        }
     }
+}
 
-    fn create_test_transaction_vals() -> TestTransactionValues {
-        TestTransactionValues {
-            tx_hash: "0xe92955b4c46b38de18c1cdd58b06d49d45d6f9ca0906a86918f4cf20650683b4",
-            sender: "0xe592427a0aece92de3edee1f18e0157c05861564",
-            receiver: "0x4b7d6c3cea01f4d54a9cad6587da106ea39da1e6",
-            topic0: "0xc42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67",
-            topic1: "0x000000000000000000000000e592427a0aece92de3edee1f18e0157c05861564",
-            topic2: "0x0000000000000000000000004b7d6c3cea01f4d54a9cad6587da106ea39da1e6",
-            data: "0xfffffffffffffffffffffffffffffffffffffffffffffffffffffffff0511b800000000000000000000000000000000000000000000000000240e540e2dc0042000000000000000000000000000000000000610413a1a7c814aa98ca36d09f8b000000000000000000000000000000000000000000000001c4846addbd259faf00000000000000000000000000000000000000000000000000000000000316ab",
-            ammount0: "-263120000",
-            ammount1: "162381653432074306",
-            sqrt_price: "1967716719848838692609454179917707",
-            liquidity: "32607304702662909871",
-            tick: 202411,
+/// Reports a decoded event. Pretty output goes through `tracing`, while JSON
+/// lines are written to stdout on their own, so they can be piped into other
+/// tools regardless of the log configuration.
+fn print_event(event: &PoolEvent, log_format: LogFormat, pools: &HashMap<Address, PoolMetadata>) {
+    match (log_format, event) {
+        (LogFormat::Pretty, PoolEvent::Swap(combined_log)) => {
+            print_log(combined_log, pools.get(&combined_log.pool))
         }
+        (LogFormat::Pretty, PoolEvent::Mint(mint)) => print_mint(mint),
+        (LogFormat::Pretty, PoolEvent::Burn(burn)) => print_burn(burn),
+        (LogFormat::Pretty, PoolEvent::V2Swap(swap)) => print_v2_swap(swap, pools.get(&swap.pool)),
+        (LogFormat::Pretty, PoolEvent::Custom(log)) => print_custom(log),
+        (LogFormat::Json, event) => println!("{}", event.to_json()),
     }
+}
 
-    // Helper function to create a test log
-    fn create_test_log() -> Log {
-        let x = create_test_transaction_vals();
+fn status(removed: bool) -> &'static str {
+    if removed {
+        "removed"
+    } else {
+        "new"
+    }
+}
 
-        Log {
-            address: Address::zero(),
-            transaction_hash: Some(H256::from_str(x.tx_hash).unwrap()),
-            topics: vec![
-                H256::from_str(x.topic0).unwrap(),
-                H256::from_str(x.topic1).unwrap(),
-                H256::from_str(x.topic2).unwrap(),
-            ],
-            data: Bytes::from_str(x.data).unwrap(),
-            ..Default::default()
+fn print_mint(mint: &MintLog) {
+    info!(
+        tx_hash = ?mint.tx_hash,
+        pool = ?mint.pool,
+        sender = ?mint.sender,
+        owner = ?mint.owner,
+        tick_lower = mint.tick_lower,
+        tick_upper = mint.tick_upper,
+        amount = %mint.amount,
+        amount0 = %mint.amount0,
+        amount1 = %mint.amount1,
+        "{} mint",
+        status(mint.removed)
+    );
+}
+
+/// The pool's token pair, when its metadata is known.
+fn pair(metadata: Option<&PoolMetadata>) -> Option<String> {
+    metadata.map(|metadata| format!("{}/{}", metadata.token0.symbol, metadata.token1.symbol))
+}
+
+fn print_v2_swap(swap: &V2SwapLog, metadata: Option<&PoolMetadata>) {
+    info!(
+        tx_hash = ?swap.tx_hash,
+        pool = ?swap.pool,
+        sender = ?swap.sender,
+        to = ?swap.to,
+        amount0_in = %swap.data.amount0_in,
+        amount1_in = %swap.data.amount1_in,
+        amount0_out = %swap.data.amount0_out,
+        amount1_out = %swap.data.amount1_out,
+        pair = pair(metadata),
+        "{} v2 swap",
+        status(swap.removed)
+    );
+}
+
+fn print_custom(log: &CustomLog) {
+    info!(
+        tx_hash = ?log.tx_hash,
+        contract = ?log.contract,
+        params = %log.params_json(),
+        "{} {}",
+        status(log.removed),
+        log.name
+    );
+}
+
+fn print_burn(burn: &BurnLog) {
+    info!(
+        tx_hash = ?burn.tx_hash,
+        pool = ?burn.pool,
+        owner = ?burn.owner,
+        tick_lower = burn.tick_lower,
+        tick_upper = burn.tick_upper,
+        amount = %burn.amount,
+        amount0 = %burn.amount0,
+        amount1 = %burn.amount1,
+        "{} burn",
+        status(burn.removed)
+    );
+}
+
+fn print_log(combined_log: &CombinedLog, metadata: Option<&PoolMetadata>) {
+    let price = match (metadata, combined_log.price) {
+        (Some(metadata), Some(price)) => Some(format!("{} {}", price, metadata.token1.symbol)),
+        _ => None,
+    };
+    info!(
+        tx_hash = ?combined_log.tx_hash,
+        pool = ?combined_log.pool,
+        sender = ?combined_log.sender,
+        receiver = ?combined_log.receiver,
+        amount0 = %combined_log.data.amount0,
+        amount1 = %combined_log.data.amount1,
+        sqrt_price = %combined_log.data.sqrt_price,
+        liquidity = combined_log.data.liquidity,
+        tick = combined_log.data.tick,
+        pair = pair(metadata),
+        price,
+        "{} swap",
+        status(combined_log.removed)
+    );
+}
+
+/// Controls how `handle_logs` re-establishes a dropped WebSocket subscription.
+///
+/// The delay between attempts starts at `initial_delay` and doubles after every
+/// consecutive failure until it reaches `max_delay`. The monitor gives up once
+/// `max_consecutive_failures` attempts in a row have failed.
+///
+/// Each delay is shortened by a random part of up to `jitter` of it, a
+/// fraction between 0 and 1, so that monitors which lost the same provider at
+/// the same time spread out their reconnects instead of retrying in lockstep.
+/// With a `jitter` of 0.2 a delay of 10 seconds becomes one of 8 to 10.
+///
+/// With several provider URLs, every `failover_after` consecutive failures the
+/// monitor moves on to the next URL, wrapping around after the last one. It
+/// stays on whichever provider works until that one fails in turn.
+///
+/// Connecting to a provider and subscribing each fail after `connect_timeout`,
+/// so an endpoint that accepts connections but never answers counts as a
+/// failed attempt instead of stalling the monitor.
+///
+/// `authorization` is sent as the `Authorization` header of every connection
+/// to a provider, WebSocket or HTTP(S), for gated endpoints that expect a
+/// token, such as `Authorization::bearer(token)`. It is checked before the
+/// first connection, see `validate_authorization`.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub max_consecutive_failures: u32,
+    pub failover_after: u32,
+    pub connect_timeout: Duration,
+    pub authorization: Option<Authorization>,
+    pub jitter: f64,
+}
+
+/// Default of `ReconnectPolicy::connect_timeout`.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default of `ReconnectPolicy::jitter`.
+pub const DEFAULT_RECONNECT_JITTER: f64 = 0.2;
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_consecutive_failures: 10,
+            failover_after: 3,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            authorization: None,
+            jitter: DEFAULT_RECONNECT_JITTER,
         }
     }
+}
 
-    #[test]
-    fn test_combined_log_creation() {
-        let x = create_test_transaction_vals();
+impl ReconnectPolicy {
+    fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.initial_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
 
-        let tx_hash = H256::from_str(x.tx_hash).unwrap();
-        let sender = Address::from_str(x.sender).unwrap();
-        let receiver = Address::from_str(x.receiver).unwrap();
-        let log_data = LogData {
-            amount0: I256::from_dec_str(x.ammount0).unwrap(),
-            amount1: I256::from_dec_str(x.ammount1).unwrap(),
-            sqrt_price: U256::from_dec_str(x.sqrt_price).unwrap(),
-            liquidity: x.liquidity.parse::<u128>().unwrap(),
-            tick: x.tick,
-        };
-        let combined_log = CombinedLog::new(Some(tx_hash), sender, receiver, log_data.clone());
+    /// `backoff` shortened by a random part of up to `jitter` of it.
+    fn jittered_backoff(&self, failures: u32) -> Duration {
+        jittered(self.backoff(failures), self.jitter)
+    }
 
-        assert_eq!(combined_log.tx_hash, tx_hash);
-        assert_eq!(combined_log.sender, sender);
-        assert_eq!(combined_log.receiver, receiver);
-        assert_eq!(combined_log.data, log_data);
+    /// The provider to use after the active one failed for the `failures`-th
+    /// time in a row.
+    fn next_provider(&self, active: usize, provider_count: usize, failures: u32) -> usize {
+        if failures.is_multiple_of(self.failover_after.max(1)) {
+            (active + 1) % provider_count
+        } else {
+            active
+        }
     }
-    #[test]
-    fn test_decode_log_data() {
-        let x = create_test_transaction_vals();
-        let bytes = Bytes::from_str(x.data).unwrap();
-        let decoded = decode_log_data(&bytes).unwrap();
+}
 
-        let actual = LogData {
-            amount0: I256::from_dec_str(x.ammount0).unwrap(),
-            amount1: I256::from_dec_str(x.ammount1).unwrap(),
-            sqrt_price: U256::from_dec_str(x.sqrt_price).unwrap(),
-            liquidity: x.liquidity.parse::<u128>().unwrap(),
-            tick: x.tick,
-        };
+fn is_http_url(provider_url: &str) -> bool {
+    provider_url.starts_with("http://") || provider_url.starts_with("https://")
+}
 
-        assert_eq!(decoded, actual);
+fn validate_provider_url(provider_url: &str) -> Result<(), MonitorError> {
+    let is_ws = provider_url.starts_with("ws://") || provider_url.starts_with("wss://");
+    if !is_ws && !is_http_url(provider_url) {
+        return Err(MonitorError::InvalidConfig(format!(
+            "invalid provider URL '{}': expected a ws://, wss://, http:// or https:// URL",
+            provider_url
+        )));
     }
+    Ok(())
+}
 
-    #[tokio::test]
-    async fn test_connect_to_provider() {
-        let provider_ws = "wss://mainnet.infura.io/ws/v3/befb17eb176e41ceb879a05778423030";
-        let result = connect_to_provider(&provider_ws).await;
-        assert!(result.is_ok());
+/// Checks every URL and that they all use the same transport, since a failover
+/// cannot switch between a log subscription and `get_logs` polling.
+fn validate_provider_urls(provider_urls: &[String]) -> Result<(), MonitorError> {
+    let Some(first) = provider_urls.first() else {
+        return Err(MonitorError::InvalidConfig(
+            "no provider URL given".to_string(),
+        ));
+    };
+    for provider_url in provider_urls {
+        validate_provider_url(provider_url)?;
     }
+    if provider_urls
+        .iter()
+        .any(|provider_url| is_http_url(provider_url) != is_http_url(first))
+    {
+        return Err(MonitorError::InvalidConfig(
+            "provider URLs must either all be WebSocket or all be HTTP(S) URLs".to_string(),
+        ));
+    }
+    Ok(())
+}
 
-    #[test]
-    fn test_initialize_database() {
-        let temp_dir = TempDir::new("tmptest").unwrap();
-        let db_path = temp_dir
-            .path()
-            .join("test.db")
-            .to_str()
-            .unwrap()
-            .to_string();
-        let result = initialize_database(&db_path);
-        assert!(result.is_ok());
-        assert!(Path::new(&db_path).exists());
+/// Checks that `authorization` makes a valid `Authorization` header, with no
+/// line breaks or other control characters and no empty or padded
+/// credentials, so a mistyped token fails on startup instead of on every
+/// connection attempt. The error leaves out the value, which is a secret.
+pub fn validate_authorization(authorization: &Authorization) -> Result<(), MonitorError> {
+    let credentials = match authorization {
+        Authorization::Basic(credentials) | Authorization::Bearer(credentials) => {
+            if credentials.contains(char::is_whitespace) {
+                return Err(MonitorError::InvalidConfig(
+                    "invalid provider authorization: the credentials contain whitespace"
+                        .to_string(),
+                ));
+            }
+            credentials
+        }
+        Authorization::Raw(value) => value,
+    };
+    if credentials.trim().is_empty() || credentials.trim() != credentials {
+        return Err(MonitorError::InvalidConfig(
+            "invalid provider authorization: the credentials are empty or padded with whitespace"
+                .to_string(),
+        ));
+    }
+    if reqwest::header::HeaderValue::from_str(&authorization.to_string()).is_err() {
+        return Err(MonitorError::InvalidConfig(
+            "invalid provider authorization: not a valid header value".to_string(),
+        ));
     }
+    Ok(())
+}
 
-    #[tokio::test]
-    async fn test_process_log() {
-        // Create a temporary database for testing
-        let temp_dir = TempDir::new("tmptest").unwrap();
-        let db_path = temp_dir
-            .path()
-            .join("test.db")
-            .to_str()
-            .unwrap()
-            .to_string();
-        let conn = initialize_database(&db_path).unwrap();
+/// Checks the provider URLs and the authorization of `reconnect`.
+fn validate_connection(
+    provider_urls: &[String],
+    reconnect: &ReconnectPolicy,
+) -> Result<(), MonitorError> {
+    validate_provider_urls(provider_urls)?;
+    if !(0.0..=1.0).contains(&reconnect.jitter) {
+        return Err(MonitorError::InvalidConfig(format!(
+            "the reconnect jitter must be between 0 and 1, got {}",
+            reconnect.jitter
+        )));
+    }
+    reconnect
+        .authorization
+        .as_ref()
+        .map_or(Ok(()), validate_authorization)
+}
 
-        // Create a test log
-        let test_log = create_test_log();
+/// The host and port of a provider URL, for logging which provider is active
+/// without revealing an API key in its path.
+fn provider_host(provider_url: &str) -> String {
+    let Some(url) = reqwest::Url::parse(provider_url).ok() else {
+        return "unknown host".to_string();
+    };
+    match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => "unknown host".to_string(),
+    }
+}
 
-        // Process the test log
-        let result = process_log(test_log.clone(), &conn).await;
-        assert!(result.is_ok());
+/// Awaits `request`, a connection or subscription request to a provider, for
+/// at most `timeout`. `action` describes the request in the error.
+async fn request_within<T, E: std::fmt::Display>(
+    timeout: Duration,
+    action: &str,
+    request: impl Future<Output = Result<T, E>>,
+) -> Result<T, MonitorError> {
+    match tokio::time::timeout(timeout, request).await {
+        Ok(result) => result
+            .map_err(|e| MonitorError::ProviderConnect(format!("failed to {}: {}", action, e))),
+        Err(_) => Err(MonitorError::ProviderConnect(format!(
+            "timed out after {:?} trying to {}",
+            timeout, action
+        ))),
+    }
+}
 
-        // Retrieve the inserted log data from the database
-        let mut stmt = conn
-            .prepare("SELECT * FROM logs")
-            .expect("Failed to prepare SQL statement");
-        let row = stmt
-            .query_row([], |row| {
-                Ok((
-                    row.get::<_, String>("tx_hash"),
-                    row.get::<_, String>("sender_address"),
-                    row.get::<_, String>("receiver_address"),
-                    row.get::<_, String>("amount0"),
-                    row.get::<_, String>("amount1"),
-                    row.get::<_, String>("sqrt_price"),
+async fn connect_ws_provider(
+    provider_ws: &str,
+    timeout: Duration,
+    authorization: Option<&Authorization>,
+) -> Result<Arc<Provider<Ws>>, MonitorError> {
+    let provider = request_within(
+        timeout,
+        "connect to provider",
+        Provider::<Ws>::connect(ConnectionDetails::new(provider_ws, authorization.cloned())),
+    )
+    .await?;
+    info!(
+        provider = provider_host(provider_ws),
+        "connected to provider"
+    );
+    Ok(Arc::new(provider))
+}
+
+/// Connects to the first of `provider_urls` that accepts a WebSocket connection
+/// within `reconnect.connect_timeout`.
+async fn connect_to_provider(
+    provider_urls: &[String],
+    reconnect: &ReconnectPolicy,
+) -> Result<Arc<Provider<Ws>>, MonitorError> {
+    let mut last_error = MonitorError::InvalidConfig("no provider URL given".to_string());
+    for provider_ws in provider_urls {
+        let authorization = reconnect.authorization.as_ref();
+        match connect_ws_provider(provider_ws, reconnect.connect_timeout, authorization).await {
+            Ok(provider) => return Ok(provider),
+            Err(e) => {
+                warn!(
+                    provider = provider_host(provider_ws),
+                    error = %e,
+                    "failed to connect to provider"
+                );
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// An HTTP client for `provider_url` that sends `authorization` with every
+/// request.
+fn http_provider(
+    provider_url: &str,
+    authorization: Option<&Authorization>,
+) -> Result<Provider<Http>, MonitorError> {
+    let url = reqwest::Url::parse(provider_url).map_err(|e| {
+        MonitorError::InvalidConfig(format!("invalid provider URL '{}': {}", provider_url, e))
+    })?;
+    let http = match authorization {
+        Some(authorization) => Http::new_with_auth(url, authorization.clone()).map_err(|e| {
+            MonitorError::InvalidConfig(format!("failed to build the HTTP client: {}", e))
+        })?,
+        None => Http::new(url),
+    };
+    Ok(Provider::new(http))
+}
+
+/// Returns a client for the first of `provider_urls` that answers a block
+/// number request. HTTP clients connect lazily, so this is what tells an
+/// unreachable provider apart.
+async fn connect_http_provider(
+    provider_urls: &[String],
+    reconnect: &ReconnectPolicy,
+) -> Result<Arc<Provider<Http>>, MonitorError> {
+    let mut last_error = MonitorError::InvalidConfig("no provider URL given".to_string());
+    for provider_url in provider_urls {
+        let client = http_provider(provider_url, reconnect.authorization.as_ref())?;
+        match client.get_block_number().await {
+            Ok(_) => {
+                info!(
+                    provider = provider_host(provider_url),
+                    "connected to provider"
+                );
+                return Ok(Arc::new(client));
+            }
+            Err(e) => {
+                warn!(
+                    provider = provider_host(provider_url),
+                    error = %e,
+                    "failed to connect to provider"
+                );
+                last_error =
+                    MonitorError::ProviderConnect(format!("failed to reach provider: {}", e));
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// Work done with a provider client, see `with_provider`. A trait rather than
+/// a closure, as the client type differs between HTTP(S) and WebSocket.
+pub(crate) trait ProviderTask {
+    type Output;
+
+    async fn run<M: Middleware + 'static>(self, client: Arc<M>) -> eyre::Result<Self::Output>;
+}
+
+/// Connects to the first reachable of `provider_urls`, with the timeout and
+/// authorization of `reconnect`, and runs `task` with the client.
+pub(crate) async fn with_provider<T: ProviderTask>(
+    provider_urls: &[String],
+    reconnect: &ReconnectPolicy,
+    task: T,
+) -> eyre::Result<T::Output> {
+    validate_connection(provider_urls, reconnect)?;
+    if is_http_url(&provider_urls[0]) {
+        task.run(connect_http_provider(provider_urls, reconnect).await?)
+            .await
+    } else {
+        task.run(connect_to_provider(provider_urls, reconnect).await?)
+            .await
+    }
+}
+
+async fn decode_log<M: Middleware>(
+    log: Log,
+    client: &M,
+    lookups: &mut LookupCache,
+    token_decimals: &HashMap<Address, TokenDecimals>,
+) -> Result<CombinedLog, MonitorError> {
+    // A contract can emit the Swap signature with fewer indexed parameters
+    let sender = Address::from(events::topic(&log, 1)?);
+    let receiver = Address::from(events::topic(&log, 2)?);
+    let log_data = decode_swap_data(&log.data)?;
+    let decimals = token_decimals.get(&log.address);
+    let price = decimals.map(|decimals| {
+        price_from_sqrt_price(log_data.sqrt_price, decimals.decimals0, decimals.decimals1)
+    });
+    let tick_price = decimals
+        .map(|decimals| price_from_tick(log_data.tick, decimals.decimals0, decimals.decimals1));
+    let block_number = log.block_number.map(|n| n.as_u64());
+    let timestamp = log_timestamp(&log, client, lookups).await?;
+    Ok(CombinedLog::new(
+        log.transaction_hash,
+        log.address,
+        sender,
+        receiver,
+        log_data,
+    )
+    .with_block(block_number, log.block_hash)
+    .with_timestamp(timestamp)
+    .with_price(price, tick_price)
+    .with_log_index(
+        log.log_index.map(|i| i.as_u64()),
+        log.removed.unwrap_or(false),
+    )
+    .with_transaction_index(log.transaction_index.map(|i| i.as_u64()))
+    .with_raw_data(log.data))
+}
+
+async fn log_timestamp<M: Middleware>(
+    log: &Log,
+    client: &M,
+    lookups: &mut LookupCache,
+) -> Result<Option<u64>, MonitorError> {
+    match log.block_number {
+        Some(block_number) => lookups.timestamp(client, block_number.as_u64()).await,
+        None => Ok(None),
+    }
+}
+
+/// Decodes a V3 swap log with `decode_log`, tags it with `decoder.source_tag`
+/// and, with `decoder.fetch_gas`, adds the gas of its transaction. Retracted
+/// swaps are only deleted, so their receipt is not fetched.
+async fn decode_swap<M: Middleware>(
+    log: Log,
+    client: &M,
+    lookups: &mut LookupCache,
+    decoder: &LogDecoder,
+) -> Result<CombinedLog, MonitorError> {
+    let mut combined_log = decode_log(log, client, lookups, &decoder.token_decimals).await?;
+    combined_log.source = decoder.source_tag.clone();
+    if decoder.fetch_gas && !combined_log.removed {
+        (combined_log.gas_used, combined_log.effective_gas_price) =
+            lookups.gas(client, combined_log.tx_hash).await?;
+    }
+    Ok(combined_log)
+}
+
+/// What `decode_event` needs to know besides the log.
+#[derive(Debug, Clone, Default)]
+struct LogDecoder {
+    /// Token decimals per pool, see `decode_log`.
+    token_decimals: HashMap<Address, TokenDecimals>,
+    /// See `MonitorOptions::custom_event`.
+    custom_event: Option<Event>,
+    /// The topic of `MonitorOptions::swap_event`, whose logs are decoded as
+    /// swaps of `pool_version`.
+    swap_topic: Option<H256>,
+    pool_version: PoolVersion,
+    /// See `MonitorOptions::fetch_gas`.
+    fetch_gas: bool,
+    /// See `MonitorOptions::source_tag`.
+    source_tag: Option<String>,
+}
+
+impl LogDecoder {
+    /// How many topics a log of the event `topic` has, with the event topic
+    /// itself, or `None` for an event that `decode_event` does not decode.
+    fn topic_count(&self, topic: H256) -> Option<usize> {
+        let custom_event = self
+            .custom_event
+            .as_ref()
+            .filter(|event| event.signature() == topic);
+        if let Some(event) = custom_event {
+            Some(1 + event.inputs.iter().filter(|input| input.indexed).count())
+        } else if self.swap_topic == Some(topic)
+            || [SWAP_EVENT, PANCAKE_SWAP_EVENT, V2_SWAP_EVENT]
+                .iter()
+                .any(|event| event_topic(event) == topic)
+        {
+            Some(3)
+        } else if topic == event_topic(MINT_EVENT) || topic == event_topic(BURN_EVENT) {
+            Some(4)
+        } else {
+            None
+        }
+    }
+
+    /// Why a log with `topics` failed to decode, see
+    /// `Metrics::record_decode_failure`.
+    fn failure_reason(&self, topics: &[H256]) -> &'static str {
+        let topic = topics.first().copied().unwrap_or_default();
+        match self.topic_count(topic) {
+            None => "unknown_event",
+            Some(count) if topics.len() < count => "missing_topics",
+            Some(_) => "invalid_data",
+        }
+    }
+}
+
+/// Decodes a V3 Swap, Mint or Burn log, the Swap log of a V3 fork, a V2 Swap
+/// log or a log of the custom event, dispatching on its event topic. A log of
+/// the overridden `Swap` topic is decoded as a swap of the pool version.
+async fn decode_event<M: Middleware>(
+    log: Log,
+    client: &M,
+    lookups: &mut LookupCache,
+    decoder: &LogDecoder,
+) -> Result<PoolEvent, MonitorError> {
+    let topic = log.topics.first().copied().unwrap_or_default();
+    let custom_event = decoder
+        .custom_event
+        .as_ref()
+        .filter(|event| event.signature() == topic);
+    if let Some(event) = custom_event {
+        let timestamp = log_timestamp(&log, client, lookups).await?;
+        Ok(PoolEvent::Custom(events::decode_custom(
+            &log, event, timestamp,
+        )?))
+    } else if decoder.swap_topic == Some(topic) {
+        match decoder.pool_version {
+            PoolVersion::V2 => {
+                let timestamp = log_timestamp(&log, client, lookups).await?;
+                Ok(PoolEvent::V2Swap(events::decode_v2_swap(&log, timestamp)?))
+            }
+            PoolVersion::V3 => Ok(decode_swap(log, client, lookups, decoder).await?.into()),
+        }
+    } else if topic == event_topic(SWAP_EVENT) || topic == event_topic(PANCAKE_SWAP_EVENT) {
+        Ok(decode_swap(log, client, lookups, decoder).await?.into())
+    } else if topic == event_topic(MINT_EVENT) {
+        let timestamp = log_timestamp(&log, client, lookups).await?;
+        Ok(PoolEvent::Mint(events::decode_mint(&log, timestamp)?))
+    } else if topic == event_topic(BURN_EVENT) {
+        let timestamp = log_timestamp(&log, client, lookups).await?;
+        Ok(PoolEvent::Burn(events::decode_burn(&log, timestamp)?))
+    } else if topic == event_topic(V2_SWAP_EVENT) {
+        let timestamp = log_timestamp(&log, client, lookups).await?;
+        Ok(PoolEvent::V2Swap(events::decode_v2_swap(&log, timestamp)?))
+    } else {
+        Err(MonitorError::Decode(format!(
+            "unknown event {:#x} in log {:?}",
+            topic, log.transaction_hash
+        )))
+    }
+}
+
+/// Controls how many swaps are buffered before they are written to the database.
+///
+/// Buffered swaps are committed in a single transaction once `max_size` of them
+/// have accumulated or `max_delay` has passed, whichever comes first.
+///
+/// `max_delay` thereby bounds what a crash can lose: every `max_delay` the
+/// buffered swaps are committed along with the block checkpoint and, for
+/// stores that do not sync each commit, synced to disk, see `SwapStore::sync`.
+/// A crash loses at most the swaps of the last interval, which are backfilled
+/// from the checkpoint on restart.
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    pub max_size: usize,
+    pub max_delay: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            max_size: 100,
+            max_delay: Duration::from_millis(1000),
+        }
+    }
+}
+
+/// Keeps the monitor running through storage outages, such as a full disk or
+/// a locked database, instead of failing on the first batch that cannot be
+/// committed.
+///
+/// A failed commit of the batch, or a failed sync of the store, which reports
+/// the failed writes of a `BackgroundStore`, is retried right away. Once
+/// `max_failures` attempts failed in a row, processing pauses and the attempt
+/// is retried every `retry_interval` until it succeeds, after which the
+/// monitor resumes where it stopped. A shutdown while paused gives up, and the
+/// events that were not stored are backfilled from the checkpoint on restart.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    pub max_failures: u32,
+    pub retry_interval: Duration,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker {
+            max_failures: 5,
+            retry_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// What `LogBatch::retry_failures` asks of the store.
+#[derive(Debug, Clone, Copy)]
+enum StoreStep {
+    Commit,
+    Sync,
+}
+
+struct LogBatch {
+    logs: Vec<PoolEvent>,
+    max_size: usize,
+    circuit_breaker: Option<CircuitBreaker>,
+    /// Ends a pause of `circuit_breaker`, see `shutdown_signal`.
+    shutdown: Option<watch::Receiver<()>>,
+}
+
+impl LogBatch {
+    fn new(max_size: usize) -> Self {
+        LogBatch {
+            logs: Vec::with_capacity(max_size),
+            max_size: max_size.max(1),
+            circuit_breaker: None,
+            shutdown: None,
+        }
+    }
+
+    /// A batch of `options.batch.max_size` that retries failed commits as
+    /// `options.circuit_breaker` says.
+    fn for_options(options: &MonitorOptions) -> Self {
+        LogBatch {
+            circuit_breaker: options.circuit_breaker.clone(),
+            shutdown: options.shutdown.clone(),
+            ..LogBatch::new(options.batch.max_size)
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.logs.len() >= self.max_size
+    }
+
+    /// Commits the buffered events with `commit`, retrying as
+    /// `retry_failures` describes.
+    async fn flush(&mut self, store: &mut dyn SwapStore) -> Result<(), MonitorError> {
+        self.retry_failures(store, StoreStep::Commit).await
+    }
+
+    /// Waits until `store` wrote the committed events, see `SwapStore::sync`,
+    /// retrying as `retry_failures` describes. A store that writes in the
+    /// background, such as `BackgroundStore`, reports the failures of its
+    /// queued writes here.
+    async fn sync(&mut self, store: &mut dyn SwapStore) -> Result<(), MonitorError> {
+        self.retry_failures(store, StoreStep::Sync).await
+    }
+
+    /// Carries out `step`. Without a circuit breaker a failure is returned;
+    /// with one, the step is retried until it succeeds, pausing after
+    /// `CircuitBreaker::max_failures` failures in a row, or until a shutdown.
+    async fn retry_failures(
+        &mut self,
+        store: &mut dyn SwapStore,
+        step: StoreStep,
+    ) -> Result<(), MonitorError> {
+        let mut failures = 0;
+        loop {
+            let attempt = match step {
+                StoreStep::Commit => self.commit(store).await,
+                StoreStep::Sync => store.sync().await,
+            };
+            let e = match attempt {
+                Ok(()) => break,
+                Err(e) => e,
+            };
+            let Some(circuit_breaker) = self.circuit_breaker.clone() else {
+                return Err(e);
+            };
+            let max_failures = circuit_breaker.max_failures.max(1);
+            failures += 1;
+            if failures < max_failures {
+                warn!(failures, error = %e, "failed to commit events, retrying");
+                continue;
+            }
+            if failures == max_failures {
+                error!(
+                    failures,
+                    error = %e,
+                    retry_interval = ?circuit_breaker.retry_interval,
+                    "database writes keep failing, pausing until they succeed again"
+                );
+                METRICS.db_writes_paused.set(1);
+            } else {
+                warn!(failures, error = %e, "database writes still failing");
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(circuit_breaker.retry_interval) => {}
+                reason = shutdown_signal(self.shutdown.clone()) => {
+                    info!("received {} while database writes are paused, giving up", reason);
+                    METRICS.db_writes_paused.set(0);
+                    return Err(e);
+                }
+            }
+        }
+        if self
+            .circuit_breaker
+            .as_ref()
+            .is_some_and(|circuit_breaker| failures >= circuit_breaker.max_failures.max(1))
+        {
+            info!(failures, "database writes succeeded again, resuming");
+            METRICS.db_writes_paused.set(0);
+        }
+        Ok(())
+    }
+
+    /// Commits the buffered events in chain order, by block number and log
+    /// index, whatever order the provider returned them in, so rowid order
+    /// matches chain order. They are kept for the next attempt if the
+    /// transaction fails.
+    async fn commit(&mut self, store: &mut dyn SwapStore) -> Result<(), MonitorError> {
+        if self.logs.is_empty() {
+            return Ok(());
+        }
+        self.logs
+            .sort_by_key(|event| (event.block_number(), event.log_index()));
+        if let Err(e) = store.insert_batch(&self.logs).await {
+            METRICS.db_insert_errors.inc();
+            return Err(e);
+        }
+        self.logs.clear();
+        Ok(())
+    }
+}
+
+/// Decides which swaps are stored. The default keeps every swap.
+#[derive(Debug, Clone, Default)]
+pub struct SwapFilter {
+    /// Keep swaps whose absolute amount0 is at least this value.
+    pub min_abs_amount0: Option<U256>,
+    /// Keep swaps whose absolute amount1 is at least this value.
+    pub min_abs_amount1: Option<U256>,
+}
+
+impl SwapFilter {
+    /// Returns whether the swap passes the configured thresholds. When both
+    /// thresholds are set, meeting either one is enough to keep the swap.
+    pub fn matches(&self, data: &LogData) -> bool {
+        self.matches_amounts(abs_u256(data.amount0), abs_u256(data.amount1))
+    }
+
+    /// Like `matches`, for a V2 swap. A token's amount is what was paid in plus
+    /// what was taken out, since a pair reports the two separately.
+    pub fn matches_v2(&self, data: &V2LogData) -> bool {
+        self.matches_amounts(
+            data.amount0_in.saturating_add(data.amount0_out),
+            data.amount1_in.saturating_add(data.amount1_out),
+        )
+    }
+
+    fn matches_amounts(&self, abs_amount0: U256, abs_amount1: U256) -> bool {
+        if self.min_abs_amount0.is_none() && self.min_abs_amount1.is_none() {
+            return true;
+        }
+        self.min_abs_amount0.is_some_and(|min| abs_amount0 >= min)
+            || self.min_abs_amount1.is_some_and(|min| abs_amount1 >= min)
+    }
+}
+
+/// Where `process_log` passes events on to besides the store, and the prices
+/// it values swaps at.
+#[derive(Default)]
+struct Outputs {
+    /// In the order they get each event. The webhook and the broadcast are
+    /// only added for live swaps: historical swaps are not worth an alert, and
+    /// not news to a dashboard.
+    sinks: Vec<Box<dyn SwapSink>>,
+    usd_prices: Option<UsdPrices>,
+}
+
+impl Outputs {
+    /// The CSV file, Kafka producer and console output of `options`, followed
+    /// by `sinks`.
+    fn open(options: &MonitorOptions, sinks: Vec<Box<dyn SwapSink>>) -> Result<Self> {
+        let mut outputs = Outputs::default();
+        if let Some(csv_path) = &options.csv_path {
+            outputs.sinks.push(Box::new(CsvSink::open(csv_path)?));
+        }
+        #[cfg(feature = "kafka")]
+        if let Some(kafka) = &options.kafka {
+            outputs.sinks.push(Box::new(KafkaSink::new(kafka)?));
+        }
+        if options.print {
+            outputs
+                .sinks
+                .push(Box::new(StdoutSink::new(options.log_format)));
+        }
+        outputs.sinks.extend(sinks);
+        Ok(outputs)
+    }
+
+    /// Passes `event` to every sink. A sink that fails is only logged, so the
+    /// others still get the event.
+    fn handle(&mut self, event: &PoolEvent, pools: &HashMap<Address, PoolMetadata>) {
+        for sink in &mut self.sinks {
+            if let Err(e) = sink.handle_event(event, pools) {
+                warn!(
+                    sink = sink.name(),
+                    tx_hash = ?event.tx_hash(),
+                    error = %e,
+                    "sink failed to handle event"
+                );
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        for sink in &mut self.sinks {
+            if let Err(e) = sink.flush() {
+                warn!(sink = sink.name(), error = %e, "sink failed to flush");
+            }
+        }
+    }
+
+    /// Closes the sinks before `run` returns.
+    fn close(&mut self) {
+        for sink in &mut self.sinks {
+            if let Err(e) = sink.close() {
+                warn!(sink = sink.name(), error = %e, "sink failed to close");
+            }
+        }
+    }
+}
+
+/// Stores `event` unless `options` filter it out, or deletes it when it was
+/// retracted. Returns whether it passed the filters.
+async fn process_log(
+    event: PoolEvent,
+    store: &mut dyn SwapStore,
+    batch: &mut LogBatch,
+    outputs: &mut Outputs,
+    options: &MonitorOptions,
+) -> Result<bool> {
+    // Retracted swaps are always passed on so a stored row is never left behind
+    let filtered = !event.removed()
+        && match &event {
+            // The parties are the indexed topics, so they are compared first
+            PoolEvent::Swap(combined_log) => {
+                !options.matches_parties(combined_log.sender, combined_log.receiver)
+                    || !options.filter.matches(&combined_log.data)
+            }
+            PoolEvent::V2Swap(swap) => {
+                !options.matches_parties(swap.sender, swap.to)
+                    || !options.filter.matches_v2(&swap.data)
+            }
+            PoolEvent::Mint(_) | PoolEvent::Burn(_) | PoolEvent::Custom(_) => false,
+        };
+    if filtered {
+        return Ok(false);
+    }
+    let event = match event {
+        PoolEvent::Swap(combined_log) => {
+            let mut combined_log = match options.token_decimals.get(&combined_log.pool) {
+                Some(decimals) => combined_log.with_normalized_amounts(decimals),
+                None => *combined_log,
+            };
+            if let Some(usd_prices) = &outputs.usd_prices {
+                let metadata = options.pools.get(&combined_log.pool);
+                combined_log.usd_value = usd_prices.swap_value(&combined_log, metadata);
+            }
+            if let (Some(price_ema), Some(price), false) =
+                (&options.price_ema, combined_log.price, combined_log.removed)
+            {
+                price_ema.update(combined_log.pool, price);
+            }
+            combined_log.direction =
+                SwapDirection::from_amounts(combined_log.data.amount0, combined_log.data.amount1);
+            if combined_log.direction.is_none() {
+                warn!(
+                    tx_hash = ?combined_log.tx_hash,
+                    amount0 = %combined_log.data.amount0,
+                    amount1 = %combined_log.data.amount1,
+                    "swap amounts do not have opposite signs, direction unknown"
+                );
+            }
+            combined_log.into()
+        }
+        event => event,
+    };
+    outputs.handle(&event, &options.pools);
+    if matches!(event, PoolEvent::Swap(_) | PoolEvent::V2Swap(_)) {
+        METRICS.swaps_processed.inc();
+    }
+    if let Some(block_number) = event.block_number() {
+        METRICS.observe_block(block_number);
+    }
+    if event.removed() {
+        // The retracted event may still be buffered, so commit the batch before deleting
+        batch.flush(store).await?;
+        if let Err(e) = store.delete(&event).await {
+            METRICS.db_insert_errors.inc();
+            return Err(e.into());
+        }
+        return Ok(true);
+    }
+    batch.logs.push(event);
+    if batch.is_full() {
+        batch.flush(store).await?;
+    }
+    Ok(true)
+}
+
+type EventSender = mpsc::Sender<Result<PoolEvent, MonitorError>>;
+
+/// Passes the events of `pending` that are confirmed to `process_log` and
+/// returns how many passed the filters.
+async fn process_confirmed(
+    pending: &mut PendingEvents,
+    store: &mut dyn SwapStore,
+    batch: &mut LogBatch,
+    outputs: &mut Outputs,
+    options: &MonitorOptions,
+) -> Result<u64> {
+    let mut processed = 0;
+    for event in pending.take_confirmed() {
+        if process_log(event, store, batch, outputs, options).await? {
+            processed += 1;
+        }
+    }
+    Ok(processed)
+}
+
+/// Buffers `event` in `pending` and processes what is ready to be stored, as
+/// `process_confirmed` does: a retracted event that has to be deleted, and the
+/// confirmed events.
+async fn process_pending(
+    event: PoolEvent,
+    pending: &mut PendingEvents,
+    store: &mut dyn SwapStore,
+    batch: &mut LogBatch,
+    outputs: &mut Outputs,
+    options: &MonitorOptions,
+) -> Result<u64> {
+    let mut processed = 0;
+    if let Some(retracted) = pending.push(event) {
+        if process_log(retracted, store, batch, outputs, options).await? {
+            processed += 1;
+        }
+    }
+    Ok(processed + process_confirmed(pending, store, batch, outputs, options).await?)
+}
+
+/// How many bytes of the data of an undecodable log its warning shows.
+const DECODE_FAILURE_PREVIEW_LEN: usize = 96;
+
+/// `data` as hex, cut off after `max_len` bytes.
+fn truncated_hex(data: &[u8], max_len: usize) -> String {
+    if data.len() <= max_len {
+        format!("0x{}", hex::encode(data))
+    } else {
+        format!("0x{}...", hex::encode(&data[..max_len]))
+    }
+}
+
+/// Decodes `log`, or skips it with a warning and counts it in
+/// `decode_failures_total` when it fails to decode. The warning tells the
+/// reason, the length of the data and the number of topics, which tell a
+/// fork with another event layout apart, and shows the start of the data.
+async fn decode_log_or_skip<M: Middleware>(
+    log: Log,
+    client: &M,
+    lookups: &mut LookupCache,
+    decoder: &LogDecoder,
+) -> Option<Result<PoolEvent, MonitorError>> {
+    let tx_hash = log.transaction_hash;
+    // Cloning the data only bumps a reference count
+    let (topics, data) = (log.topics.clone(), log.data.clone());
+    match decode_event(log, client, lookups, decoder).await {
+        Err(MonitorError::Decode(e)) => {
+            let reason = decoder.failure_reason(&topics);
+            METRICS.record_decode_failure(reason);
+            warn!(
+                ?tx_hash,
+                reason,
+                data_len = data.len(),
+                topics = topics.len(),
+                data = truncated_hex(&data, DECODE_FAILURE_PREVIEW_LEN),
+                error = %e,
+                "skipping log that failed to decode"
+            );
+            None
+        }
+        event => Some(event),
+    }
+}
+
+/// Decodes every log of `stream` and forwards it to `sender`. A log that fails
+/// to decode is skipped with a warning, so one malformed payload does not stop
+/// the monitor. Breaks once another error, such as a failed timestamp lookup,
+/// has been forwarded or the receiving side has been dropped.
+async fn process_stream<S, M>(
+    mut stream: S,
+    client: &M,
+    decoder: &LogDecoder,
+    sender: &EventSender,
+) -> ControlFlow<()>
+where
+    S: Stream<Item = Log> + Unpin,
+    M: Middleware,
+{
+    let mut lookups = LookupCache::default();
+    while let Some(log) = stream.next().await {
+        METRICS.record_activity();
+        let event = match decode_log_or_skip(log, client, &mut lookups, decoder).await {
+            Some(event) => event,
+            None => continue,
+        };
+        let failed = event.is_err();
+        if sender.send(event).await.is_err() || failed {
+            return ControlFlow::Break(());
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+/// Whether `log` lies in `until_block` or a later block.
+fn reaches_block(log: &Log, until_block: Option<u64>) -> bool {
+    until_block.is_some_and(|until_block| {
+        log.block_number
+            .is_some_and(|block_number| block_number.as_u64() >= until_block)
+    })
+}
+
+/// Forwards the logs of `until_block` from `reached` on, the first log of that
+/// block or later that the subscription delivered.
+///
+/// A subscription only tells that a block is complete once a log of a later
+/// block arrives, so the rest of the block is fetched with `get_logs` instead.
+/// When `reached` is already past `until_block` nothing remains to forward.
+async fn finish_block<M: Middleware>(
+    client: &M,
+    pool_filter: &Filter,
+    until_block: u64,
+    reached: &Log,
+    decoder: &LogDecoder,
+    sender: &EventSender,
+) {
+    if reached
+        .block_number
+        .map(|block_number| block_number.as_u64())
+        != Some(until_block)
+    {
+        return;
+    }
+    let block_filter = pool_filter
+        .clone()
+        .from_block(until_block)
+        .to_block(until_block);
+    let logs = match client.get_logs(&block_filter).await {
+        Ok(logs) => logs,
+        Err(e) => {
+            let _ = sender
+                .send(Err(MonitorError::ProviderConnect(format!(
+                    "failed to fetch the logs of block {}: {}",
+                    until_block, e
+                ))))
+                .await;
+            return;
+        }
+    };
+    let remaining = logs
+        .into_iter()
+        .filter(|log| log.log_index >= reached.log_index);
+    let _ = process_stream(
+        futures_util::stream::iter(remaining),
+        client,
+        decoder,
+        sender,
+    )
+    .await;
+}
+
+/// How the live stream of `run` follows the chain, beyond what `event_stream`
+/// offers.
+#[derive(Debug, Clone, Copy, Default)]
+struct LiveOptions {
+    /// End the stream once the logs of this block have been forwarded.
+    until_block: Option<u64>,
+    /// Fetch the logs of every new block instead of subscribing to logs, see
+    /// `follow_heads`. Only used with WebSocket providers.
+    follow_heads: bool,
+}
+
+/// Forwards the logs of `stream`, a subscription to the logs of `pool_filter`,
+/// until it closes. With `until_block` set, breaks once the logs of that block
+/// have been forwarded.
+async fn follow_logs<M: Middleware>(
+    client: &M,
+    stream: LogStream<'_>,
+    pool_filter: &Filter,
+    decoder: &LogDecoder,
+    until_block: Option<u64>,
+    sender: &EventSender,
+) -> ControlFlow<()> {
+    let mut reached = None;
+    let stream = stream.take_while(|log| {
+        let done = reaches_block(log, until_block);
+        if done {
+            reached = Some(log.clone());
+        }
+        future::ready(!done)
+    });
+    let flow = tokio::select! {
+        flow = process_stream(stream, client, decoder, sender) => flow,
+        never = heartbeat(client) => match never {},
+    };
+    flow?;
+    if let (Some(reached), Some(until_block)) = (reached, until_block) {
+        info!(until_block, "reached the last block to monitor");
+        finish_block(client, pool_filter, until_block, &reached, decoder, sender).await;
+        return ControlFlow::Break(());
+    }
+    warn!("subscription closed by provider");
+    ControlFlow::Continue(())
+}
+
+/// Asks the provider for the block number every `HEARTBEAT_INTERVAL` and
+/// records each answer as activity, since a log subscription to a quiet pool
+/// sends nothing for long stretches. Never returns.
+async fn heartbeat<M: Middleware>(client: &M) -> std::convert::Infallible {
+    // Subscribing was activity already, so the first check waits a full interval
+    let start = tokio::time::Instant::now() + HEARTBEAT_INTERVAL;
+    let mut interval = tokio::time::interval_at(start, HEARTBEAT_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        match client.get_block_number().await {
+            Ok(_) => METRICS.record_activity(),
+            Err(e) => warn!(error = %e, "heartbeat to provider failed"),
+        }
+    }
+}
+
+/// Fetches the logs of the blocks up to `head` that were not covered yet and
+/// forwards them to `sender`. `next_block` is the first block not covered, and
+/// unset before the first head. A head at or below the covered blocks replaced
+/// them in a reorg, so it is fetched again. Breaks once the logs of
+/// `until_block` have been forwarded or as `process_stream` does.
+async fn process_head<M: Middleware>(
+    client: &M,
+    pool_filter: &Filter,
+    head: u64,
+    next_block: &mut Option<u64>,
+    until_block: Option<u64>,
+    decoder: &LogDecoder,
+    sender: &EventSender,
+) -> Result<ControlFlow<()>, MonitorError> {
+    let from_block = next_block.map_or(head, |next_block| next_block.min(head));
+    let to_block = until_block.map_or(head, |until_block| head.min(until_block));
+    if from_block <= to_block {
+        let block_filter = pool_filter
+            .clone()
+            .from_block(from_block)
+            .to_block(to_block);
+        let logs = client.get_logs(&block_filter).await.map_err(|e| {
+            MonitorError::ProviderConnect(format!(
+                "failed to fetch logs for blocks {}..={}: {}",
+                from_block, to_block, e
+            ))
+        })?;
+        let logs = futures_util::stream::iter(logs);
+        if process_stream(logs, client, decoder, sender)
+            .await
+            .is_break()
+        {
+            return Ok(ControlFlow::Break(()));
+        }
+        *next_block = Some(to_block + 1);
+    } else {
+        *next_block = Some(from_block);
+    }
+    if let (Some(until_block), Some(next_block)) = (until_block, *next_block) {
+        if next_block > until_block {
+            info!(until_block, "reached the last block to monitor");
+            return Ok(ControlFlow::Break(()));
+        }
+    }
+    Ok(ControlFlow::Continue(()))
+}
+
+/// Subscribes to new blocks and fetches the logs of each with `get_logs`, for
+/// providers whose log subscriptions occasionally miss events. Every block is
+/// covered at the cost of a request per block, and blocks missed while
+/// reconnecting are fetched along with the next head. Unlike a log
+/// subscription, this does not report logs retracted by a reorg.
+///
+/// `heads` is the subscription to new blocks. Breaks as `process_head` does.
+async fn follow_heads<M: Middleware>(
+    client: &M,
+    mut heads: HeadStream<'_>,
+    pool_filter: &Filter,
+    decoder: &LogDecoder,
+    until_block: Option<u64>,
+    next_block: &mut Option<u64>,
+    sender: &EventSender,
+) -> ControlFlow<()> {
+    while let Some(head) = heads.next().await {
+        METRICS.record_activity();
+        let flow = process_head(
+            client,
+            pool_filter,
+            head,
+            next_block,
+            until_block,
+            decoder,
+            sender,
+        )
+        .await;
+        match flow {
+            Ok(flow) => flow?,
+            Err(e) => {
+                warn!(error = %e, "failed to fetch the logs of a new block");
+                return ControlFlow::Continue(());
+            }
+        }
+    }
+    warn!("subscription closed by provider");
+    ControlFlow::Continue(())
+}
+
+/// Subscribes to the logs of `pool_filter`, or to new blocks with
+/// `live.follow_heads`, and forwards them to `sender`, reconnecting as
+/// `reconnect` describes. `connect` opens the source of a provider URL within
+/// the given timeout, `connect_ws_provider` outside of tests. A connection
+/// counts as failed unless it subscribes within `reconnect.connect_timeout`.
+/// With `live.until_block` set, returns once the logs of that block have been
+/// forwarded.
+async fn handle_logs<S, C, Fut>(
+    provider_urls: &[String],
+    connect: C,
+    pool_filter: &Filter,
+    decoder: &LogDecoder,
+    reconnect: &ReconnectPolicy,
+    live: LiveOptions,
+    sender: EventSender,
+) where
+    S: LogSource,
+    C: Fn(&str, Duration) -> Fut,
+    Fut: Future<Output = Result<Arc<S>, MonitorError>>,
+{
+    let mut failures = 0;
+    let mut active = 0;
+    let mut next_block = None;
+    loop {
+        let provider_ws = &provider_urls[active];
+        let timeout = reconnect.connect_timeout;
+        match connect(provider_ws, timeout).await {
+            Ok(source) => {
+                let flow = if live.follow_heads {
+                    let heads = source.subscribe_heads();
+                    match request_within(timeout, "subscribe to new blocks", heads).await {
+                        Ok(heads) => {
+                            info!("subscribed to new blocks");
+                            METRICS.set_subscribed(true);
+                            failures = 0;
+                            follow_heads(
+                                source.client(),
+                                heads,
+                                pool_filter,
+                                decoder,
+                                live.until_block,
+                                &mut next_block,
+                                &sender,
+                            )
+                            .await
+                        }
+                        Err(e) => {
+                            warn!(error = %e, "failed to subscribe to new blocks");
+                            ControlFlow::Continue(())
+                        }
+                    }
+                } else {
+                    let stream = source.subscribe(pool_filter);
+                    match request_within(timeout, "subscribe to pool logs", stream).await {
+                        Ok(stream) => {
+                            info!("subscribed to pool logs");
+                            METRICS.set_subscribed(true);
+                            failures = 0;
+                            follow_logs(
+                                source.client(),
+                                stream,
+                                pool_filter,
+                                decoder,
+                                live.until_block,
+                                &sender,
+                            )
+                            .await
+                        }
+                        Err(e) => {
+                            warn!(error = %e, "failed to subscribe to pool logs");
+                            ControlFlow::Continue(())
+                        }
+                    }
+                };
+                METRICS.set_subscribed(false);
+                if flow.is_break() {
+                    return;
+                }
+            }
+            Err(e) => warn!(
+                provider = provider_host(provider_ws),
+                error = %e,
+                "failed to connect to provider"
+            ),
+        }
+
+        failures += 1;
+        if failures > reconnect.max_consecutive_failures {
+            let _ = sender
+                .send(Err(MonitorError::ProviderConnect(format!(
+                    "giving up after {} consecutive failed connection attempts",
+                    failures - 1
+                ))))
+                .await;
+            return;
+        }
+        let next = reconnect.next_provider(active, provider_urls.len(), failures);
+        if next != active {
+            active = next;
+            warn!(
+                provider = provider_host(&provider_urls[active]),
+                "failing over to the next provider"
+            );
+        }
+        let delay = reconnect.jittered_backoff(failures);
+        warn!(
+            ?delay,
+            attempt = failures,
+            max_attempts = reconnect.max_consecutive_failures,
+            "reconnecting"
+        );
+        tokio::time::sleep(delay).await;
+        METRICS.reconnects.inc();
+    }
+}
+
+/// A stream of decoded pool events fed by a background subscription task.
+///
+/// The task stops when the stream is closed or dropped.
+pub struct EventStream {
+    receiver: mpsc::Receiver<Result<PoolEvent, MonitorError>>,
+    task: JoinHandle<()>,
+}
+
+impl EventStream {
+    /// Stops the subscription. Events that were already decoded are still
+    /// yielded by the stream before it ends.
+    pub fn close(&mut self) {
+        self.receiver.close();
+        self.task.abort();
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Result<PoolEvent, MonitorError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A stream of decoded swaps, see `swap_stream`.
+pub struct SwapStream {
+    events: EventStream,
+}
+
+impl SwapStream {
+    /// Stops the subscription. Swaps that were already decoded are still
+    /// yielded by the stream before it ends.
+    pub fn close(&mut self) {
+        self.events.close();
+    }
+}
+
+impl Stream for SwapStream {
+    type Item = Result<CombinedLog, MonitorError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            return match Pin::new(&mut self.events).poll_next(cx) {
+                Poll::Ready(Some(Ok(PoolEvent::Swap(swap)))) => Poll::Ready(Some(Ok(*swap))),
+                // Only swaps are subscribed to, but skip anything else just in case
+                Poll::Ready(Some(Ok(_))) => continue,
+                Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => Poll::Ready(None),
+                Poll::Pending => Poll::Pending,
+            };
+        }
+    }
+}
+
+/// Default time between `get_logs` polls for HTTP providers, roughly one block.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(12);
+
+/// Default of `MonitorOptions::health_max_age`, ten blocks.
+pub const DEFAULT_HEALTH_MAX_AGE: Duration = Duration::from_secs(120);
+
+/// Default of `MonitorOptions::throughput_interval`.
+pub const DEFAULT_THROUGHPUT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time between checks that a quiet log subscription's provider still answers.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Fetches the logs from `next_block`, or the current head when unset, up to
+/// the head but not past `until_block`, and returns them with the block to
+/// continue from.
+async fn fetch_new_logs<M: Middleware>(
+    client: &M,
+    pool_filter: &Filter,
+    next_block: Option<u64>,
+    until_block: Option<u64>,
+) -> Result<(Vec<Log>, Option<u64>), MonitorError> {
+    let head = client
+        .get_block_number()
+        .await
+        .map_err(|e| MonitorError::ProviderConnect(format!("failed to fetch block number: {}", e)))?
+        .as_u64();
+    let from_block = next_block.unwrap_or(head);
+    let to_block = until_block.map_or(head, |until_block| head.min(until_block));
+    if from_block > to_block {
+        return Ok((Vec::new(), Some(from_block)));
+    }
+
+    let block_filter = pool_filter
+        .clone()
+        .from_block(from_block)
+        .to_block(to_block);
+    let logs = client.get_logs(&block_filter).await.map_err(|e| {
+        MonitorError::ProviderConnect(format!(
+            "failed to fetch logs for blocks {}..={}: {}",
+            from_block, to_block, e
+        ))
+    })?;
+    Ok((logs, Some(to_block + 1)))
+}
+
+/// Polls the first of `providers` for logs in new blocks every `poll_interval`,
+/// starting at the current head, and forwards them to `sender`. Failed polls
+/// are retried with the backoff of `reconnect`, failing over to the next
+/// provider as it describes. Each provider is paired with its URL for logging.
+/// With `until_block` set, returns once the logs of that block have been
+/// forwarded.
+async fn poll_logs<M: Middleware>(
+    providers: Vec<(String, M)>,
+    pool_filter: &Filter,
+    decoder: &LogDecoder,
+    poll_interval: Duration,
+    reconnect: &ReconnectPolicy,
+    until_block: Option<u64>,
+    sender: EventSender,
+) {
+    let mut next_block = None;
+    let mut failures = 0;
+    let mut active = 0;
+    loop {
+        let (provider_url, client) = &providers[active];
+        match fetch_new_logs(client, pool_filter, next_block, until_block).await {
+            Ok((logs, next)) => {
+                METRICS.set_subscribed(true);
+                failures = 0;
+                next_block = next;
+                let logs = futures_util::stream::iter(logs);
+                if process_stream(logs, client, decoder, &sender)
+                    .await
+                    .is_break()
+                {
+                    return;
+                }
+                if let (Some(until_block), Some(next_block)) = (until_block, next_block) {
+                    if next_block > until_block {
+                        info!(until_block, "reached the last block to monitor");
+                        return;
+                    }
+                }
+                tokio::time::sleep(poll_interval).await;
+            }
+            Err(e) => {
+                warn!(
+                    provider = provider_host(provider_url),
+                    error = %e,
+                    "failed to poll pool logs"
+                );
+                METRICS.set_subscribed(false);
+                failures += 1;
+                if failures > reconnect.max_consecutive_failures {
+                    let _ = sender
+                        .send(Err(MonitorError::ProviderConnect(format!(
+                            "giving up after {} consecutive failed polls",
+                            failures - 1
+                        ))))
+                        .await;
+                    return;
+                }
+                let next = reconnect.next_provider(active, providers.len(), failures);
+                if next != active {
+                    active = next;
+                    warn!(
+                        provider = provider_host(&providers[active].0),
+                        "failing over to the next provider"
+                    );
+                }
+                tokio::time::sleep(reconnect.jittered_backoff(failures)).await;
+                METRICS.reconnects.inc();
+            }
+        }
+    }
+}
+
+/// Watches the Swap events of the given pools and yields each decoded swap as
+/// it arrives.
+///
+/// `ws://` or `wss://` provider URLs use a log subscription, which is
+/// re-established according to `reconnect` whenever it drops. `http://` or
+/// `https://` URLs poll `get_logs` for new blocks every `poll_interval` instead.
+/// The first URL is used until it fails; the others are fallbacks of the same
+/// transport, see `ReconnectPolicy`. Either way the work runs on a background
+/// task. Logs that cannot be decoded are skipped, and the stream yields an
+/// error and ends when the monitor gives up.
+pub async fn swap_stream(
+    provider_urls: &[String],
+    contract_addresses: &[String],
+    token_decimals: HashMap<Address, TokenDecimals>,
+    reconnect: ReconnectPolicy,
+    poll_interval: Duration,
+) -> Result<SwapStream> {
+    let events = event_stream(
+        provider_urls,
+        contract_addresses,
+        &[SWAP_EVENT],
+        token_decimals,
+        reconnect,
+        poll_interval,
+    )
+    .await?;
+    Ok(SwapStream { events })
+}
+
+/// Like `swap_stream`, but for any of `SWAP_EVENT`, `MINT_EVENT`, `BURN_EVENT`
+/// and `V2_SWAP_EVENT` listed in `events`.
+pub async fn event_stream(
+    provider_urls: &[String],
+    contract_addresses: &[String],
+    events: &[&str],
+    token_decimals: HashMap<Address, TokenDecimals>,
+    reconnect: ReconnectPolicy,
+    poll_interval: Duration,
+) -> Result<EventStream> {
+    let decoder = LogDecoder {
+        token_decimals,
+        ..Default::default()
+    };
+    spawn_event_stream(
+        provider_urls,
+        contract_addresses,
+        events,
+        decoder,
+        reconnect,
+        poll_interval,
+        LiveOptions::default(),
+    )
+}
+
+/// Like `event_stream`, but for the logs of `event`, an event of any contract
+/// parsed with `parse_event_signature`, which are yielded as
+/// `PoolEvent::Custom`.
+pub async fn custom_event_stream(
+    provider_urls: &[String],
+    contract_addresses: &[String],
+    event: Event,
+    reconnect: ReconnectPolicy,
+    poll_interval: Duration,
+) -> Result<EventStream> {
+    let decoder = LogDecoder {
+        custom_event: Some(event.clone()),
+        ..Default::default()
+    };
+    spawn_event_stream(
+        provider_urls,
+        contract_addresses,
+        &[event.abi_signature()],
+        decoder,
+        reconnect,
+        poll_interval,
+        LiveOptions::default(),
+    )
+}
+
+/// Starts the background task of `event_stream`, following the chain as `live`
+/// describes.
+fn spawn_event_stream(
+    provider_urls: &[String],
+    contract_addresses: &[String],
+    events: &[impl AsRef<str>],
+    decoder: LogDecoder,
+    reconnect: ReconnectPolicy,
+    poll_interval: Duration,
+    live: LiveOptions,
+) -> Result<EventStream> {
+    validate_connection(provider_urls, &reconnect)?;
+    let pool_filter = create_pool_filter(contract_addresses, events, None, None)?;
+    let (sender, receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+
+    let task = if is_http_url(&provider_urls[0]) {
+        let providers = provider_urls
+            .iter()
+            .map(|provider_url| {
+                Ok((
+                    provider_url.clone(),
+                    http_provider(provider_url, reconnect.authorization.as_ref())?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        tokio::spawn(async move {
+            poll_logs(
+                providers,
+                &pool_filter,
+                &decoder,
+                poll_interval,
+                &reconnect,
+                live.until_block,
+                sender,
+            )
+            .await;
+            METRICS.set_subscribed(false);
+        })
+    } else {
+        let provider_urls = provider_urls.to_vec();
+        let authorization = reconnect.authorization.clone();
+        tokio::spawn(async move {
+            handle_logs(
+                &provider_urls,
+                |provider_ws, timeout| {
+                    let provider_ws = provider_ws.to_string();
+                    let authorization = authorization.clone();
+                    async move {
+                        connect_ws_provider(&provider_ws, timeout, authorization.as_ref()).await
+                    }
+                },
+                &pool_filter,
+                &decoder,
+                &reconnect,
+                live,
+                sender,
+            )
+            .await;
+        })
+    };
+
+    Ok(EventStream { receiver, task })
+}
+
+/// Size of the data of a `PANCAKE_SWAP_EVENT` log, nine 32-byte words minus the
+/// two indexed addresses.
+const SWAP_WITH_FEES_DATA_LEN: usize = 7 * 32;
+
+/// Decodes the data of a Uniswap V3 `Swap` log or, going by its longer length,
+/// of a fork's `Swap` log with protocol fees.
+pub(crate) fn decode_swap_data(data: &[u8]) -> Result<LogData, MonitorError> {
+    if data.len() == SWAP_WITH_FEES_DATA_LEN {
+        decode_log_data_with_fees(data)
+    } else {
+        decode_log_data(data)
+    }
+}
+
+fn decode_log_data(data: &[u8]) -> Result<LogData, MonitorError> {
+    let (amount0, amount1, sqrt_price, liquidity, tick): (I256, I256, U256, u128, i32) =
+        AbiDecode::decode(data)?;
+
+    Ok(LogData {
+        amount0,
+        amount1,
+        sqrt_price,
+        liquidity,
+        tick,
+        protocol_fees_token0: None,
+        protocol_fees_token1: None,
+    })
+}
+
+/// Decodes the data of a `PANCAKE_SWAP_EVENT` log, which appends the protocol
+/// fees of token0 and token1 to the fields of a V3 swap.
+fn decode_log_data_with_fees(data: &[u8]) -> Result<LogData, MonitorError> {
+    let (amount0, amount1, sqrt_price, liquidity, tick, fees0, fees1): (
+        I256,
+        I256,
+        U256,
+        u128,
+        i32,
+        u128,
+        u128,
+    ) = AbiDecode::decode(data)?;
+
+    Ok(LogData {
+        amount0,
+        amount1,
+        sqrt_price,
+        liquidity,
+        tick,
+        protocol_fees_token0: Some(fees0),
+        protocol_fees_token1: Some(fees1),
+    })
+}
+
+/// Number of blocks requested per `get_logs` call during a backfill.
+pub const DEFAULT_BACKFILL_CHUNK_SIZE: u64 = 2000;
+
+/// Upper bound on `BackfillConfig::concurrency`, so a misconfigured backfill
+/// does not flood the provider past its rate limits.
+pub const MAX_BACKFILL_CONCURRENCY: usize = 16;
+
+/// Controls how historical logs are fetched during a backfill.
+///
+/// The range is split into chunks of `chunk_size` blocks, and up to
+/// `concurrency` of them are requested with `get_logs` at once, capped at
+/// `MAX_BACKFILL_CONCURRENCY`. Chunks are still stored one after the other in
+/// block order, so the checkpoint never passes a block whose events are not
+/// stored yet.
+#[derive(Debug, Clone)]
+pub struct BackfillConfig {
+    pub chunk_size: u64,
+    pub concurrency: usize,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        BackfillConfig {
+            chunk_size: DEFAULT_BACKFILL_CHUNK_SIZE,
+            concurrency: 1,
+        }
+    }
+}
+
+fn is_too_many_results_error(error: &str) -> bool {
+    error.contains("more than 10000 results") || error.contains("response size exceeded")
+}
+
+/// Fetches the logs matching `pool_filter` in blocks `start..=end`. When the
+/// provider rejects a request for returning too many results, its range is
+/// split in halves that are requested one after the other.
+async fn fetch_logs_chunk<M: Middleware>(
+    client: &M,
+    pool_filter: &Filter,
+    start: u64,
+    end: u64,
+) -> Result<Vec<Log>> {
+    let mut logs = Vec::new();
+    // The first half of a split range is on top, so logs stay in block order
+    let mut ranges = vec![(start, end)];
+    while let Some((start, end)) = ranges.pop() {
+        let chunk_filter = pool_filter.clone().from_block(start).to_block(end);
+        match client.get_logs(&chunk_filter).await {
+            Ok(chunk) => logs.extend(chunk),
+            Err(e) if start < end && is_too_many_results_error(&e.to_string()) => {
+                let middle = start + (end - start) / 2;
+                warn!(
+                    start,
+                    end, "too many results, retrying with fewer blocks per request"
+                );
+                ranges.push((middle + 1, end));
+                ranges.push((start, middle));
+            }
+            Err(e) => {
+                return Err(MonitorError::ProviderConnect(format!(
+                    "failed to fetch logs for blocks {}..={}: {}",
+                    start, end, e
+                ))
+                .into())
+            }
+        }
+    }
+    Ok(logs)
+}
+
+/// Processes the historical events matching `pool_filter` in blocks
+/// `from_block..=to_block` and returns how many passed the filters, along with
+/// the events still waiting for `options.confirmations`.
+///
+/// Logs are fetched as described by `options.backfill`, see `fetch_logs_chunk`.
+/// The chunks are requested with `buffered` rather than `buffer_unordered`,
+/// which yields them in block order and counts fetched chunks waiting for an
+/// earlier one against the concurrency limit, so a slow request does not let
+/// fetched logs pile up in memory.
+async fn backfill<M: Middleware>(
+    client: &M,
+    store: &mut dyn SwapStore,
+    outputs: &mut Outputs,
+    pool_filter: &Filter,
+    from_block: u64,
+    to_block: u64,
+    options: &MonitorOptions,
+) -> Result<(usize, PendingEvents)> {
+    let mut batch = LogBatch::for_options(options);
+    let mut pending = PendingEvents::new(options.confirmations);
+    if options.confirmations > 0 {
+        pending.observe_head(fetch_block_number(client).await?);
+    }
+    let mut lookups = LookupCache::default();
+    let decoder = options.decoder();
+    let chunk_size = options.backfill.chunk_size.max(1);
+    let concurrency = options
+        .backfill
+        .concurrency
+        .clamp(1, MAX_BACKFILL_CONCURRENCY);
+    let mut processed = 0;
+
+    let starts = (from_block..=to_block).step_by(chunk_size.try_into().unwrap_or(usize::MAX));
+    let mut chunks = futures_util::stream::iter(starts)
+        .map(|start| {
+            let end = start.saturating_add(chunk_size - 1).min(to_block);
+            async move {
+                let logs = fetch_logs_chunk(client, pool_filter, start, end).await;
+                (start, end, logs)
+            }
+        })
+        .buffered(concurrency);
+
+    while let Some((start, end, logs)) = chunks.next().await {
+        let logs = logs?;
+        let count = logs.len();
+        for log in logs {
+            let Some(event) = decode_log_or_skip(log, client, &mut lookups, &decoder).await else {
+                continue;
+            };
+            processed += process_pending(event?, &mut pending, store, &mut batch, outputs, options)
+                .await? as usize;
+        }
+        batch.flush(store).await?;
+        outputs.flush();
+        info!(start, end, events = count, "backfilled blocks");
+    }
+
+    Ok((processed, pending))
+}
+
+async fn fetch_block_number<M: Middleware>(client: &M) -> Result<u64, MonitorError> {
+    Ok(client
+        .get_block_number()
+        .await
+        .map_err(|e| MonitorError::ProviderConnect(format!("failed to fetch block number: {}", e)))?
+        .as_u64())
+}
+
+/// Stores the events of `events` as they are confirmed, see `PendingEvents`,
+/// until the stream ends, `options.idle_timeout` passes or `shutdown_signal`
+/// completes. `pending` holds the events a backfill left waiting for
+/// confirmations.
+///
+/// On shutdown the stream is closed and drained, so the events it already
+/// received are added to `batch`. The caller commits them with
+/// `finish_writes`.
+async fn consume_events(
+    events: &mut EventStream,
+    store: &mut dyn SwapStore,
+    batch: &mut LogBatch,
+    outputs: &mut Outputs,
+    options: &MonitorOptions,
+    skip_through_block: Option<u64>,
+    pending: &mut PendingEvents,
+) -> Result<u64> {
+    let mut processed = 0;
+    let shutdown = shutdown_signal(options.shutdown.clone());
+    tokio::pin!(shutdown);
+    let mut flush_timer =
+        tokio::time::interval(options.batch.max_delay.max(Duration::from_millis(1)));
+    flush_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let idle_deadline = |timeout: Duration| tokio::time::Instant::now() + timeout;
+    let mut idle_until = options.idle_timeout.map(idle_deadline);
+    let throughput_interval = options.throughput_interval.max(Duration::from_millis(1));
+    let mut throughput_timer = tokio::time::interval_at(
+        tokio::time::Instant::now() + throughput_interval,
+        throughput_interval,
+    );
+    throughput_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut reported_swaps = METRICS.swaps_processed.get();
+
+    loop {
+        tokio::select! {
+            event = events.next() => match event {
+                Some(event) => {
+                    idle_until = options.idle_timeout.map(idle_deadline);
+                    let event = event?;
+                    // Events up to the backfilled head were already read by the backfill
+                    let backfilled = !event.removed() && matches!(
+                        (event.block_number(), skip_through_block),
+                        (Some(block), Some(skip_through)) if block <= skip_through
+                    );
+                    // The stream runs on to confirm the last block, but its later
+                    // events are not stored
+                    let past_last_block = matches!(
+                        (event.block_number(), options.until_block),
+                        (Some(block), Some(until_block)) if block > until_block
+                    );
+                    if past_last_block {
+                        pending.observe_head(event.block_number().unwrap_or_default());
+                        processed +=
+                            process_confirmed(pending, store, batch, outputs, options).await?;
+                    } else if !backfilled {
+                        processed +=
+                            process_pending(event, pending, store, batch, outputs, options)
+                                .await?;
+                    }
+                }
+                None => {
+                    // The stream only ends by itself once it covered the
+                    // confirmations of `until_block`
+                    if let Some(until_block) = options.until_block {
+                        pending.observe_head(until_block.saturating_add(options.confirmations));
+                        processed +=
+                            process_confirmed(pending, store, batch, outputs, options).await?;
+                    }
+                    pending.drop_unconfirmed();
+                    return Ok(processed);
+                }
+            },
+            head = pending.next_head() => {
+                pending.observe_head(head);
+                processed += process_confirmed(pending, store, batch, outputs, options).await?;
+            }
+            _ = flush_timer.tick() => {
+                batch.flush(store).await?;
+                // Synced first, so the circuit breaker also covers the queued
+                // writes of a store that writes in the background
+                batch.sync(store).await?;
+                if let Some(max_rows) = options.max_rows {
+                    let pruned = store.prune(max_rows).await?;
+                    if pruned > 0 {
+                        info!(pruned, max_rows, "pruned the oldest events");
+                    }
+                }
+                outputs.flush();
+            }
+            _ = throughput_timer.tick() => {
+                let total = METRICS.swaps_processed.get();
+                let swaps = total - reported_swaps;
+                reported_swaps = total;
+                let rate = METRICS.record_throughput(swaps, throughput_interval);
+                info!(
+                    "processed {} swaps in last {}s ({:.1}/s)",
+                    swaps,
+                    throughput_interval.as_secs(),
+                    rate
+                );
+            }
+            reason = &mut shutdown => {
+                info!("received {}, shutting down", reason);
+                break;
+            }
+            _ = idle(idle_until) => {
+                info!(
+                    timeout = ?options.idle_timeout,
+                    "no events within the idle timeout, shutting down"
+                );
+                break;
+            }
+        }
+    }
+    events.close();
+    while let Some(event) = events.next().await {
+        processed += process_pending(event?, pending, store, batch, outputs, options).await?;
+    }
+    pending.drop_unconfirmed();
+    Ok(processed)
+}
+
+/// Completes on Ctrl-C, on SIGTERM on Unix or once a value is sent to
+/// `trigger`, and returns what it was.
+async fn shutdown_signal(trigger: Option<watch::Receiver<()>>) -> &'static str {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!(error = %e, "failed to listen for SIGTERM");
+                future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
+    // A dropped sender never triggers the shutdown
+    let triggered = async {
+        let stopped = match trigger {
+            Some(mut trigger) => trigger.changed().await.is_ok(),
+            None => false,
+        };
+        if !stopped {
+            future::pending::<()>().await;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "Ctrl-C",
+        _ = terminate => "SIGTERM",
+        _ = triggered => "a shutdown request",
+    }
+}
+
+/// Commits what `batch` still buffers, even when the monitor stopped with an
+/// error, waits until the store wrote it and closes the outputs. This is the
+/// last step of a shutdown, after `consume_events` drained the stream, so the
+/// commit is tried once, without pausing for the circuit breaker.
+async fn finish_writes(
+    store: &mut dyn SwapStore,
+    batch: &mut LogBatch,
+    outputs: &mut Outputs,
+) -> Result<()> {
+    let flushed = match batch.commit(store).await {
+        Ok(()) => store.sync().await.map_err(eyre::Report::from),
+        Err(e) => Err(e.into()),
+    };
+    outputs.close();
+    flushed
+}
+
+/// Completes at `deadline`, or never when it is unset.
+async fn idle(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Optional settings for `run`. The defaults store every swap of the given
+/// pools without backfilling history.
+#[derive(Debug, Clone)]
+pub struct MonitorOptions {
+    /// Token decimals per pool, used to compute human-readable prices.
+    /// `run` adds the decimals of pools listed in `pools`.
+    pub token_decimals: HashMap<Address, TokenDecimals>,
+    /// Token metadata per pool. `run` fills in the missing pools from the
+    /// `pools` table, or fetches them from the chain and stores them there.
+    pub pools: HashMap<Address, PoolMetadata>,
+    pub reconnect: ReconnectPolicy,
+    /// The chain the provider is expected to serve, for example 1 for
+    /// Ethereum mainnet. `run` logs the chain id of the provider on startup
+    /// and warns when it differs, as pool addresses of another chain match
+    /// nothing, or fails with `fail_on_chain_id_mismatch`.
+    pub expected_chain_id: Option<u64>,
+    pub fail_on_chain_id_mismatch: bool,
+    pub pool_version: PoolVersion,
+    /// The kinds of events to subscribe to and store, all of those of
+    /// `pool_version` when unset. They share one subscription, and each log is
+    /// decoded by its first topic into the table of its kind.
+    pub events: Option<Vec<EventKind>>,
+    /// Monitor this event instead of the Uniswap events of `pool_version`, for
+    /// contracts other than Uniswap pools. Parse it with `parse_event_signature`.
+    /// Its logs are decoded from the event's ABI and stored in the
+    /// `custom_events` table, with the parameters as JSON; the swap filters and
+    /// outputs do not apply to them.
+    pub custom_event: Option<Event>,
+    /// Subscribe to this `Swap` signature instead of `SWAP_EVENT` and
+    /// `PANCAKE_SWAP_EVENT`, or `V2_SWAP_EVENT` for V2 pairs, for deployments
+    /// that emit their swaps under a different topic. Its logs are decoded as
+    /// swaps of `pool_version`, so the data must have the same layout.
+    pub swap_event: Option<String>,
+    /// Fetch the receipt of each V3 swap's transaction and store its
+    /// `gas_used` and `effective_gas_price`, for fee and MEV analysis. This
+    /// costs a `get_transaction_receipt` call per transaction, so it is off
+    /// by default.
+    pub fetch_gas: bool,
+    /// Stored in the `source` column of each V3 swap, to tell which monitor
+    /// instance or configuration wrote a row when several share a database.
+    /// `None` leaves the column empty.
+    pub source_tag: Option<String>,
+    /// Time between `get_logs` polls when the provider URL is HTTP(S).
+    pub poll_interval: Duration,
+    pub batch: BatchConfig,
+    /// Keep at most this many rows per event table, deleting the oldest by
+    /// block number each time the batch is committed on `batch.max_delay`,
+    /// so the database stays bounded on a small disk. See `SwapStore::prune`.
+    pub max_rows: Option<u64>,
+    /// Retry failed commits of the batch instead of stopping, see
+    /// `CircuitBreaker`. Without one, the first failed commit ends `run` with
+    /// the error.
+    pub circuit_breaker: Option<CircuitBreaker>,
+    /// Only store an event once this many blocks were mined on top of its
+    /// block, so events of blocks that a reorg orphans are never stored.
+    /// Events are buffered until then, and the chain head is read every
+    /// `poll_interval` to confirm them on quiet pools. 0 stores events as
+    /// they arrive.
+    pub confirmations: u64,
+    pub backfill: BackfillConfig,
+    /// Report every stored event in `log_format`. Storing is not affected, so
+    /// services that only need the database can turn it off to keep their
+    /// logs quiet.
+    pub print: bool,
+    pub log_format: LogFormat,
+    /// Backfill historical swaps from this block up to the current head before
+    /// storing live swaps. When unset, the monitor resumes from the last
+    /// checkpoint stored in the database, if there is one.
+    pub backfill_from: Option<u64>,
+    /// Stop once the events of this block have been stored, for example to
+    /// collect a bounded dataset. When unset, `run` monitors until Ctrl-C.
+    pub until_block: Option<u64>,
+    /// With a WebSocket provider, subscribe to new blocks and fetch the logs of
+    /// each instead of subscribing to logs, for providers whose log
+    /// subscriptions miss events. HTTP(S) providers already poll every block.
+    pub follow_heads: bool,
+    pub filter: SwapFilter,
+    /// Only store swaps sent by this address, for example to track a single
+    /// router or bot.
+    pub only_sender: Option<Address>,
+    /// Only store swaps whose recipient, the `to` address of V2 swaps, is this
+    /// address.
+    pub only_receiver: Option<Address>,
+    /// Return from `run` once no event has arrived for this long, for example
+    /// to take a snapshot of a quiet pool in a script. When unset, `run`
+    /// monitors until Ctrl-C or SIGTERM.
+    pub idle_timeout: Option<Duration>,
+    /// Stops `run` like Ctrl-C once a value is sent on the sender of this
+    /// receiver, for applications that handle signals themselves or embed
+    /// the monitor. Dropping the sender does not stop it.
+    pub shutdown: Option<watch::Receiver<()>>,
+    /// Also append every stored swap to this CSV file.
+    pub csv_path: Option<PathBuf>,
+    /// Also publish every stored swap to a Kafka topic.
+    #[cfg(feature = "kafka")]
+    pub kafka: Option<KafkaConfig>,
+    /// Chainlink USD price feed per token address. Swaps of pools with one of
+    /// these tokens and known token metadata are stored with their USD value.
+    pub price_feeds: HashMap<Address, Address>,
+    /// Track a moving average of the price of each pool, updated with every
+    /// stored swap whose pool has known token decimals.
+    pub price_ema: Option<PriceEma>,
+    /// Post large live swaps to a webhook.
+    pub webhook: Option<WebhookConfig>,
+    /// Serve Prometheus metrics on `/metrics` and a health check on `/healthz`
+    /// at this address while running.
+    pub metrics_addr: Option<SocketAddr>,
+    /// How long `/healthz` reports healthy after the provider was last heard
+    /// from.
+    pub health_max_age: Duration,
+    /// How often the number of live swaps processed since the last report is
+    /// logged, along with their rate per second.
+    pub throughput_interval: Duration,
+    /// Send every live swap to the WebSocket clients connected at this address,
+    /// for example a browser dashboard.
+    pub broadcast_addr: Option<SocketAddr>,
+}
+
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        MonitorOptions {
+            token_decimals: HashMap::new(),
+            pools: HashMap::new(),
+            reconnect: ReconnectPolicy::default(),
+            expected_chain_id: None,
+            fail_on_chain_id_mismatch: false,
+            pool_version: PoolVersion::default(),
+            events: None,
+            custom_event: None,
+            swap_event: None,
+            fetch_gas: false,
+            source_tag: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            batch: BatchConfig::default(),
+            max_rows: None,
+            circuit_breaker: None,
+            confirmations: 0,
+            backfill: BackfillConfig::default(),
+            print: true,
+            log_format: LogFormat::default(),
+            backfill_from: None,
+            until_block: None,
+            follow_heads: false,
+            filter: SwapFilter::default(),
+            only_sender: None,
+            only_receiver: None,
+            idle_timeout: None,
+            shutdown: None,
+            csv_path: None,
+            #[cfg(feature = "kafka")]
+            kafka: None,
+            price_feeds: HashMap::new(),
+            price_ema: None,
+            webhook: None,
+            metrics_addr: None,
+            health_max_age: DEFAULT_HEALTH_MAX_AGE,
+            throughput_interval: DEFAULT_THROUGHPUT_INTERVAL,
+            broadcast_addr: None,
+        }
+    }
+}
+
+impl MonitorOptions {
+    /// The signatures of the events to subscribe to.
+    fn event_signatures(&self) -> Result<Vec<String>, MonitorError> {
+        if let Some(event) = &self.custom_event {
+            return Ok(vec![event.abi_signature()]);
+        }
+        let kinds = self
+            .events
+            .as_deref()
+            .unwrap_or(self.pool_version.event_kinds());
+        if kinds.is_empty() {
+            return Err(MonitorError::InvalidConfig(
+                "no events to monitor".to_string(),
+            ));
+        }
+        let mut signatures = Vec::new();
+        for &kind in kinds {
+            let kind_signatures = match (kind, &self.swap_event) {
+                (EventKind::Swap, Some(swap_event)) => vec![swap_event.clone()],
+                _ => self
+                    .pool_version
+                    .signatures(kind)
+                    .iter()
+                    .map(|event| event.to_string())
+                    .collect(),
+            };
+            if kind_signatures.is_empty() {
+                return Err(MonitorError::InvalidConfig(format!(
+                    "{} events are only stored for v3 pools",
+                    kind.name()
+                )));
+            }
+            for signature in kind_signatures {
+                if !signatures.contains(&signature) {
+                    signatures.push(signature);
+                }
+            }
+        }
+        Ok(signatures)
+    }
+
+    fn decoder(&self) -> LogDecoder {
+        LogDecoder {
+            token_decimals: self.token_decimals.clone(),
+            custom_event: self.custom_event.clone(),
+            swap_topic: self.swap_event.as_deref().map(event_topic),
+            pool_version: self.pool_version,
+            fetch_gas: self.fetch_gas,
+            source_tag: self.source_tag.clone(),
+        }
+    }
+
+    /// Whether a swap between `sender` and `receiver` passes `only_sender` and
+    /// `only_receiver`.
+    fn matches_parties(&self, sender: Address, receiver: Address) -> bool {
+        self.only_sender
+            .is_none_or(|only_sender| sender == only_sender)
+            && self
+                .only_receiver
+                .is_none_or(|only_receiver| receiver == only_receiver)
+    }
+}
+
+/// Adds the token metadata of the monitored pools to `options.pools`, reading
+/// it from `store` or else fetching it from the chain, and fills in their
+/// `options.token_decimals`. A pool whose metadata cannot be fetched is
+/// monitored without it. With `options.custom_event` the contracts are not
+/// pools, so nothing is loaded.
+async fn load_pool_metadata<M: Middleware + 'static>(
+    client: Arc<M>,
+    store: &mut dyn SwapStore,
+    contract_addresses: &[String],
+    options: &mut MonitorOptions,
+) -> Result<(), MonitorError> {
+    if options.custom_event.is_some() {
+        return Ok(());
+    }
+    for address in contract_addresses {
+        let pool = parse_pool_address(address)?;
+        let metadata = match options.pools.get(&pool) {
+            Some(metadata) => metadata.clone(),
+            None => match store.read_pool(pool).await? {
+                Some(metadata) => metadata,
+                None => match fetch_pool_metadata(client.clone(), pool).await {
+                    Ok(metadata) => {
+                        store.insert_pool(&metadata).await?;
+                        metadata
+                    }
+                    Err(e) => {
+                        warn!(?pool, error = %e, "continuing without token metadata");
+                        continue;
+                    }
+                },
+            },
+        };
+        // Decimals configured explicitly take precedence
+        options
+            .token_decimals
+            .entry(pool)
+            .or_insert_with(|| metadata.decimals());
+        options.pools.insert(pool, metadata);
+    }
+    Ok(())
+}
+
+/// Reads the chain id of the provider and checks it against
+/// `options.expected_chain_id`, see there.
+async fn check_chain_id<M: Middleware>(
+    client: &M,
+    options: &MonitorOptions,
+) -> Result<(), MonitorError> {
+    let chain_id = match client.get_chainid().await {
+        Ok(chain_id) => chain_id.low_u64(),
+        Err(e) if options.expected_chain_id.is_some() => {
+            return Err(MonitorError::ProviderConnect(format!(
+                "failed to read the chain id: {}",
+                e
+            )))
+        }
+        Err(e) => {
+            warn!(error = %e, "failed to read the chain id");
+            return Ok(());
+        }
+    };
+    info!(chain_id, "connected to chain");
+    match options.expected_chain_id {
+        Some(expected) if expected != chain_id => {
+            if options.fail_on_chain_id_mismatch {
+                return Err(MonitorError::InvalidConfig(format!(
+                    "the provider serves chain {} instead of the expected chain {}",
+                    chain_id, expected
+                )));
+            }
+            warn!(
+                chain_id,
+                expected,
+                "the provider serves another chain than expected, the pools may match no events"
+            );
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Starts `spawn_price_oracle` for `options.price_feeds`, unless there are
+/// none, and values the swaps of `outputs` at its prices. Returns the task
+/// refreshing the prices.
+async fn start_price_oracle<M: Middleware + 'static>(
+    client: Arc<M>,
+    options: &MonitorOptions,
+    outputs: &mut Outputs,
+) -> Option<JoinHandle<()>> {
+    if options.price_feeds.is_empty() {
+        return None;
+    }
+    let (usd_prices, handle) = spawn_price_oracle(client, options.price_feeds.clone()).await;
+    outputs.usd_prices = Some(usd_prices);
+    Some(handle)
+}
+
+/// Backfills from `from_block` to the current head, or `options.until_block`
+/// when that comes first, as `backfill_range` does.
+async fn backfill_to_head<M: Middleware>(
+    client: &M,
+    store: &mut dyn SwapStore,
+    outputs: &mut Outputs,
+    contract_addresses: &[String],
+    from_block: u64,
+    options: &MonitorOptions,
+) -> Result<(u64, usize, PendingEvents)> {
+    let to_block = match options.until_block {
+        Some(until_block) => Some(fetch_block_number(client).await?.min(until_block)),
+        None => None,
+    };
+    backfill_range(
+        client,
+        store,
+        outputs,
+        contract_addresses,
+        from_block,
+        to_block,
+        options,
+    )
+    .await
+}
+
+/// Backfills the latest block, or the latest with `options.confirmations`
+/// blocks on top of it, as `backfill_range` does.
+async fn backfill_latest_block<M: Middleware>(
+    client: &M,
+    store: &mut dyn SwapStore,
+    outputs: &mut Outputs,
+    contract_addresses: &[String],
+    options: &MonitorOptions,
+) -> Result<(u64, usize, PendingEvents)> {
+    let block = fetch_block_number(client)
+        .await?
+        .saturating_sub(options.confirmations);
+    backfill_range(
+        client,
+        store,
+        outputs,
+        contract_addresses,
+        block,
+        Some(block),
+        options,
+    )
+    .await
+}
+
+/// Backfills from `from_block` up to `to_block`, or the current head when
+/// unset, and returns the last backfilled block, the number of events that
+/// passed the filters and the events still waiting for confirmations.
+async fn backfill_range<M: Middleware>(
+    client: &M,
+    store: &mut dyn SwapStore,
+    outputs: &mut Outputs,
+    contract_addresses: &[String],
+    from_block: u64,
+    to_block: Option<u64>,
+    options: &MonitorOptions,
+) -> Result<(u64, usize, PendingEvents)> {
+    let head = match to_block {
+        Some(to_block) => to_block,
+        None => fetch_block_number(client).await?,
+    };
+    info!(from_block, head, "backfilling events");
+    let pool_filter = create_pool_filter(
+        contract_addresses,
+        &options.event_signatures()?,
+        Some(from_block),
+        Some(head),
+    )?;
+    let (count, pending) = backfill(
+        client,
+        store,
+        outputs,
+        &pool_filter,
+        from_block,
+        head,
+        options,
+    )
+    .await?;
+    info!(events = count, head, "backfill complete");
+    Ok((head, count, pending))
+}
+
+/// Stores the events of the given pools from `from_block` up to `to_block`, or
+/// the current head when unset, and returns the last backfilled block.
+///
+/// Unlike `run` nothing is subscribed, so this returns once the range is
+/// stored. `options.backfill_from` is ignored in favour of `from_block`.
+/// Events of the last `options.confirmations` blocks of the chain are left
+/// out, as they are not confirmed yet.
+pub async fn run_backfill(
+    provider_urls: &[String],
+    contract_addresses: &[String],
+    store: Box<dyn SwapStore>,
+    from_block: u64,
+    to_block: Option<u64>,
+    options: MonitorOptions,
+) -> Result<u64> {
+    let range = Some((from_block, to_block));
+    run_backfill_range(provider_urls, contract_addresses, store, range, options).await
+}
+
+/// Stores the events of the given pools in the latest block and returns its
+/// number, for snapshot jobs run periodically instead of a subscription.
+///
+/// With `options.confirmations` the latest block with that many blocks on top
+/// of it is stored instead, so its events are confirmed. Like `run_backfill`
+/// this ignores `options.backfill_from` and the stored checkpoint.
+pub async fn run_once(
+    provider_urls: &[String],
+    contract_addresses: &[String],
+    store: Box<dyn SwapStore>,
+    options: MonitorOptions,
+) -> Result<u64> {
+    run_backfill_range(provider_urls, contract_addresses, store, None, options).await
+}
+
+/// Backfills `range`, the first and optional last block, or the latest block
+/// when unset, for `run_backfill` and `run_once`.
+async fn run_backfill_range(
+    provider_urls: &[String],
+    contract_addresses: &[String],
+    mut store: Box<dyn SwapStore>,
+    range: Option<(u64, Option<u64>)>,
+    mut options: MonitorOptions,
+) -> Result<u64> {
+    validate_connection(provider_urls, &options.reconnect)?;
+    store.init().await?;
+    let store = store.as_mut();
+    let mut outputs = Outputs::open(&options, Vec::new())?;
+    let head = if is_http_url(&provider_urls[0]) {
+        let client = connect_http_provider(provider_urls, &options.reconnect).await?;
+        backfill_with(
+            client,
+            store,
+            &mut outputs,
+            contract_addresses,
+            range,
+            &mut options,
+        )
+        .await
+    } else {
+        let client = connect_to_provider(provider_urls, &options.reconnect).await?;
+        backfill_with(
+            client,
+            store,
+            &mut outputs,
+            contract_addresses,
+            range,
+            &mut options,
+        )
+        .await
+    };
+    outputs.close();
+    let (head, _, mut pending) = head?;
+    pending.drop_unconfirmed();
+    // Wait until the last queued batch is written
+    store.sync().await?;
+    Ok(head)
+}
+
+/// Loads the pool metadata and backfills `range` with `client`, see
+/// `run_backfill_range`, valuing the swaps at the prices of the price oracle
+/// while it runs.
+async fn backfill_with<M: Middleware + 'static>(
+    client: Arc<M>,
+    store: &mut dyn SwapStore,
+    outputs: &mut Outputs,
+    contract_addresses: &[String],
+    range: Option<(u64, Option<u64>)>,
+    options: &mut MonitorOptions,
+) -> Result<(u64, usize, PendingEvents)> {
+    check_chain_id(client.as_ref(), options).await?;
+    load_pool_metadata(client.clone(), store, contract_addresses, options).await?;
+    let price_oracle = start_price_oracle(client.clone(), options, outputs).await;
+    let result = match range {
+        Some((from_block, to_block)) => {
+            backfill_range(
+                client.as_ref(),
+                store,
+                outputs,
+                contract_addresses,
+                from_block,
+                to_block,
+                options,
+            )
+            .await
+        }
+        None => {
+            backfill_latest_block(client.as_ref(), store, outputs, contract_addresses, options)
+                .await
+        }
+    };
+    if let Some(price_oracle) = price_oracle {
+        price_oracle.abort();
+    }
+    result
+}
+
+/// Stores and prints the events of the given pools until the subscription ends,
+/// `options.until_block` has been stored, `options.idle_timeout` passes without
+/// an event or Ctrl-C or SIGTERM is received, and returns the number of
+/// backfilled and live events that passed `options.filter` and were stored or
+/// deleted. For V3 pools these are Swap, Mint and Burn events, for V2 pairs
+/// their Swap events, see `options.pool_version`.
+///
+/// On startup the tokens of every pool are looked up, see `MonitorOptions::pools`.
+/// `provider_urls` are WebSocket or HTTP(S) endpoints, the first one preferred,
+/// see `swap_stream`.
+/// Events are written to `store`, see `open_store`, in batches as described by
+/// `options.batch`. On Ctrl-C, SIGTERM or `options.shutdown` the subscription
+/// is closed, the events it already decoded are stored and the batch is
+/// committed and synced before returning, so no received event is lost on
+/// shutdown.
+pub async fn run(
+    provider_urls: &[String],
+    contract_addresses: &[String],
+    store: Box<dyn SwapStore>,
+    options: MonitorOptions,
+) -> eyre::Result<u64> {
+    run_with_sinks(
+        provider_urls,
+        contract_addresses,
+        store,
+        Vec::new(),
+        options,
+    )
+    .await
+}
+
+/// Like `run`, but also passes every stored event to `sinks`, after the
+/// outputs set in `options`, see `SwapSink`.
+pub async fn run_with_sinks(
+    provider_urls: &[String],
+    contract_addresses: &[String],
+    mut store: Box<dyn SwapStore>,
+    sinks: Vec<Box<dyn SwapSink>>,
+    mut options: MonitorOptions,
+) -> eyre::Result<u64> {
+    validate_connection(provider_urls, &options.reconnect)?;
+    store.init().await?;
+    let store = store.as_mut();
+    let metrics_server = options
+        .metrics_addr
+        .map(|addr| spawn_metrics_server(addr, options.health_max_age))
+        .transpose()?
+        .map(|(_, handle)| handle);
+    let (broadcast, broadcast_server) = match options.broadcast_addr {
+        Some(addr) => {
+            let (_, broadcast, handle) = spawn_broadcast_server(addr).await?;
+            (Some(broadcast), Some(handle))
+        }
+        None => (None, None),
+    };
+    let mut outputs = Outputs::open(&options, sinks)?;
+    let (price_oracle, head_watch) = if is_http_url(&provider_urls[0]) {
+        let client = connect_http_provider(provider_urls, &options.reconnect).await?;
+        check_chain_id(client.as_ref(), &options).await?;
+        load_pool_metadata(client.clone(), store, contract_addresses, &mut options).await?;
+        let head_watch = (options.confirmations > 0)
+            .then(|| spawn_head_watch(client.clone(), options.poll_interval));
+        (
+            start_price_oracle(client, &options, &mut outputs).await,
+            head_watch,
+        )
+    } else {
+        let client = connect_to_provider(provider_urls, &options.reconnect).await?;
+        check_chain_id(client.as_ref(), &options).await?;
+        load_pool_metadata(client.clone(), store, contract_addresses, &mut options).await?;
+        let head_watch = (options.confirmations > 0)
+            .then(|| spawn_head_watch(client.clone(), options.poll_interval));
+        (
+            start_price_oracle(client, &options, &mut outputs).await,
+            head_watch,
+        )
+    };
+    // Subscribe before backfilling so no swap falls between history and the live stream
+    let mut events = spawn_event_stream(
+        provider_urls,
+        contract_addresses,
+        &options.event_signatures()?,
+        options.decoder(),
+        options.reconnect.clone(),
+        options.poll_interval,
+        LiveOptions {
+            // The blocks after the last one confirm it
+            until_block: options
+                .until_block
+                .map(|until_block| until_block.saturating_add(options.confirmations)),
+            follow_heads: options.follow_heads,
+        },
+    )?;
+
+    let backfill_from = match options.backfill_from {
+        Some(from_block) => Some(from_block),
+        // A batch can be committed part-way through a block, so the checkpoint
+        // block itself is scanned again
+        None => store.read_checkpoint().await?,
+    };
+
+    let mut backfilled_to = None;
+    let mut backfilled = 0;
+    let mut pending = PendingEvents::new(options.confirmations);
+    if let Some(from_block) = backfill_from {
+        let (head, count, unconfirmed) = if is_http_url(&provider_urls[0]) {
+            let client = connect_http_provider(provider_urls, &options.reconnect).await?;
+            backfill_to_head(
+                client.as_ref(),
+                store,
+                &mut outputs,
+                contract_addresses,
+                from_block,
+                &options,
+            )
+            .await?
+        } else {
+            let client = connect_to_provider(provider_urls, &options.reconnect).await?;
+            backfill_to_head(
+                client.as_ref(),
+                store,
+                &mut outputs,
+                contract_addresses,
+                from_block,
+                &options,
+            )
+            .await?
+        };
+        backfilled_to = Some(head);
+        backfilled = count as u64;
+        pending = unconfirmed;
+    }
+    let (head_watch, head_updater) = head_watch.unzip();
+    if let Some(heads) = head_watch {
+        pending.watch_heads(heads);
+    }
+
+    if let Some(webhook) = options.webhook.clone() {
+        outputs.sinks.push(Box::new(Webhook::new(webhook)));
+    }
+    if let Some(broadcast) = broadcast {
+        outputs.sinks.push(Box::new(broadcast));
+    }
+    let mut batch = LogBatch::for_options(&options);
+    // Nothing is left to monitor, while the subscription would only end with an
+    // event past the last block
+    let backfilled_all = pending.is_empty()
+        && matches!(
+            (backfilled_to, options.until_block),
+            (Some(head), Some(until_block)) if head >= until_block
+        );
+    let result = if backfilled_all {
+        info!("backfilled through the last block to monitor");
+        events.close();
+        Ok(0)
+    } else {
+        consume_events(
+            &mut events,
+            store,
+            &mut batch,
+            &mut outputs,
+            &options,
+            backfilled_to,
+            &mut pending,
+        )
+        .await
+    };
+    let flushed = finish_writes(store, &mut batch, &mut outputs).await;
+    for task in [metrics_server, broadcast_server, price_oracle, head_updater]
+        .into_iter()
+        .flatten()
+    {
+        task.abort();
+    }
+    let live = result.and_then(|live| flushed.map(|()| live))?;
+    Ok(backfilled + live)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::events::tests::{
+        create_test_burn_log, create_test_custom_log, create_test_mint_log,
+        create_test_v2_swap_log, TRADE_EVENT,
+    };
+    use crate::source::tests::MockLogSource;
+    use ethers::providers::{JsonRpcError, MockProvider, MockResponse};
+    use ethers::types::{Block, ValueOrArray, U64};
+
+    struct TestTransactionValues {
+        tx_hash: &'static str,
+        pool: &'static str,
+        sender: &'static str,
+        receiver: &'static str,
+        data: &'static str,
+        topic0: &'static str,
+        topic1: &'static str,
+        topic2: &'static str,
+        ammount0: &'static str,
+        ammount1: &'static str,
+        sqrt_price: &'static str,
+        liquidity: &'static str,
+        tick: i32,
+        block_number: u64,
+        block_hash: &'static str,
+        timestamp: u64,
+        decimals0: u8,
+        decimals1: u8,
+        price: f64,
+        log_index: u64,
+        transaction_index: u64,
+    }
+
+    fn create_test_transaction_vals() -> TestTransactionValues {
+        TestTransactionValues {
+            tx_hash: "0xe92955b4c46b38de18c1cdd58b06d49d45d6f9ca0906a86918f4cf20650683b4",
+            pool: "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640",
+            sender: "0xe592427a0aece92de3edee1f18e0157c05861564",
+            receiver: "0x4b7d6c3cea01f4d54a9cad6587da106ea39da1e6",
+            topic0: "0xc42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67",
+            topic1: "0x000000000000000000000000e592427a0aece92de3edee1f18e0157c05861564",
+            topic2: "0x0000000000000000000000004b7d6c3cea01f4d54a9cad6587da106ea39da1e6",
+            data: "0xfffffffffffffffffffffffffffffffffffffffffffffffffffffffff0511b800000000000000000000000000000000000000000000000000240e540e2dc0042000000000000000000000000000000000000610413a1a7c814aa98ca36d09f8b000000000000000000000000000000000000000000000001c4846addbd259faf00000000000000000000000000000000000000000000000000000000000316ab",
+            ammount0: "-263120000",
+            ammount1: "162381653432074306",
+            sqrt_price: "1967716719848838692609454179917707",
+            liquidity: "32607304702662909871",
+            tick: 202411,
+            block_number: 17_500_000,
+            block_hash: "0x2f2a9c0e1f5d6a3c4b8e7d9f0a1b2c3d4e5f60718293a4b5c6d7e8f901234567",
+            timestamp: 1_687_000_000,
+            // USDC/WETH: (1967716719848838692609454179917707 / 2^96)^2 * 10^(6 - 18)
+            decimals0: 6,
+            decimals1: 18,
+            price: 0.000616830705123844,
+            log_index: 42,
+            transaction_index: 7,
+        }
+    }
+
+    // Helper function to create a test log
+    pub(crate) fn create_test_log() -> Log {
+        let x = create_test_transaction_vals();
+
+        Log {
+            address: Address::from_str(x.pool).unwrap(),
+            transaction_hash: Some(H256::from_str(x.tx_hash).unwrap()),
+            topics: vec![
+                H256::from_str(x.topic0).unwrap(),
+                H256::from_str(x.topic1).unwrap(),
+                H256::from_str(x.topic2).unwrap(),
+            ],
+            data: Bytes::from_str(x.data).unwrap(),
+            block_number: Some(x.block_number.into()),
+            block_hash: Some(H256::from_str(x.block_hash).unwrap()),
+            log_index: Some(x.log_index.into()),
+            transaction_index: Some(x.transaction_index.into()),
+            ..Default::default()
+        }
+    }
+
+    // Token metadata of the test transaction's USDC/WETH pool
+    pub(crate) fn create_test_pool_metadata() -> PoolMetadata {
+        let x = create_test_transaction_vals();
+        PoolMetadata {
+            pool: Address::from_str(x.pool).unwrap(),
+            token0: TokenMetadata {
+                address: Address::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48").unwrap(),
+                symbol: "USDC".to_string(),
+                decimals: x.decimals0,
+            },
+            token1: TokenMetadata {
+                address: Address::from_str("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").unwrap(),
+                symbol: "WETH".to_string(),
+                decimals: x.decimals1,
+            },
+        }
+    }
+
+    // Helper function to create a decoded swap from the test transaction
+    pub(crate) fn create_test_combined_log() -> CombinedLog {
+        let x = create_test_transaction_vals();
+        let log_data = LogData {
+            amount0: I256::from_dec_str(x.ammount0).unwrap(),
+            amount1: I256::from_dec_str(x.ammount1).unwrap(),
+            sqrt_price: U256::from_dec_str(x.sqrt_price).unwrap(),
+            liquidity: x.liquidity.parse::<u128>().unwrap(),
+            tick: x.tick,
+            protocol_fees_token0: None,
+            protocol_fees_token1: None,
+        };
+        CombinedLog::new(
+            Some(H256::from_str(x.tx_hash).unwrap()),
+            Address::from_str(x.pool).unwrap(),
+            Address::from_str(x.sender).unwrap(),
+            Address::from_str(x.receiver).unwrap(),
+            log_data,
+        )
+        .with_block(
+            Some(x.block_number),
+            Some(H256::from_str(x.block_hash).unwrap()),
+        )
+        .with_timestamp(Some(x.timestamp))
+        .with_log_index(Some(x.log_index), false)
+        .with_transaction_index(Some(x.transaction_index))
+    }
+
+    // Helper function to create the test swap at another block, with a hash of
+    // its own, and log index
+    pub(crate) fn swap_at(block_number: u64, log_index: u64) -> CombinedLog {
+        CombinedLog {
+            block_number: Some(block_number),
+            block_hash: Some(H256::from_low_u64_be(block_number)),
+            log_index: Some(log_index),
+            ..create_test_combined_log()
+        }
+    }
+
+    // Helper function to create a mocked provider that answers `get_block` calls
+    fn create_test_client(block_timestamps: &[u64]) -> (Provider<MockProvider>, MockProvider) {
+        let (client, mock) = Provider::mocked();
+        // The mock pops responses from the back, so push them in reverse order
+        for timestamp in block_timestamps.iter().rev() {
+            let block = Block::<H256> {
+                timestamp: (*timestamp).into(),
+                ..Default::default()
+            };
+            mock.push(block).unwrap();
+        }
+        (client, mock)
+    }
+
+    fn count_rows(store: &SqliteStore) -> i64 {
+        store
+            .connection()
+            .query_row("SELECT COUNT(*) FROM logs", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_combined_log_creation() {
+        let x = create_test_transaction_vals();
+
+        let tx_hash = H256::from_str(x.tx_hash).unwrap();
+        let pool = Address::from_str(x.pool).unwrap();
+        let sender = Address::from_str(x.sender).unwrap();
+        let receiver = Address::from_str(x.receiver).unwrap();
+        let log_data = LogData {
+            amount0: I256::from_dec_str(x.ammount0).unwrap(),
+            amount1: I256::from_dec_str(x.ammount1).unwrap(),
+            sqrt_price: U256::from_dec_str(x.sqrt_price).unwrap(),
+            liquidity: x.liquidity.parse::<u128>().unwrap(),
+            tick: x.tick,
+            protocol_fees_token0: None,
+            protocol_fees_token1: None,
+        };
+        let combined_log =
+            CombinedLog::new(Some(tx_hash), pool, sender, receiver, log_data.clone());
+
+        assert_eq!(combined_log.tx_hash, tx_hash);
+        assert_eq!(combined_log.pool, pool);
+        assert_eq!(combined_log.sender, sender);
+        assert_eq!(combined_log.receiver, receiver);
+        assert_eq!(combined_log.data, log_data);
+        assert_eq!(combined_log.amount0(), log_data.amount0);
+        assert_eq!(combined_log.amount1(), log_data.amount1);
+        assert_eq!(combined_log.sqrt_price(), log_data.sqrt_price);
+        assert_eq!(combined_log.liquidity(), log_data.liquidity);
+        assert_eq!(combined_log.tick(), log_data.tick);
+    }
+    #[test]
+    fn test_decode_log_data() {
+        let x = create_test_transaction_vals();
+        let bytes = Bytes::from_str(x.data).unwrap();
+        let decoded = decode_log_data(&bytes).unwrap();
+
+        let actual = LogData {
+            amount0: I256::from_dec_str(x.ammount0).unwrap(),
+            amount1: I256::from_dec_str(x.ammount1).unwrap(),
+            sqrt_price: U256::from_dec_str(x.sqrt_price).unwrap(),
+            liquidity: x.liquidity.parse::<u128>().unwrap(),
+            tick: x.tick,
+            protocol_fees_token0: None,
+            protocol_fees_token1: None,
+        };
+
+        assert_eq!(decoded, actual);
+    }
+
+    #[tokio::test]
+    async fn test_decode_pancake_swap_log() {
+        let x = create_test_transaction_vals();
+        // PancakeSwap V3 appends protocolFeesToken0 and protocolFeesToken1
+        let mut log = create_test_log();
+        log.topics[0] = event_topic(PANCAKE_SWAP_EVENT);
+        let mut data = log.data.to_vec();
+        data.extend(H256::from_low_u64_be(1_500).as_bytes());
+        data.extend(H256::from_low_u64_be(0).as_bytes());
+        log.data = data.into();
+
+        let (client, _mock) = create_test_client(&[x.timestamp]);
+        let mut lookups = LookupCache::default();
+        let Ok(PoolEvent::Swap(swap)) =
+            decode_event(log, &client, &mut lookups, &LogDecoder::default()).await
+        else {
+            panic!("expected a swap");
+        };
+        assert_eq!(
+            swap.data,
+            LogData {
+                protocol_fees_token0: Some(1_500),
+                protocol_fees_token1: Some(0),
+                ..create_test_combined_log().data
+            }
+        );
+
+        // The Uniswap layout has no fee fields
+        let bytes = Bytes::from_str(x.data).unwrap();
+        assert_eq!(decode_swap_data(&bytes).unwrap().protocol_fees_token0, None);
+        assert!(decode_log_data_with_fees(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_event_signatures() {
+        assert_eq!(
+            MonitorOptions::default().event_signatures().unwrap(),
+            vec![SWAP_EVENT, PANCAKE_SWAP_EVENT, MINT_EVENT, BURN_EVENT]
+        );
+        let options = MonitorOptions {
+            events: Some(vec![EventKind::Burn, EventKind::Swap, EventKind::Burn]),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.event_signatures().unwrap(),
+            vec![BURN_EVENT, SWAP_EVENT, PANCAKE_SWAP_EVENT]
+        );
+
+        // V2 pairs only have their swaps stored
+        let options = MonitorOptions {
+            pool_version: PoolVersion::V2,
+            events: Some(vec![EventKind::Swap, EventKind::Mint]),
+            ..Default::default()
+        };
+        let err = options.event_signatures().unwrap_err();
+        assert_eq!(err.to_string(), "mint events are only stored for v3 pools");
+        let options = MonitorOptions {
+            events: Some(vec![]),
+            ..Default::default()
+        };
+        assert!(options.event_signatures().is_err());
+
+        assert_eq!("mint".parse::<EventKind>().unwrap(), EventKind::Mint);
+        assert!("collect".parse::<EventKind>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decode_overridden_swap_event() {
+        let x = create_test_transaction_vals();
+        const FORK_SWAP_EVENT: &str =
+            "Swap(address,address,int256,int256,uint160,uint128,int24,uint24)";
+        let options = MonitorOptions {
+            swap_event: Some(FORK_SWAP_EVENT.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.event_signatures().unwrap(),
+            vec![FORK_SWAP_EVENT, MINT_EVENT, BURN_EVENT]
+        );
+
+        let mut log = create_test_log();
+        log.topics[0] = event_topic(FORK_SWAP_EVENT);
+        // Trailing fields after those of a Uniswap swap are ignored
+        let mut data = log.data.to_vec();
+        data.extend(H256::from_low_u64_be(500).as_bytes());
+        log.data = data.into();
+        let (client, _mock) = create_test_client(&[x.timestamp]);
+        let mut lookups = LookupCache::default();
+        let Ok(PoolEvent::Swap(swap)) =
+            decode_event(log.clone(), &client, &mut lookups, &options.decoder()).await
+        else {
+            panic!("expected a swap");
+        };
+        assert_eq!(swap.data, create_test_combined_log().data);
+
+        // Without the override the topic is unknown
+        let (client, _mock) = create_test_client(&[x.timestamp]);
+        let decoded = decode_event(log, &client, &mut lookups, &LogDecoder::default()).await;
+        assert!(matches!(decoded, Err(MonitorError::Decode(_))));
+
+        let options = MonitorOptions {
+            pool_version: PoolVersion::V2,
+            ..options
+        };
+        assert_eq!(options.event_signatures().unwrap(), vec![FORK_SWAP_EVENT]);
+    }
+
+    #[test]
+    fn test_price_from_sqrt_price() {
+        let x = create_test_transaction_vals();
+        let sqrt_price = U256::from_dec_str(x.sqrt_price).unwrap();
+        let price = price_from_sqrt_price(sqrt_price, x.decimals0, x.decimals1);
+        assert!((price - x.price).abs() / x.price < 1e-12);
+
+        // The swap itself traded at roughly the pool price: 0.1624 WETH for 263.12 USDC
+        let execution_price = 0.162_381_65 / 263.12;
+        assert!((price - execution_price).abs() / execution_price < 1e-3);
+
+        // sqrtPriceX96 == 2^96 means a raw price of exactly 1
+        let one = U256::from(2).pow(U256::from(96));
+        assert_eq!(price_from_sqrt_price(one, 18, 18), 1.0);
+        assert_eq!(price_from_sqrt_price(one, 18, 6), 1e12);
+
+        // The largest possible uint160 value must not overflow
+        let max_sqrt_price = (U256::one() << 160) - 1;
+        assert!(price_from_sqrt_price(max_sqrt_price, 0, 0).is_finite());
+    }
+
+    #[test]
+    fn test_price_from_tick() {
+        let x = create_test_transaction_vals();
+        let price = price_from_tick(x.tick, x.decimals0, x.decimals1);
+        // The tick is the sqrt price rounded down to a 1.0001 step
+        assert!(price <= x.price);
+        assert!((price - x.price) / x.price > -1e-4);
+
+        // Negative ticks give the reciprocal price of the reversed pair
+        let reversed = price_from_tick(-x.tick, x.decimals1, x.decimals0);
+        assert!((reversed * price - 1.0).abs() < 1e-12);
+        assert_eq!(price_from_tick(0, 18, 18), 1.0);
+
+        // The extreme ticks of Uniswap V3 stay finite and positive
+        assert!(price_from_tick(887_272, 0, 0).is_finite());
+        assert!(price_from_tick(-887_272, 0, 0) > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_consume_events_stops_when_idle() {
+        let (sender, receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let task = tokio::spawn(async move {
+            sender
+                .send(Ok(create_test_combined_log().into()))
+                .await
+                .unwrap();
+            let mut other = create_test_combined_log();
+            other.sender = Address::from_low_u64_be(7);
+            sender.send(Ok(other.into())).await.unwrap();
+            // A quiet pool: the subscription stays open without further events
+            std::future::pending::<()>().await;
+        });
+        let mut events = EventStream { receiver, task };
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let options = MonitorOptions {
+            idle_timeout: Some(Duration::from_millis(50)),
+            only_sender: Some(create_test_combined_log().sender),
+            ..Default::default()
+        };
+
+        let mut batch = LogBatch::new(1);
+        let processed = tokio::time::timeout(
+            Duration::from_secs(5),
+            consume_events(
+                &mut events,
+                &mut store,
+                &mut batch,
+                &mut Outputs::default(),
+                &options,
+                None,
+                &mut PendingEvents::new(0),
+            ),
+        )
+        .await
+        .expect("consume_events kept waiting for events")
+        .unwrap();
+        // The swap of another sender is received but not counted
+        assert_eq!(processed, 1);
+        assert_eq!(count_rows(&store), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stores_received_events() {
+        let (sender, receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let (trigger, shutdown) = watch::channel(());
+        let task = tokio::spawn(async move {
+            for log_index in 0..5 {
+                let swap = CombinedLog {
+                    log_index: Some(log_index),
+                    ..create_test_combined_log()
+                };
+                sender.send(Ok(swap.into())).await.unwrap();
+            }
+            // Shut down while the swaps may still be buffered in the channel
+            trigger.send(()).unwrap();
+            std::future::pending::<()>().await;
+        });
+        let mut events = EventStream { receiver, task };
+        // The writer thread stores the batches after they are queued, so the
+        // rows are only there if `finish_writes` waits for it
+        let temp_dir = tempdir::TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let mut store =
+            BackgroundStore::spawn(Box::new(SqliteStore::open(db_path).unwrap())).unwrap();
+        let options = MonitorOptions {
+            shutdown: Some(shutdown),
+            batch: BatchConfig {
+                max_size: 100,
+                max_delay: Duration::from_secs(3600),
+            },
+            ..Default::default()
+        };
+        let mut batch = LogBatch::new(options.batch.max_size);
+        let mut outputs = Outputs::default();
+        let processed = tokio::time::timeout(
+            Duration::from_secs(5),
+            consume_events(
+                &mut events,
+                &mut store,
+                &mut batch,
+                &mut outputs,
+                &options,
+                None,
+                &mut PendingEvents::new(0),
+            ),
+        )
+        .await
+        .expect("consume_events did not shut down")
+        .unwrap();
+        assert_eq!(processed, 5);
+
+        finish_writes(&mut store, &mut batch, &mut outputs)
+            .await
+            .unwrap();
+        assert_eq!(count_rows(&SqliteStore::open(db_path).unwrap()), 5);
+    }
+
+    #[tokio::test]
+    async fn test_consume_events_waits_for_confirmations() {
+        let block = create_test_combined_log().block_number.unwrap();
+        let swap = |block_number: u64, log_index: u64| CombinedLog {
+            block_number: Some(block_number),
+            log_index: Some(log_index),
+            ..create_test_combined_log()
+        };
+        let (sender, receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let task = tokio::spawn(async move {
+            let orphaned = swap(block, 1);
+            for event in [
+                swap(block, 0),
+                orphaned.clone(),
+                CombinedLog {
+                    removed: true,
+                    ..orphaned
+                },
+                // Past the last block, it only confirms the earlier blocks
+                swap(block + 1, 0),
+            ] {
+                sender.send(Ok(event.into())).await.unwrap();
+            }
+        });
+        let mut events = EventStream { receiver, task };
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let options = MonitorOptions {
+            confirmations: 2,
+            until_block: Some(block),
+            ..Default::default()
+        };
+        let mut batch = LogBatch::new(1);
+        let mut pending = PendingEvents::new(options.confirmations);
+        let processed = consume_events(
+            &mut events,
+            &mut store,
+            &mut batch,
+            &mut Outputs::default(),
+            &options,
+            None,
+            &mut pending,
+        )
+        .await
+        .unwrap();
+        // The stream ending means the chain covered the confirmations of the last block
+        assert_eq!(processed, 1);
+        assert!(pending.is_empty());
+        let stored: Vec<Option<u64>> = store
+            .connection()
+            .prepare("SELECT log_index FROM logs")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(stored, vec![Some(0)]);
+    }
+
+    #[tokio::test]
+    async fn test_swap_stream_close_drains_buffered_swaps() {
+        let (sender, receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let task = tokio::spawn(async move {
+            let mint = events::decode_mint(&create_test_mint_log(), None).unwrap();
+            sender.send(Ok(PoolEvent::Mint(mint))).await.unwrap();
+            sender
+                .send(Ok(create_test_combined_log().into()))
+                .await
+                .unwrap();
+            sender
+                .send(Ok(create_test_combined_log().into()))
+                .await
+                .unwrap();
+            // Keep the subscription open until it is aborted
+            std::future::pending::<()>().await;
+        });
+        // Events other than swaps are skipped
+        let mut swaps = SwapStream {
+            events: EventStream { receiver, task },
+        };
+
+        let first = swaps.next().await.unwrap().unwrap();
+        assert_eq!(first, create_test_combined_log());
+        tokio::task::yield_now().await;
+
+        swaps.close();
+        assert!(swaps.next().await.unwrap().is_ok());
+        assert!(swaps.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_connect_to_provider() {
+        let provider_ws = "wss://mainnet.infura.io/ws/v3/befb17eb176e41ceb879a05778423030";
+        let result =
+            connect_to_provider(&[provider_ws.to_string()], &ReconnectPolicy::default()).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connect_to_unresponsive_provider_times_out() {
+        // The listener takes connections but never answers the handshake
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let provider_ws = format!("ws://{}", listener.local_addr().unwrap());
+        let reconnect = ReconnectPolicy {
+            connect_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let result = connect_to_provider(&[provider_ws], &reconnect).await;
+        let Err(MonitorError::ProviderConnect(message)) = result else {
+            panic!("expected a timeout");
+        };
+        assert!(message.contains("timed out"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn test_connect_to_provider_sends_authorization() {
+        use tokio::io::AsyncReadExt;
+
+        // The listener reads the handshake request and never answers it
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let provider_ws = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(stream.read_u8().await.unwrap());
+            }
+            String::from_utf8(request).unwrap().to_lowercase()
+        });
+
+        let reconnect = ReconnectPolicy {
+            connect_timeout: Duration::from_millis(200),
+            authorization: Some(Authorization::bearer("secret-token")),
+            ..Default::default()
+        };
+        assert!(connect_to_provider(&[provider_ws], &reconnect)
+            .await
+            .is_err());
+        let request = server.await.unwrap();
+        assert!(
+            request.contains("\r\nauthorization: bearer secret-token\r\n"),
+            "{request}"
+        );
+    }
+
+    #[test]
+    fn test_validate_authorization() {
+        assert!(validate_authorization(&Authorization::bearer("abc.def-123")).is_ok());
+        assert!(validate_authorization(&Authorization::basic("user", "password")).is_ok());
+        assert!(validate_authorization(&Authorization::raw("Token abc")).is_ok());
+
+        for invalid in [
+            Authorization::bearer(""),
+            Authorization::bearer("abc def"),
+            Authorization::bearer("abc\n"),
+            Authorization::raw(" Token abc"),
+            Authorization::raw("Token abc\r\nX-Injected: 1"),
+        ] {
+            let err = validate_authorization(&invalid).unwrap_err();
+            assert!(matches!(err, MonitorError::InvalidConfig(_)));
+            assert!(!err.to_string().contains("abc"), "{err}");
+        }
+
+        let reconnect = ReconnectPolicy {
+            authorization: Some(Authorization::bearer("")),
+            ..Default::default()
+        };
+        let urls = ["wss://a.example".to_string()];
+        assert!(validate_connection(&urls, &reconnect).is_err());
+        assert!(validate_connection(&urls, &ReconnectPolicy::default()).is_ok());
+    }
+
+    #[test]
+    fn test_create_pool_filter() {
+        let x = create_test_transaction_vals();
+        assert!(create_pool_filter(
+            &[x.pool.to_string(), x.sender.to_string()],
+            &[SWAP_EVENT],
+            None,
+            None
+        )
+        .is_ok());
+
+        let err = create_pool_filter(
+            &[x.pool.to_string(), "0xzz".to_string()],
+            &[SWAP_EVENT],
+            None,
+            None,
+        )
+        .unwrap_err();
+        assert!(matches!(err, MonitorError::InvalidAddress(_)));
+        assert!(err.to_string().starts_with("invalid pool address '0xzz'"));
+        assert!(create_pool_filter(&[], &[SWAP_EVENT], None, None).is_err());
+
+        let v3_events = [SWAP_EVENT, PANCAKE_SWAP_EVENT, MINT_EVENT, BURN_EVENT];
+        let filter = create_pool_filter(&[x.pool.to_string()], &v3_events, None, None).unwrap();
+        let topics = filter.topics[0].clone().unwrap();
+        assert_eq!(
+            topics,
+            ValueOrArray::Array(
+                v3_events
+                    .iter()
+                    .map(|event| Some(event_topic(event)))
+                    .collect()
+            )
+        );
+        // Without a range the filter suits the live subscription
+        assert_eq!(filter.get_from_block(), None);
+        assert_eq!(filter.get_to_block(), None);
+
+        let filter =
+            create_pool_filter(&[x.pool.to_string()], &[SWAP_EVENT], Some(100), Some(200)).unwrap();
+        assert_eq!(filter.get_from_block(), Some(U64::from(100)));
+        assert_eq!(filter.get_to_block(), Some(U64::from(200)));
+        let filter =
+            create_pool_filter(&[x.pool.to_string()], &[SWAP_EVENT], Some(100), None).unwrap();
+        assert_eq!(filter.get_from_block(), Some(U64::from(100)));
+        assert_eq!(filter.get_to_block(), None);
+    }
+
+    #[test]
+    fn test_validate_provider_url() {
+        assert!(validate_provider_url("wss://eth-mainnet.g.alchemy.com/v2/key").is_ok());
+        assert!(validate_provider_url("ws://localhost:8546").is_ok());
+        assert!(validate_provider_url("https://mainnet.infura.io/v3/key").is_ok());
+        assert!(validate_provider_url("http://localhost:8545").is_ok());
+        assert!(validate_provider_url("localhost:8546").is_err());
+        assert!(validate_provider_url("ipc:///tmp/geth.ipc").is_err());
+
+        let urls = |urls: &[&str]| urls.iter().map(|url| url.to_string()).collect::<Vec<_>>();
+        assert!(validate_provider_urls(&urls(&["wss://a.example", "ws://localhost:8546"])).is_ok());
+        assert!(validate_provider_urls(&urls(&["https://a.example", "wss://b.example"])).is_err());
+        assert!(validate_provider_urls(&urls(&["https://a.example", "localhost:8545"])).is_err());
+        assert!(validate_provider_urls(&[]).is_err());
+        assert_eq!(
+            provider_host("wss://mainnet.infura.io/ws/v3/secret"),
+            "mainnet.infura.io"
+        );
+        assert_eq!(provider_host("http://localhost:8545"), "localhost:8545");
+    }
+
+    #[tokio::test]
+    async fn test_poll_logs() {
+        let x = create_test_transaction_vals();
+        let pool_filter =
+            create_pool_filter(&[x.pool.to_string()], &[SWAP_EVENT], None, None).unwrap();
+        let (client, mock) = Provider::mocked();
+        // Popped from the back: block number, logs since that block, then the
+        // timestamp of the swap's block. The next poll finds no responses left.
+        mock.push(Block::<H256> {
+            timestamp: x.timestamp.into(),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push::<Vec<Log>, _>(vec![create_test_log()]).unwrap();
+        mock.push(U64::from(x.block_number)).unwrap();
+
+        let reconnect = ReconnectPolicy {
+            max_consecutive_failures: 0,
+            ..Default::default()
+        };
+        let (sender, mut receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        poll_logs(
+            vec![("http://localhost:8545".to_string(), client)],
+            &pool_filter,
+            &LogDecoder::default(),
+            Duration::from_millis(1),
+            &reconnect,
+            None,
+            sender,
+        )
+        .await;
+
+        let Ok(PoolEvent::Swap(swap)) = receiver.recv().await.unwrap() else {
+            panic!("expected a swap");
+        };
+        assert_eq!(swap.tx_hash, H256::from_str(x.tx_hash).unwrap());
+        assert_eq!(swap.timestamp, Some(x.timestamp));
+        // Once the provider stops answering, the poller gives up with an error
+        assert!(receiver.recv().await.unwrap().is_err());
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handle_logs_stores_replayed_logs() {
+        let x = create_test_transaction_vals();
+        let pool_filter =
+            create_pool_filter(&[x.pool.to_string()], &[SWAP_EVENT], None, None).unwrap();
+        let (client, mock) = Provider::mocked();
+        // Both logs are of the same block, so its timestamp is fetched once
+        mock.push(Block::<H256> {
+            timestamp: x.timestamp.into(),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut second = create_test_log();
+        second.transaction_hash = Some(H256::from_low_u64_be(1));
+        let source = Arc::new(MockLogSource {
+            client,
+            logs: vec![create_test_log(), second],
+            heads: vec![],
+        });
+
+        let reconnect = ReconnectPolicy {
+            max_consecutive_failures: 0,
+            ..Default::default()
+        };
+        let (sender, receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let task = tokio::spawn(async move {
+            handle_logs(
+                &["ws://localhost:8546".to_string()],
+                |_, _| future::ready(Ok::<_, MonitorError>(source.clone())),
+                &pool_filter,
+                &LogDecoder::default(),
+                &reconnect,
+                LiveOptions::default(),
+                sender,
+            )
+            .await;
+        });
+        let mut events = EventStream { receiver, task };
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let mut batch = LogBatch::new(100);
+        let result = consume_events(
+            &mut events,
+            &mut store,
+            &mut batch,
+            &mut Outputs::default(),
+            &MonitorOptions::default(),
+            None,
+            &mut PendingEvents::new(0),
+        )
+        .await;
+        // The replay ends like a closed subscription, which exhausts the policy
+        assert!(result.is_err());
+        batch.flush(&mut store).await.unwrap();
+
+        assert_eq!(count_rows(&store), 2);
+        let timestamp: i64 = store
+            .connection()
+            .query_row(
+                "SELECT timestamp FROM logs WHERE tx_hash = ?1",
+                [format!("{:?}", H256::from_low_u64_be(1))],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(timestamp as u64, x.timestamp);
+    }
+
+    #[test]
+    fn test_decode_failure_reason() {
+        let decoder = LogDecoder::default();
+        let log = create_test_log();
+        assert_eq!(decoder.failure_reason(&log.topics), "invalid_data");
+        assert_eq!(decoder.failure_reason(&log.topics[..2]), "missing_topics");
+        assert_eq!(decoder.failure_reason(&[]), "unknown_event");
+        let mint_topics = [event_topic(MINT_EVENT), H256::zero(), H256::zero()];
+        assert_eq!(decoder.failure_reason(&mint_topics), "missing_topics");
+
+        assert_eq!(truncated_hex(&[0xde, 0xad], 4), "0xdead");
+        assert_eq!(truncated_hex(&[0xde, 0xad, 0xbe, 0xef], 2), "0xdead...");
+    }
+
+    #[tokio::test]
+    async fn test_poll_logs_skips_undecodable_log() {
+        let x = create_test_transaction_vals();
+        let pool_filter =
+            create_pool_filter(&[x.pool.to_string()], &[SWAP_EVENT], None, None).unwrap();
+        let mut truncated = create_test_log();
+        truncated.transaction_hash = Some(H256::from_low_u64_be(1));
+        truncated.data = truncated.data[..40].to_vec().into();
+        let (client, mock) = Provider::mocked();
+        // The truncated log fails before its timestamp is fetched
+        mock.push(Block::<H256> {
+            timestamp: x.timestamp.into(),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push::<Vec<Log>, _>(vec![truncated, create_test_log()])
+            .unwrap();
+        mock.push(U64::from(x.block_number)).unwrap();
+
+        let reconnect = ReconnectPolicy {
+            max_consecutive_failures: 0,
+            ..Default::default()
+        };
+        let (sender, mut receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        poll_logs(
+            vec![("http://localhost:8545".to_string(), client)],
+            &pool_filter,
+            &LogDecoder::default(),
+            Duration::from_millis(1),
+            &reconnect,
+            None,
+            sender,
+        )
+        .await;
+
+        let Ok(PoolEvent::Swap(swap)) = receiver.recv().await.unwrap() else {
+            panic!("expected the swap after the malformed log");
+        };
+        assert_eq!(swap.tx_hash, H256::from_str(x.tx_hash).unwrap());
+        // The only error is the poller giving up once the provider stops answering
+        assert!(matches!(
+            receiver.recv().await.unwrap(),
+            Err(MonitorError::ProviderConnect(_))
+        ));
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_poll_logs_stops_after_until_block() {
+        let x = create_test_transaction_vals();
+        let pool_filter =
+            create_pool_filter(&[x.pool.to_string()], &[SWAP_EVENT], None, None).unwrap();
+        let (client, mock) = Provider::mocked();
+        // Popped from the back: the first poll starts one block before the swap,
+        // the second sees a head past the last block and stops at that block
+        mock.push(Block::<H256> {
+            timestamp: x.timestamp.into(),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push::<Vec<Log>, _>(vec![create_test_log()]).unwrap();
+        mock.push(U64::from(x.block_number + 5)).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(U64::from(x.block_number - 1)).unwrap();
+
+        let (sender, mut receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        poll_logs(
+            vec![("http://localhost:8545".to_string(), client)],
+            &pool_filter,
+            &LogDecoder::default(),
+            Duration::from_millis(1),
+            &ReconnectPolicy::default(),
+            Some(x.block_number),
+            sender,
+        )
+        .await;
+
+        let Ok(PoolEvent::Swap(swap)) = receiver.recv().await.unwrap() else {
+            panic!("expected a swap");
+        };
+        assert_eq!(swap.block_number, Some(x.block_number));
+        // The poller returned cleanly instead of polling past the block
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_finish_block_forwards_rest_of_block() {
+        let x = create_test_transaction_vals();
+        let pool_filter =
+            create_pool_filter(&[x.pool.to_string()], &[SWAP_EVENT], None, None).unwrap();
+        let log_at = |log_index: u64| Log {
+            log_index: Some(log_index.into()),
+            ..create_test_log()
+        };
+        let (client, mock) = create_test_client(&[x.timestamp]);
+        // The subscription already forwarded log 41 and delivered log 42
+        mock.push::<Vec<Log>, _>(vec![log_at(41), log_at(42), log_at(43)])
+            .unwrap();
+
+        let (sender, mut receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        finish_block(
+            &client,
+            &pool_filter,
+            x.block_number,
+            &log_at(42),
+            &LogDecoder::default(),
+            &sender,
+        )
+        .await;
+        drop(sender);
+
+        let mut log_indexes = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            log_indexes.push(event.unwrap().log_index());
+        }
+        assert_eq!(log_indexes, vec![Some(42), Some(43)]);
+
+        // A log past the block means the block was already complete
+        let (sender, mut receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let mut later = create_test_log();
+        later.block_number = Some((x.block_number + 1).into());
+        finish_block(
+            &client,
+            &pool_filter,
+            x.block_number,
+            &later,
+            &LogDecoder::default(),
+            &sender,
+        )
+        .await;
+        drop(sender);
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_process_head_covers_every_block() {
+        let x = create_test_transaction_vals();
+        let pool_filter =
+            create_pool_filter(&[x.pool.to_string()], &[SWAP_EVENT], None, None).unwrap();
+        let (client, mock) = Provider::mocked();
+        // Popped from the back, one `get_logs` response per head plus the
+        // timestamp of the swap's block
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(Block::<H256> {
+            timestamp: x.timestamp.into(),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push::<Vec<Log>, _>(vec![create_test_log()]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+
+        let (sender, mut receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let mut next_block = None;
+        let block = x.block_number;
+        let decoder = LogDecoder::default();
+        // An empty block, then a head two blocks later covering the skipped ones,
+        // a reorged head that is fetched again and a head past the last block
+        for (head, until_block, breaks) in [
+            (block - 10, None, false),
+            (block + 2, None, false),
+            (block + 1, None, false),
+            (block + 10, Some(block + 3), true),
+        ] {
+            let flow = process_head(
+                &client,
+                &pool_filter,
+                head,
+                &mut next_block,
+                until_block,
+                &decoder,
+                &sender,
+            )
+            .await
+            .unwrap();
+            assert_eq!(flow.is_break(), breaks, "head {head}");
+        }
+
+        let Ok(PoolEvent::Swap(swap)) = receiver.try_recv().unwrap() else {
+            panic!("expected a swap");
+        };
+        assert_eq!(swap.timestamp, Some(x.timestamp));
+        assert!(receiver.try_recv().is_err());
+
+        let get_logs = |from_block: u64, to_block: u64| {
+            mock.assert_request(
+                "eth_getLogs",
+                [pool_filter
+                    .clone()
+                    .from_block(from_block)
+                    .to_block(to_block)],
+            )
+            .unwrap();
+        };
+        get_logs(block - 10, block - 10);
+        get_logs(block - 9, block + 2);
+        mock.assert_request(
+            "eth_getBlockByNumber",
+            (ethers::types::BlockNumber::Number(block.into()), false),
+        )
+        .unwrap();
+        get_logs(block + 1, block + 1);
+        get_logs(block + 2, block + 3);
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            max_consecutive_failures: 5,
+            failover_after: 2,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            authorization: None,
+            jitter: 0.5,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2), Duration::from_secs(2));
+        assert_eq!(policy.backoff(4), Duration::from_secs(8));
+        assert_eq!(policy.backoff(5), Duration::from_secs(10));
+        assert_eq!(policy.backoff(100), Duration::from_secs(10));
+
+        // Every second failure in a row moves on, wrapping around
+        assert_eq!(policy.next_provider(0, 2, 1), 0);
+        assert_eq!(policy.next_provider(0, 2, 2), 1);
+        assert_eq!(policy.next_provider(1, 2, 4), 0);
+        assert_eq!(policy.next_provider(0, 1, 2), 0);
+
+        // Jitter shortens a delay by up to half of it
+        let delay = Duration::from_secs(10);
+        for _ in 0..100 {
+            let jittered = policy.jittered_backoff(5);
+            assert!(jittered >= Duration::from_secs(5) && jittered <= delay);
+        }
+        let steady = ReconnectPolicy {
+            jitter: 0.0,
+            ..policy.clone()
+        };
+        assert_eq!(steady.jittered_backoff(5), delay);
+
+        let invalid = ReconnectPolicy {
+            jitter: 1.5,
+            ..policy
+        };
+        let err = validate_connection(&["wss://one.example".to_string()], &invalid).unwrap_err();
+        assert!(matches!(err, MonitorError::InvalidConfig(_)));
+    }
+
+    #[tokio::test]
+    async fn test_poll_logs_fails_over_to_next_provider() {
+        let x = create_test_transaction_vals();
+        let pool_filter =
+            create_pool_filter(&[x.pool.to_string()], &[SWAP_EVENT], None, None).unwrap();
+        // The first provider has no responses, so every poll of it fails
+        let (down, _down_mock) = Provider::mocked();
+        let (up, up_mock) = Provider::mocked();
+        up_mock
+            .push(Block::<H256> {
+                timestamp: x.timestamp.into(),
+                ..Default::default()
+            })
+            .unwrap();
+        up_mock
+            .push::<Vec<Log>, _>(vec![create_test_log()])
+            .unwrap();
+        up_mock.push(U64::from(x.block_number)).unwrap();
+
+        let reconnect = ReconnectPolicy {
+            initial_delay: Duration::from_millis(1),
+            max_consecutive_failures: 1,
+            failover_after: 1,
+            ..Default::default()
+        };
+        let (sender, mut receiver) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        poll_logs(
+            vec![
+                ("http://down.example".to_string(), down),
+                ("http://up.example".to_string(), up),
+            ],
+            &pool_filter,
+            &LogDecoder::default(),
+            Duration::from_millis(1),
+            &reconnect,
+            None,
+            sender,
+        )
+        .await;
+
+        let Ok(PoolEvent::Swap(swap)) = receiver.recv().await.unwrap() else {
+            panic!("expected a swap from the second provider");
+        };
+        assert_eq!(swap.tx_hash, H256::from_str(x.tx_hash).unwrap());
+        // Both providers then fail in a row, which exhausts the policy
+        assert!(receiver.recv().await.unwrap().is_err());
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_swap_direction() {
+        let amount = |value: i64| I256::from(value);
+        assert_eq!(
+            SwapDirection::from_amounts(amount(5), amount(-3)),
+            Some(SwapDirection::ZeroForOne)
+        );
+        assert_eq!(
+            SwapDirection::from_amounts(amount(-5), amount(3)),
+            Some(SwapDirection::OneForZero)
+        );
+        assert_eq!(SwapDirection::from_amounts(amount(5), amount(3)), None);
+        assert_eq!(SwapDirection::from_amounts(amount(0), amount(-3)), None);
+        for direction in [SwapDirection::ZeroForOne, SwapDirection::OneForZero] {
+            assert_eq!(
+                direction.as_str().parse::<SwapDirection>().unwrap(),
+                direction
+            );
+        }
+        assert!("sell".parse::<SwapDirection>().is_err());
+
+        // The test swap pays WETH in and takes USDC out
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let mut batch = LogBatch::new(1);
+        let mut same_sign = create_test_combined_log();
+        same_sign.data.amount0 = amount(1);
+        same_sign.log_index = Some(5);
+        for swap in [create_test_combined_log(), same_sign] {
+            process_log(
+                swap.into(),
+                &mut store,
+                &mut batch,
+                &mut Outputs::default(),
+                &MonitorOptions::default(),
+            )
+            .await
+            .unwrap();
+        }
+        let directions = store
+            .connection()
+            .prepare("SELECT direction FROM logs ORDER BY rowid")
+            .unwrap()
+            .query_map([], |row| row.get::<_, Option<String>>(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(directions, vec![Some("token1->token0".to_string()), None]);
+    }
+
+    #[test]
+    fn test_abs_u256() {
+        assert_eq!(abs_u256(I256::from(-5)), U256::from(5));
+        assert_eq!(abs_u256(I256::from(5)), U256::from(5));
+        assert_eq!(abs_u256(I256::MIN), U256::one() << 255);
+        assert_eq!(abs_u256(I256::MAX), (U256::one() << 255) - 1);
+
+        // Filtering a swap of I256::MIN compares its magnitude instead of panicking
+        let filter = SwapFilter {
+            min_abs_amount0: Some(U256::one() << 255),
+            min_abs_amount1: None,
+        };
+        let mut data = create_test_combined_log().data;
+        data.amount0 = I256::MIN;
+        assert!(filter.matches(&data));
+        data.amount0 = I256::MIN + I256::one();
+        assert!(!filter.matches(&data));
+    }
+
+    #[test]
+    fn test_try_to_i128() {
+        for amount in [0, -1, i128::MAX, i128::MIN] {
+            assert_eq!(try_to_i128(I256::from(amount)), Some(amount));
+        }
+        assert_eq!(try_to_i128(I256::from(i128::MAX) + I256::one()), None);
+        assert_eq!(try_to_i128(I256::from(i128::MIN) - I256::one()), None);
+        assert_eq!(try_to_i128(I256::MAX), None);
+        assert_eq!(try_to_i128(I256::MIN), None);
+
+        assert_eq!(try_to_u128(U256::from(u128::MAX)), Some(u128::MAX));
+        assert_eq!(try_to_u128(U256::from(u128::MAX) + 1), None);
+        assert_eq!(try_to_u128(U256::MAX), None);
+    }
+
+    #[test]
+    fn test_to_token_units() {
+        assert_eq!(to_token_units(I256::from(-263_120_000i64), 6), -263.12);
+        assert_eq!(to_token_units(I256::from(5), 0), 5.0);
+        assert_eq!(to_token_units(I256::zero(), 18), 0.0);
+
+        // The extremes of I256 must neither panic nor overflow
+        let min = to_token_units(I256::MIN, 0);
+        assert_eq!(min, -(2f64.powi(255)));
+        assert!(to_token_units(I256::MAX, 0).is_finite());
+        assert!(to_token_units(I256::MAX, u8::MAX) > 0.0);
+    }
+
+    #[test]
+    fn test_to_token_units_decimal() {
+        let units = to_token_units_decimal;
+        assert_eq!(units(I256::from(-263_120_000i64), 6), "-263.12");
+        assert_eq!(units(I256::from(5), 0), "5");
+        assert_eq!(units(I256::zero(), 18), "0");
+        assert_eq!(units(I256::from(-5), 18), "-0.000000000000000005");
+        assert_eq!(units(I256::from(1_000_000_000_000_000_000i64), 18), "1");
+        // Amounts past the precision of f64 keep every digit
+        let amount = I256::from_dec_str("123456789012345678901234567890").unwrap();
+        assert_eq!(units(amount, 24), "123456.78901234567890123456789");
+        assert_eq!(units(-amount, 30), "-0.12345678901234567890123456789");
+
+        assert_eq!(
+            units(I256::MIN, 0),
+            "-57896044618658097711785492504343953926634992332820282019728792003956564819968"
+        );
+        let tiny = units(I256::one(), u8::MAX);
+        assert_eq!(tiny.len(), 2 + u8::MAX as usize);
+        assert!(tiny.starts_with("0.000") && tiny.ends_with("01"));
+    }
+
+    #[tokio::test]
+    async fn test_process_log() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+
+        // Create a test log
+        let test_log = create_test_log();
+
+        // Process the test log
+        let expected = create_test_transaction_vals();
+        let (client, _mock) = create_test_client(&[expected.timestamp]);
+        let mut lookups = LookupCache::default();
+        let token_decimals = HashMap::from([(
+            test_log.address,
+            TokenDecimals {
+                decimals0: expected.decimals0,
+                decimals1: expected.decimals1,
+            },
+        )]);
+        let combined_log = decode_log(test_log, &client, &mut lookups, &token_decimals)
+            .await
+            .unwrap();
+        let options = MonitorOptions {
+            token_decimals,
+            ..Default::default()
+        };
+        let mut batch = LogBatch::new(1);
+        let result = process_log(
+            combined_log.into(),
+            &mut store,
+            &mut batch,
+            &mut Outputs::default(),
+            &options,
+        )
+        .await;
+        assert!(result.is_ok());
+        assert!(batch.logs.is_empty());
+
+        // Retrieve the inserted log data from the database
+        let mut stmt = store
+            .connection()
+            .prepare(
+                "SELECT logs.*, sender.address AS sender_address, receiver.address AS receiver_address
+                 FROM logs
+                 JOIN addresses sender ON sender.id = logs.sender_id
+                 JOIN addresses receiver ON receiver.id = logs.receiver_id",
+            )
+            .expect("Failed to prepare SQL statement");
+        let row = stmt
+            .query_row([], |row| {
+                Ok((
+                    row.get::<_, String>("tx_hash"),
+                    row.get::<_, String>("pool_address"),
+                    row.get::<_, String>("sender_address"),
+                    row.get::<_, String>("receiver_address"),
+                    row.get::<_, String>("amount0"),
+                    row.get::<_, String>("amount1"),
+                    row.get::<_, String>("sqrt_price"),
                     row.get::<_, String>("liquidity"),
                     row.get::<_, i32>("tick"),
+                    row.get::<_, u64>("block_number"),
+                    row.get::<_, String>("block_hash"),
+                    row.get::<_, u64>("timestamp"),
+                    row.get::<_, f64>("price"),
+                    row.get::<_, f64>("amount0_normalized"),
+                    row.get::<_, f64>("amount1_normalized"),
+                    row.get::<_, f64>("tick_price"),
                 ))
             })
             .expect("Failed to execute SQL query");
 
         // Check if the retrieved values match the expected values
-        let expected = create_test_transaction_vals();
         assert_eq!(row.0.unwrap(), expected.tx_hash);
-        assert_eq!(row.1.unwrap(), expected.sender);
-        assert_eq!(row.2.unwrap(), expected.receiver);
-        assert_eq!(row.3.unwrap(), expected.ammount0);
-        assert_eq!(row.4.unwrap(), expected.ammount1);
-        assert_eq!(row.5.unwrap(), expected.sqrt_price);
-        assert_eq!(row.6.unwrap(), expected.liquidity);
-        assert_eq!(row.7.unwrap(), expected.tick);
+        assert_eq!(row.1.unwrap(), expected.pool);
+        assert_eq!(row.2.unwrap(), expected.sender);
+        assert_eq!(row.3.unwrap(), expected.receiver);
+        assert_eq!(row.4.unwrap(), expected.ammount0);
+        assert_eq!(row.5.unwrap(), expected.ammount1);
+        assert_eq!(row.6.unwrap(), expected.sqrt_price);
+        assert_eq!(row.7.unwrap(), expected.liquidity);
+        assert_eq!(row.8.unwrap(), expected.tick);
+        assert_eq!(row.9.unwrap(), expected.block_number);
+        assert_eq!(row.10.unwrap(), expected.block_hash);
+        assert_eq!(row.11.unwrap(), expected.timestamp);
+        assert!((row.12.unwrap() - expected.price).abs() / expected.price < 1e-12);
+        assert_eq!(row.13.unwrap(), -263.12);
+        assert!((row.14.unwrap() - 0.162_381_653_432_074).abs() < 1e-15);
+        assert_eq!(
+            row.15.unwrap(),
+            price_from_tick(expected.tick, expected.decimals0, expected.decimals1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_pending_log() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+
+        let mut test_log = create_test_log();
+        test_log.block_number = None;
+        test_log.block_hash = None;
+        // No block is fetched for a pending log, so the mock has no responses queued
+        let (client, _mock) = create_test_client(&[]);
+        let mut lookups = LookupCache::default();
+        let combined_log = decode_log(test_log, &client, &mut lookups, &HashMap::new())
+            .await
+            .unwrap();
+        let mut batch = LogBatch::new(1);
+        process_log(
+            combined_log.into(),
+            &mut store,
+            &mut batch,
+            &mut Outputs::default(),
+            &MonitorOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        let (block_number, block_hash, timestamp, price): (
+            Option<u64>,
+            Option<String>,
+            Option<u64>,
+            Option<f64>,
+        ) = store
+            .connection()
+            .query_row(
+                "SELECT block_number, block_hash, timestamp, price FROM logs",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(block_number, None);
+        assert_eq!(block_hash, None);
+        assert_eq!(timestamp, None);
+        // Without known token decimals the price is left empty
+        assert_eq!(price, None);
+        // The data is kept as received for decoding it again
+        let raw_data: Vec<u8> = store
+            .connection()
+            .query_row("SELECT raw_data FROM logs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(raw_data, create_test_log().data.to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_log_batch_flushes_when_full() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        // Each swap needs its own log index to be stored as a separate row
+        let swaps: Vec<CombinedLog> = (0..4)
+            .map(|log_index| create_test_combined_log().with_log_index(Some(log_index), false))
+            .collect();
+
+        let mut batch = LogBatch::new(3);
+        let options = MonitorOptions::default();
+        let mut swaps = swaps.into_iter();
+        for _ in 0..2 {
+            let swap = swaps.next().unwrap();
+            process_log(
+                swap.into(),
+                &mut store,
+                &mut batch,
+                &mut Outputs::default(),
+                &options,
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(count_rows(&store), 0);
+
+        let swap = swaps.next().unwrap();
+        process_log(
+            swap.into(),
+            &mut store,
+            &mut batch,
+            &mut Outputs::default(),
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(count_rows(&store), 3);
+        assert!(batch.logs.is_empty());
+
+        // A partial batch is written by an explicit flush
+        let swap = swaps.next().unwrap();
+        process_log(
+            swap.into(),
+            &mut store,
+            &mut batch,
+            &mut Outputs::default(),
+            &options,
+        )
+        .await
+        .unwrap();
+        batch.flush(&mut store).await.unwrap();
+        assert_eq!(count_rows(&store), 4);
+    }
+
+    /// Fails the first `failures` batches, like a database that is locked for
+    /// a while.
+    pub(crate) struct FlakyStore {
+        pub(crate) store: SqliteStore,
+        pub(crate) failures: usize,
+        pub(crate) attempts: usize,
+    }
+
+    impl FlakyStore {
+        pub(crate) fn new(failures: usize) -> Self {
+            FlakyStore {
+                store: SqliteStore::open_in_memory().unwrap(),
+                failures,
+                attempts: 0,
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl SwapStore for FlakyStore {
+        async fn init(&mut self) -> Result<(), MonitorError> {
+            self.store.init().await
+        }
+
+        async fn insert(&mut self, event: &PoolEvent) -> Result<(), MonitorError> {
+            self.store.insert(event).await
+        }
+
+        async fn insert_batch(&mut self, events: &[PoolEvent]) -> Result<(), MonitorError> {
+            self.attempts += 1;
+            if self.attempts <= self.failures {
+                return Err(MonitorError::Database("database is locked".to_string()));
+            }
+            self.store.insert_batch(events).await
+        }
+
+        async fn delete(&mut self, event: &PoolEvent) -> Result<usize, MonitorError> {
+            self.store.delete(event).await
+        }
+
+        async fn sync(&mut self) -> Result<(), MonitorError> {
+            self.store.sync().await
+        }
+
+        async fn read_checkpoint(&mut self) -> Result<Option<u64>, MonitorError> {
+            self.store.read_checkpoint().await
+        }
+
+        async fn advance_checkpoint(&mut self, block_number: u64) -> Result<(), MonitorError> {
+            self.store.advance_checkpoint(block_number).await
+        }
+
+        async fn insert_pool(&mut self, metadata: &PoolMetadata) -> Result<(), MonitorError> {
+            self.store.insert_pool(metadata).await
+        }
+
+        async fn read_pool(&mut self, pool: Address) -> Result<Option<PoolMetadata>, MonitorError> {
+            self.store.read_pool(pool).await
+        }
+
+        async fn prune(&mut self, max_rows: u64) -> Result<usize, MonitorError> {
+            self.store.prune(max_rows).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_log_batch_circuit_breaker() {
+        let mut store = FlakyStore::new(5);
+        let swap = PoolEvent::from(create_test_combined_log());
+
+        // Without a circuit breaker the first failure is returned
+        let mut batch = LogBatch::new(10);
+        batch.logs.push(swap.clone());
+        assert!(batch.flush(&mut store).await.is_err());
+        assert_eq!(batch.logs.len(), 1);
+
+        // With one, the commit is retried, pausing after two failures, until
+        // the database accepts it
+        let (shutdown, shutdown_receiver) = watch::channel(());
+        let options = MonitorOptions {
+            circuit_breaker: Some(CircuitBreaker {
+                max_failures: 2,
+                retry_interval: Duration::from_millis(1),
+            }),
+            shutdown: Some(shutdown_receiver),
+            ..Default::default()
+        };
+        let mut batch = LogBatch::for_options(&options);
+        batch.logs.push(swap.clone());
+        batch.flush(&mut store).await.unwrap();
+        assert_eq!(store.attempts, 6);
+        assert_eq!(count_rows(&store.store), 1);
+        assert!(batch.logs.is_empty());
+
+        // A shutdown ends the pause with the error
+        store.failures = usize::MAX;
+        batch.logs.push(swap);
+        shutdown.send(()).unwrap();
+        let err = batch.flush(&mut store).await.unwrap_err();
+        assert!(err.to_string().contains("database is locked"), "{err}");
+        assert_eq!(batch.logs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_log_batch_circuit_breaker_in_background() {
+        // The batch is only queued, so its failures show when the store syncs
+        let mut store = BackgroundStore::spawn(Box::new(FlakyStore::new(3))).unwrap();
+        let options = MonitorOptions {
+            circuit_breaker: Some(CircuitBreaker {
+                max_failures: 2,
+                retry_interval: Duration::from_millis(1),
+            }),
+            ..Default::default()
+        };
+        let mut batch = LogBatch::for_options(&options);
+        let swap = PoolEvent::from(create_test_combined_log());
+        batch.logs.push(swap.clone());
+        batch.flush(&mut store).await.unwrap();
+        batch.sync(&mut store).await.unwrap();
+
+        // The failed batch was kept and written once the store recovered
+        assert_eq!(
+            store.read_checkpoint().await.unwrap(),
+            create_test_combined_log().block_number
+        );
+        assert_eq!(store.delete(&swap).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_halves_chunk_on_too_many_results() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let x = create_test_transaction_vals();
+        let pool_filter =
+            create_pool_filter(&[x.pool.to_string()], &[SWAP_EVENT], None, None).unwrap();
+
+        let (client, mock) = Provider::mocked();
+        // Responses are popped from the back, so they are pushed in reverse order:
+        // blocks 2000..=2999, the block of the swap, 1000..=1999, 0..=999, then the
+        // rejected 0..=1999 request. Both halves are fetched before the chunk is stored.
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(Block::<H256> {
+            timestamp: x.timestamp.into(),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![create_test_log()]).unwrap();
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: -32005,
+            message: "query returned more than 10000 results".to_string(),
+            data: None,
+        }));
+
+        let options = MonitorOptions::default();
+        let (processed, _) = backfill(
+            &client,
+            &mut store,
+            &mut Outputs::default(),
+            &pool_filter,
+            0,
+            2999,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(processed, 1);
+
+        let rows: i64 = store
+            .connection()
+            .query_row("SELECT COUNT(*) FROM logs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 1);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_stores_concurrent_chunks_in_order() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let x = create_test_transaction_vals();
+        let pool_filter =
+            create_pool_filter(&[x.pool.to_string()], &[SWAP_EVENT], None, None).unwrap();
+        let log_at = |block_number: u64, log_index: u64| Log {
+            block_number: Some(block_number.into()),
+            log_index: Some(log_index.into()),
+            ..create_test_log()
+        };
+
+        let (client, mock) = Provider::mocked();
+        let block = Block::<H256> {
+            timestamp: x.timestamp.into(),
+            ..Default::default()
+        };
+        // The mock answers each request as soon as it is polled, so the chunks
+        // of blocks 0..=9, 10..=19 and 20..=29 are each fetched and stored in
+        // turn. Responses are pushed in reverse order.
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(block.clone()).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log_at(15, 1)]).unwrap();
+        mock.push(block).unwrap();
+        mock.push::<Vec<Log>, _>(vec![log_at(5, 0)]).unwrap();
+
+        let options = MonitorOptions {
+            backfill: BackfillConfig {
+                chunk_size: 10,
+                concurrency: 2,
+            },
+            ..Default::default()
+        };
+        let (processed, _) = backfill(
+            &client,
+            &mut store,
+            &mut Outputs::default(),
+            &pool_filter,
+            0,
+            29,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(processed, 2);
+
+        let blocks: Vec<i64> = store
+            .connection()
+            .prepare("SELECT block_number FROM logs ORDER BY rowid")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(blocks, vec![5, 15]);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_stores_shuffled_logs_in_order() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let x = create_test_transaction_vals();
+        let pool_filter =
+            create_pool_filter(&[x.pool.to_string()], &[SWAP_EVENT], None, None).unwrap();
+        let log_at = |block_number: u64, log_index: u64| Log {
+            block_number: Some(block_number.into()),
+            log_index: Some(log_index.into()),
+            ..create_test_log()
+        };
+
+        let (client, mock) = Provider::mocked();
+        let block = Block::<H256> {
+            timestamp: x.timestamp.into(),
+            ..Default::default()
+        };
+        // Responses are pushed in reverse order: the logs, then a block for
+        // each change of block number
+        for _ in 0..3 {
+            mock.push(block.clone()).unwrap();
+        }
+        mock.push::<Vec<Log>, _>(vec![
+            log_at(12, 0),
+            log_at(10, 4),
+            log_at(10, 1),
+            log_at(11, 3),
+        ])
+        .unwrap();
+
+        let (processed, _) = backfill(
+            &client,
+            &mut store,
+            &mut Outputs::default(),
+            &pool_filter,
+            0,
+            99,
+            &MonitorOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(processed, 4);
+
+        let stored: Vec<(i64, i64)> = store
+            .connection()
+            .prepare("SELECT block_number, log_index FROM logs ORDER BY rowid")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(stored, vec![(10, 1), (10, 4), (11, 3), (12, 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_range_stops_at_to_block() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let x = create_test_transaction_vals();
+        let pools = [x.pool.to_string()];
+
+        // An explicit end block is used as is, without asking for the head
+        let (client, mock) = create_test_client(&[x.timestamp]);
+        mock.push::<Vec<Log>, _>(vec![create_test_log()]).unwrap();
+        let options = MonitorOptions::default();
+        let to_block = x.block_number + 10;
+        let (last, count, _) = backfill_range(
+            &client,
+            &mut store,
+            &mut Outputs::default(),
+            &pools,
+            x.block_number,
+            Some(to_block),
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!((last, count), (to_block, 1));
+        assert_eq!(count_rows(&store), 1);
+
+        // Without one, the range ends at the head
+        let (client, mock) = Provider::mocked();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(U64::from(to_block + 5)).unwrap();
+        let (last, count, _) = backfill_range(
+            &client,
+            &mut store,
+            &mut Outputs::default(),
+            &pools,
+            to_block + 1,
+            None,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!((last, count), (to_block + 5, 0));
+
+        // Events of the blocks within the confirmations of the head are left pending
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let (client, mock) = create_test_client(&[x.timestamp, x.timestamp]);
+        let mut recent = create_test_log();
+        recent.block_number = Some((x.block_number + 2).into());
+        recent.log_index = Some(0.into());
+        mock.push::<Vec<Log>, _>(vec![create_test_log(), recent])
+            .unwrap();
+        mock.push(U64::from(x.block_number + 3)).unwrap();
+        mock.push(U64::from(x.block_number + 3)).unwrap();
+        let options = MonitorOptions {
+            confirmations: 2,
+            ..Default::default()
+        };
+        let (last, count, pending) = backfill_range(
+            &client,
+            &mut store,
+            &mut Outputs::default(),
+            &pools,
+            x.block_number,
+            None,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!((last, count), (x.block_number + 3, 1));
+        assert_eq!(pending.len(), 1);
+        assert_eq!(count_rows(&store), 1);
+    }
+
+    #[tokio::test]
+    async fn test_check_chain_id() {
+        let (client, mock) = Provider::mocked();
+        let mut options = MonitorOptions::default();
+        // Without an expected chain the chain id is only logged
+        mock.push(U256::from(5)).unwrap();
+        check_chain_id(&client, &options).await.unwrap();
+
+        options.expected_chain_id = Some(1);
+        mock.push(U256::from(1)).unwrap();
+        check_chain_id(&client, &options).await.unwrap();
+        mock.push(U256::from(5)).unwrap();
+        check_chain_id(&client, &options).await.unwrap();
+
+        options.fail_on_chain_id_mismatch = true;
+        mock.push(U256::from(5)).unwrap();
+        let err = check_chain_id(&client, &options).await.unwrap_err();
+        assert!(matches!(err, MonitorError::InvalidConfig(_)));
+        assert!(err
+            .to_string()
+            .contains("chain 5 instead of the expected chain 1"));
+        // With nothing to answer, the chain cannot be checked
+        let err = check_chain_id(&client, &options).await.unwrap_err();
+        assert!(matches!(err, MonitorError::ProviderConnect(_)));
+        options.expected_chain_id = None;
+        check_chain_id(&client, &options).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_backfill_latest_block() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let x = create_test_transaction_vals();
+        let pools = [x.pool.to_string()];
+
+        let (client, mock) = create_test_client(&[x.timestamp]);
+        mock.push::<Vec<Log>, _>(vec![create_test_log()]).unwrap();
+        mock.push(U64::from(x.block_number)).unwrap();
+        let options = MonitorOptions::default();
+        let (block, count, _) = backfill_latest_block(
+            &client,
+            &mut store,
+            &mut Outputs::default(),
+            &pools,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!((block, count), (x.block_number, 1));
+        assert_eq!(count_rows(&store), 1);
+
+        // With confirmations the latest confirmed block is fetched instead
+        let (client, mock) = Provider::mocked();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(U64::from(x.block_number + 5)).unwrap();
+        mock.push(U64::from(x.block_number + 5)).unwrap();
+        let options = MonitorOptions {
+            confirmations: 5,
+            ..Default::default()
+        };
+        let (block, count, pending) = backfill_latest_block(
+            &client,
+            &mut store,
+            &mut Outputs::default(),
+            &pools,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!((block, count), (x.block_number, 0));
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_swap_filter_min_amounts() {
+        let x = create_test_transaction_vals();
+        let data = create_test_combined_log().data;
+        assert!(SwapFilter::default().matches(&data));
+
+        // |amount0| is 263120000, |amount1| is 162381653432074306
+        let low = SwapFilter {
+            min_abs_amount0: Some(U256::from(1_000_000u64)),
+            min_abs_amount1: None,
+        };
+        assert!(low.matches(&data));
+        let exact = SwapFilter {
+            min_abs_amount0: Some(U256::from_dec_str(&x.ammount0[1..]).unwrap()),
+            min_abs_amount1: None,
+        };
+        assert!(exact.matches(&data));
+        let high = SwapFilter {
+            min_abs_amount0: Some(U256::from(1_000_000_000u64)),
+            min_abs_amount1: Some(U256::exp10(18)),
+        };
+        assert!(!high.matches(&data));
+        // Meeting either threshold keeps the swap
+        let either = SwapFilter {
+            min_abs_amount0: Some(U256::from(1_000_000_000u64)),
+            min_abs_amount1: Some(U256::exp10(17)),
+        };
+        assert!(either.matches(&data));
+
+        let min = LogData {
+            amount0: I256::MIN,
+            ..data
+        };
+        let at_max_magnitude = SwapFilter {
+            min_abs_amount0: Some(U256::one() << 255),
+            min_abs_amount1: None,
+        };
+        assert!(at_max_magnitude.matches(&min));
+    }
+
+    /// Records the transactions of the swaps it gets, or fails every one.
+    struct TestSink {
+        swaps: Arc<std::sync::Mutex<Vec<H256>>>,
+        fail: bool,
+    }
+
+    impl SwapSink for TestSink {
+        fn handle(&mut self, swap: &CombinedLog, _metadata: Option<&PoolMetadata>) -> Result<()> {
+            if self.fail {
+                bail!("sink is down");
+            }
+            self.swaps.lock().unwrap().push(swap.tx_hash);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_log_fans_out_to_sinks() {
+        let swaps = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = |fail| {
+            Box::new(TestSink {
+                swaps: swaps.clone(),
+                fail,
+            }) as Box<dyn SwapSink>
+        };
+        let options = MonitorOptions {
+            print: false,
+            ..Default::default()
+        };
+        let mut outputs =
+            Outputs::open(&options, vec![sink(true), sink(false), sink(false)]).unwrap();
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let mut batch = LogBatch::new(1);
+        let swap = create_test_combined_log();
+        // The failing sink neither stops the others nor the store
+        assert!(process_log(
+            swap.clone().into(),
+            &mut store,
+            &mut batch,
+            &mut outputs,
+            &options
+        )
+        .await
+        .unwrap());
+        assert_eq!(*swaps.lock().unwrap(), vec![swap.tx_hash, swap.tx_hash]);
+        assert_eq!(
+            get_swaps_by_tx_hash(store.connection(), swap.tx_hash)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // Events of other kinds only reach sinks that handle them
+        let mint = PoolEvent::Mint(events::decode_mint(&create_test_mint_log(), None).unwrap());
+        process_log(mint, &mut store, &mut batch, &mut outputs, &options)
+            .await
+            .unwrap();
+        assert_eq!(swaps.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_process_log_updates_price_ema() {
+        let price_ema = PriceEma::new(0.5).unwrap();
+        let options = MonitorOptions {
+            print: false,
+            price_ema: Some(price_ema.clone()),
+            ..Default::default()
+        };
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let mut batch = LogBatch::new(1);
+        let mut outputs = Outputs::default();
+        let priced_swap = |log_index: u64, price: Option<f64>, removed: bool| CombinedLog {
+            log_index: Some(log_index),
+            price,
+            removed,
+            ..create_test_combined_log()
+        };
+        // Swaps without a price and retracted swaps leave the average alone
+        for swap in [
+            priced_swap(1, Some(1_000.0), false),
+            priced_swap(2, None, false),
+            priced_swap(3, Some(3_000.0), false),
+            priced_swap(1, Some(9_000.0), true),
+        ] {
+            process_log(swap.into(), &mut store, &mut batch, &mut outputs, &options)
+                .await
+                .unwrap();
+        }
+        let pool = create_test_combined_log().pool;
+        assert_eq!(price_ema.get(pool), Some(2_000.0));
+    }
+
+    #[tokio::test]
+    async fn test_process_log_keeps_only_matching_parties() {
+        let swap = create_test_combined_log();
+        let other = Address::from_low_u64_be(7);
+        for (only_sender, only_receiver, kept) in [
+            (Some(swap.sender), None, 1),
+            (Some(other), None, 0),
+            (Some(swap.sender), Some(swap.receiver), 1),
+            (Some(swap.sender), Some(other), 0),
+            (None, Some(swap.sender), 0),
+        ] {
+            let mut store = SqliteStore::open_in_memory().unwrap();
+            let options = MonitorOptions {
+                only_sender,
+                only_receiver,
+                ..Default::default()
+            };
+            let mut batch = LogBatch::new(1);
+            process_log(
+                swap.clone().into(),
+                &mut store,
+                &mut batch,
+                &mut Outputs::default(),
+                &options,
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                count_rows(&store),
+                kept,
+                "{only_sender:?} {only_receiver:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_log_drops_filtered_swaps() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let options = MonitorOptions {
+            filter: SwapFilter {
+                min_abs_amount0: Some(U256::from(1_000_000_000u64)),
+                min_abs_amount1: None,
+            },
+            ..Default::default()
+        };
+
+        let mut batch = LogBatch::new(1);
+        process_log(
+            create_test_combined_log().into(),
+            &mut store,
+            &mut batch,
+            &mut Outputs::default(),
+            &options,
+        )
+        .await
+        .unwrap();
+        let rows: i64 = store
+            .connection()
+            .query_row("SELECT COUNT(*) FROM logs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    #[tokio::test]
+    async fn test_process_removed_log() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let x = create_test_transaction_vals();
+
+        // A second swap in the same transaction must survive the removal
+        let mut other_log = create_test_log();
+        other_log.log_index = Some((x.log_index + 1).into());
+        let mut removed_log = create_test_log();
+        removed_log.removed = Some(true);
+
+        let (client, _mock) = create_test_client(&[x.timestamp]);
+        let mut lookups = LookupCache::default();
+        let mut batch = LogBatch::new(10);
+        for log in [create_test_log(), other_log, removed_log] {
+            let combined_log = decode_log(log, &client, &mut lookups, &HashMap::new())
+                .await
+                .unwrap();
+            process_log(
+                combined_log.into(),
+                &mut store,
+                &mut batch,
+                &mut Outputs::default(),
+                &MonitorOptions::default(),
+            )
+            .await
+            .unwrap();
+        }
+        batch.flush(&mut store).await.unwrap();
+
+        assert_eq!(count_rows(&store), 1);
+        let remaining_index: u64 = store
+            .connection()
+            .query_row("SELECT log_index FROM logs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(remaining_index, x.log_index + 1);
+    }
+
+    #[tokio::test]
+    async fn test_process_mint_and_burn_logs() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let count = |store: &SqliteStore, table: &str| -> i64 {
+            store
+                .connection()
+                .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+
+        let mut removed_mint = create_test_mint_log();
+        removed_mint.removed = Some(true);
+        // The mint, the burn and the retracted mint each need their block's timestamp
+        let (client, _mock) = create_test_client(&[1_687_000_012, 1_687_000_024, 1_687_000_012]);
+        let mut lookups = LookupCache::default();
+        let mut batch = LogBatch::new(10);
+        for log in [create_test_mint_log(), create_test_burn_log()] {
+            let event = decode_event(log, &client, &mut lookups, &LogDecoder::default())
+                .await
+                .unwrap();
+            process_log(
+                event,
+                &mut store,
+                &mut batch,
+                &mut Outputs::default(),
+                &MonitorOptions::default(),
+            )
+            .await
+            .unwrap();
+        }
+        batch.flush(&mut store).await.unwrap();
+        assert_eq!(count(&store, "mints"), 1);
+        assert_eq!(count(&store, "burns"), 1);
+        assert_eq!(count(&store, "logs"), 0);
+
+        let (tick_lower, amount1, timestamp): (i32, String, u64) = store
+            .connection()
+            .query_row(
+                "SELECT tick_lower, amount1, timestamp FROM burns",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(tick_lower, -887_270);
+        assert_eq!(amount1, "1641068681400452224");
+        assert_eq!(timestamp, 1_687_000_024);
+
+        // A retracted mint is deleted again
+        let event = decode_event(removed_mint, &client, &mut lookups, &LogDecoder::default())
+            .await
+            .unwrap();
+        process_log(
+            event,
+            &mut store,
+            &mut batch,
+            &mut Outputs::default(),
+            &MonitorOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(count(&store, "mints"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_process_v2_swap_log() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let (client, _mock) = create_test_client(&[1_687_000_036]);
+        let mut lookups = LookupCache::default();
+        let event = decode_event(
+            create_test_v2_swap_log(),
+            &client,
+            &mut lookups,
+            &LogDecoder::default(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(event, PoolEvent::V2Swap(_)));
+
+        // 1850.123456 USDC out meets the threshold although nothing was paid in
+        let options = MonitorOptions {
+            filter: SwapFilter {
+                min_abs_amount0: Some(U256::from(1_000_000_000u64)),
+                min_abs_amount1: None,
+            },
+            pool_version: PoolVersion::V2,
+            ..Default::default()
+        };
+        let mut batch = LogBatch::new(1);
+        process_log(
+            event,
+            &mut store,
+            &mut batch,
+            &mut Outputs::default(),
+            &options,
+        )
+        .await
+        .unwrap();
+
+        let (amount0_out, amount1_in, timestamp): (String, String, u64) = store
+            .connection()
+            .query_row(
+                "SELECT amount0_out, amount1_in, timestamp FROM v2_swaps",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(amount0_out, "1850123456");
+        assert_eq!(amount1_in, "1000000000000000000");
+        assert_eq!(timestamp, 1_687_000_036);
+        assert_eq!(count_rows(&store), 0);
+    }
+
+    #[tokio::test]
+    async fn test_process_custom_event_log() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let (client, _mock) = create_test_client(&[1_687_000_048]);
+        let mut lookups = LookupCache::default();
+        // The swap filters do not apply to custom events
+        let options = MonitorOptions {
+            custom_event: Some(parse_event_signature(TRADE_EVENT).unwrap()),
+            only_sender: Some(Address::zero()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.event_signatures().unwrap(),
+            vec!["Trade(address,int256,bytes4,bool)"]
+        );
+        let event = decode_event(
+            create_test_custom_log(),
+            &client,
+            &mut lookups,
+            &options.decoder(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(event, PoolEvent::Custom(_)));
+
+        let mut batch = LogBatch::new(1);
+        let stored = process_log(
+            event,
+            &mut store,
+            &mut batch,
+            &mut Outputs::default(),
+            &options,
+        )
+        .await
+        .unwrap();
+        assert!(stored);
+
+        let (name, params, timestamp): (String, String, u64) = store
+            .connection()
+            .query_row(
+                "SELECT event_name, params, timestamp FROM custom_events",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(name, "Trade");
+        let params: serde_json::Value = serde_json::from_str(&params).unwrap();
+        assert_eq!(params["delta"], "-1500");
+        assert_eq!(timestamp, 1_687_000_048);
+        assert_eq!(count_rows(&store), 0);
+    }
+
+    #[test]
+    fn test_swap_filter_v2_amounts() {
+        let data = V2LogData {
+            amount0_in: U256::zero(),
+            amount1_in: U256::from(600u64),
+            amount0_out: U256::from(1_000u64),
+            amount1_out: U256::from(500u64),
+        };
+        let filter = |min_abs_amount1: u64| SwapFilter {
+            min_abs_amount0: None,
+            min_abs_amount1: Some(U256::from(min_abs_amount1)),
+        };
+        // Both directions count towards a token's amount
+        assert!(filter(1_100).matches_v2(&data));
+        assert!(!filter(1_101).matches_v2(&data));
+
+        let max = V2LogData {
+            amount0_in: U256::MAX,
+            amount0_out: U256::MAX,
+            ..data
+        };
+        assert!(SwapFilter {
+            min_abs_amount0: Some(U256::MAX),
+            min_abs_amount1: None,
+        }
+        .matches_v2(&max));
+    }
+
+    #[tokio::test]
+    async fn test_decode_log_rejects_missing_topics() {
+        let mut log = create_test_log();
+        log.topics.truncate(1);
+        let (client, _mock) = create_test_client(&[]);
+        let mut lookups = LookupCache::default();
+        let error = decode_event(log, &client, &mut lookups, &LogDecoder::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, MonitorError::Decode(_)));
+        assert!(error
+            .to_string()
+            .ends_with("has 1 topics, expected at least 2"));
+    }
+
+    #[tokio::test]
+    async fn test_decode_event_rejects_unknown_topic() {
+        let mut log = create_test_log();
+        log.topics[0] = event_topic("Transfer(address,address,uint256)");
+        let (client, _mock) = create_test_client(&[]);
+        let mut lookups = LookupCache::default();
+        let result = decode_event(log, &client, &mut lookups, &LogDecoder::default()).await;
+        assert!(result.unwrap_err().to_string().starts_with("unknown event"));
+    }
+
+    #[test]
+    fn test_combined_log_to_json() {
+        let x = create_test_transaction_vals();
+        let json = create_test_combined_log().to_json();
+
+        assert_eq!(json["tx_hash"], x.tx_hash);
+        assert_eq!(json["pool"], x.pool);
+        assert_eq!(json["sender"], x.sender);
+        assert_eq!(json["receiver"], x.receiver);
+        assert_eq!(json["amount0"], x.ammount0);
+        assert_eq!(json["amount1"], x.ammount1);
+        assert_eq!(json["sqrt_price"], x.sqrt_price);
+        assert_eq!(json["liquidity"], x.liquidity);
+        assert_eq!(json["tick"], x.tick);
+        assert_eq!(json["block_number"], x.block_number);
+        assert_eq!(json["log_index"], x.log_index);
+        assert_eq!(json["transaction_index"], x.transaction_index);
+        assert_eq!(json["price"], serde_json::Value::Null);
+        // A JSON line must not contain raw newlines
+        assert!(!json.to_string().contains('\n'));
+    }
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("pretty".parse::<LogFormat>().unwrap(), LogFormat::Pretty);
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_load_pool_metadata_prefers_stored_metadata() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let metadata = create_test_pool_metadata();
+        store.insert_pool(&metadata).await.unwrap();
+        let unknown_pool = "0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8";
+
+        // The mock has no responses, so fetching the unknown pool's tokens fails
+        let (client, _mock) = create_test_client(&[]);
+        let explicit = TokenDecimals {
+            decimals0: 8,
+            decimals1: 18,
+        };
+        let mut options = MonitorOptions {
+            token_decimals: HashMap::from([(metadata.pool, explicit)]),
+            ..Default::default()
+        };
+        load_pool_metadata(
+            Arc::new(client),
+            &mut store,
+            &[format!("{:#x}", metadata.pool), unknown_pool.to_string()],
+            &mut options,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            options.pools,
+            HashMap::from([(metadata.pool, metadata.clone())])
+        );
+        assert_eq!(options.token_decimals[&metadata.pool], explicit);
+        assert_eq!(options.token_decimals.len(), 1);
+    }
+
+    #[test]
+    fn test_pool_version_from_str() {
+        assert_eq!("v2".parse::<PoolVersion>().unwrap(), PoolVersion::V2);
+        assert_eq!("v3".parse::<PoolVersion>().unwrap(), PoolVersion::V3);
+        assert!("v4".parse::<PoolVersion>().is_err());
+        assert_eq!(PoolVersion::default(), PoolVersion::V3);
+    }
+
+    #[tokio::test]
+    async fn test_block_timestamp_cache() {
+        let (client, mock) = create_test_client(&[100, 200]);
+        let mut lookups = LookupCache::default();
+
+        assert_eq!(lookups.timestamp(&client, 1).await.unwrap(), Some(100));
+        // A repeated block is served from the cache without another request
+        assert_eq!(lookups.timestamp(&client, 1).await.unwrap(), Some(100));
+        assert_eq!(lookups.timestamp(&client, 2).await.unwrap(), Some(200));
+
+        mock.assert_request("eth_getBlockByNumber", ("0x1", false))
+            .unwrap();
+        mock.assert_request("eth_getBlockByNumber", ("0x2", false))
+            .unwrap();
+        assert!(mock
+            .assert_request("eth_getBlockByNumber", ("0x2", false))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decode_event_tags_source() {
+        let (client, mock) = Provider::mocked();
+        mock.push(Block::<H256> {
+            timestamp: 100.into(),
+            ..Default::default()
+        })
+        .unwrap();
+        let decoder = MonitorOptions {
+            source_tag: Some("eu-1".to_string()),
+            ..Default::default()
+        }
+        .decoder();
+        let event = decode_event(
+            create_test_log(),
+            &client,
+            &mut LookupCache::default(),
+            &decoder,
+        )
+        .await
+        .unwrap();
+        match event {
+            PoolEvent::Swap(swap) => assert_eq!(swap.source.as_deref(), Some("eu-1")),
+            event => panic!("expected a swap, got {:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decode_event_fetches_gas() {
+        use ethers::types::TransactionReceipt;
+
+        let (client, mock) = Provider::mocked();
+        let receipt = TransactionReceipt {
+            gas_used: Some(U256::from(184_214)),
+            effective_gas_price: Some(U256::from(23_512_000_000u64)),
+            ..Default::default()
+        };
+        // Responses are popped from the back, so they are pushed in reverse order
+        mock.push::<Option<TransactionReceipt>, _>(None).unwrap();
+        mock.push(receipt).unwrap();
+        mock.push(Block::<H256> {
+            timestamp: 100.into(),
+            ..Default::default()
+        })
+        .unwrap();
+        let decoder = LogDecoder {
+            fetch_gas: true,
+            ..Default::default()
+        };
+        let mut lookups = LookupCache::default();
+        let gas = |event: PoolEvent| match event {
+            PoolEvent::Swap(swap) => (swap.gas_used, swap.effective_gas_price),
+            event => panic!("expected a swap, got {:?}", event),
+        };
+
+        let first = decode_event(create_test_log(), &client, &mut lookups, &decoder)
+            .await
+            .unwrap();
+        let expected = (Some(184_214), Some(U256::from(23_512_000_000u64)));
+        assert_eq!(gas(first), expected);
+        // Another swap of the same transaction is served from the cache
+        let second = Log {
+            log_index: Some(U256::from(99)),
+            ..create_test_log()
+        };
+        let second = decode_event(second, &client, &mut lookups, &decoder)
+            .await
+            .unwrap();
+        assert_eq!(gas(second), expected);
+
+        // A transaction without a receipt yet leaves the columns empty
+        let pending = Log {
+            transaction_hash: Some(H256::repeat_byte(0xab)),
+            ..create_test_log()
+        };
+        let pending = decode_event(pending, &client, &mut lookups, &decoder)
+            .await
+            .unwrap();
+        assert_eq!(gas(pending), (None, None));
+
+        // Without `fetch_gas` no receipt is requested
+        let event = decode_event(
+            create_test_log(),
+            &client,
+            &mut lookups,
+            &Default::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(gas(event), (None, None));
     }
 }
@@ -1,19 +1,538 @@
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
+use ethers::abi::EventExt;
+use ethers::providers::Authorization;
+use ethers::types::{Address, H256, U256};
 use eyre::Result;
+use std::collections::HashMap;
 use std::env;
-use uniswap_swap_monitor::run;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+#[cfg(feature = "kafka")]
+use uniswap_swap_monitor::KafkaConfig;
+use uniswap_swap_monitor::{
+    open_sharded_store, parse_event_signature, print_database_stats, reprocess_all,
+    resolve_pool_addresses, run, run_backfill, run_once, spawn_api_server, verify_swaps,
+    BackfillConfig, BatchConfig, CircuitBreaker, Config, DurabilityMode, EventKind, LogFormat,
+    MonitorOptions, NullStore, PoolVersion, PriceEma, ReconnectPolicy, SqliteStore, SwapFilter,
+    TokenPair, WebhookConfig, DEFAULT_HEALTH_MAX_AGE, DEFAULT_THROUGHPUT_INTERVAL, IN_MEMORY_PATH,
+    UNISWAP_V3_FACTORY,
+};
+
+/// Monitors Uniswap pools and stores their events. Every flag falls back to
+/// the environment variable named in its help, which may also be set in `.env`.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// TOML file with the provider URLs, database and pools to monitor; the
+    /// other flags and variables take precedence over it
+    #[arg(long, env = "CONFIG_PATH", global = true)]
+    config: Option<PathBuf>,
+
+    /// WebSocket or HTTP(S) provider URL; repeat the flag or separate several
+    /// by commas to fail over to the next one. Defaults to Infura with `INFURA_KEY`
+    #[arg(
+        long = "provider-ws",
+        visible_alias = "provider-url",
+        env = "PROVIDER_URL",
+        value_delimiter = ',',
+        global = true
+    )]
+    provider_urls: Vec<String>,
+
+    /// Pool contract address, repeat the flag or separate several by commas
+    #[arg(
+        long = "pool",
+        env = "POOL_ADDRESS",
+        value_delimiter = ',',
+        global = true
+    )]
+    pools: Vec<String>,
+
+    /// Uniswap V3 pool by its tokens and fee tier, as `token0:token1:fee`,
+    /// looked up with `getPool` of the factory at `FACTORY_ADDRESS`, which
+    /// defaults to Uniswap's; repeat the flag or separate several by commas
+    #[arg(long = "pair", env = "POOL_PAIR", value_delimiter = ',', global = true)]
+    pairs: Vec<TokenPair>,
+
+    /// SQLite database path, `:memory:` or a `postgres://` URL
+    #[arg(long, env = "DB_PATH", global = true)]
+    db: Option<String>,
+
+    /// Backfill events from this block before monitoring
+    #[arg(long, env = "FROM_BLOCK", global = true)]
+    from_block: Option<u64>,
+
+    /// Stop monitoring once the events of this block have been stored
+    #[arg(long, env = "UNTIL_BLOCK", global = true)]
+    until_block: Option<u64>,
+
+    /// Store the events of the latest block, or the latest confirmed one with
+    /// `CONFIRMATIONS`, and exit instead of subscribing, for periodic jobs
+    #[arg(long, env = "ONCE", global = true)]
+    once: bool,
+
+    /// Exit once no event has arrived for this many seconds
+    #[arg(long = "idle-timeout", env = "IDLE_TIMEOUT_SECS", global = true)]
+    idle_timeout_secs: Option<u64>,
+
+    /// Fetch the logs of every new block instead of subscribing to logs, for
+    /// WebSocket providers that miss log notifications
+    #[arg(long = "follow-head-only", env = "FOLLOW_HEAD_ONLY", global = true)]
+    follow_heads: bool,
+
+    /// `pretty` or `json`
+    #[arg(long, env = "LOG_FORMAT", value_parser = parse_log_format, global = true)]
+    log_format: Option<LogFormat>,
+
+    /// Send live swaps as JSON messages to WebSocket clients on this port, for
+    /// example a browser dashboard
+    #[arg(long, env = "BROADCAST_PORT", global = true)]
+    broadcast_port: Option<u16>,
+
+    /// Serve the stored swaps as JSON on `GET /swaps` on this port, see the
+    /// `serve` subcommand; needs a SQLite database file
+    #[arg(long, env = "API_PORT", global = true)]
+    api_port: Option<u16>,
+
+    /// Store events without printing each of them
+    #[arg(long, env = "QUIET", global = true)]
+    quiet: bool,
+
+    /// Print events without writing them to the database; `--db` is not needed
+    #[arg(long, env = "DRY_RUN", global = true)]
+    dry_run: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Store live events, after backfilling from the checkpoint or
+    /// `--from-block` (the default)
+    Monitor,
+    /// Store the events of a block range and exit
+    Backfill {
+        /// Last block to backfill; defaults to the current head
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
+    /// Print a summary of the swaps stored in a SQLite database and exit
+    Stats,
+    /// Decode the stored raw data of the swaps in a SQLite database again,
+    /// rewriting the decoded columns, and exit
+    Reprocess,
+    /// Compare the stored swaps of the given transactions in a SQLite database
+    /// with their logs on chain, print the differences and exit, failing if
+    /// there are any
+    Verify {
+        /// Hashes of the transactions to check
+        #[arg(required = true)]
+        tx_hashes: Vec<H256>,
+    },
+    /// Serve the swaps stored in a SQLite database on `--api-port` until
+    /// Ctrl-C, without monitoring
+    Serve,
+}
+
+fn parse_log_format(format: &str) -> Result<LogFormat, String> {
+    format.parse().map_err(|e: eyre::Report| e.to_string())
+}
+
+fn optional_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn optional_amount(name: &str) -> Result<Option<U256>> {
+    optional_var(name)
+        .map(|amount| {
+            U256::from_dec_str(&amount).map_err(|e| eyre::eyre!("invalid {}: {}", name, e))
+        })
+        .transpose()
+}
+
+fn optional_address(name: &str) -> Result<Option<Address>> {
+    optional_var(name)
+        .map(|address| {
+            Address::from_str(address.trim()).map_err(|e| eyre::eyre!("invalid {}: {}", name, e))
+        })
+        .transpose()
+}
+
+/// Reads `PRICE_FEEDS`, a comma-separated list of `token=feed` address pairs.
+fn price_feeds() -> Result<HashMap<Address, Address>> {
+    let Some(feeds) = optional_var("PRICE_FEEDS") else {
+        return Ok(HashMap::new());
+    };
+    feeds
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let (token, feed) = pair.split_once('=').ok_or_else(|| {
+                eyre::eyre!("invalid PRICE_FEEDS entry '{}': expected token=feed", pair)
+            })?;
+            let parse = |address: &str| {
+                Address::from_str(address.trim())
+                    .map_err(|e| eyre::eyre!("invalid PRICE_FEEDS entry '{}': {}", pair, e))
+            };
+            Ok((parse(token)?, parse(feed)?))
+        })
+        .collect()
+}
+
+fn webhook_config() -> Result<Option<WebhookConfig>> {
+    let Some(url) = optional_var("WEBHOOK_URL") else {
+        return Ok(None);
+    };
+    let mut config = WebhookConfig::new(url);
+    config.threshold = SwapFilter {
+        min_abs_amount0: optional_amount("WEBHOOK_MIN_AMOUNT0")?,
+        min_abs_amount1: optional_amount("WEBHOOK_MIN_AMOUNT1")?,
+    };
+    config.min_usd_value = optional_var("WEBHOOK_MIN_USD")
+        .map(|value| value.parse::<f64>())
+        .transpose()?;
+    if let Some(seconds) = optional_var("WEBHOOK_INTERVAL_SECS") {
+        config.min_interval = Duration::from_secs(seconds.parse()?);
+    }
+    Ok(Some(config))
+}
+
+/// Reads `KAFKA_BROKERS` and `KAFKA_TOPIC`, which defaults to `swaps`.
+#[cfg(feature = "kafka")]
+fn kafka_config() -> Option<KafkaConfig> {
+    Some(KafkaConfig {
+        brokers: optional_var("KAFKA_BROKERS")?,
+        topic: optional_var("KAFKA_TOPIC").unwrap_or_else(|| "swaps".to_string()),
+    })
+}
+
+/// Opens the SQLite database of `--db`, or of the config file, for the
+/// subcommand `name`, which only works on stored swaps.
+fn open_existing_sqlite(
+    name: &str,
+    db: Option<String>,
+    config: Option<&Config>,
+) -> Result<SqliteStore> {
+    Ok(SqliteStore::open(&existing_sqlite_path(name, db, config)?)?)
+}
+
+/// The path of the SQLite database of `--db`, or of the config file, for the
+/// subcommand `name`, failing unless it exists.
+fn existing_sqlite_path(name: &str, db: Option<String>, config: Option<&Config>) -> Result<String> {
+    let db_path = db
+        .or_else(|| config.and_then(|config| config.db_path.clone()))
+        .ok_or_else(|| eyre::eyre!("DB_PATH is not set (use --db, DB_PATH or --config)"))?;
+    if db_path.starts_with("postgres") {
+        eyre::bail!("{} only supports SQLite databases", name);
+    }
+    // Opening a missing file would create an empty database
+    if db_path != IN_MEMORY_PATH && !Path::new(&db_path).exists() {
+        eyre::bail!("database '{}' does not exist", db_path);
+    }
+    Ok(db_path)
+}
+
+/// The address the query server listens on for `--api-port`.
+fn api_addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], port))
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
-    let provider_ws = format!(
-        "wss://mainnet.infura.io/ws/v3/{}",
-        env::var("INFURA_KEY").unwrap()
-    );
-    let contract_address = env::var("POOL_ADDRESS").unwrap();
-    let db_path = env::var("DB_PATH").unwrap();
-
-    run(&provider_ws, &contract_address, &db_path).await?;
+    let cli = Cli::parse();
+    // Logs go to stderr so that JSON swap lines on stdout stay machine-readable
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+    let config = cli.config.as_deref().map(Config::from_file).transpose()?;
+    if let Some(command @ (Command::Stats | Command::Reprocess)) = &cli.command {
+        let name = match command {
+            Command::Reprocess => "reprocess",
+            _ => "stats",
+        };
+        let mut store = open_existing_sqlite(name, cli.db.clone(), config.as_ref())?;
+        if let Command::Reprocess = command {
+            let stats = reprocess_all(store.connection_mut())?;
+            info!(
+                swaps = stats.swaps,
+                updated = stats.updated,
+                skipped = stats.skipped,
+                "reprocessed stored swaps"
+            );
+        } else {
+            print_database_stats(store.connection())?;
+        }
+        return Ok(());
+    }
+    if let Some(Command::Serve) = &cli.command {
+        let db_path = existing_sqlite_path("serve", cli.db.clone(), config.as_ref())?;
+        let port = cli
+            .api_port
+            .ok_or_else(|| eyre::eyre!("serve needs --api-port or API_PORT"))?;
+        let (_, server) = spawn_api_server(api_addr(port), &db_path)?;
+        tokio::signal::ctrl_c().await?;
+        server.abort();
+        return Ok(());
+    }
+    let mut provider_urls = cli.provider_urls;
+    if provider_urls.is_empty() {
+        if let Some(provider_ws) = optional_var("PROVIDER_WS") {
+            provider_urls = provider_ws.split(',').map(str::to_string).collect();
+        } else if let Some(config) = &config {
+            provider_urls = config.provider_urls.clone();
+        }
+    }
+    let mut provider_urls: Vec<String> = provider_urls
+        .iter()
+        .map(|provider_url| provider_url.trim().to_string())
+        .filter(|provider_url| !provider_url.is_empty())
+        .collect();
+    if provider_urls.is_empty() {
+        let infura_key = optional_var("INFURA_KEY").ok_or_else(|| {
+            eyre::eyre!("PROVIDER_URL is not set (use --provider-ws, PROVIDER_URL or INFURA_KEY)")
+        })?;
+        provider_urls.push(format!("wss://mainnet.infura.io/ws/v3/{}", infura_key));
+    }
+    let mut reconnect = ReconnectPolicy::default();
+    if let Some(seconds) = optional_var("CONNECT_TIMEOUT_SECS") {
+        reconnect.connect_timeout = Duration::try_from_secs_f64(seconds.parse()?)
+            .map_err(|e| eyre::eyre!("invalid CONNECT_TIMEOUT_SECS: {}", e))?;
+    }
+    if let Some(jitter) = optional_var("RECONNECT_JITTER") {
+        reconnect.jitter = jitter.parse()?;
+    }
+    reconnect.authorization = optional_var("PROVIDER_AUTHORIZATION").map(Authorization::raw);
+    if let Some(Command::Verify { tx_hashes }) = &cli.command {
+        let store = open_existing_sqlite("verify", cli.db.clone(), config.as_ref())?;
+        let mismatches =
+            verify_swaps(&provider_urls, &reconnect, store.connection(), tx_hashes).await?;
+        for mismatch in &mismatches {
+            println!("{}", mismatch);
+        }
+        if !mismatches.is_empty() {
+            eyre::bail!("{} stored fields differ from the chain", mismatches.len());
+        }
+        info!(
+            transactions = tx_hashes.len(),
+            "stored swaps match the chain"
+        );
+        return Ok(());
+    }
+    let mut contract_addresses: Vec<String> = cli
+        .pools
+        .iter()
+        .map(|address| address.trim().to_string())
+        .filter(|address| !address.is_empty())
+        .collect();
+    if !cli.pairs.is_empty() {
+        let factory = optional_var("FACTORY_ADDRESS");
+        let factory = Address::from_str(factory.as_deref().unwrap_or(UNISWAP_V3_FACTORY))?;
+        let pools = resolve_pool_addresses(&provider_urls, &reconnect, factory, &cli.pairs).await?;
+        for (pair, pool) in cli.pairs.iter().zip(&pools) {
+            info!(%pair, pool, "found the pool of a token pair");
+        }
+        contract_addresses.extend(pools);
+    }
+    let mut token_decimals = HashMap::new();
+    if let Some(config) = &config {
+        if contract_addresses.is_empty() {
+            contract_addresses = config.pool_addresses();
+            token_decimals = config.token_decimals();
+        }
+    }
+    if contract_addresses.is_empty() {
+        eyre::bail!("POOL_ADDRESS is not set (use --pool, POOL_ADDRESS, --pair or --config)");
+    }
+    let mut batch = BatchConfig::default();
+    if let Some(size) = optional_var("BATCH_SIZE") {
+        batch.max_size = size.parse()?;
+    }
+    if let Some(seconds) = optional_var("FLUSH_INTERVAL_SECS") {
+        batch.max_delay = Duration::try_from_secs_f64(seconds.parse()?)
+            .map_err(|e| eyre::eyre!("invalid FLUSH_INTERVAL_SECS: {}", e))?;
+    }
+    // Either setting turns the circuit breaker on, with the default of the other
+    let mut circuit_breaker = None;
+    if let Some(failures) = optional_var("DB_MAX_FAILURES") {
+        circuit_breaker
+            .get_or_insert_with(CircuitBreaker::default)
+            .max_failures = failures.parse()?;
+    }
+    if let Some(seconds) = optional_var("DB_RETRY_INTERVAL_SECS") {
+        circuit_breaker
+            .get_or_insert_with(CircuitBreaker::default)
+            .retry_interval = Duration::try_from_secs_f64(seconds.parse()?)
+            .map_err(|e| eyre::eyre!("invalid DB_RETRY_INTERVAL_SECS: {}", e))?;
+    }
+    let mut backfill = BackfillConfig::default();
+    if let Some(size) = optional_var("BACKFILL_CHUNK_SIZE") {
+        backfill.chunk_size = size.parse()?;
+    }
+    if let Some(concurrency) = optional_var("BACKFILL_CONCURRENCY") {
+        backfill.concurrency = concurrency.parse()?;
+    }
+    #[cfg(not(feature = "kafka"))]
+    if optional_var("KAFKA_BROKERS").is_some() {
+        eyre::bail!("KAFKA_BROKERS is set, but the monitor was built without the kafka feature");
+    }
+    let options = MonitorOptions {
+        token_decimals,
+        reconnect,
+        expected_chain_id: optional_var("EXPECTED_CHAIN_ID")
+            .map(|chain_id| chain_id.parse())
+            .transpose()?,
+        fail_on_chain_id_mismatch: optional_var("FAIL_ON_CHAIN_ID_MISMATCH")
+            .map(|fail| fail.parse())
+            .transpose()?
+            .unwrap_or_default(),
+        batch,
+        max_rows: optional_var("MAX_ROWS")
+            .map(|rows| rows.parse())
+            .transpose()?,
+        circuit_breaker,
+        confirmations: optional_var("CONFIRMATIONS")
+            .map(|confirmations| confirmations.parse())
+            .transpose()?
+            .unwrap_or_default(),
+        backfill,
+        backfill_from: cli.from_block,
+        until_block: cli.until_block,
+        follow_heads: cli.follow_heads,
+        idle_timeout: cli.idle_timeout_secs.map(Duration::from_secs),
+        print: !cli.quiet,
+        log_format: cli.log_format.unwrap_or_default(),
+        pool_version: optional_var("POOL_VERSION")
+            .map(|version| version.parse::<PoolVersion>())
+            .transpose()?
+            .unwrap_or_default(),
+        events: optional_var("EVENTS")
+            .map(|kinds| {
+                kinds
+                    .split(',')
+                    .map(|kind| kind.trim().parse::<EventKind>())
+                    .collect::<Result<Vec<_>>>()
+            })
+            .transpose()?,
+        swap_event: optional_var("SWAP_EVENT_SIGNATURE")
+            .map(|signature| parse_event_signature(&signature).map(|event| event.abi_signature()))
+            .transpose()?,
+        fetch_gas: optional_var("FETCH_GAS")
+            .map(|fetch| fetch.parse())
+            .transpose()?
+            .unwrap_or_default(),
+        source_tag: optional_var("SOURCE_TAG"),
+        custom_event: optional_var("EVENT_SIGNATURE")
+            .map(|signature| parse_event_signature(&signature))
+            .transpose()?,
+        filter: SwapFilter {
+            min_abs_amount0: optional_amount("MIN_AMOUNT0")?,
+            min_abs_amount1: optional_amount("MIN_AMOUNT1")?,
+        },
+        only_sender: optional_address("ONLY_SENDER")?,
+        only_receiver: optional_address("ONLY_RECEIVER")?,
+        csv_path: optional_var("CSV_PATH").map(PathBuf::from),
+        #[cfg(feature = "kafka")]
+        kafka: kafka_config(),
+        webhook: webhook_config()?,
+        price_feeds: price_feeds()?,
+        price_ema: optional_var("PRICE_EMA_ALPHA")
+            .map(|alpha| PriceEma::new(alpha.parse()?).map_err(eyre::Report::from))
+            .transpose()?,
+        metrics_addr: optional_var("METRICS_PORT")
+            .map(|port| port.parse::<u16>())
+            .transpose()?
+            .map(|port| SocketAddr::from(([0, 0, 0, 0], port))),
+        health_max_age: optional_var("HEALTH_MAX_AGE_SECS")
+            .map(|seconds| seconds.parse().map(Duration::from_secs))
+            .transpose()?
+            .unwrap_or(DEFAULT_HEALTH_MAX_AGE),
+        throughput_interval: optional_var("THROUGHPUT_INTERVAL_SECS")
+            .map(|seconds| seconds.parse().map(Duration::from_secs))
+            .transpose()?
+            .unwrap_or(DEFAULT_THROUGHPUT_INTERVAL),
+        broadcast_addr: cli
+            .broadcast_port
+            .map(|port| SocketAddr::from(([0, 0, 0, 0], port))),
+        ..Default::default()
+    };
+
+    let pool_db_paths = config
+        .as_ref()
+        .map(Config::pool_db_paths)
+        .unwrap_or_default();
+    let db_path = if cli.dry_run {
+        None
+    } else {
+        Some(
+            cli.db
+                .or_else(|| config.and_then(|config| config.db_path))
+                .ok_or_else(|| {
+                    eyre::eyre!("DB_PATH is not set (use --db, DB_PATH, --config or --dry-run)")
+                })?,
+        )
+    };
+    let store = match &db_path {
+        None => {
+            info!("dry run, events are not stored");
+            Box::new(NullStore)
+        }
+        Some(db_path) => {
+            let durability = optional_var("SQLITE_DURABILITY")
+                .map(|mode| mode.parse::<DurabilityMode>())
+                .transpose()?
+                .unwrap_or_default();
+            open_sharded_store(db_path, &pool_db_paths, durability).await?
+        }
+    };
+    // Pools with a `db_path` of their own in the config file are not served
+    let api_server = match (cli.api_port, &db_path) {
+        (Some(port), Some(db_path)) => Some(spawn_api_server(api_addr(port), db_path)?.1),
+        (Some(_), None) => eyre::bail!("API_PORT cannot be combined with a dry run"),
+        (None, _) => None,
+    };
+    match cli.command.unwrap_or(Command::Monitor) {
+        Command::Monitor if cli.once => {
+            let block = run_once(&provider_urls, &contract_addresses, store, options).await?;
+            info!(block, "stored the events of the latest block");
+        }
+        Command::Monitor => {
+            let processed = run(&provider_urls, &contract_addresses, store, options).await?;
+            info!(events = processed, "monitor stopped");
+        }
+        Command::Backfill { .. } if cli.once => {
+            eyre::bail!("--once cannot be combined with backfill");
+        }
+        Command::Backfill { to_block } => {
+            let Some(from_block) = options.backfill_from else {
+                eyre::bail!("backfill needs --from-block or FROM_BLOCK");
+            };
+            run_backfill(
+                &provider_urls,
+                &contract_addresses,
+                store,
+                from_block,
+                to_block,
+                options,
+            )
+            .await?;
+        }
+        Command::Stats | Command::Reprocess | Command::Verify { .. } | Command::Serve => {
+            unreachable!("stats, reprocess, verify and serve return before storing events")
+        }
+    }
+    if let Some(api_server) = api_server {
+        api_server.abort();
+    }
 
     Ok(())
 }
@@ -0,0 +1,349 @@
+use crate::error::{MonitorError, Result};
+use crate::{with_provider, ProviderTask, ReconnectPolicy, TokenDecimals};
+use ethers::{
+    contract::abigen,
+    core::{abi::AbiDecode, types::Address},
+    providers::Middleware,
+};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+abigen!(
+    IUniswapPool,
+    r#"[
+        function token0() external view returns (address)
+        function token1() external view returns (address)
+    ]"#;
+
+    IERC20Metadata,
+    r#"[
+        function symbol() external view returns (string)
+        function decimals() external view returns (uint8)
+    ]"#;
+
+    IUniswapV3Factory,
+    r#"[
+        function getPool(address tokenA, address tokenB, uint24 fee) external view returns (address pool)
+    ]"#;
+);
+
+/// The Uniswap V3 factory on Ethereum mainnet and most chains Uniswap deployed
+/// to, see `fetch_pool_address`.
+pub const UNISWAP_V3_FACTORY: &str = "0x1f98431c8ad98523631ae4a59f728a8c0061f984";
+
+/// Two tokens and a fee tier in hundredths of a basis point, such as 500 for
+/// the 0.05% pools, naming a Uniswap V3 pool. Parses from
+/// `token0:token1:fee`, in either token order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenPair {
+    pub token0: Address,
+    pub token1: Address,
+    pub fee: u32,
+}
+
+impl FromStr for TokenPair {
+    type Err = MonitorError;
+
+    fn from_str(pair: &str) -> Result<Self> {
+        let invalid = |reason: String| {
+            MonitorError::InvalidConfig(format!("invalid token pair '{}': {}", pair, reason))
+        };
+        let parts: Vec<&str> = pair.split(':').map(str::trim).collect();
+        let [token0, token1, fee] = parts[..] else {
+            return Err(invalid("expected token0:token1:fee".to_string()));
+        };
+        let token = |token: &str| {
+            Address::from_str(token)
+                .map_err(|e| invalid(format!("invalid token address '{}': {}", token, e)))
+        };
+        let fee: u32 = fee
+            .parse()
+            .map_err(|e| invalid(format!("invalid fee '{}': {}", fee, e)))?;
+        // The factory takes the fee as a uint24
+        if fee >= 1 << 24 {
+            return Err(invalid(format!("fee {} does not fit in 24 bits", fee)));
+        }
+        Ok(TokenPair {
+            token0: token(token0)?,
+            token1: token(token1)?,
+            fee,
+        })
+    }
+}
+
+impl fmt::Display for TokenPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}:{:#x}:{}", self.token0, self.token1, self.fee)
+    }
+}
+
+/// Looks up the address of the V3 pool of `pair` with `getPool` of `factory`,
+/// usually `UNISWAP_V3_FACTORY`. Fails when the factory has no such pool,
+/// which it reports as the zero address, as subscribing to that would never
+/// match a log.
+pub async fn fetch_pool_address<M: Middleware + 'static>(
+    client: Arc<M>,
+    factory: Address,
+    pair: TokenPair,
+) -> Result<Address> {
+    let contract = IUniswapV3Factory::new(factory, client);
+    let pool = contract
+        .get_pool(pair.token0, pair.token1, pair.fee)
+        .call()
+        .await
+        .map_err(|e| {
+            MonitorError::ProviderConnect(format!(
+                "failed to fetch the pool of {} from factory {:#x}: {}",
+                pair, factory, e
+            ))
+        })?;
+    if pool.is_zero() {
+        return Err(MonitorError::InvalidConfig(format!(
+            "factory {:#x} has no pool of {}",
+            factory, pair
+        )));
+    }
+    Ok(pool)
+}
+
+/// Returns the pool address of each of `pairs`, see `fetch_pool_address`,
+/// formatted for `run`, with a client as connected by `with_provider`.
+pub async fn resolve_pool_addresses(
+    provider_urls: &[String],
+    reconnect: &ReconnectPolicy,
+    factory: Address,
+    pairs: &[TokenPair],
+) -> eyre::Result<Vec<String>> {
+    struct ResolvePools<'a> {
+        factory: Address,
+        pairs: &'a [TokenPair],
+    }
+
+    impl ProviderTask for ResolvePools<'_> {
+        type Output = Vec<String>;
+
+        async fn run<M: Middleware + 'static>(self, client: Arc<M>) -> eyre::Result<Vec<String>> {
+            let mut pools = Vec::new();
+            for &pair in self.pairs {
+                let pool = fetch_pool_address(client.clone(), self.factory, pair).await?;
+                pools.push(format!("{:#x}", pool));
+            }
+            Ok(pools)
+        }
+    }
+
+    with_provider(provider_urls, reconnect, ResolvePools { factory, pairs }).await
+}
+
+/// The ERC-20 symbol and decimals of a pool token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TokenMetadata {
+    pub address: Address,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+/// The tokens of a pool, as stored in the `pools` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PoolMetadata {
+    pub pool: Address,
+    pub token0: TokenMetadata,
+    pub token1: TokenMetadata,
+}
+
+impl PoolMetadata {
+    pub fn decimals(&self) -> TokenDecimals {
+        TokenDecimals {
+            decimals0: self.token0.decimals,
+            decimals1: self.token1.decimals,
+        }
+    }
+}
+
+/// Reads `token0()` and `token1()` of `pool` and the `symbol()` and
+/// `decimals()` of both tokens. Works for Uniswap V2 pairs and V3 pools alike.
+pub async fn fetch_pool_metadata<M: Middleware + 'static>(
+    client: Arc<M>,
+    pool: Address,
+) -> Result<PoolMetadata> {
+    let contract = IUniswapPool::new(pool, client.clone());
+    let token0 = contract.token_0().call().await.map_err(|e| {
+        MonitorError::ProviderConnect(format!("failed to fetch token0 of pool {:?}: {}", pool, e))
+    })?;
+    let token1 = contract.token_1().call().await.map_err(|e| {
+        MonitorError::ProviderConnect(format!("failed to fetch token1 of pool {:?}: {}", pool, e))
+    })?;
+    Ok(PoolMetadata {
+        pool,
+        token0: fetch_token_metadata(&client, token0).await?,
+        token1: fetch_token_metadata(&client, token1).await?,
+    })
+}
+
+async fn fetch_token_metadata<M: Middleware + 'static>(
+    client: &Arc<M>,
+    token: Address,
+) -> Result<TokenMetadata> {
+    let contract = IERC20Metadata::new(token, client.clone());
+    // The raw return data is decoded by hand, see `decode_symbol`
+    let raw_symbol = client
+        .call(&contract.symbol().tx, None)
+        .await
+        .map_err(|e| {
+            MonitorError::ProviderConnect(format!(
+                "failed to fetch symbol of token {:?}: {}",
+                token, e
+            ))
+        })?;
+    let decimals = contract.decimals().call().await.map_err(|e| {
+        MonitorError::ProviderConnect(format!(
+            "failed to fetch decimals of token {:?}: {}",
+            token, e
+        ))
+    })?;
+    Ok(TokenMetadata {
+        address: token,
+        symbol: decode_symbol(&raw_symbol)?,
+        decimals,
+    })
+}
+
+/// Decodes the return data of `symbol()`, which is an ABI-encoded `string` for
+/// standard tokens and a NUL-padded `bytes32` for some early ones such as MKR.
+pub(crate) fn decode_symbol(data: &[u8]) -> Result<String> {
+    if let Ok(symbol) = String::decode(data) {
+        return Ok(symbol);
+    }
+    if data.len() != 32 {
+        return Err(MonitorError::Decode(format!(
+            "invalid symbol() return data of {} bytes",
+            data.len()
+        )));
+    }
+    let len = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(data.len());
+    String::from_utf8(data[..len].to_vec())
+        .map_err(|e| MonitorError::Decode(format!("invalid bytes32 symbol: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::core::{abi::AbiEncode, types::Bytes};
+    use ethers::providers::Provider;
+    use std::str::FromStr;
+
+    const USDC: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+    const MKR: &str = "0x9f8f72aa9304c8b593d555f12ef6589cc3a579a2";
+
+    fn bytes32_symbol(symbol: &str) -> Vec<u8> {
+        let mut data = symbol.as_bytes().to_vec();
+        data.resize(32, 0);
+        data
+    }
+
+    #[test]
+    fn test_decode_symbol() {
+        assert_eq!(decode_symbol(&"USDC".to_string().encode()).unwrap(), "USDC");
+        assert_eq!(decode_symbol(&bytes32_symbol("MKR")).unwrap(), "MKR");
+        assert!(decode_symbol(&[0x4d, 0x4b, 0x52]).is_err());
+    }
+
+    #[test]
+    fn test_parse_token_pair() {
+        let usdc = Address::from_str(USDC).unwrap();
+        let mkr = Address::from_str(MKR).unwrap();
+        let pair: TokenPair = format!("{} : {}:3000", USDC, MKR).parse().unwrap();
+        assert_eq!(
+            pair,
+            TokenPair {
+                token0: usdc,
+                token1: mkr,
+                fee: 3000,
+            }
+        );
+        assert_eq!(pair.to_string().parse::<TokenPair>().unwrap(), pair);
+
+        for invalid in [
+            format!("{}:{}", USDC, MKR),
+            format!("{}:0xzz:500", USDC),
+            format!("{}:{}:0.05%", USDC, MKR),
+            format!("{}:{}:16777216", USDC, MKR),
+        ] {
+            let err = invalid.parse::<TokenPair>().unwrap_err();
+            assert!(matches!(err, MonitorError::InvalidConfig(_)), "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_pool_address() {
+        let (client, mock) = Provider::mocked();
+        let pool = Address::from_str("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640").unwrap();
+        // Responses are popped from the back, so they are pushed in reverse order
+        mock.push::<Bytes, _>(Bytes::from(Address::zero().encode()))
+            .unwrap();
+        mock.push::<Bytes, _>(Bytes::from(pool.encode())).unwrap();
+
+        let client = Arc::new(client);
+        let factory = Address::from_str(UNISWAP_V3_FACTORY).unwrap();
+        let pair = TokenPair {
+            token0: Address::from_str(USDC).unwrap(),
+            token1: Address::from_str(MKR).unwrap(),
+            fee: 500,
+        };
+        assert_eq!(
+            fetch_pool_address(client.clone(), factory, pair)
+                .await
+                .unwrap(),
+            pool
+        );
+        // The factory returns the zero address for pools that do not exist
+        let err = fetch_pool_address(client, factory, pair).await.unwrap_err();
+        assert!(matches!(err, MonitorError::InvalidConfig(_)));
+        assert!(err.to_string().contains("has no pool of"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_pool_metadata() {
+        let (client, mock) = Provider::mocked();
+        let usdc = Address::from_str(USDC).unwrap();
+        let mkr = Address::from_str(MKR).unwrap();
+        // The mock pops responses from the back, so push them in reverse order
+        let responses = [
+            usdc.encode(),
+            mkr.encode(),
+            "USDC".to_string().encode(),
+            6u8.encode(),
+            bytes32_symbol("MKR"),
+            18u8.encode(),
+        ];
+        for response in responses.into_iter().rev() {
+            mock.push::<Bytes, _>(Bytes::from(response)).unwrap();
+        }
+
+        let pool = Address::from_str("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640").unwrap();
+        let metadata = fetch_pool_metadata(Arc::new(client), pool).await.unwrap();
+        assert_eq!(metadata.pool, pool);
+        assert_eq!(
+            metadata.token0,
+            TokenMetadata {
+                address: usdc,
+                symbol: "USDC".to_string(),
+                decimals: 6,
+            }
+        );
+        assert_eq!(metadata.token1.symbol, "MKR");
+        assert_eq!(
+            metadata.decimals(),
+            TokenDecimals {
+                decimals0: 6,
+                decimals1: 18,
+            }
+        );
+    }
+}
@@ -0,0 +1,367 @@
+use ethers::core::types::Address;
+use eyre::{eyre, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{
+    Encoder, Gauge, GaugeVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// Counters and gauges describing the monitor itself.
+pub struct Metrics {
+    registry: Registry,
+    /// V2 and V3 swaps that passed the filter and were stored or deleted.
+    pub swaps_processed: IntCounter,
+    /// Failed attempts to write or delete events in the database.
+    pub db_insert_errors: IntCounter,
+    /// 1 while the circuit breaker paused processing because database writes
+    /// keep failing, see `CircuitBreaker`.
+    pub db_writes_paused: IntGauge,
+    /// Logs that failed to decode and were skipped, labeled by `reason`, see
+    /// `record_decode_failure`.
+    pub decode_failures: IntCounterVec,
+    /// Attempts to re-establish the subscription or to poll again after an error.
+    pub reconnects: IntCounter,
+    /// Highest block number of a processed event.
+    pub last_block_seen: IntGauge,
+    /// 1 while subscribed to the provider or polling it without errors.
+    pub subscription_active: IntGauge,
+    /// Unix timestamp of the last log, new block, successful poll or heartbeat
+    /// from the provider.
+    pub last_activity: IntGauge,
+    /// Swaps processed per second over the last throughput interval, see
+    /// `record_throughput`.
+    pub swap_rate: Gauge,
+    /// The moving average of the swap price of each pool, labeled by `pool`,
+    /// see `PriceEma`.
+    pub price_ema: GaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let swaps_processed = IntCounter::new(
+            "swaps_processed_total",
+            "Swaps that passed the filter and were stored or deleted",
+        )
+        .unwrap();
+        let db_insert_errors = IntCounter::new(
+            "db_insert_errors_total",
+            "Failed attempts to write or delete events in the database",
+        )
+        .unwrap();
+        let db_writes_paused = IntGauge::new(
+            "db_writes_paused",
+            "1 while processing is paused because database writes keep failing",
+        )
+        .unwrap();
+        let decode_failures = IntCounterVec::new(
+            Opts::new(
+                "decode_failures_total",
+                "Logs that failed to decode and were skipped, by reason",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        let reconnects = IntCounter::new(
+            "reconnects_total",
+            "Reconnects to the provider after a dropped subscription or failed poll",
+        )
+        .unwrap();
+        let last_block_seen = IntGauge::new(
+            "last_block_seen",
+            "Highest block number of a processed event",
+        )
+        .unwrap();
+        let subscription_active = IntGauge::new(
+            "subscription_active",
+            "1 while subscribed to the provider or polling it without errors",
+        )
+        .unwrap();
+        let last_activity = IntGauge::new(
+            "last_activity_timestamp_seconds",
+            "Unix time of the last log, block, poll or heartbeat from the provider",
+        )
+        .unwrap();
+        let swap_rate = Gauge::new(
+            "swap_rate_per_second",
+            "Swaps processed per second over the last throughput interval",
+        )
+        .unwrap();
+        let price_ema = GaugeVec::new(
+            Opts::new(
+                "pool_price_ema",
+                "Exponential moving average of the swap price, token0 in token1, by pool",
+            ),
+            &["pool"],
+        )
+        .unwrap();
+        // Registering distinct names in a fresh registry cannot fail
+        for collector in [
+            Box::new(swaps_processed.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(db_insert_errors.clone()),
+            Box::new(db_writes_paused.clone()),
+            Box::new(decode_failures.clone()),
+            Box::new(reconnects.clone()),
+            Box::new(last_block_seen.clone()),
+            Box::new(subscription_active.clone()),
+            Box::new(last_activity.clone()),
+            Box::new(swap_rate.clone()),
+            Box::new(price_ema.clone()),
+        ] {
+            registry.register(collector).unwrap();
+        }
+        Metrics {
+            registry,
+            swaps_processed,
+            db_insert_errors,
+            db_writes_paused,
+            decode_failures,
+            reconnects,
+            last_block_seen,
+            subscription_active,
+            last_activity,
+            swap_rate,
+            price_ema,
+        }
+    }
+
+    /// Raises `last_block_seen` to `block_number` unless it is already higher,
+    /// so a reorged or backfilled older block does not move it back.
+    pub fn observe_block(&self, block_number: u64) {
+        let block_number = i64::try_from(block_number).unwrap_or(i64::MAX);
+        if block_number > self.last_block_seen.get() {
+            self.last_block_seen.set(block_number);
+        }
+    }
+
+    /// Records whether the monitor is subscribed to the provider. Subscribing
+    /// counts as activity.
+    pub fn set_subscribed(&self, active: bool) {
+        self.subscription_active.set(i64::from(active));
+        if active {
+            self.record_activity();
+        }
+    }
+
+    /// Counts a log that failed to decode. `reason` is `unknown_event` for a
+    /// log of an event the monitor does not decode, `missing_topics` for one
+    /// with fewer indexed parameters than the event has, as some forks emit,
+    /// and `invalid_data` for data of an unexpected length or layout.
+    pub fn record_decode_failure(&self, reason: &str) {
+        self.decode_failures.with_label_values(&[reason]).inc();
+    }
+
+    /// Sets the `price_ema` of `pool`.
+    pub fn record_price_ema(&self, pool: Address, price: f64) {
+        self.price_ema
+            .with_label_values(&[&format!("{:#x}", pool)])
+            .set(price);
+    }
+
+    /// Records that the provider was heard from just now.
+    pub fn record_activity(&self) {
+        self.last_activity.set(unix_time());
+    }
+
+    /// Sets `swap_rate` from the `swaps` processed within `interval` and
+    /// returns the rate.
+    pub fn record_throughput(&self, swaps: u64, interval: Duration) -> f64 {
+        let rate = swaps as f64 / interval.as_secs_f64().max(f64::MIN_POSITIVE);
+        self.swap_rate.set(rate);
+        rate
+    }
+
+    /// Whether the subscription is active and the provider was heard from
+    /// within `max_age`, as `/healthz` reports.
+    pub fn is_healthy(&self, max_age: Duration) -> bool {
+        let age = unix_time().saturating_sub(self.last_activity.get());
+        self.subscription_active.get() == 1
+            && u64::try_from(age).is_ok_and(|age| age <= max_age.as_secs())
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        // Encoding into a Vec only fails for malformed metric families
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+fn unix_time() -> i64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    i64::try_from(now.as_secs()).unwrap_or(i64::MAX)
+}
+
+/// The metrics of this process, shared by the producer tasks and `run`.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+async fn handle_request(
+    request: Request<Body>,
+    health_max_age: Duration,
+) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(header::CONTENT_TYPE, TextEncoder::new().format_type())
+            .body(Body::from(METRICS.encode())),
+        (&Method::GET, "/healthz") if METRICS.is_healthy(health_max_age) => {
+            Response::builder().body(Body::from("ok\n"))
+        }
+        (&Method::GET, "/healthz") => Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(Body::from("no recent activity from the provider\n")),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.unwrap())
+}
+
+/// Serves `METRICS` on `GET /metrics` at `addr` in a background task and
+/// returns the bound address, which tells the port when `addr` uses port 0.
+///
+/// `GET /healthz` answers 200 while the subscription is active and the
+/// provider was heard from within `health_max_age`, and 503 otherwise, for
+/// liveness probes to restart a wedged monitor.
+pub fn spawn_metrics_server(
+    addr: SocketAddr,
+    health_max_age: Duration,
+) -> Result<(SocketAddr, JoinHandle<()>)> {
+    let server = Server::try_bind(&addr)
+        .map_err(|e| eyre!("failed to bind metrics server to {}: {}", addr, e))?
+        .serve(make_service_fn(move |_| async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(request, health_max_age)
+            }))
+        }));
+    let local_addr = server.local_addr();
+    info!(addr = %local_addr, "serving metrics");
+    let handle = tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!(error = %e, "metrics server failed");
+        }
+    });
+    Ok((local_addr, handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observe_block_only_moves_forward() {
+        let metrics = Metrics::new();
+        metrics.observe_block(17_500_000);
+        metrics.observe_block(17_499_999);
+        assert_eq!(metrics.last_block_seen.get(), 17_500_000);
+        metrics.observe_block(u64::MAX);
+        assert_eq!(metrics.last_block_seen.get(), i64::MAX);
+    }
+
+    #[test]
+    fn test_record_throughput() {
+        let metrics = Metrics::new();
+        let rate = metrics.record_throughput(144, Duration::from_secs(60));
+        assert_eq!(rate, 2.4);
+        assert_eq!(metrics.swap_rate.get(), 2.4);
+        assert_eq!(metrics.record_throughput(0, Duration::from_secs(60)), 0.0);
+    }
+
+    #[test]
+    fn test_record_decode_failure() {
+        let metrics = Metrics::new();
+        metrics.record_decode_failure("invalid_data");
+        metrics.record_decode_failure("invalid_data");
+        metrics.record_decode_failure("missing_topics");
+        let count = |reason| metrics.decode_failures.with_label_values(&[reason]).get();
+        assert_eq!(count("invalid_data"), 2);
+        assert_eq!(count("missing_topics"), 1);
+        assert!(metrics
+            .encode()
+            .contains("decode_failures_total{reason=\"invalid_data\"} 2"));
+    }
+
+    #[test]
+    fn test_record_price_ema() {
+        let metrics = Metrics::new();
+        metrics.record_price_ema(Address::from_low_u64_be(1), 1_850.5);
+        assert!(metrics.encode().contains(
+            "pool_price_ema{pool=\"0x0000000000000000000000000000000000000001\"} 1850.5"
+        ));
+    }
+
+    #[test]
+    fn test_is_healthy() {
+        let metrics = Metrics::new();
+        assert!(!metrics.is_healthy(Duration::from_secs(60)));
+        metrics.set_subscribed(true);
+        assert!(metrics.is_healthy(Duration::from_secs(60)));
+
+        // Subscribed, but silent for longer than the window
+        metrics.last_activity.set(unix_time() - 120);
+        assert!(!metrics.is_healthy(Duration::from_secs(60)));
+        metrics.record_activity();
+        assert!(metrics.is_healthy(Duration::from_secs(60)));
+        metrics.set_subscribed(false);
+        assert!(!metrics.is_healthy(Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let (addr, server) =
+            spawn_metrics_server(([127, 0, 0, 1], 0).into(), Duration::from_secs(60)).unwrap();
+        METRICS.reconnects.inc();
+
+        let body = reqwest::get(format!("http://{}/metrics", addr))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        for name in [
+            "swaps_processed_total",
+            "db_insert_errors_total",
+            "db_writes_paused",
+            "reconnects_total",
+            "last_block_seen",
+            "subscription_active",
+            "last_activity_timestamp_seconds",
+            "swap_rate_per_second",
+        ] {
+            assert!(body.contains(&format!("# TYPE {name}")), "{name} missing");
+        }
+        // Other tests may increment the shared counter concurrently
+        let reconnects = body
+            .lines()
+            .find_map(|line| line.strip_prefix("reconnects_total "))
+            .unwrap();
+        assert!(reconnects.parse::<u64>().unwrap() >= 1);
+
+        let status = reqwest::get(format!("http://{}/other", addr))
+            .await
+            .unwrap()
+            .status();
+        assert_eq!(status, reqwest::StatusCode::NOT_FOUND);
+
+        // Other tests may subscribe concurrently, so only the codes are checked
+        let status = reqwest::get(format!("http://{}/healthz", addr))
+            .await
+            .unwrap()
+            .status();
+        assert!(
+            status == reqwest::StatusCode::OK || status == reqwest::StatusCode::SERVICE_UNAVAILABLE,
+            "{status}"
+        );
+        server.abort();
+    }
+}
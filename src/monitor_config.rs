@@ -0,0 +1,227 @@
+use crate::error::MonitorError;
+use crate::store::{open_store, DurabilityMode, SwapStore};
+use crate::{run_with_sinks, LogFormat, MonitorOptions, PoolVersion, SwapSink, TokenDecimals};
+use ethers::core::types::{Address, U256};
+use ethers::providers::Authorization;
+
+/// Builds the arguments of `run` step by step, for call sites that set more
+/// than a few options:
+///
+/// ```no_run
+/// # use uniswap_swap_monitor::MonitorConfig;
+/// # use ethers::core::types::U256;
+/// # async fn monitor() -> eyre::Result<()> {
+/// let processed = MonitorConfig::new()
+///     .provider("wss://eth-mainnet.example/ws")
+///     .pools(["0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640"])
+///     .db_path("swaps.db")
+///     .min_amount0(U256::from(1_000_000_000u64))
+///     .confirmations(12)
+///     .run()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Settings without a method of their own are set on `MonitorOptions` and
+/// passed with `options`.
+#[derive(Default)]
+pub struct MonitorConfig {
+    provider_urls: Vec<String>,
+    pools: Vec<String>,
+    db_path: Option<String>,
+    durability: DurabilityMode,
+    store: Option<Box<dyn SwapStore>>,
+    sinks: Vec<Box<dyn SwapSink>>,
+    options: MonitorOptions,
+}
+
+impl MonitorConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a WebSocket or HTTP(S) provider URL. Providers added later are
+    /// fallbacks of the first, see `ReconnectPolicy`.
+    pub fn provider(mut self, provider_url: impl Into<String>) -> Self {
+        self.provider_urls.push(provider_url.into());
+        self
+    }
+
+    /// Sends `authorization` to the providers, see `ReconnectPolicy`.
+    pub fn authorization(mut self, authorization: Authorization) -> Self {
+        self.options.reconnect.authorization = Some(authorization);
+        self
+    }
+
+    /// Adds pool addresses to monitor.
+    pub fn pools(mut self, pools: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.pools.extend(pools.into_iter().map(Into::into));
+        self
+    }
+
+    /// Stores the events in the SQLite database at `db_path`, or PostgreSQL
+    /// for a `postgres://` URL, opened with `open_store`.
+    pub fn db_path(mut self, db_path: impl Into<String>) -> Self {
+        self.db_path = Some(db_path.into());
+        self
+    }
+
+    /// How SQLite databases opened from `db_path` sync to disk.
+    pub fn durability(mut self, durability: DurabilityMode) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Stores the events in `store` instead of a database opened from
+    /// `db_path`.
+    pub fn store(mut self, store: Box<dyn SwapStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Also passes every stored event to `sink`, see `run_with_sinks`.
+    pub fn sink(mut self, sink: impl SwapSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Replaces all options set so far, for settings without a method here.
+    pub fn options(mut self, options: MonitorOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn pool_version(mut self, pool_version: PoolVersion) -> Self {
+        self.options.pool_version = pool_version;
+        self
+    }
+
+    /// The token decimals of `pool`, see `MonitorOptions::token_decimals`.
+    pub fn token_decimals(mut self, pool: Address, decimals: TokenDecimals) -> Self {
+        self.options.token_decimals.insert(pool, decimals);
+        self
+    }
+
+    /// Only stores swaps with an absolute raw amount0 of at least `amount`,
+    /// see `SwapFilter`.
+    pub fn min_amount0(mut self, amount: U256) -> Self {
+        self.options.filter.min_abs_amount0 = Some(amount);
+        self
+    }
+
+    /// Only stores swaps with an absolute raw amount1 of at least `amount`,
+    /// see `SwapFilter`.
+    pub fn min_amount1(mut self, amount: U256) -> Self {
+        self.options.filter.min_abs_amount1 = Some(amount);
+        self
+    }
+
+    /// Tags every stored swap with `source_tag`, see `MonitorOptions::source_tag`.
+    pub fn source_tag(mut self, source_tag: impl Into<String>) -> Self {
+        self.options.source_tag = Some(source_tag.into());
+        self
+    }
+
+    /// See `MonitorOptions::confirmations`.
+    pub fn confirmations(mut self, confirmations: u64) -> Self {
+        self.options.confirmations = confirmations;
+        self
+    }
+
+    /// Backfills from `from_block` before monitoring live events.
+    pub fn from_block(mut self, from_block: u64) -> Self {
+        self.options.backfill_from = Some(from_block);
+        self
+    }
+
+    /// Stops once the events of `until_block` have been stored.
+    pub fn until_block(mut self, until_block: u64) -> Self {
+        self.options.until_block = Some(until_block);
+        self
+    }
+
+    pub fn log_format(mut self, log_format: LogFormat) -> Self {
+        self.options.log_format = log_format;
+        self
+    }
+
+    /// Stores events without printing them, see `MonitorOptions::print`.
+    pub fn quiet(mut self) -> Self {
+        self.options.print = false;
+        self
+    }
+
+    /// Opens the store, unless one was given, and calls `run_with_sinks`.
+    pub async fn run(self) -> eyre::Result<u64> {
+        let store = match (self.store, self.db_path) {
+            (Some(store), _) => store,
+            (None, Some(db_path)) => open_store(&db_path, self.durability).await?,
+            (None, None) => {
+                return Err(MonitorError::InvalidConfig(
+                    "no database given, set db_path or store".to_string(),
+                )
+                .into())
+            }
+        };
+        run_with_sinks(
+            &self.provider_urls,
+            &self.pools,
+            store,
+            self.sinks,
+            self.options,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::NullStore;
+    use crate::StdoutSink;
+
+    #[test]
+    fn test_monitor_config_sets_options() {
+        let pool = Address::from_low_u64_be(1);
+        let config = MonitorConfig::new()
+            .provider("wss://one.example")
+            .provider("wss://two.example")
+            .pools([format!("{:#x}", pool)])
+            .min_amount1(U256::from(5))
+            .confirmations(3)
+            .source_tag("eu-1")
+            .until_block(100)
+            .sink(StdoutSink::new(LogFormat::Json))
+            .quiet();
+        assert_eq!(
+            config.provider_urls,
+            ["wss://one.example", "wss://two.example"]
+        );
+        assert_eq!(config.pools, [format!("{:#x}", pool)]);
+        assert_eq!(config.sinks.len(), 1);
+        assert_eq!(config.options.filter.min_abs_amount0, None);
+        assert_eq!(config.options.filter.min_abs_amount1, Some(U256::from(5)));
+        assert_eq!(config.options.confirmations, 3);
+        assert_eq!(config.options.source_tag.as_deref(), Some("eu-1"));
+        assert_eq!(config.options.until_block, Some(100));
+        assert!(!config.options.print);
+    }
+
+    #[tokio::test]
+    async fn test_monitor_config_run_validates() {
+        let err = MonitorConfig::new()
+            .provider("wss://one.example")
+            .run()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("no database given"), "{err}");
+
+        let err = MonitorConfig::new()
+            .store(Box::new(NullStore))
+            .run()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("no provider URL given"), "{err}");
+    }
+}
@@ -0,0 +1,260 @@
+use crate::error::{MonitorError, Result};
+use crate::{to_token_units, CombinedLog, PoolMetadata};
+use ethers::{
+    contract::abigen,
+    core::types::{Address, I256, U256},
+    providers::Middleware,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+abigen!(
+    AggregatorV3Interface,
+    r#"[
+        function decimals() external view returns (uint8)
+        function latestRoundData() external view returns (uint80 roundId, int256 answer, uint256 startedAt, uint256 updatedAt, uint80 answeredInRound)
+    ]"#;
+);
+
+/// Time between two reads of the price feeds. Swaps in between are valued at
+/// the cached prices, so valuing a swap never calls the provider.
+pub const PRICE_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Answers that were not updated for this long are stale. The longest
+/// heartbeat of Chainlink's USD feeds is a day, so an hour of slack is added.
+const MAX_ANSWER_AGE: Duration = Duration::from_secs(25 * 60 * 60);
+
+/// The USD prices of tokens with a Chainlink price feed, as last read by the
+/// task of `spawn_price_oracle`.
+#[derive(Debug, Clone, Default)]
+pub struct UsdPrices {
+    prices: Arc<RwLock<HashMap<Address, f64>>>,
+}
+
+impl UsdPrices {
+    /// The USD price of one whole `token`, unless its feed failed or gave an
+    /// unusable answer on the last read.
+    pub fn get(&self, token: Address) -> Option<f64> {
+        self.prices.read().unwrap().get(&token).copied()
+    }
+
+    fn set(&self, token: Address, price: Option<f64>) {
+        let mut prices = self.prices.write().unwrap();
+        match price {
+            Some(price) => prices.insert(token, price),
+            None => prices.remove(&token),
+        };
+    }
+
+    /// The dollar value of a swap of the pool described by `metadata`: the
+    /// normalized amount of its first token with a price, times that price.
+    pub fn swap_value(
+        &self,
+        combined_log: &CombinedLog,
+        metadata: Option<&PoolMetadata>,
+    ) -> Option<f64> {
+        let metadata = metadata?;
+        [
+            (metadata.token0.address, combined_log.amount0_normalized),
+            (metadata.token1.address, combined_log.amount1_normalized),
+        ]
+        .into_iter()
+        .find_map(|(token, amount)| Some(amount?.abs() * self.get(token)?))
+    }
+}
+
+/// The price in a feed's round as returned by `latestRoundData`, or `None` for
+/// an answer that must not be used: one that is not positive, from a round
+/// that has not completed, or older than `MAX_ANSWER_AGE` at `now`.
+fn round_price(round: (u128, I256, U256, U256, u128), decimals: u8, now: u64) -> Option<f64> {
+    let (round_id, answer, _, updated_at, answered_in_round) = round;
+    if answer <= I256::zero() || updated_at.is_zero() || answered_in_round < round_id {
+        return None;
+    }
+    let age = now.saturating_sub(updated_at.try_into().unwrap_or(u64::MAX));
+    if age > MAX_ANSWER_AGE.as_secs() {
+        return None;
+    }
+    Some(to_token_units(answer, decimals))
+}
+
+async fn fetch_feed_price<M: Middleware + 'static>(
+    client: &Arc<M>,
+    feed: Address,
+) -> Result<Option<f64>> {
+    let contract = AggregatorV3Interface::new(feed, client.clone());
+    let decimals = contract.decimals().call().await.map_err(|e| {
+        MonitorError::ProviderConnect(format!(
+            "failed to fetch decimals of price feed {:?}: {}",
+            feed, e
+        ))
+    })?;
+    let round = contract.latest_round_data().call().await.map_err(|e| {
+        MonitorError::ProviderConnect(format!(
+            "failed to fetch the latest round of price feed {:?}: {}",
+            feed, e
+        ))
+    })?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok(round_price(round, decimals, now))
+}
+
+async fn refresh_prices<M: Middleware + 'static>(
+    client: &Arc<M>,
+    feeds: &HashMap<Address, Address>,
+    prices: &UsdPrices,
+) {
+    for (&token, &feed) in feeds {
+        let price = match fetch_feed_price(client, feed).await {
+            Ok(Some(price)) => Some(price),
+            Ok(None) => {
+                warn!(
+                    ?token,
+                    ?feed,
+                    "price feed answer is stale or invalid, not valuing swaps"
+                );
+                None
+            }
+            Err(e) => {
+                warn!(?token, ?feed, error = %e, "failed to read price feed, not valuing swaps");
+                None
+            }
+        };
+        prices.set(token, price);
+    }
+}
+
+/// Reads the price of each token of `feeds`, which maps tokens to their
+/// Chainlink USD price feeds, and returns the prices along with a background
+/// task that reads them again every `PRICE_REFRESH_INTERVAL`.
+///
+/// A token whose feed cannot be read, or answers with a stale or non-positive
+/// price, has no price until a later read succeeds, so its swaps get no USD
+/// value rather than a wrong one.
+pub async fn spawn_price_oracle<M: Middleware + 'static>(
+    client: Arc<M>,
+    feeds: HashMap<Address, Address>,
+) -> (UsdPrices, JoinHandle<()>) {
+    let prices = UsdPrices::default();
+    refresh_prices(&client, &feeds, &prices).await;
+    let task_prices = prices.clone();
+    let handle = tokio::spawn(async move {
+        let start = tokio::time::Instant::now() + PRICE_REFRESH_INTERVAL;
+        let mut interval = tokio::time::interval_at(start, PRICE_REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            refresh_prices(&client, &feeds, &task_prices).await;
+        }
+    });
+    (prices, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::create_test_combined_log;
+    use crate::TokenMetadata;
+    use ethers::abi::{encode, Token};
+    use ethers::core::types::Bytes;
+    use ethers::providers::Provider;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn round(answer: i64, updated_at: u64) -> (u128, I256, U256, U256, u128) {
+        (
+            7,
+            I256::from(answer),
+            U256::from(updated_at),
+            U256::from(updated_at),
+            7,
+        )
+    }
+
+    #[test]
+    fn test_round_price() {
+        assert_eq!(
+            round_price(round(185_012_345_678, NOW), 8, NOW),
+            Some(1_850.123_456_78)
+        );
+        assert_eq!(
+            round_price(round(100_000_000, NOW - 3_600), 8, NOW),
+            Some(1.0)
+        );
+
+        // Unusable answers are skipped instead of valuing swaps with them
+        assert_eq!(round_price(round(-1, NOW), 8, NOW), None);
+        assert_eq!(round_price(round(0, NOW), 8, NOW), None);
+        assert_eq!(
+            round_price(round(100_000_000, NOW - 26 * 3_600), 8, NOW),
+            None
+        );
+        assert_eq!(round_price(round(100_000_000, 0), 8, NOW), None);
+        let mut unfinished = round(100_000_000, NOW);
+        unfinished.4 = 6;
+        assert_eq!(round_price(unfinished, 8, NOW), None);
+    }
+
+    #[test]
+    fn test_swap_value() {
+        let mut swap = create_test_combined_log();
+        swap.amount0_normalized = Some(-263.5);
+        swap.amount1_normalized = Some(0.5);
+        let token = |address: u64, symbol: &str| TokenMetadata {
+            address: Address::from_low_u64_be(address),
+            symbol: symbol.to_string(),
+            decimals: 18,
+        };
+        let metadata = PoolMetadata {
+            pool: swap.pool,
+            token0: token(1, "LINK"),
+            token1: token(2, "WETH"),
+        };
+        let prices = UsdPrices::default();
+        assert_eq!(prices.swap_value(&swap, Some(&metadata)), None);
+
+        prices.set(metadata.token1.address, Some(2_000.0));
+        assert_eq!(prices.swap_value(&swap, Some(&metadata)), Some(1_000.0));
+        // token0 is used when both have a price
+        prices.set(metadata.token0.address, Some(10.0));
+        assert_eq!(prices.swap_value(&swap, Some(&metadata)), Some(2_635.0));
+        assert_eq!(prices.swap_value(&swap, None), None);
+
+        prices.set(metadata.token0.address, None);
+        swap.amount1_normalized = None;
+        assert_eq!(prices.swap_value(&swap, Some(&metadata)), None);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_price_oracle() {
+        let (client, mock) = Provider::mocked();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let latest_round = encode(&[
+            Token::Uint(7.into()),
+            Token::Int(U256::from(200_000_000_000u64)),
+            Token::Uint(now.into()),
+            Token::Uint(now.into()),
+            Token::Uint(7.into()),
+        ]);
+        // The mock answers the last pushed response first
+        mock.push::<Bytes, _>(Bytes::from(latest_round)).unwrap();
+        mock.push::<Bytes, _>(Bytes::from(encode(&[Token::Uint(8.into())])))
+            .unwrap();
+
+        let weth = Address::from_low_u64_be(2);
+        let other = Address::from_low_u64_be(3);
+        let feeds = HashMap::from([(weth, Address::from_low_u64_be(9))]);
+        let (prices, task) = spawn_price_oracle(Arc::new(client), feeds).await;
+        assert_eq!(prices.get(weth), Some(2_000.0));
+        assert_eq!(prices.get(other), None);
+        task.abort();
+    }
+}
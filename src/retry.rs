@@ -0,0 +1,123 @@
+use rand::Rng;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+/// Attempts made for a single enrichment call to the provider, such as fetching
+/// a block's timestamp.
+pub(crate) const PROVIDER_CALL_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry of a provider call, doubled for each further one.
+pub(crate) const PROVIDER_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// The part of `backoff_delay` that is randomly taken off at most.
+const BACKOFF_JITTER: f64 = 0.5;
+
+/// The delay before retry number `retry`, counting from 1: `base_delay` doubled
+/// for every earlier retry, with a random part of up to half of it taken off,
+/// see `jittered`.
+pub(crate) fn backoff_delay(base_delay: Duration, retry: u32) -> Duration {
+    let delay = base_delay.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+    jittered(delay, BACKOFF_JITTER)
+}
+
+/// Shortens `delay` by a random part of up to `jitter`, between 0 and 1, of
+/// it, so that monitors sharing a node do not retry in lockstep.
+pub(crate) fn jittered(delay: Duration, jitter: f64) -> Duration {
+    jitter_delay(delay, jitter, rand::thread_rng().gen())
+}
+
+/// Shortens `delay` by `sample`, between 0 and 1, times `jitter` of it.
+pub(crate) fn jitter_delay(delay: Duration, jitter: f64, sample: f64) -> Duration {
+    delay.mul_f64(1.0 - jitter.clamp(0.0, 1.0) * sample)
+}
+
+/// Calls `f` until it succeeds, at most `attempts` times, sleeping with jittered
+/// exponential backoff from `base_delay` between attempts. Returns the error of
+/// the last attempt when all of them fail.
+pub(crate) async fn retry<F, Fut, T, E>(
+    attempts: u32,
+    base_delay: Duration,
+    mut f: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Display,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < attempts => {
+                let delay = backoff_delay(base_delay, attempt);
+                warn!(error = %e, attempt, ?delay, "provider call failed, retrying");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay_is_jittered_exponential() {
+        let base_delay = Duration::from_millis(100);
+        for _ in 0..100 {
+            let delay = backoff_delay(base_delay, 3);
+            assert!(delay >= Duration::from_millis(200), "{delay:?}");
+            assert!(delay <= Duration::from_millis(400), "{delay:?}");
+        }
+        // Saturates instead of overflowing
+        assert!(backoff_delay(Duration::MAX, u32::MAX) > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_jitter_delay() {
+        let delay = Duration::from_secs(10);
+        assert_eq!(jitter_delay(delay, 0.5, 0.0), delay);
+        assert_eq!(jitter_delay(delay, 0.5, 0.5), Duration::from_millis(7_500));
+        assert_eq!(jitter_delay(delay, 0.5, 1.0), Duration::from_secs(5));
+        // A jitter outside of 0 to 1 is clamped
+        assert_eq!(jitter_delay(delay, 1.5, 1.0), Duration::ZERO);
+        assert_eq!(jitter_delay(delay, -1.0, 1.0), delay);
+        assert_eq!(jittered(delay, 0.0), delay);
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let mut calls = 0;
+        let result = retry(3, Duration::from_millis(1), || {
+            calls += 1;
+            let result = if calls < 3 { Err("timeout") } else { Ok(calls) };
+            async move { result }
+        })
+        .await;
+        assert_eq!(result, Ok(3));
+
+        let mut calls = 0;
+        let result: Result<(), String> = retry(2, Duration::from_millis(1), || {
+            calls += 1;
+            let error = format!("timeout {calls}");
+            async move { Err(error) }
+        })
+        .await;
+        assert_eq!(result, Err("timeout 2".to_string()));
+
+        // A single attempt is never retried, and neither is zero
+        for attempts in [0, 1] {
+            let mut calls = 0;
+            let _: Result<(), &str> = retry(attempts, Duration::from_millis(1), || {
+                calls += 1;
+                async { Err("timeout") }
+            })
+            .await;
+            assert_eq!(calls, 1);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{print_event, print_log, CombinedLog, LogFormat, PoolEvent, PoolMetadata};
+use ethers::core::types::Address;
+use eyre::Result;
+use std::collections::HashMap;
+
+/// A destination that `run` passes events to besides the store, such as the
+/// console, a CSV file or a webhook.
+///
+/// Every sink sees each event that passed the filters, retracted swaps
+/// included, in the order they arrive. A sink that fails is logged with a
+/// warning and skipped for that event, so it never keeps the other sinks or
+/// the store from getting it.
+pub trait SwapSink: Send {
+    /// Handles a swap, with the metadata of its pool when that is known.
+    fn handle(&mut self, swap: &CombinedLog, metadata: Option<&PoolMetadata>) -> Result<()>;
+
+    /// Handles an event of any kind. The default passes swaps to `handle` and
+    /// ignores the other kinds.
+    fn handle_event(
+        &mut self,
+        event: &PoolEvent,
+        pools: &HashMap<Address, PoolMetadata>,
+    ) -> Result<()> {
+        match event {
+            PoolEvent::Swap(swap) => self.handle(swap, pools.get(&swap.pool)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes out what the sink buffers. Called whenever a batch of events is
+    /// committed to the store.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once when the monitor stops.
+    fn close(&mut self) -> Result<()> {
+        self.flush()
+    }
+
+    /// Names the sink in the warnings about its failures.
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// Prints every event on the console in `log_format`, as the monitor does
+/// unless `MonitorOptions::print` is turned off.
+pub struct StdoutSink {
+    log_format: LogFormat,
+}
+
+impl StdoutSink {
+    pub fn new(log_format: LogFormat) -> Self {
+        StdoutSink { log_format }
+    }
+}
+
+impl SwapSink for StdoutSink {
+    fn handle(&mut self, swap: &CombinedLog, metadata: Option<&PoolMetadata>) -> Result<()> {
+        match self.log_format {
+            LogFormat::Pretty => print_log(swap, metadata),
+            LogFormat::Json => println!("{}", swap.to_json()),
+        }
+        Ok(())
+    }
+
+    fn handle_event(
+        &mut self,
+        event: &PoolEvent,
+        pools: &HashMap<Address, PoolMetadata>,
+    ) -> Result<()> {
+        print_event(event, self.log_format, pools);
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+}
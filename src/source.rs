@@ -0,0 +1,93 @@
+use crate::error::MonitorError;
+use async_trait::async_trait;
+use ethers::{
+    core::types::{Filter, Log},
+    providers::{Middleware, Provider, StreamExt, Ws},
+};
+use futures_util::{future, Stream};
+use std::pin::Pin;
+
+/// Logs delivered by a subscription, in the order they arrived.
+pub(crate) type LogStream<'a> = Pin<Box<dyn Stream<Item = Log> + Send + 'a>>;
+
+/// Block numbers of the new heads delivered by a subscription.
+pub(crate) type HeadStream<'a> = Pin<Box<dyn Stream<Item = u64> + Send + 'a>>;
+
+/// A connection that pushes logs and new heads, which `handle_logs` follows.
+///
+/// It is implemented for WebSocket providers, and in tests for a mock that
+/// replays a fixed list of logs, so the live stream runs without an endpoint.
+#[async_trait]
+pub(crate) trait LogSource: Send + Sync {
+    type Client: Middleware;
+
+    /// The client that block timestamps and the logs of whole blocks are
+    /// fetched with.
+    fn client(&self) -> &Self::Client;
+
+    /// Subscribes to the logs matching `filter`.
+    async fn subscribe(&self, filter: &Filter) -> Result<LogStream<'_>, MonitorError>;
+
+    /// Subscribes to new blocks.
+    async fn subscribe_heads(&self) -> Result<HeadStream<'_>, MonitorError>;
+}
+
+#[async_trait]
+impl LogSource for Provider<Ws> {
+    type Client = Self;
+
+    fn client(&self) -> &Self {
+        self
+    }
+
+    async fn subscribe(&self, filter: &Filter) -> Result<LogStream<'_>, MonitorError> {
+        let stream = self
+            .subscribe_logs(filter)
+            .await
+            .map_err(|e| MonitorError::ProviderConnect(e.to_string()))?;
+        Ok(Box::pin(stream))
+    }
+
+    async fn subscribe_heads(&self) -> Result<HeadStream<'_>, MonitorError> {
+        let heads = self
+            .subscribe_blocks()
+            .await
+            .map_err(|e| MonitorError::ProviderConnect(e.to_string()))?;
+        // Only pending blocks lack a number, and they are not subscribed to
+        let heads =
+            heads.filter_map(|block| future::ready(block.number.map(|number| number.as_u64())));
+        Ok(Box::pin(heads))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ethers::providers::MockProvider;
+
+    /// Replays `logs` to every log subscription and `heads` to every
+    /// subscription to new blocks, then ends them as a provider closing the
+    /// subscription would. Other requests go to `client`.
+    pub(crate) struct MockLogSource {
+        pub(crate) client: Provider<MockProvider>,
+        pub(crate) logs: Vec<Log>,
+        pub(crate) heads: Vec<u64>,
+    }
+
+    #[async_trait]
+    impl LogSource for MockLogSource {
+        type Client = Provider<MockProvider>;
+
+        fn client(&self) -> &Self::Client {
+            &self.client
+        }
+
+        async fn subscribe(&self, _filter: &Filter) -> Result<LogStream<'_>, MonitorError> {
+            Ok(Box::pin(futures_util::stream::iter(self.logs.clone())))
+        }
+
+        async fn subscribe_heads(&self) -> Result<HeadStream<'_>, MonitorError> {
+            Ok(Box::pin(futures_util::stream::iter(self.heads.clone())))
+        }
+    }
+}
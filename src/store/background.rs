@@ -0,0 +1,386 @@
+use super::SwapStore;
+use crate::error::{MonitorError, Result};
+use crate::{PoolEvent, PoolMetadata};
+use async_trait::async_trait;
+use ethers::core::types::Address;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tokio::sync::{mpsc, oneshot};
+
+/// Writes queued before `insert` and `insert_batch` wait for the writer thread
+/// to catch up, which bounds the memory a slow disk can take up.
+const WRITER_QUEUE_CAPACITY: usize = 16;
+
+/// A write that `insert`, `insert_batch` and `advance_checkpoint` queue
+/// without waiting for it.
+enum Write {
+    Insert(Box<PoolEvent>),
+    Batch(Vec<PoolEvent>),
+    Checkpoint(u64),
+}
+
+enum Command {
+    Init(oneshot::Sender<Result<()>>),
+    Write(Write),
+    /// Stores the writes held back since one failed, see `BackgroundStore`.
+    Retry(oneshot::Sender<Result<()>>),
+    Delete(PoolEvent, oneshot::Sender<Result<usize>>),
+    Sync(oneshot::Sender<Result<()>>),
+    ReadCheckpoint(oneshot::Sender<Result<Option<u64>>>),
+    InsertPool(PoolMetadata, oneshot::Sender<Result<()>>),
+    ReadPool(Address, oneshot::Sender<Result<Option<PoolMetadata>>>),
+    Prune(u64, oneshot::Sender<Result<usize>>),
+}
+
+/// Runs a store on a thread of its own, so blocking writes such as the disk
+/// syncs of SQLite do not stall the tokio workers that follow the stream.
+///
+/// `insert` and `insert_batch` return once the events are queued, and the
+/// other methods once the writer has carried out everything queued before
+/// them, so reads see all earlier writes. When a queued write fails, the
+/// writer keeps it and holds back the writes queued after it, so no later
+/// batch moves the checkpoint past the events that were not stored. The next
+/// call retries them in order first, and returns the error if they still fail,
+/// so a store that recovers, such as a disk that was briefly full, loses
+/// nothing. Dropping the store waits for the queued writes to finish.
+pub struct BackgroundStore {
+    commands: Option<mpsc::Sender<Command>>,
+    /// The error of the first queued write that failed.
+    failure: Arc<Mutex<Option<String>>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl BackgroundStore {
+    /// Moves `store` to a new writer thread.
+    pub fn spawn(store: Box<dyn SwapStore>) -> Result<Self> {
+        let (commands, receiver) = mpsc::channel(WRITER_QUEUE_CAPACITY);
+        let failure = Arc::new(Mutex::new(None));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .map_err(|e| MonitorError::Database(format!("failed to start writer: {}", e)))?;
+        let writer_failure = failure.clone();
+        let writer = std::thread::Builder::new()
+            .name("store-writer".to_string())
+            .spawn(move || runtime.block_on(run_writer(store, receiver, writer_failure)))
+            .map_err(|e| MonitorError::Database(format!("failed to start writer: {}", e)))?;
+        Ok(BackgroundStore {
+            commands: Some(commands),
+            failure,
+            writer: Some(writer),
+        })
+    }
+
+    fn check_failure(&self) -> Result<()> {
+        match self.failure.lock().unwrap().as_ref() {
+            Some(e) => Err(failed_write(e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Has the writer store the writes it held back after a failed write, if
+    /// any, and fails if they still cannot be stored.
+    async fn retry_failed_writes(&self) -> Result<()> {
+        if self.failure.lock().unwrap().is_none() {
+            return Ok(());
+        }
+        let (reply, response) = oneshot::channel();
+        let commands = self.commands.as_ref().expect("writer is running");
+        if commands.send(Command::Retry(reply)).await.is_err() {
+            return Err(writer_stopped());
+        }
+        response.await.map_err(|_| writer_stopped())?
+    }
+
+    async fn send(&self, command: Command) -> Result<()> {
+        self.retry_failed_writes().await?;
+        let commands = self.commands.as_ref().expect("writer is running");
+        if commands.send(command).await.is_err() {
+            self.check_failure()?;
+            return Err(writer_stopped());
+        }
+        Ok(())
+    }
+
+    /// Queues the command built around a reply channel and waits for its reply.
+    async fn request<T>(
+        &self,
+        command: impl Fn(oneshot::Sender<Result<T>>) -> Command,
+    ) -> Result<T> {
+        if let Some(result) = self.try_request(&command).await {
+            return result;
+        }
+        // A write queued before the command failed, so the write is retried
+        // and the command sent once more
+        match self.try_request(&command).await {
+            Some(result) => result,
+            None => {
+                self.check_failure()?;
+                Err(writer_stopped())
+            }
+        }
+    }
+
+    /// Like `request`, but returns `None` when the writer dropped the reply,
+    /// as it does while a write has failed.
+    async fn try_request<T>(
+        &self,
+        command: &impl Fn(oneshot::Sender<Result<T>>) -> Command,
+    ) -> Option<Result<T>> {
+        let (reply, response) = oneshot::channel();
+        if let Err(e) = self.send(command(reply)).await {
+            return Some(Err(e));
+        }
+        response.await.ok()
+    }
+}
+
+fn failed_write(error: &str) -> MonitorError {
+    MonitorError::Database(format!("an earlier write failed: {}", error))
+}
+
+fn writer_stopped() -> MonitorError {
+    MonitorError::Database("the writer thread has stopped".to_string())
+}
+
+async fn run_writer(
+    mut store: Box<dyn SwapStore>,
+    mut commands: mpsc::Receiver<Command>,
+    failure: Arc<Mutex<Option<String>>>,
+) {
+    // The write that failed and the writes queued after it, in order
+    let mut unwritten = VecDeque::new();
+    while let Some(command) = commands.recv().await {
+        match command {
+            Command::Write(write) => {
+                let failed = !unwritten.is_empty();
+                unwritten.push_back(write);
+                if !failed {
+                    if let Err(e) = write_all(store.as_mut(), &mut unwritten).await {
+                        *failure.lock().unwrap() = Some(e.to_string());
+                    }
+                }
+            }
+            Command::Retry(reply) => {
+                let written = write_all(store.as_mut(), &mut unwritten).await;
+                *failure.lock().unwrap() = written.as_ref().err().map(ToString::to_string);
+                let _ = reply.send(written);
+            }
+            // Anything else could see the store without the held back writes,
+            // so its reply is dropped and the caller gets the failure
+            _ if !unwritten.is_empty() => {}
+            Command::Init(reply) => {
+                let _ = reply.send(store.init().await);
+            }
+            Command::Delete(event, reply) => {
+                let _ = reply.send(store.delete(&event).await);
+            }
+            Command::Sync(reply) => {
+                let _ = reply.send(store.sync().await);
+            }
+            Command::ReadCheckpoint(reply) => {
+                let _ = reply.send(store.read_checkpoint().await);
+            }
+            Command::InsertPool(metadata, reply) => {
+                let _ = reply.send(store.insert_pool(&metadata).await);
+            }
+            Command::ReadPool(pool, reply) => {
+                let _ = reply.send(store.read_pool(pool).await);
+            }
+            Command::Prune(max_rows, reply) => {
+                let _ = reply.send(store.prune(max_rows).await);
+            }
+        }
+    }
+}
+
+/// Carries out `writes` in order, removing each once it succeeded, and
+/// returns the error of the first that fails.
+async fn write_all(store: &mut dyn SwapStore, writes: &mut VecDeque<Write>) -> Result<()> {
+    while let Some(write) = writes.front() {
+        match write {
+            Write::Insert(event) => store.insert(event).await?,
+            Write::Batch(events) => store.insert_batch(events).await?,
+            Write::Checkpoint(block_number) => store.advance_checkpoint(*block_number).await?,
+        }
+        writes.pop_front();
+    }
+    Ok(())
+}
+
+impl Drop for BackgroundStore {
+    fn drop(&mut self) {
+        // Closing the queue lets the writer finish the queued writes and exit
+        self.commands.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[async_trait]
+impl SwapStore for BackgroundStore {
+    async fn init(&mut self) -> Result<()> {
+        self.request(Command::Init).await
+    }
+
+    async fn insert(&mut self, event: &PoolEvent) -> Result<()> {
+        self.send(Command::Write(Write::Insert(Box::new(event.clone()))))
+            .await
+    }
+
+    async fn insert_batch(&mut self, events: &[PoolEvent]) -> Result<()> {
+        self.send(Command::Write(Write::Batch(events.to_vec())))
+            .await
+    }
+
+    async fn delete(&mut self, event: &PoolEvent) -> Result<usize> {
+        self.request(|reply| Command::Delete(event.clone(), reply))
+            .await
+    }
+
+    async fn sync(&mut self) -> Result<()> {
+        self.request(Command::Sync).await
+    }
+
+    async fn read_checkpoint(&mut self) -> Result<Option<u64>> {
+        self.request(Command::ReadCheckpoint).await
+    }
+
+    async fn advance_checkpoint(&mut self, block_number: u64) -> Result<()> {
+        self.send(Command::Write(Write::Checkpoint(block_number)))
+            .await
+    }
+
+    async fn insert_pool(&mut self, metadata: &PoolMetadata) -> Result<()> {
+        self.request(|reply| Command::InsertPool(metadata.clone(), reply))
+            .await
+    }
+
+    async fn read_pool(&mut self, pool: Address) -> Result<Option<PoolMetadata>> {
+        self.request(|reply| Command::ReadPool(pool, reply)).await
+    }
+
+    async fn prune(&mut self, max_rows: u64) -> Result<usize> {
+        self.request(|reply| Command::Prune(max_rows, reply)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{NullStore, SqliteStore};
+    use crate::tests::{create_test_combined_log, create_test_pool_metadata, FlakyStore};
+
+    /// Fails every batch, like a full disk.
+    struct FullDiskStore(NullStore);
+
+    #[async_trait]
+    impl SwapStore for FullDiskStore {
+        async fn init(&mut self) -> Result<()> {
+            self.0.init().await
+        }
+
+        async fn insert(&mut self, event: &PoolEvent) -> Result<()> {
+            self.0.insert(event).await
+        }
+
+        async fn insert_batch(&mut self, _events: &[PoolEvent]) -> Result<()> {
+            Err(MonitorError::Database("disk full".to_string()))
+        }
+
+        async fn delete(&mut self, event: &PoolEvent) -> Result<usize> {
+            self.0.delete(event).await
+        }
+
+        async fn sync(&mut self) -> Result<()> {
+            self.0.sync().await
+        }
+
+        async fn read_checkpoint(&mut self) -> Result<Option<u64>> {
+            self.0.read_checkpoint().await
+        }
+
+        async fn advance_checkpoint(&mut self, block_number: u64) -> Result<()> {
+            self.0.advance_checkpoint(block_number).await
+        }
+
+        async fn insert_pool(&mut self, metadata: &PoolMetadata) -> Result<()> {
+            self.0.insert_pool(metadata).await
+        }
+
+        async fn read_pool(&mut self, pool: Address) -> Result<Option<PoolMetadata>> {
+            self.0.read_pool(pool).await
+        }
+
+        async fn prune(&mut self, max_rows: u64) -> Result<usize> {
+            self.0.prune(max_rows).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_background_store_reads_see_queued_writes() {
+        let sqlite = SqliteStore::open_in_memory().unwrap();
+        let mut store = BackgroundStore::spawn(Box::new(sqlite)).unwrap();
+        store.init().await.unwrap();
+
+        let swap = PoolEvent::from(create_test_combined_log());
+        for _ in 0..WRITER_QUEUE_CAPACITY * 2 {
+            store
+                .insert_batch(std::slice::from_ref(&swap))
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            store.read_checkpoint().await.unwrap(),
+            create_test_combined_log().block_number
+        );
+        assert_eq!(store.delete(&swap).await.unwrap(), 1);
+
+        let metadata = create_test_pool_metadata();
+        store.insert_pool(&metadata).await.unwrap();
+        assert_eq!(
+            store.read_pool(metadata.pool).await.unwrap(),
+            Some(metadata)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_background_store_retries_failed_writes() {
+        let mut store = BackgroundStore::spawn(Box::new(FlakyStore::new(1))).unwrap();
+        let swaps: Vec<PoolEvent> = (0..2)
+            .map(|log_index| {
+                PoolEvent::from(create_test_combined_log().with_log_index(Some(log_index), false))
+            })
+            .collect();
+        // The first batch fails once, and the second is held back behind it
+        for swap in &swaps {
+            store
+                .insert_batch(std::slice::from_ref(swap))
+                .await
+                .unwrap();
+        }
+
+        // The next call stores both in order
+        store.sync().await.unwrap();
+        for swap in &swaps {
+            assert_eq!(store.delete(swap).await.unwrap(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_background_store_fails_while_writes_fail() {
+        let mut store = BackgroundStore::spawn(Box::new(FullDiskStore(NullStore))).unwrap();
+        let swap = PoolEvent::from(create_test_combined_log());
+        // The batch is only queued, so its failure shows on the next call
+        store
+            .insert_batch(std::slice::from_ref(&swap))
+            .await
+            .unwrap();
+
+        let err = store.sync().await.unwrap_err();
+        assert!(err.to_string().contains("disk full"), "{err}");
+        assert!(store.insert_batch(&[swap]).await.is_err());
+        assert!(store.read_checkpoint().await.is_err());
+    }
+}
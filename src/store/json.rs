@@ -0,0 +1,124 @@
+use super::query::{select_swaps, skip_corrupt, StoredSwap};
+use super::sqlite::{insert_log, TableName};
+use crate::error::{MonitorError, Result};
+use crate::CombinedLog;
+use rusqlite::Connection;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+/// Swaps inserted per transaction by `import_json`.
+const IMPORT_BATCH_SIZE: usize = 1_000;
+
+/// Writes every stored swap to `writer` as newline-delimited JSON, one
+/// serialized `CombinedLog` per line in chain order, and returns the number
+/// of swaps written, for backups or sharing a dataset. Rows are read and
+/// written one at a time, so memory stays bounded however large the table is.
+/// Corrupted rows are left out like in the queries.
+pub fn export_json(conn: &Connection, mut writer: impl Write) -> Result<u64> {
+    let mut stmt = conn.prepare(&select_swaps("ORDER BY block_number, log_index"))?;
+    let mut rows = stmt.query([])?;
+    let mut exported = 0;
+    while let Some(row) = rows.next()? {
+        let swap = StoredSwap::from_row(row)?;
+        let Some(swap) = skip_corrupt(swap.rowid, swap.decode()) else {
+            continue;
+        };
+        serde_json::to_writer(&mut writer, &swap).map_err(write_error)?;
+        writer.write_all(b"\n").map_err(write_error)?;
+        exported += 1;
+    }
+    writer.flush().map_err(write_error)?;
+    Ok(exported)
+}
+
+/// Stores the swaps of an `export_json` export read from `reader` and returns
+/// the number of swaps inserted. Swaps that are already stored, as identified
+/// by their transaction hash and log index, are skipped, so an export can be
+/// imported into a database holding some of its swaps. The block checkpoint
+/// is left as it is.
+pub fn import_json(conn: &mut Connection, reader: impl BufRead) -> Result<u64> {
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut inserted = 0;
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| {
+            MonitorError::Database(format!("failed to read the JSON import: {}", e))
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let swap: CombinedLog = serde_json::from_str(&line).map_err(|e| {
+            MonitorError::Decode(format!("invalid swap on line {}: {}", line_number + 1, e))
+        })?;
+        batch.push(swap);
+        if batch.len() == IMPORT_BATCH_SIZE {
+            inserted += insert_swaps(conn, &batch)?;
+            batch.clear();
+        }
+    }
+    inserted += insert_swaps(conn, &batch)?;
+    Ok(inserted)
+}
+
+/// Inserts `swaps` in one transaction and returns how many were not stored yet.
+fn insert_swaps(conn: &mut Connection, swaps: &[CombinedLog]) -> Result<u64> {
+    let tx = conn.transaction()?;
+    let swap_table = TableName::default();
+    let mut inserted = 0;
+    for swap in swaps {
+        insert_log(&tx, &swap_table, swap)?;
+        inserted += tx.changes();
+    }
+    tx.commit()?;
+    Ok(inserted)
+}
+
+fn write_error(e: impl fmt::Display) -> MonitorError {
+    MonitorError::Database(format!("failed to write the JSON export: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::query::get_swaps_by_block_range;
+    use crate::store::sqlite::{initialize_in_memory_database, insert_logs_batch};
+    use crate::tests::create_test_combined_log;
+    use crate::PoolEvent;
+    use ethers::core::types::Bytes;
+
+    #[test]
+    fn test_export_and_import_json() {
+        let mut conn = initialize_in_memory_database().unwrap();
+        let mut first = create_test_combined_log();
+        first.data.liquidity = u128::MAX;
+        first.data.protocol_fees_token0 = Some(12);
+        first.raw_data = Some(Bytes::from(vec![1, 2, 3]));
+        let mut second = create_test_combined_log();
+        second.log_index = Some(99);
+        second.price = None;
+        insert_logs_batch(
+            &mut conn,
+            &[PoolEvent::from(second), PoolEvent::from(first)],
+        )
+        .unwrap();
+        let block_number = create_test_combined_log().block_number.unwrap();
+        let stored = get_swaps_by_block_range(&conn, block_number, block_number).unwrap();
+
+        let mut export = Vec::new();
+        assert_eq!(export_json(&conn, &mut export).unwrap(), 2);
+        let export = String::from_utf8(export).unwrap();
+        assert_eq!(export.lines().count(), 2);
+
+        let mut restored = initialize_in_memory_database().unwrap();
+        assert_eq!(import_json(&mut restored, export.as_bytes()).unwrap(), 2);
+        assert_eq!(
+            get_swaps_by_block_range(&restored, block_number, block_number).unwrap(),
+            stored
+        );
+        // Importing again stores no duplicates
+        assert_eq!(import_json(&mut restored, export.as_bytes()).unwrap(), 0);
+
+        let err = import_json(&mut restored, "\n{\"tx_hash\": 1}\n".as_bytes()).unwrap_err();
+        assert!(matches!(err, MonitorError::Decode(_)));
+        assert!(err.to_string().contains("line 2"), "{err}");
+    }
+}
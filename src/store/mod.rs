@@ -0,0 +1,126 @@
+use crate::error::Result;
+use crate::{PoolEvent, PoolMetadata};
+use async_trait::async_trait;
+use ethers::core::types::Address;
+use std::collections::HashMap;
+
+mod background;
+#[cfg(feature = "serde")]
+mod json;
+mod null;
+mod postgres;
+mod query;
+mod sharded;
+mod sqlite;
+
+pub use background::BackgroundStore;
+#[cfg(feature = "serde")]
+pub use json::{export_json, import_json};
+pub use null::NullStore;
+pub use postgres::PostgresStore;
+pub use query::{
+    aggregate_volume, database_stats, find_block_gaps, get_swaps, get_swaps_by_block_range,
+    get_swaps_by_sender, get_swaps_by_tx_hash, get_swaps_since, liquidity_series, net_volume,
+    print_database_stats, BlockGap, DatabaseStats, SwapQuery, VolumeBucket, DOWNTIME_SECS,
+};
+pub use sharded::ShardedStore;
+pub use sqlite::{
+    reprocess_all, DurabilityMode, ReprocessStats, SqliteStore, TableName, DEFAULT_SWAP_TABLE,
+    IN_MEMORY_PATH,
+};
+
+/// The tables events are stored in, which `SwapStore::prune` bounds.
+const EVENT_TABLES: &[&str] = &["logs", "mints", "burns", "v2_swaps", "custom_events"];
+
+/// Rows deleted per statement when pruning, so a single delete does not hold
+/// the database lock for long.
+const PRUNE_BATCH_SIZE: i64 = 10_000;
+
+/// A database that decoded pool events are written to.
+///
+/// Storing an event that is already stored, as identified by its transaction
+/// hash and log index, must leave a single row.
+#[async_trait]
+pub trait SwapStore: Send {
+    /// Creates the tables and indexes if they do not exist yet.
+    async fn init(&mut self) -> Result<()>;
+
+    /// Stores a single event.
+    async fn insert(&mut self, event: &PoolEvent) -> Result<()>;
+
+    /// Stores the events and advances the block checkpoint atomically, so the
+    /// checkpoint never runs ahead of the stored rows.
+    async fn insert_batch(&mut self, events: &[PoolEvent]) -> Result<()>;
+
+    /// Deletes an event retracted by a chain reorganization and returns the
+    /// number of deleted rows.
+    async fn delete(&mut self, event: &PoolEvent) -> Result<usize>;
+
+    /// Makes the committed events and checkpoint durable on disk, for stores
+    /// whose commits return before they are. On the others it does nothing.
+    async fn sync(&mut self) -> Result<()>;
+
+    /// Returns the highest block number whose events have been committed.
+    async fn read_checkpoint(&mut self) -> Result<Option<u64>>;
+
+    /// Moves the block checkpoint up to `block_number` without storing events,
+    /// for a store that had none in blocks whose events were stored elsewhere,
+    /// such as a shard of a `ShardedStore`. A lower block leaves it unchanged.
+    async fn advance_checkpoint(&mut self, block_number: u64) -> Result<()>;
+
+    /// Stores the token metadata of a pool, replacing an earlier entry.
+    async fn insert_pool(&mut self, metadata: &PoolMetadata) -> Result<()>;
+
+    /// Returns the stored token metadata of `pool`, if there is any.
+    async fn read_pool(&mut self, pool: Address) -> Result<Option<PoolMetadata>>;
+
+    /// Deletes the oldest events, by block number, of each event table that
+    /// holds more than `max_rows` rows, `PRUNE_BATCH_SIZE` rows per statement,
+    /// and returns the number of deleted rows. The checkpoint is kept.
+    async fn prune(&mut self, max_rows: u64) -> Result<usize>;
+}
+
+fn is_postgres_url(db_url: &str) -> bool {
+    db_url.starts_with("postgres://") || db_url.starts_with("postgresql://")
+}
+
+/// Opens a `PostgresStore` for `postgres://` and `postgresql://` URLs and a
+/// `SqliteStore` for anything else, which is treated as a file path.
+/// `sqlite_durability` only applies to the latter. A `SqliteStore`, whose
+/// writes block, runs in a `BackgroundStore`.
+pub async fn open_store(
+    db_url: &str,
+    sqlite_durability: DurabilityMode,
+) -> Result<Box<dyn SwapStore>> {
+    if is_postgres_url(db_url) {
+        Ok(Box::new(PostgresStore::connect(db_url).await?))
+    } else {
+        let store = SqliteStore::open_with_durability(db_url, sqlite_durability)?;
+        Ok(Box::new(BackgroundStore::spawn(Box::new(store))?))
+    }
+}
+
+/// Opens the store at `db_url` as `open_store` does, and when `pool_db_urls`
+/// gives some pools a database of their own, a `ShardedStore` that writes the
+/// events of those pools there instead. Pools sharing a URL share a store.
+pub async fn open_sharded_store(
+    db_url: &str,
+    pool_db_urls: &HashMap<Address, String>,
+    sqlite_durability: DurabilityMode,
+) -> Result<Box<dyn SwapStore>> {
+    let default = open_store(db_url, sqlite_durability).await?;
+    if pool_db_urls.is_empty() {
+        return Ok(default);
+    }
+    let mut pools_by_url: HashMap<&str, Vec<Address>> = HashMap::new();
+    for (&pool, url) in pool_db_urls {
+        if url != db_url {
+            pools_by_url.entry(url).or_default().push(pool);
+        }
+    }
+    let mut store = ShardedStore::new(default);
+    for (url, pools) in pools_by_url {
+        store.add_pool_store(&pools, open_store(url, sqlite_durability).await?);
+    }
+    Ok(Box::new(store))
+}
@@ -0,0 +1,85 @@
+use super::SwapStore;
+use crate::error::Result;
+use crate::{PoolEvent, PoolMetadata};
+use async_trait::async_trait;
+use ethers::core::types::Address;
+
+/// A store that discards every event, for a dry run that only prints what a
+/// real run would store.
+///
+/// It holds no checkpoint and no pool metadata, so `run` does not resume a
+/// backfill and fetches the token metadata from the chain on every start.
+#[derive(Debug, Default)]
+pub struct NullStore;
+
+#[async_trait]
+impl SwapStore for NullStore {
+    async fn init(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn insert(&mut self, _event: &PoolEvent) -> Result<()> {
+        Ok(())
+    }
+
+    async fn insert_batch(&mut self, _events: &[PoolEvent]) -> Result<()> {
+        Ok(())
+    }
+
+    async fn delete(&mut self, _event: &PoolEvent) -> Result<usize> {
+        Ok(0)
+    }
+
+    async fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn read_checkpoint(&mut self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    async fn advance_checkpoint(&mut self, _block_number: u64) -> Result<()> {
+        Ok(())
+    }
+
+    async fn insert_pool(&mut self, _metadata: &PoolMetadata) -> Result<()> {
+        Ok(())
+    }
+
+    async fn read_pool(&mut self, _pool: Address) -> Result<Option<PoolMetadata>> {
+        Ok(None)
+    }
+
+    async fn prune(&mut self, _max_rows: u64) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{create_test_combined_log, create_test_pool_metadata};
+
+    #[tokio::test]
+    async fn test_null_store_keeps_nothing() {
+        let mut store = NullStore;
+        let swap = PoolEvent::from(create_test_combined_log());
+        store.init().await.unwrap();
+        store.insert(&swap).await.unwrap();
+        store
+            .insert_batch(&[PoolEvent::from(create_test_combined_log())])
+            .await
+            .unwrap();
+        store
+            .insert_pool(&create_test_pool_metadata())
+            .await
+            .unwrap();
+
+        store.advance_checkpoint(10).await.unwrap();
+        assert_eq!(store.read_checkpoint().await.unwrap(), None);
+        assert_eq!(store.delete(&swap).await.unwrap(), 0);
+        let pool = create_test_pool_metadata().pool;
+        assert_eq!(store.read_pool(pool).await.unwrap(), None);
+        assert_eq!(store.prune(0).await.unwrap(), 0);
+    }
+}
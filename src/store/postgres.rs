@@ -0,0 +1,605 @@
+use super::{SwapStore, EVENT_TABLES, PRUNE_BATCH_SIZE};
+use crate::error::{MonitorError, Result};
+use crate::{
+    BurnLog, CombinedLog, CustomLog, MintLog, PoolEvent, PoolMetadata, SwapDirection,
+    TokenMetadata, V2SwapLog,
+};
+use async_trait::async_trait;
+use ethers::core::types::Address;
+use std::str::FromStr;
+use tokio_postgres::{Client, GenericClient, NoTls};
+use tracing::error;
+
+/// Stores swaps in a PostgreSQL database.
+///
+/// Amounts, `sqrt_price` and `liquidity` are stored as `NUMERIC` so they keep
+/// their full precision and can be used in arithmetic queries.
+pub struct PostgresStore {
+    client: Client,
+}
+
+impl PostgresStore {
+    /// Connects to the server described by `url`, a `postgres://` connection
+    /// string. The schema is created by `init`.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls).await.map_err(|e| {
+            MonitorError::Database(format!("failed to connect to PostgreSQL: {}", e))
+        })?;
+        // The connection performs the actual I/O and resolves once the client is dropped
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!(error = %e, "PostgreSQL connection error");
+            }
+        });
+        Ok(PostgresStore { client })
+    }
+
+    /// The underlying client, for running queries against the stored swaps.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+#[async_trait]
+impl SwapStore for PostgresStore {
+    async fn init(&mut self) -> Result<()> {
+        self.client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS logs (
+        tx_hash TEXT NOT NULL,
+        pool_address TEXT NOT NULL,
+        sender_address TEXT NOT NULL,
+        receiver_address TEXT NOT NULL,
+        amount0 NUMERIC(78, 0) NOT NULL,
+        amount1 NUMERIC(78, 0) NOT NULL,
+        sqrt_price NUMERIC(78, 0) NOT NULL,
+        liquidity NUMERIC(39, 0) NOT NULL,
+        tick INTEGER NOT NULL,
+        block_number BIGINT,
+        block_hash TEXT,
+        timestamp BIGINT,
+        price DOUBLE PRECISION,
+        log_index BIGINT,
+        amount0_normalized DOUBLE PRECISION,
+        amount1_normalized DOUBLE PRECISION,
+        tick_price DOUBLE PRECISION,
+        protocol_fees_token0 NUMERIC(39, 0),
+        protocol_fees_token1 NUMERIC(39, 0),
+        usd_value DOUBLE PRECISION,
+        direction TEXT,
+        raw_data BYTEA,
+        transaction_index BIGINT,
+        gas_used BIGINT,
+        effective_gas_price NUMERIC(78, 0),
+        source TEXT
+      );
+      ALTER TABLE logs ADD COLUMN IF NOT EXISTS amount0_normalized DOUBLE PRECISION;
+      ALTER TABLE logs ADD COLUMN IF NOT EXISTS amount1_normalized DOUBLE PRECISION;
+      ALTER TABLE logs ADD COLUMN IF NOT EXISTS tick_price DOUBLE PRECISION;
+      ALTER TABLE logs ADD COLUMN IF NOT EXISTS protocol_fees_token0 NUMERIC(39, 0);
+      ALTER TABLE logs ADD COLUMN IF NOT EXISTS protocol_fees_token1 NUMERIC(39, 0);
+      ALTER TABLE logs ADD COLUMN IF NOT EXISTS usd_value DOUBLE PRECISION;
+      ALTER TABLE logs ADD COLUMN IF NOT EXISTS direction TEXT;
+      ALTER TABLE logs ADD COLUMN IF NOT EXISTS raw_data BYTEA;
+      ALTER TABLE logs ADD COLUMN IF NOT EXISTS transaction_index BIGINT;
+      ALTER TABLE logs ADD COLUMN IF NOT EXISTS gas_used BIGINT;
+      ALTER TABLE logs ADD COLUMN IF NOT EXISTS effective_gas_price NUMERIC(78, 0);
+      ALTER TABLE logs ADD COLUMN IF NOT EXISTS source TEXT;
+      CREATE UNIQUE INDEX IF NOT EXISTS idx_logs_tx_log_index ON logs (tx_hash, log_index);
+      CREATE INDEX IF NOT EXISTS idx_logs_sender ON logs (sender_address);
+      CREATE INDEX IF NOT EXISTS idx_logs_block ON logs (block_number);
+      CREATE INDEX IF NOT EXISTS idx_logs_timestamp ON logs (timestamp);
+      CREATE TABLE IF NOT EXISTS mints (
+        tx_hash TEXT NOT NULL,
+        pool_address TEXT NOT NULL,
+        sender_address TEXT NOT NULL,
+        owner_address TEXT NOT NULL,
+        tick_lower INTEGER NOT NULL,
+        tick_upper INTEGER NOT NULL,
+        amount NUMERIC(39, 0) NOT NULL,
+        amount0 NUMERIC(78, 0) NOT NULL,
+        amount1 NUMERIC(78, 0) NOT NULL,
+        block_number BIGINT,
+        block_hash TEXT,
+        timestamp BIGINT,
+        log_index BIGINT
+      );
+      CREATE UNIQUE INDEX IF NOT EXISTS idx_mints_tx_log_index ON mints (tx_hash, log_index);
+      CREATE TABLE IF NOT EXISTS burns (
+        tx_hash TEXT NOT NULL,
+        pool_address TEXT NOT NULL,
+        owner_address TEXT NOT NULL,
+        tick_lower INTEGER NOT NULL,
+        tick_upper INTEGER NOT NULL,
+        amount NUMERIC(39, 0) NOT NULL,
+        amount0 NUMERIC(78, 0) NOT NULL,
+        amount1 NUMERIC(78, 0) NOT NULL,
+        block_number BIGINT,
+        block_hash TEXT,
+        timestamp BIGINT,
+        log_index BIGINT
+      );
+      CREATE UNIQUE INDEX IF NOT EXISTS idx_burns_tx_log_index ON burns (tx_hash, log_index);
+      CREATE TABLE IF NOT EXISTS v2_swaps (
+        tx_hash TEXT NOT NULL,
+        pool_address TEXT NOT NULL,
+        sender_address TEXT NOT NULL,
+        to_address TEXT NOT NULL,
+        amount0_in NUMERIC(78, 0) NOT NULL,
+        amount1_in NUMERIC(78, 0) NOT NULL,
+        amount0_out NUMERIC(78, 0) NOT NULL,
+        amount1_out NUMERIC(78, 0) NOT NULL,
+        block_number BIGINT,
+        block_hash TEXT,
+        timestamp BIGINT,
+        log_index BIGINT
+      );
+      CREATE UNIQUE INDEX IF NOT EXISTS idx_v2_swaps_tx_log_index ON v2_swaps (tx_hash, log_index);
+      CREATE TABLE IF NOT EXISTS custom_events (
+        tx_hash TEXT NOT NULL,
+        contract_address TEXT NOT NULL,
+        event_name TEXT NOT NULL,
+        params JSONB NOT NULL,
+        block_number BIGINT,
+        block_hash TEXT,
+        timestamp BIGINT,
+        log_index BIGINT
+      );
+      CREATE UNIQUE INDEX IF NOT EXISTS idx_custom_events_tx_log_index ON custom_events (tx_hash, log_index);
+      CREATE TABLE IF NOT EXISTS pools (
+        pool_address TEXT PRIMARY KEY,
+        token0_address TEXT NOT NULL,
+        token0_symbol TEXT NOT NULL,
+        token0_decimals INTEGER NOT NULL,
+        token1_address TEXT NOT NULL,
+        token1_symbol TEXT NOT NULL,
+        token1_decimals INTEGER NOT NULL
+      );
+      CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value BIGINT NOT NULL
+      );",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn insert(&mut self, event: &PoolEvent) -> Result<()> {
+        insert_event(&self.client, event).await
+    }
+
+    async fn insert_batch(&mut self, events: &[PoolEvent]) -> Result<()> {
+        let tx = self.client.transaction().await?;
+        for event in events {
+            insert_event(&tx, event).await?;
+        }
+        if let Some(block_number) = events.iter().filter_map(PoolEvent::block_number).max() {
+            update_checkpoint(&tx, block_number).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete(&mut self, event: &PoolEvent) -> Result<usize> {
+        let table = match event {
+            PoolEvent::Swap(_) => "logs",
+            PoolEvent::Mint(_) => "mints",
+            PoolEvent::Burn(_) => "burns",
+            PoolEvent::V2Swap(_) => "v2_swaps",
+            PoolEvent::Custom(_) => "custom_events",
+        };
+        let deleted = self
+            .client
+            .execute(
+                &format!(
+                    "DELETE FROM {table} WHERE tx_hash = $1 AND log_index IS NOT DISTINCT FROM $2"
+                ),
+                &[
+                    &format!("{:#x}", event.tx_hash()),
+                    &event.log_index().map(to_bigint).transpose()?,
+                ],
+            )
+            .await?;
+        Ok(deleted as usize)
+    }
+
+    async fn sync(&mut self) -> Result<()> {
+        // Commits are flushed to the server's WAL before they return
+        Ok(())
+    }
+
+    async fn read_checkpoint(&mut self) -> Result<Option<u64>> {
+        let row = self
+            .client
+            .query_opt("SELECT value FROM meta WHERE key = $1", &[&CHECKPOINT_KEY])
+            .await?;
+        row.map(|row| {
+            let value: i64 = row.get(0);
+            u64::try_from(value)
+                .map_err(|_| MonitorError::Database(format!("invalid checkpoint {}", value)))
+        })
+        .transpose()
+    }
+
+    async fn advance_checkpoint(&mut self, block_number: u64) -> Result<()> {
+        update_checkpoint(&self.client, block_number).await
+    }
+
+    async fn insert_pool(&mut self, metadata: &PoolMetadata) -> Result<()> {
+        self.client
+            .execute(
+                "INSERT INTO pools (pool_address, token0_address, token0_symbol, token0_decimals, token1_address, token1_symbol, token1_decimals)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)
+                 ON CONFLICT (pool_address) DO UPDATE SET
+                   token0_address = excluded.token0_address,
+                   token0_symbol = excluded.token0_symbol,
+                   token0_decimals = excluded.token0_decimals,
+                   token1_address = excluded.token1_address,
+                   token1_symbol = excluded.token1_symbol,
+                   token1_decimals = excluded.token1_decimals",
+                &[
+                    &format!("{:#x}", metadata.pool),
+                    &format!("{:#x}", metadata.token0.address),
+                    &metadata.token0.symbol,
+                    &i32::from(metadata.token0.decimals),
+                    &format!("{:#x}", metadata.token1.address),
+                    &metadata.token1.symbol,
+                    &i32::from(metadata.token1.decimals),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn read_pool(&mut self, pool: Address) -> Result<Option<PoolMetadata>> {
+        let row = self
+            .client
+            .query_opt(
+                "SELECT token0_address, token0_symbol, token0_decimals, token1_address, token1_symbol, token1_decimals
+                 FROM pools WHERE pool_address = $1",
+                &[&format!("{:#x}", pool)],
+            )
+            .await?;
+        row.map(|row| {
+            Ok(PoolMetadata {
+                pool,
+                token0: TokenMetadata {
+                    address: parse_address(row.get(0))?,
+                    symbol: row.get(1),
+                    decimals: to_decimals(row.get(2))?,
+                },
+                token1: TokenMetadata {
+                    address: parse_address(row.get(3))?,
+                    symbol: row.get(4),
+                    decimals: to_decimals(row.get(5))?,
+                },
+            })
+        })
+        .transpose()
+    }
+
+    async fn prune(&mut self, max_rows: u64) -> Result<usize> {
+        let max_rows = i64::try_from(max_rows).unwrap_or(i64::MAX);
+        let mut pruned = 0;
+        for table in EVENT_TABLES {
+            let row = self
+                .client
+                .query_one(&format!("SELECT COUNT(*) FROM {table}"), &[])
+                .await?;
+            let mut excess = row.get::<_, i64>(0).saturating_sub(max_rows);
+            while excess > 0 {
+                let deleted = self
+                    .client
+                    .execute(
+                        &format!(
+                            "DELETE FROM {table} WHERE ctid IN
+                             (SELECT ctid FROM {table} ORDER BY block_number, log_index LIMIT $1)"
+                        ),
+                        &[&excess.min(PRUNE_BATCH_SIZE)],
+                    )
+                    .await?;
+                if deleted == 0 {
+                    break;
+                }
+                pruned += deleted as usize;
+                excess -= deleted as i64;
+            }
+        }
+        Ok(pruned)
+    }
+}
+
+fn parse_address(address: &str) -> Result<Address> {
+    Address::from_str(address).map_err(|e| {
+        MonitorError::InvalidAddress(format!("invalid stored address '{}': {}", address, e))
+    })
+}
+
+fn to_decimals(value: i32) -> Result<u8> {
+    u8::try_from(value)
+        .map_err(|_| MonitorError::Database(format!("invalid token decimals {}", value)))
+}
+
+const CHECKPOINT_KEY: &str = "last_block";
+
+fn to_bigint(value: u64) -> Result<i64> {
+    i64::try_from(value)
+        .map_err(|_| MonitorError::Database(format!("{} does not fit in a BIGINT column", value)))
+}
+
+/// Moves the checkpoint up to `block_number`, never back.
+async fn update_checkpoint<C: GenericClient + Sync>(client: &C, block_number: u64) -> Result<()> {
+    client
+        .execute(
+            "INSERT INTO meta (key, value) VALUES ($1, $2)
+             ON CONFLICT (key) DO UPDATE SET value = GREATEST(meta.value, excluded.value)",
+            &[&CHECKPOINT_KEY, &to_bigint(block_number)?],
+        )
+        .await?;
+    Ok(())
+}
+
+async fn insert_log<C: GenericClient + Sync>(client: &C, combined_log: &CombinedLog) -> Result<()> {
+    // Decimal strings are cast to NUMERIC by the server, which keeps full precision
+    client
+        .execute(
+            "INSERT INTO logs (tx_hash, pool_address, sender_address, receiver_address, amount0, amount1, sqrt_price, liquidity, tick, block_number, block_hash, timestamp, price, log_index, amount0_normalized, amount1_normalized, tick_price, protocol_fees_token0, protocol_fees_token1, usd_value, direction, raw_data, transaction_index, gas_used, effective_gas_price, source)
+             VALUES ($1, $2, $3, $4, $5::TEXT::NUMERIC, $6::TEXT::NUMERIC, $7::TEXT::NUMERIC, $8::TEXT::NUMERIC, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18::TEXT::NUMERIC, $19::TEXT::NUMERIC, $20, $21, $22, $23, $24, $25::TEXT::NUMERIC, $26)
+             ON CONFLICT DO NOTHING",
+            &[
+                &format!("{:#x}", combined_log.tx_hash),
+                &format!("{:#x}", combined_log.pool),
+                &format!("{:#x}", combined_log.sender),
+                &format!("{:#x}", combined_log.receiver),
+                &combined_log.data.amount0.to_string(),
+                &combined_log.data.amount1.to_string(),
+                &combined_log.data.sqrt_price.to_string(),
+                &combined_log.data.liquidity.to_string(),
+                &combined_log.data.tick,
+                &combined_log.block_number.map(to_bigint).transpose()?,
+                &combined_log.block_hash.map(|hash| format!("{:#x}", hash)),
+                &combined_log.timestamp.map(to_bigint).transpose()?,
+                &combined_log.price,
+                &combined_log.log_index.map(to_bigint).transpose()?,
+                &combined_log.amount0_normalized,
+                &combined_log.amount1_normalized,
+                &combined_log.tick_price,
+                &combined_log
+                    .data
+                    .protocol_fees_token0
+                    .map(|fees| fees.to_string()),
+                &combined_log
+                    .data
+                    .protocol_fees_token1
+                    .map(|fees| fees.to_string()),
+                &combined_log.usd_value,
+                &combined_log.direction.map(SwapDirection::as_str),
+                &combined_log.raw_data.as_deref(),
+                &combined_log.transaction_index.map(to_bigint).transpose()?,
+                &combined_log.gas_used.map(to_bigint).transpose()?,
+                &combined_log
+                    .effective_gas_price
+                    .map(|price| price.to_string()),
+                &combined_log.source,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+async fn insert_mint<C: GenericClient + Sync>(client: &C, mint: &MintLog) -> Result<()> {
+    client
+        .execute(
+            "INSERT INTO mints (tx_hash, pool_address, sender_address, owner_address, tick_lower, tick_upper, amount, amount0, amount1, block_number, block_hash, timestamp, log_index)
+             VALUES ($1, $2, $3, $4, $5, $6, $7::TEXT::NUMERIC, $8::TEXT::NUMERIC, $9::TEXT::NUMERIC, $10, $11, $12, $13)
+             ON CONFLICT DO NOTHING",
+            &[
+                &format!("{:#x}", mint.tx_hash),
+                &format!("{:#x}", mint.pool),
+                &format!("{:#x}", mint.sender),
+                &format!("{:#x}", mint.owner),
+                &mint.tick_lower,
+                &mint.tick_upper,
+                &mint.amount.to_string(),
+                &mint.amount0.to_string(),
+                &mint.amount1.to_string(),
+                &mint.block_number.map(to_bigint).transpose()?,
+                &mint.block_hash.map(|hash| format!("{:#x}", hash)),
+                &mint.timestamp.map(to_bigint).transpose()?,
+                &mint.log_index.map(to_bigint).transpose()?,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+async fn insert_burn<C: GenericClient + Sync>(client: &C, burn: &BurnLog) -> Result<()> {
+    client
+        .execute(
+            "INSERT INTO burns (tx_hash, pool_address, owner_address, tick_lower, tick_upper, amount, amount0, amount1, block_number, block_hash, timestamp, log_index)
+             VALUES ($1, $2, $3, $4, $5, $6::TEXT::NUMERIC, $7::TEXT::NUMERIC, $8::TEXT::NUMERIC, $9, $10, $11, $12)
+             ON CONFLICT DO NOTHING",
+            &[
+                &format!("{:#x}", burn.tx_hash),
+                &format!("{:#x}", burn.pool),
+                &format!("{:#x}", burn.owner),
+                &burn.tick_lower,
+                &burn.tick_upper,
+                &burn.amount.to_string(),
+                &burn.amount0.to_string(),
+                &burn.amount1.to_string(),
+                &burn.block_number.map(to_bigint).transpose()?,
+                &burn.block_hash.map(|hash| format!("{:#x}", hash)),
+                &burn.timestamp.map(to_bigint).transpose()?,
+                &burn.log_index.map(to_bigint).transpose()?,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+async fn insert_v2_swap<C: GenericClient + Sync>(client: &C, swap: &V2SwapLog) -> Result<()> {
+    client
+        .execute(
+            "INSERT INTO v2_swaps (tx_hash, pool_address, sender_address, to_address, amount0_in, amount1_in, amount0_out, amount1_out, block_number, block_hash, timestamp, log_index)
+             VALUES ($1, $2, $3, $4, $5::TEXT::NUMERIC, $6::TEXT::NUMERIC, $7::TEXT::NUMERIC, $8::TEXT::NUMERIC, $9, $10, $11, $12)
+             ON CONFLICT DO NOTHING",
+            &[
+                &format!("{:#x}", swap.tx_hash),
+                &format!("{:#x}", swap.pool),
+                &format!("{:#x}", swap.sender),
+                &format!("{:#x}", swap.to),
+                &swap.data.amount0_in.to_string(),
+                &swap.data.amount1_in.to_string(),
+                &swap.data.amount0_out.to_string(),
+                &swap.data.amount1_out.to_string(),
+                &swap.block_number.map(to_bigint).transpose()?,
+                &swap.block_hash.map(|hash| format!("{:#x}", hash)),
+                &swap.timestamp.map(to_bigint).transpose()?,
+                &swap.log_index.map(to_bigint).transpose()?,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+async fn insert_custom_event<C: GenericClient + Sync>(client: &C, log: &CustomLog) -> Result<()> {
+    client
+        .execute(
+            "INSERT INTO custom_events (tx_hash, contract_address, event_name, params, block_number, block_hash, timestamp, log_index)
+             VALUES ($1, $2, $3, $4::TEXT::JSONB, $5, $6, $7, $8)
+             ON CONFLICT DO NOTHING",
+            &[
+                &format!("{:#x}", log.tx_hash),
+                &format!("{:#x}", log.contract),
+                &log.name,
+                &log.params_json().to_string(),
+                &log.block_number.map(to_bigint).transpose()?,
+                &log.block_hash.map(|hash| format!("{:#x}", hash)),
+                &log.timestamp.map(to_bigint).transpose()?,
+                &log.log_index.map(to_bigint).transpose()?,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+async fn insert_event<C: GenericClient + Sync>(client: &C, event: &PoolEvent) -> Result<()> {
+    match event {
+        PoolEvent::Swap(combined_log) => insert_log(client, combined_log).await,
+        PoolEvent::Mint(mint) => insert_mint(client, mint).await,
+        PoolEvent::Burn(burn) => insert_burn(client, burn).await,
+        PoolEvent::V2Swap(swap) => insert_v2_swap(client, swap).await,
+        PoolEvent::Custom(log) => insert_custom_event(client, log).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::{
+        create_test_custom_log, create_test_mint_log, create_test_v2_swap_log, TRADE_EVENT,
+    };
+    use crate::events::{decode_custom, decode_mint, decode_v2_swap, parse_event_signature};
+    use crate::tests::{create_test_combined_log, create_test_pool_metadata};
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL server, set TEST_POSTGRES_URL"]
+    async fn test_postgres_store_round_trip() {
+        let url = std::env::var("TEST_POSTGRES_URL").unwrap();
+        let mut store = PostgresStore::connect(&url).await.unwrap();
+        store.init().await.unwrap();
+        store
+            .client()
+            .batch_execute("TRUNCATE logs, mints, burns, v2_swaps, custom_events, pools, meta")
+            .await
+            .unwrap();
+
+        let combined_log = CombinedLog {
+            raw_data: Some(vec![0xde, 0xad, 0xbe, 0xef].into()),
+            ..create_test_combined_log()
+        };
+        let swap = PoolEvent::from(combined_log.clone());
+        let mint = PoolEvent::Mint(decode_mint(&create_test_mint_log(), None).unwrap());
+        let v2_swap = PoolEvent::V2Swap(decode_v2_swap(&create_test_v2_swap_log(), None).unwrap());
+        store
+            .insert_batch(&[swap.clone(), swap.clone(), mint.clone(), v2_swap.clone()])
+            .await
+            .unwrap();
+        assert_eq!(
+            store.read_checkpoint().await.unwrap(),
+            v2_swap.block_number()
+        );
+        // A lower block leaves the checkpoint alone
+        store.advance_checkpoint(1).await.unwrap();
+        assert_eq!(
+            store.read_checkpoint().await.unwrap(),
+            v2_swap.block_number()
+        );
+
+        let row = store
+            .client()
+            .query_one("SELECT COUNT(*), MIN(amount0)::TEXT FROM logs", &[])
+            .await
+            .unwrap();
+        assert_eq!(row.get::<_, i64>(0), 1);
+        assert_eq!(
+            row.get::<_, String>(1),
+            combined_log.data.amount0.to_string()
+        );
+        let row = store
+            .client()
+            .query_one("SELECT raw_data, transaction_index FROM logs", &[])
+            .await
+            .unwrap();
+        assert_eq!(row.get::<_, Vec<u8>>(0), vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(
+            row.get::<_, Option<i64>>(1),
+            combined_log.transaction_index.map(|i| i as i64)
+        );
+
+        let event = parse_event_signature(TRADE_EVENT).unwrap();
+        let custom =
+            PoolEvent::Custom(decode_custom(&create_test_custom_log(), &event, None).unwrap());
+        store.insert(&custom).await.unwrap();
+        let row = store
+            .client()
+            .query_one("SELECT params->>'delta' FROM custom_events", &[])
+            .await
+            .unwrap();
+        assert_eq!(row.get::<_, String>(0), "-1500");
+
+        assert_eq!(store.delete(&swap).await.unwrap(), 1);
+        assert_eq!(store.delete(&mint).await.unwrap(), 1);
+        assert_eq!(store.delete(&v2_swap).await.unwrap(), 1);
+        assert_eq!(store.delete(&custom).await.unwrap(), 1);
+
+        // Pruning to a single row keeps the latest swap
+        let mut later = combined_log.clone();
+        later.block_number = later.block_number.map(|block_number| block_number + 1);
+        later.log_index = later.log_index.map(|log_index| log_index + 1);
+        store
+            .insert_batch(&[swap.clone(), PoolEvent::from(later.clone())])
+            .await
+            .unwrap();
+        assert_eq!(store.prune(1).await.unwrap(), 1);
+        let row = store
+            .client()
+            .query_one("SELECT block_number FROM logs", &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            row.get::<_, i64>(0),
+            to_bigint(later.block_number.unwrap()).unwrap()
+        );
+
+        let mut metadata = create_test_pool_metadata();
+        store.insert_pool(&metadata).await.unwrap();
+        metadata.token1.symbol = "ETH".to_string();
+        store.insert_pool(&metadata).await.unwrap();
+        assert_eq!(
+            store.read_pool(metadata.pool).await.unwrap(),
+            Some(metadata)
+        );
+    }
+}
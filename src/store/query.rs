@@ -0,0 +1,1025 @@
+use super::sqlite::parse_address;
+use crate::error::{MonitorError, Result};
+use crate::{abs_u256, CombinedLog, LogData, SwapDirection};
+use ethers::core::types::{Address, Bytes, H256, I256, U256};
+use rusqlite::{params, Connection, OptionalExtension, Params, Row};
+use std::fmt;
+use std::str::FromStr;
+use tracing::warn;
+
+const SWAP_COLUMNS: &str = "tx_hash, pool_address, sender.address, receiver.address, amount0, amount1, sqrt_price, liquidity, tick, block_number, block_hash, timestamp, price, log_index, amount0_normalized, amount1_normalized, tick_price, protocol_fees_token0, protocol_fees_token1, usd_value, direction, raw_data, transaction_index, gas_used, effective_gas_price, source, logs.rowid";
+
+// Rows whose text columns cannot be parsed back, after a partial write or a
+// manual edit, are left out of the results with a warning naming their rowid,
+// see `skip_corrupt`, so one bad row does not hide all the others.
+
+/// Returns the stored swaps of blocks `from_block..=to_block`, in chain order.
+pub fn get_swaps_by_block_range(
+    conn: &Connection,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<CombinedLog>> {
+    query_swaps(
+        conn,
+        "WHERE block_number BETWEEN ?1 AND ?2",
+        params![from_block, to_block],
+    )
+}
+
+/// Returns the stored swaps sent by `sender`, in chain order.
+pub fn get_swaps_by_sender(conn: &Connection, sender: Address) -> Result<Vec<CombinedLog>> {
+    query_swaps(
+        conn,
+        "WHERE sender_id = (SELECT id FROM addresses WHERE address = ?1)",
+        params![format!("{:#x}", sender)],
+    )
+}
+
+/// Returns the stored swaps of blocks mined after `unix_timestamp`, in chain
+/// order, for example to export the swaps stored since the previous export.
+/// Swaps without a timestamp are left out.
+pub fn get_swaps_since(conn: &Connection, unix_timestamp: u64) -> Result<Vec<CombinedLog>> {
+    query_swaps(conn, "WHERE timestamp > ?1", params![unix_timestamp])
+}
+
+/// Returns the stored swaps of the transaction `tx_hash`, in chain order.
+pub fn get_swaps_by_tx_hash(conn: &Connection, tx_hash: H256) -> Result<Vec<CombinedLog>> {
+    query_swaps(
+        conn,
+        "WHERE tx_hash = ?1",
+        params![format!("{:#x}", tx_hash)],
+    )
+}
+
+/// The filters and page of `get_swaps`. Unset filters match every swap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapQuery {
+    pub pool: Option<Address>,
+    pub from_block: Option<u64>,
+    pub to_block: Option<u64>,
+    /// Return at most this many swaps, 100 by default.
+    pub limit: u64,
+    /// Skip this many of the matching swaps first, to page through them.
+    pub offset: u64,
+}
+
+impl Default for SwapQuery {
+    fn default() -> Self {
+        SwapQuery {
+            pool: None,
+            from_block: None,
+            to_block: None,
+            limit: 100,
+            offset: 0,
+        }
+    }
+}
+
+/// Returns a page of the stored swaps that match `query`, in chain order, for
+/// example to serve them over HTTP, see `spawn_api_server`. Corrupted rows
+/// count towards the page, so a page can hold fewer than `query.limit` swaps
+/// while later pages still have some.
+pub fn get_swaps(conn: &Connection, query: &SwapQuery) -> Result<Vec<CombinedLog>> {
+    let mut stmt = conn.prepare(&select_swaps(
+        "WHERE (?1 IS NULL OR pool_address = ?1)
+           AND (?2 IS NULL OR block_number >= ?2)
+           AND (?3 IS NULL OR block_number <= ?3)
+         ORDER BY block_number, log_index
+         LIMIT ?4 OFFSET ?5",
+    ))?;
+    let params = params![
+        query.pool.map(|pool| format!("{:#x}", pool)),
+        query.from_block,
+        query.to_block,
+        query.limit.min(i64::MAX as u64),
+        query.offset.min(i64::MAX as u64),
+    ];
+    let rows = stmt
+        .query_map(params, StoredSwap::from_row)?
+        .collect::<rusqlite::Result<Vec<StoredSwap>>>()?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|swap| skip_corrupt(swap.rowid, swap.decode()))
+        .collect())
+}
+
+/// The swaps of one pool within a time bucket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeBucket {
+    /// Unix timestamp of the start of the bucket, a multiple of its length.
+    pub start: u64,
+    /// Sum of the absolute raw `amount0` of the swaps.
+    pub volume0: U256,
+    /// Sum of the absolute raw `amount1` of the swaps.
+    pub volume1: U256,
+    pub swap_count: u64,
+}
+
+/// Groups the stored swaps of `pool` into buckets of `bucket_seconds`, for
+/// example 3600 for hourly volume, and returns the buckets that contain swaps
+/// in chronological order. Swaps without a timestamp are left out.
+///
+/// The raw amounts are summed at full precision; `amount0_normalized` and
+/// `amount1_normalized` scale them by the token decimals.
+pub fn aggregate_volume(
+    conn: &Connection,
+    pool: Address,
+    bucket_seconds: u64,
+) -> Result<Vec<VolumeBucket>> {
+    if bucket_seconds == 0 {
+        return Err(MonitorError::InvalidConfig(
+            "bucket_seconds must be positive".to_string(),
+        ));
+    }
+    let mut stmt = conn.prepare(
+        "SELECT rowid, timestamp, amount0, amount1 FROM logs
+         WHERE pool_address = ?1 AND timestamp IS NOT NULL
+         ORDER BY timestamp",
+    )?;
+    let rows = stmt
+        .query_map(params![format!("{:#x}", pool)], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, u64>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut buckets: Vec<VolumeBucket> = Vec::new();
+    for (rowid, timestamp, amount0, amount1) in rows {
+        let Some((amount0, amount1)) = skip_corrupt(rowid, parse_amounts(&amount0, &amount1))
+        else {
+            continue;
+        };
+        let start = timestamp - timestamp % bucket_seconds;
+        match buckets.last_mut() {
+            Some(bucket) if bucket.start == start => {
+                bucket.volume0 = bucket.volume0.saturating_add(amount0);
+                bucket.volume1 = bucket.volume1.saturating_add(amount1);
+                bucket.swap_count += 1;
+            }
+            _ => buckets.push(VolumeBucket {
+                start,
+                volume0: amount0,
+                volume1: amount1,
+                swap_count: 1,
+            }),
+        }
+    }
+    Ok(buckets)
+}
+
+/// Returns the net `amount0` and `amount1` of the stored swaps sent by `sender`
+/// to `receiver`, for example to follow the flow of tokens between a router
+/// and a recipient. Amounts keep the sign of the pool's point of view, so a
+/// positive total went into the pools and a negative one left them.
+///
+/// The raw amounts are summed at full precision, and a total that does not fit
+/// in an `I256` is an error rather than wrapping around.
+pub fn net_volume(conn: &Connection, sender: Address, receiver: Address) -> Result<(I256, I256)> {
+    let mut stmt = conn.prepare(
+        "SELECT rowid, amount0, amount1 FROM logs
+         WHERE sender_id = (SELECT id FROM addresses WHERE address = ?1)
+           AND receiver_id = (SELECT id FROM addresses WHERE address = ?2)",
+    )?;
+    let rows = stmt
+        .query_map(
+            params![format!("{:#x}", sender), format!("{:#x}", receiver)],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            },
+        )?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let overflow = |token: &str| {
+        MonitorError::Decode(format!(
+            "the net {} of swaps from {:#x} to {:#x} overflows",
+            token, sender, receiver
+        ))
+    };
+    let (mut net0, mut net1) = (I256::zero(), I256::zero());
+    for (rowid, amount0, amount1) in rows {
+        let amounts = parse_i256(&amount0).and_then(|amount0| Ok((amount0, parse_i256(&amount1)?)));
+        let Some((amount0, amount1)) = skip_corrupt(rowid, amounts) else {
+            continue;
+        };
+        net0 = net0
+            .checked_add(amount0)
+            .ok_or_else(|| overflow("amount0"))?;
+        net1 = net1
+            .checked_add(amount1)
+            .ok_or_else(|| overflow("amount1"))?;
+    }
+    Ok((net0, net1))
+}
+
+/// Returns the in-range liquidity of `pool` after each of its stored swaps, as
+/// pairs of block number and liquidity in chain order, for example to plot
+/// how liquidity providers enter and leave the pool. A block with several
+/// swaps has a pair for each. Swaps without a block number are left out.
+pub fn liquidity_series(conn: &Connection, pool: Address) -> Result<Vec<(u64, u128)>> {
+    let mut stmt = conn.prepare(
+        "SELECT rowid, block_number, liquidity FROM logs
+         WHERE pool_address = ?1 AND block_number IS NOT NULL
+         ORDER BY block_number, log_index",
+    )?;
+    let rows = stmt
+        .query_map(params![format!("{:#x}", pool)], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, u64>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(rowid, block_number, liquidity)| {
+            skip_corrupt(rowid, parse_liquidity(&liquidity))
+                .map(|liquidity| (block_number, liquidity))
+        })
+        .collect())
+}
+
+/// How long after its block a swap may be committed while the monitor is
+/// watching live, see `BlockGap::during_downtime`.
+pub const DOWNTIME_SECS: u64 = 600;
+
+/// A run of consecutive blocks without a stored swap, between two blocks that
+/// have one, see `find_block_gaps`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockGap {
+    /// The first block without a swap.
+    pub from_block: u64,
+    /// The last block without a swap.
+    pub to_block: u64,
+    /// Whether the monitor was down during the gap: the swaps of the block
+    /// after it were committed more than `DOWNTIME_SECS` after that block,
+    /// going by the checkpoint history, so its blocks were only collected by a
+    /// backfill, if at all. `false` when the history or the block timestamp is
+    /// missing, such as for swaps stored before the history was kept.
+    pub during_downtime: bool,
+}
+
+impl BlockGap {
+    /// The number of blocks in the gap.
+    pub fn blocks(&self) -> u64 {
+        self.to_block - self.from_block + 1
+    }
+}
+
+/// Returns the gaps between the earliest and the latest stored swap, of all
+/// pools, in block order.
+///
+/// Most gaps are blocks in which the pools simply saw no swap. Each advance of
+/// the checkpoint is recorded with the time it was committed, and a gap whose
+/// next swap was committed long after its block is marked `during_downtime`.
+/// The monitor backfills from its checkpoint after every restart, so such a
+/// gap is usually a quiet stretch as well, but it is worth checking against
+/// the chain, for example in case `FROM_BLOCK` skipped past the checkpoint. A
+/// gap much larger than the usual ones can also be left by `MAX_ROWS` pruning
+/// or by pools added later.
+pub fn find_block_gaps(conn: &Connection) -> Result<Vec<BlockGap>> {
+    let mut stmt = conn.prepare(
+        "SELECT block_number, MAX(timestamp) FROM logs WHERE block_number IS NOT NULL
+         GROUP BY block_number ORDER BY block_number",
+    )?;
+    let blocks = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, u64>(0)?, row.get::<_, Option<u64>>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    // The batch holding a block is the first whose checkpoint reaches it
+    let mut committed_at = conn.prepare(
+        "SELECT recorded_at FROM checkpoints WHERE block_number >= ?1
+         ORDER BY block_number LIMIT 1",
+    )?;
+    let mut gaps = Vec::new();
+    for pair in blocks.windows(2) {
+        let [(previous, _), (block_number, timestamp)] = *pair else {
+            continue;
+        };
+        if block_number > previous + 1 {
+            let recorded_at: Option<u64> = committed_at
+                .query_row(params![block_number], |row| row.get(0))
+                .optional()?;
+            let during_downtime = match (timestamp, recorded_at) {
+                (Some(timestamp), Some(recorded_at)) => {
+                    recorded_at > timestamp.saturating_add(DOWNTIME_SECS)
+                }
+                _ => false,
+            };
+            gaps.push(BlockGap {
+                from_block: previous + 1,
+                to_block: block_number - 1,
+                during_downtime,
+            });
+        }
+    }
+    Ok(gaps)
+}
+
+/// A summary of the swaps in the `logs` table, for a quick health check of the
+/// collected data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatabaseStats {
+    pub swap_count: u64,
+    pub earliest_block: Option<u64>,
+    pub latest_block: Option<u64>,
+    pub pool_count: u64,
+    /// The swap with the largest absolute raw `amount0`, or `amount1` if equal.
+    pub largest_swap: Option<CombinedLog>,
+    /// The longest of `find_block_gaps`, the earliest of those as long.
+    pub largest_gap: Option<BlockGap>,
+}
+
+impl fmt::Display for DatabaseStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let block = |block: Option<u64>| block.map_or("-".to_string(), |block| block.to_string());
+        writeln!(f, "swaps:          {}", self.swap_count)?;
+        writeln!(f, "pools:          {}", self.pool_count)?;
+        writeln!(f, "earliest block: {}", block(self.earliest_block))?;
+        write!(f, "latest block:   {}", block(self.latest_block))?;
+        if let Some(swap) = &self.largest_swap {
+            write!(
+                f,
+                "\nlargest swap:   {:#x} in pool {:#x} at block {}, amount0 {}, amount1 {}",
+                swap.tx_hash,
+                swap.pool,
+                block(swap.block_number),
+                swap.data.amount0,
+                swap.data.amount1
+            )?;
+        }
+        if let Some(gap) = &self.largest_gap {
+            write!(
+                f,
+                "\nlargest gap:    blocks {} to {} without swaps ({} blocks)",
+                gap.from_block,
+                gap.to_block,
+                gap.blocks()
+            )?;
+            if gap.during_downtime {
+                write!(f, " during downtime")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Summarizes the stored swaps.
+pub fn database_stats(conn: &Connection) -> Result<DatabaseStats> {
+    let (swap_count, earliest_block, latest_block, pool_count) = conn.query_row(
+        "SELECT COUNT(*), MIN(block_number), MAX(block_number), COUNT(DISTINCT pool_address)
+         FROM logs",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    )?;
+    // The amounts are stored as text, so they are compared after parsing
+    let mut stmt = conn.prepare("SELECT rowid, amount0, amount1 FROM logs")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut largest = None;
+    for (rowid, amount0, amount1) in rows {
+        let Some(amounts) = skip_corrupt(rowid, parse_amounts(&amount0, &amount1)) else {
+            continue;
+        };
+        if largest.is_none_or(|(_, largest)| amounts > largest) {
+            largest = Some((rowid, amounts));
+        }
+    }
+    let largest_swap = largest
+        .map(|(rowid, _)| {
+            conn.query_row(
+                &select_swaps("WHERE logs.rowid = ?1"),
+                params![rowid],
+                StoredSwap::from_row,
+            )
+        })
+        .transpose()?
+        .and_then(|swap| skip_corrupt(swap.rowid, swap.decode()));
+    let mut largest_gap: Option<BlockGap> = None;
+    for gap in find_block_gaps(conn)? {
+        if largest_gap.is_none_or(|largest| gap.blocks() > largest.blocks()) {
+            largest_gap = Some(gap);
+        }
+    }
+    Ok(DatabaseStats {
+        swap_count,
+        earliest_block,
+        latest_block,
+        pool_count,
+        largest_swap,
+        largest_gap,
+    })
+}
+
+/// Prints `database_stats` as a formatted summary to stdout.
+pub fn print_database_stats(conn: &Connection) -> Result<()> {
+    println!("{}", database_stats(conn)?);
+    Ok(())
+}
+
+/// Selects `SWAP_COLUMNS` of the rows matching `condition`, with the parties
+/// looked up in `addresses`.
+pub(super) fn select_swaps(condition: &str) -> String {
+    format!(
+        "SELECT {SWAP_COLUMNS} FROM logs
+         JOIN addresses sender ON sender.id = logs.sender_id
+         JOIN addresses receiver ON receiver.id = logs.receiver_id
+         {condition}"
+    )
+}
+
+fn query_swaps(
+    conn: &Connection,
+    condition: &str,
+    params: impl Params,
+) -> Result<Vec<CombinedLog>> {
+    let mut stmt = conn.prepare(&select_swaps(&format!(
+        "{condition} ORDER BY block_number, log_index"
+    )))?;
+    let rows = stmt
+        .query_map(params, StoredSwap::from_row)?
+        .collect::<rusqlite::Result<Vec<StoredSwap>>>()?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|swap| skip_corrupt(swap.rowid, swap.decode()))
+        .collect())
+}
+
+/// A `logs` row as stored, before the text columns are parsed.
+pub(super) struct StoredSwap {
+    tx_hash: String,
+    pool: Option<String>,
+    sender: String,
+    receiver: String,
+    amount0: String,
+    amount1: String,
+    sqrt_price: String,
+    liquidity: String,
+    tick: i32,
+    block_number: Option<u64>,
+    block_hash: Option<String>,
+    timestamp: Option<u64>,
+    price: Option<f64>,
+    log_index: Option<u64>,
+    amount0_normalized: Option<f64>,
+    amount1_normalized: Option<f64>,
+    tick_price: Option<f64>,
+    protocol_fees_token0: Option<String>,
+    protocol_fees_token1: Option<String>,
+    usd_value: Option<f64>,
+    direction: Option<String>,
+    raw_data: Option<Vec<u8>>,
+    transaction_index: Option<u64>,
+    gas_used: Option<u64>,
+    effective_gas_price: Option<String>,
+    source: Option<String>,
+    pub(super) rowid: i64,
+}
+
+impl StoredSwap {
+    pub(super) fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(StoredSwap {
+            tx_hash: row.get(0)?,
+            pool: row.get(1)?,
+            sender: row.get(2)?,
+            receiver: row.get(3)?,
+            amount0: row.get(4)?,
+            amount1: row.get(5)?,
+            sqrt_price: row.get(6)?,
+            liquidity: row.get(7)?,
+            tick: row.get(8)?,
+            block_number: row.get(9)?,
+            block_hash: row.get(10)?,
+            timestamp: row.get(11)?,
+            price: row.get(12)?,
+            log_index: row.get(13)?,
+            amount0_normalized: row.get(14)?,
+            amount1_normalized: row.get(15)?,
+            tick_price: row.get(16)?,
+            protocol_fees_token0: row.get(17)?,
+            protocol_fees_token1: row.get(18)?,
+            usd_value: row.get(19)?,
+            direction: row.get(20)?,
+            raw_data: row.get(21)?,
+            transaction_index: row.get(22)?,
+            gas_used: row.get(23)?,
+            effective_gas_price: row.get(24)?,
+            source: row.get(25)?,
+            rowid: row.get(26)?,
+        })
+    }
+
+    pub(super) fn decode(self) -> Result<CombinedLog> {
+        let data = LogData {
+            amount0: parse_i256(&self.amount0)?,
+            amount1: parse_i256(&self.amount1)?,
+            sqrt_price: U256::from_dec_str(&self.sqrt_price).map_err(|e| {
+                MonitorError::Decode(format!(
+                    "invalid stored sqrt_price '{}': {}",
+                    self.sqrt_price, e
+                ))
+            })?,
+            liquidity: parse_liquidity(&self.liquidity)?,
+            tick: self.tick,
+            protocol_fees_token0: parse_fees(self.protocol_fees_token0.as_deref())?,
+            protocol_fees_token1: parse_fees(self.protocol_fees_token1.as_deref())?,
+        };
+        Ok(CombinedLog {
+            tx_hash: parse_hash(&self.tx_hash)?,
+            // Rows written before the column existed have no pool address
+            pool: self
+                .pool
+                .as_deref()
+                .map(parse_address)
+                .transpose()?
+                .unwrap_or_default(),
+            sender: parse_address(&self.sender)?,
+            receiver: parse_address(&self.receiver)?,
+            data,
+            block_number: self.block_number,
+            block_hash: self.block_hash.as_deref().map(parse_hash).transpose()?,
+            timestamp: self.timestamp,
+            price: self.price,
+            tick_price: self.tick_price,
+            amount0_normalized: self.amount0_normalized,
+            amount1_normalized: self.amount1_normalized,
+            usd_value: self.usd_value,
+            direction: self
+                .direction
+                .as_deref()
+                .map(|direction| {
+                    direction.parse::<SwapDirection>().map_err(|e| {
+                        MonitorError::Decode(format!("invalid stored direction: {}", e))
+                    })
+                })
+                .transpose()?,
+            log_index: self.log_index,
+            transaction_index: self.transaction_index,
+            gas_used: self.gas_used,
+            effective_gas_price: self
+                .effective_gas_price
+                .as_deref()
+                .map(|price| {
+                    U256::from_dec_str(price).map_err(|e| {
+                        MonitorError::Decode(format!(
+                            "invalid stored effective_gas_price '{}': {}",
+                            price, e
+                        ))
+                    })
+                })
+                .transpose()?,
+            source: self.source,
+            // Retracted swaps are deleted, so a stored swap is never removed
+            removed: false,
+            raw_data: self.raw_data.map(Bytes::from),
+        })
+    }
+}
+
+/// Returns the value of a stored row that could be parsed, or else logs a
+/// warning naming the row and returns `None`.
+pub(super) fn skip_corrupt<T>(rowid: i64, parsed: Result<T>) -> Option<T> {
+    match parsed {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!(rowid, error = %e, "skipping a corrupted row of the logs table");
+            None
+        }
+    }
+}
+
+/// The absolute values of a stored `amount0` and `amount1`.
+fn parse_amounts(amount0: &str, amount1: &str) -> Result<(U256, U256)> {
+    Ok((
+        abs_u256(parse_i256(amount0)?),
+        abs_u256(parse_i256(amount1)?),
+    ))
+}
+
+fn parse_i256(amount: &str) -> Result<I256> {
+    I256::from_dec_str(amount)
+        .map_err(|e| MonitorError::Decode(format!("invalid stored amount '{}': {}", amount, e)))
+}
+
+fn parse_liquidity(liquidity: &str) -> Result<u128> {
+    liquidity.parse().map_err(|e| {
+        MonitorError::Decode(format!("invalid stored liquidity '{}': {}", liquidity, e))
+    })
+}
+
+fn parse_fees(fees: Option<&str>) -> Result<Option<u128>> {
+    fees.map(|fees| {
+        fees.parse().map_err(|e| {
+            MonitorError::Decode(format!("invalid stored protocol fees '{}': {}", fees, e))
+        })
+    })
+    .transpose()
+}
+
+fn parse_hash(hash: &str) -> Result<H256> {
+    H256::from_str(hash)
+        .map_err(|e| MonitorError::Decode(format!("invalid stored hash '{}': {}", hash, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::sqlite::{initialize_in_memory_database, insert_logs_batch};
+    use crate::tests::{create_test_combined_log, swap_at};
+    use crate::PoolEvent;
+
+    #[test]
+    fn test_query_swaps_round_trip() {
+        let mut conn = initialize_in_memory_database().unwrap();
+        let swap = CombinedLog {
+            price: Some(0.000_617_1),
+            tick_price: Some(0.000_617_0),
+            amount0_normalized: Some(-263.12),
+            amount1_normalized: Some(0.162_381_653_432_074),
+            usd_value: Some(263.12),
+            direction: Some(SwapDirection::OneForZero),
+            raw_data: Some(Bytes::from(vec![0xde, 0xad, 0xbe, 0xef])),
+            ..create_test_combined_log()
+        };
+        let mut later = create_test_combined_log();
+        later.data.protocol_fees_token0 = Some(u128::MAX);
+        later.data.protocol_fees_token1 = Some(0);
+        later.block_number = Some(17_500_010);
+        later.timestamp = swap.timestamp.map(|timestamp| timestamp + 120);
+        later.log_index = Some(3);
+        later.transaction_index = Some(1);
+        later.gas_used = Some(184_214);
+        later.effective_gas_price = Some(U256::from(23_512_000_000u64));
+        later.source = Some("eu-1".to_string());
+        later.sender = Address::from_low_u64_be(7);
+        later.data.amount0 = I256::MIN;
+        insert_logs_batch(&mut conn, &[later.clone().into(), swap.clone().into()]).unwrap();
+
+        assert_eq!(
+            get_swaps_by_block_range(&conn, 17_500_000, 17_500_010).unwrap(),
+            vec![swap.clone(), later.clone()]
+        );
+        assert_eq!(
+            get_swaps_by_block_range(&conn, 17_500_001, 17_500_009).unwrap(),
+            vec![]
+        );
+        let since = swap.timestamp.unwrap();
+        assert_eq!(
+            get_swaps_since(&conn, since - 1).unwrap(),
+            vec![swap.clone(), later.clone()]
+        );
+        assert_eq!(get_swaps_since(&conn, since).unwrap(), vec![later.clone()]);
+        assert_eq!(get_swaps_by_sender(&conn, swap.sender).unwrap(), vec![swap]);
+        assert_eq!(
+            get_swaps_by_sender(&conn, later.sender).unwrap(),
+            vec![later]
+        );
+    }
+
+    #[test]
+    fn test_get_swaps() {
+        let mut conn = initialize_in_memory_database().unwrap();
+        let swap_in = |pool: u64, block_number: u64, log_index: u64| CombinedLog {
+            pool: Address::from_low_u64_be(pool),
+            ..swap_at(block_number, log_index)
+        };
+        let swaps = [
+            swap_in(1, 100, 0),
+            swap_in(2, 100, 1),
+            swap_in(1, 101, 2),
+            swap_in(1, 102, 3),
+        ];
+        let events: Vec<PoolEvent> = swaps.iter().cloned().map(PoolEvent::from).collect();
+        insert_logs_batch(&mut conn, &events).unwrap();
+
+        assert_eq!(
+            get_swaps(&conn, &SwapQuery::default()).unwrap(),
+            swaps.to_vec()
+        );
+        let query = SwapQuery {
+            pool: Some(Address::from_low_u64_be(1)),
+            from_block: Some(101),
+            ..Default::default()
+        };
+        assert_eq!(get_swaps(&conn, &query).unwrap(), swaps[2..].to_vec());
+        let query = SwapQuery {
+            to_block: Some(101),
+            limit: 2,
+            offset: 1,
+            ..Default::default()
+        };
+        assert_eq!(get_swaps(&conn, &query).unwrap(), swaps[1..3].to_vec());
+    }
+
+    #[test]
+    fn test_database_stats() {
+        let mut conn = initialize_in_memory_database().unwrap();
+        let empty = database_stats(&conn).unwrap();
+        assert_eq!(empty.swap_count, 0);
+        assert_eq!(empty.earliest_block, None);
+        assert_eq!(empty.largest_swap, None);
+        assert_eq!(empty.largest_gap, None);
+        assert!(empty.to_string().contains("earliest block: -"));
+
+        let swap = create_test_combined_log();
+        let mut largest = create_test_combined_log();
+        largest.pool = Address::from_low_u64_be(1);
+        largest.block_number = Some(17_500_010);
+        largest.log_index = Some(5);
+        largest.data.amount0 = I256::MIN;
+        let mut earlier = create_test_combined_log();
+        earlier.block_number = Some(17_499_990);
+        earlier.log_index = Some(2);
+        insert_logs_batch(
+            &mut conn,
+            &[swap.into(), largest.clone().into(), earlier.into()],
+        )
+        .unwrap();
+
+        let stats = database_stats(&conn).unwrap();
+        assert_eq!(stats.swap_count, 3);
+        assert_eq!(stats.pool_count, 2);
+        assert_eq!(stats.earliest_block, Some(17_499_990));
+        assert_eq!(stats.latest_block, Some(17_500_010));
+        assert_eq!(stats.largest_swap, Some(largest));
+        // Of the two gaps of 9 blocks the earlier is reported
+        assert_eq!(
+            stats.largest_gap,
+            Some(BlockGap {
+                from_block: 17_499_991,
+                to_block: 17_499_999,
+                during_downtime: true,
+            })
+        );
+        assert!(stats.to_string().contains("swaps:          3"));
+        assert!(stats.to_string().contains(
+            "largest gap:    blocks 17499991 to 17499999 without swaps (9 blocks) during downtime"
+        ));
+    }
+
+    #[test]
+    fn test_find_block_gaps() {
+        let mut conn = initialize_in_memory_database().unwrap();
+        assert!(find_block_gaps(&conn).unwrap().is_empty());
+        let swap = |block_number: u64, log_index: u64| CombinedLog {
+            timestamp: Some(block_number * 12),
+            ..swap_at(block_number, log_index)
+        };
+        let pending = CombinedLog {
+            block_number: None,
+            ..swap_at(0, 4)
+        };
+        let other_pool = CombinedLog {
+            pool: Address::from_low_u64_be(1),
+            ..swap(103, 9)
+        };
+        insert_logs_batch(
+            &mut conn,
+            &[
+                swap(100, 0).into(),
+                swap(100, 1).into(),
+                swap(101, 2).into(),
+                pending.into(),
+                other_pool.into(),
+            ],
+        )
+        .unwrap();
+        insert_logs_batch(&mut conn, &[swap(110, 3).into()]).unwrap();
+        // Block 103 was committed live, block 110 only after a restart
+        let record = |block_number: u64, recorded_at: u64| {
+            conn.execute(
+                "UPDATE checkpoints SET recorded_at = ?2 WHERE block_number = ?1",
+                params![block_number, recorded_at],
+            )
+            .unwrap()
+        };
+        assert_eq!(record(103, 103 * 12 + 30), 1);
+        assert_eq!(record(110, 110 * 12 + DOWNTIME_SECS + 1), 1);
+
+        let gaps = find_block_gaps(&conn).unwrap();
+        assert_eq!(
+            gaps,
+            vec![
+                BlockGap {
+                    from_block: 102,
+                    to_block: 102,
+                    during_downtime: false,
+                },
+                BlockGap {
+                    from_block: 104,
+                    to_block: 109,
+                    during_downtime: true,
+                },
+            ]
+        );
+        assert_eq!(gaps[1].blocks(), 6);
+
+        // Without a history, as for swaps stored by earlier versions, no gap is
+        // put down to downtime
+        conn.execute("DELETE FROM checkpoints", []).unwrap();
+        assert!(find_block_gaps(&conn)
+            .unwrap()
+            .iter()
+            .all(|gap| !gap.during_downtime));
+    }
+
+    #[test]
+    fn test_queries_skip_corrupted_rows() {
+        let mut conn = initialize_in_memory_database().unwrap();
+        let block_number = create_test_combined_log().block_number.unwrap();
+        let swap = |log_index: u64, amount0: i64| {
+            let mut swap = swap_at(block_number, log_index);
+            swap.data.amount0 = I256::from(amount0);
+            PoolEvent::from(swap)
+        };
+        insert_logs_batch(&mut conn, &[swap(1, 100), swap(2, -5_000), swap(3, 7)]).unwrap();
+        // A partial write truncated an amount, and a manual edit overflowed one
+        conn.execute("UPDATE logs SET amount0 = '-5,0' WHERE log_index = 2", [])
+            .unwrap();
+        conn.execute(
+            "UPDATE logs SET amount1 = ?1 WHERE log_index = 3",
+            params![format!("{}0", I256::MAX)],
+        )
+        .unwrap();
+
+        let swap = create_test_combined_log();
+        let swaps = get_swaps_by_block_range(&conn, block_number, block_number).unwrap();
+        assert_eq!(swaps.len(), 1);
+        assert_eq!(swaps[0].data.amount0, I256::from(100));
+        assert_eq!(get_swaps_by_tx_hash(&conn, swap.tx_hash).unwrap().len(), 1);
+
+        let timestamp = swap.timestamp.unwrap();
+        let buckets = aggregate_volume(&conn, swap.pool, timestamp + 1).unwrap();
+        assert_eq!(buckets[0].volume0, U256::from(100));
+        assert_eq!(buckets[0].swap_count, 1);
+
+        let stats = database_stats(&conn).unwrap();
+        assert_eq!(stats.swap_count, 3);
+        assert_eq!(stats.largest_swap.unwrap().log_index, Some(1));
+    }
+
+    #[test]
+    fn test_liquidity_series() {
+        let mut conn = initialize_in_memory_database().unwrap();
+        let swap = |block_number: u64, log_index: u64, liquidity: u128| {
+            let mut swap = swap_at(block_number, log_index);
+            swap.data.liquidity = liquidity;
+            swap
+        };
+        let pending = CombinedLog {
+            block_number: None,
+            ..swap(0, 3, 1)
+        };
+        let other_pool = CombinedLog {
+            pool: Address::from_low_u64_be(1),
+            ..swap(10, 9, 1)
+        };
+        insert_logs_batch(
+            &mut conn,
+            &[
+                swap(20, 0, u128::MAX).into(),
+                swap(10, 5, 7).into(),
+                swap(10, 2, 1 << 100).into(),
+                pending.into(),
+                other_pool.into(),
+            ],
+        )
+        .unwrap();
+
+        // Values past u64 and i64 round-trip through the TEXT column exactly
+        let pool = create_test_combined_log().pool;
+        assert_eq!(
+            liquidity_series(&conn, pool).unwrap(),
+            vec![(10, 1 << 100), (10, 7), (20, u128::MAX)]
+        );
+        assert!(liquidity_series(&conn, Address::zero()).unwrap().is_empty());
+
+        // A corrupted row is left out
+        conn.execute(
+            "UPDATE logs SET liquidity = 'x' WHERE block_number = 20",
+            [],
+        )
+        .unwrap();
+        assert_eq!(
+            liquidity_series(&conn, pool).unwrap(),
+            vec![(10, 1 << 100), (10, 7)]
+        );
+    }
+
+    #[test]
+    fn test_net_volume() {
+        let mut conn = initialize_in_memory_database().unwrap();
+        let (router, recipient) = (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        let block_number = create_test_combined_log().block_number.unwrap();
+        let swap = |log_index: u64, sender: Address, receiver: Address, amount0: I256| {
+            let mut swap = swap_at(block_number, log_index);
+            swap.sender = sender;
+            swap.receiver = receiver;
+            swap.data.amount0 = amount0;
+            swap.data.amount1 = -amount0 * I256::from(3);
+            PoolEvent::from(swap)
+        };
+        insert_logs_batch(
+            &mut conn,
+            &[
+                swap(1, router, recipient, I256::from(100)),
+                swap(2, router, recipient, I256::from(-30)),
+                // The opposite direction and other parties are left out
+                swap(3, recipient, router, I256::from(1_000)),
+                swap(4, router, Address::from_low_u64_be(3), I256::from(1_000)),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            net_volume(&conn, router, recipient).unwrap(),
+            (I256::from(70), I256::from(-210))
+        );
+        assert_eq!(
+            net_volume(&conn, recipient, Address::from_low_u64_be(3)).unwrap(),
+            (I256::zero(), I256::zero())
+        );
+
+        // A corrupted row is skipped, and a total beyond I256 is an error
+        conn.execute("UPDATE logs SET amount0 = 'abc' WHERE log_index = 2", [])
+            .unwrap();
+        assert_eq!(
+            net_volume(&conn, router, recipient).unwrap(),
+            (I256::from(100), I256::from(-300))
+        );
+        conn.execute(
+            "UPDATE logs SET amount0 = ?1",
+            params![I256::MAX.to_string()],
+        )
+        .unwrap();
+        let err = net_volume(&conn, router, recipient).unwrap_err();
+        assert!(err.to_string().contains("net amount0"), "{err}");
+    }
+
+    #[test]
+    fn test_aggregate_volume() {
+        let mut conn = initialize_in_memory_database().unwrap();
+        let block_number = create_test_combined_log().block_number.unwrap();
+        let swap = |log_index: u64, timestamp: Option<u64>, amount0: i64| {
+            let mut swap = swap_at(block_number, log_index);
+            swap.timestamp = timestamp;
+            swap.data.amount0 = I256::from(amount0);
+            swap.data.amount1 = I256::from(-amount0 * 2);
+            PoolEvent::from(swap)
+        };
+        let mut other_pool = create_test_combined_log();
+        other_pool.pool = Address::from_low_u64_be(1);
+        other_pool.log_index = Some(9);
+        insert_logs_batch(
+            &mut conn,
+            &[
+                swap(1, Some(7_200), 100),
+                swap(2, Some(3_600), -50),
+                swap(3, Some(7_199), 20),
+                swap(4, None, 1_000),
+                other_pool.into(),
+            ],
+        )
+        .unwrap();
+
+        let pool = create_test_combined_log().pool;
+        assert_eq!(
+            aggregate_volume(&conn, pool, 3_600).unwrap(),
+            vec![
+                VolumeBucket {
+                    start: 3_600,
+                    volume0: U256::from(70),
+                    volume1: U256::from(140),
+                    swap_count: 2,
+                },
+                VolumeBucket {
+                    start: 7_200,
+                    volume0: U256::from(100),
+                    volume1: U256::from(200),
+                    swap_count: 1,
+                },
+            ]
+        );
+        assert_eq!(aggregate_volume(&conn, pool, 86_400).unwrap().len(), 1);
+        assert!(aggregate_volume(&conn, Address::zero(), 3_600)
+            .unwrap()
+            .is_empty());
+        assert!(aggregate_volume(&conn, pool, 0).is_err());
+    }
+}
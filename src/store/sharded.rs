@@ -0,0 +1,225 @@
+use super::SwapStore;
+use crate::error::Result;
+use crate::{PoolEvent, PoolMetadata};
+use async_trait::async_trait;
+use ethers::core::types::Address;
+use std::collections::HashMap;
+
+/// A store that writes the events of each pool to its own database, so busy
+/// pools do not contend for the lock of a shared SQLite file.
+///
+/// Events of pools without a database of their own go to the default store.
+/// Each database keeps its own checkpoint, and the combined checkpoint is the
+/// lowest of those that are set. Every batch advances the checkpoint of each
+/// database to the highest block of the batch, including the databases with
+/// no events in it, so a pool that has been quiet for a while does not hold
+/// back the checkpoint and make a resumed backfill start earlier than needed.
+pub struct ShardedStore {
+    /// The default store first, followed by the stores of the pools.
+    stores: Vec<Box<dyn SwapStore>>,
+    /// The index in `stores` of each pool with a store of its own.
+    routes: HashMap<Address, usize>,
+}
+
+impl ShardedStore {
+    /// Creates a store that writes to `default` except for the pools that are
+    /// added with `add_pool_store`.
+    pub fn new(default: Box<dyn SwapStore>) -> Self {
+        Self {
+            stores: vec![default],
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Writes the events and metadata of each of `pools` to `store`.
+    pub fn add_pool_store(&mut self, pools: &[Address], store: Box<dyn SwapStore>) {
+        let index = self.stores.len();
+        self.stores.push(store);
+        for &pool in pools {
+            self.routes.insert(pool, index);
+        }
+    }
+
+    fn store_index(&self, pool: Address) -> usize {
+        self.routes.get(&pool).copied().unwrap_or(0)
+    }
+
+    fn store_for(&mut self, pool: Address) -> &mut Box<dyn SwapStore> {
+        let index = self.store_index(pool);
+        &mut self.stores[index]
+    }
+}
+
+#[async_trait]
+impl SwapStore for ShardedStore {
+    async fn init(&mut self) -> Result<()> {
+        for store in &mut self.stores {
+            store.init().await?;
+        }
+        Ok(())
+    }
+
+    async fn insert(&mut self, event: &PoolEvent) -> Result<()> {
+        self.store_for(event.pool()).insert(event).await
+    }
+
+    /// Commits the events of each database separately, in the order of the
+    /// batch within each database, then advances the checkpoint of the
+    /// databases whose events end before the highest block of the batch.
+    async fn insert_batch(&mut self, events: &[PoolEvent]) -> Result<()> {
+        let mut batches = vec![Vec::new(); self.stores.len()];
+        for event in events {
+            batches[self.store_index(event.pool())].push(event.clone());
+        }
+        for (store, batch) in self.stores.iter_mut().zip(&batches) {
+            if !batch.is_empty() {
+                store.insert_batch(batch).await?;
+            }
+        }
+        let Some(highest) = events.iter().filter_map(PoolEvent::block_number).max() else {
+            return Ok(());
+        };
+        for (store, batch) in self.stores.iter_mut().zip(&batches) {
+            if batch.iter().filter_map(PoolEvent::block_number).max() < Some(highest) {
+                store.advance_checkpoint(highest).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete(&mut self, event: &PoolEvent) -> Result<usize> {
+        self.store_for(event.pool()).delete(event).await
+    }
+
+    async fn sync(&mut self) -> Result<()> {
+        for store in &mut self.stores {
+            store.sync().await?;
+        }
+        Ok(())
+    }
+
+    async fn read_checkpoint(&mut self) -> Result<Option<u64>> {
+        let mut checkpoint: Option<u64> = None;
+        for store in &mut self.stores {
+            if let Some(block_number) = store.read_checkpoint().await? {
+                checkpoint = Some(checkpoint.map_or(block_number, |c| c.min(block_number)));
+            }
+        }
+        Ok(checkpoint)
+    }
+
+    async fn advance_checkpoint(&mut self, block_number: u64) -> Result<()> {
+        for store in &mut self.stores {
+            store.advance_checkpoint(block_number).await?;
+        }
+        Ok(())
+    }
+
+    async fn insert_pool(&mut self, metadata: &PoolMetadata) -> Result<()> {
+        self.store_for(metadata.pool).insert_pool(metadata).await
+    }
+
+    async fn read_pool(&mut self, pool: Address) -> Result<Option<PoolMetadata>> {
+        self.store_for(pool).read_pool(pool).await
+    }
+
+    /// Prunes each database to `max_rows` separately.
+    async fn prune(&mut self, max_rows: u64) -> Result<usize> {
+        let mut pruned = 0;
+        for store in &mut self.stores {
+            pruned += store.prune(max_rows).await?;
+        }
+        Ok(pruned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::SqliteStore;
+    use crate::tests::{create_test_combined_log, create_test_pool_metadata, swap_at};
+    use crate::CombinedLog;
+
+    fn swap_in(pool: Address, block_number: u64, log_index: u64) -> PoolEvent {
+        PoolEvent::from(CombinedLog {
+            pool,
+            ..swap_at(block_number, log_index)
+        })
+    }
+
+    #[tokio::test]
+    async fn test_sharded_store_routes_by_pool() {
+        let busy = Address::from_low_u64_be(1);
+        let other = Address::from_low_u64_be(2);
+        let mut store = ShardedStore::new(Box::new(SqliteStore::open_in_memory().unwrap()));
+        store.add_pool_store(&[busy], Box::new(SqliteStore::open_in_memory().unwrap()));
+        store.init().await.unwrap();
+
+        store
+            .insert_batch(&[
+                swap_in(busy, 12, 0),
+                swap_in(other, 10, 1),
+                swap_in(busy, 14, 2),
+            ])
+            .await
+            .unwrap();
+        store.insert(&swap_in(other, 11, 3)).await.unwrap();
+        // Each database holds its own pool's events, and both checkpoints
+        // reach the end of the batch
+        assert_eq!(store.stores[0].read_checkpoint().await.unwrap(), Some(14));
+        assert_eq!(store.stores[1].read_checkpoint().await.unwrap(), Some(14));
+        assert_eq!(store.read_checkpoint().await.unwrap(), Some(14));
+        assert_eq!(store.delete(&swap_in(busy, 12, 0)).await.unwrap(), 1);
+        assert_eq!(store.delete(&swap_in(other, 12, 0)).await.unwrap(), 0);
+        assert_eq!(store.delete(&swap_in(other, 11, 3)).await.unwrap(), 1);
+
+        let mut metadata = create_test_pool_metadata();
+        metadata.pool = busy;
+        store.insert_pool(&metadata).await.unwrap();
+        assert_eq!(store.read_pool(busy).await.unwrap(), Some(metadata));
+        assert_eq!(store.stores[0].read_pool(busy).await.unwrap(), None);
+        assert_eq!(store.read_pool(other).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_sharded_store_checkpoint_skips_empty_stores() {
+        let pool = Address::from_low_u64_be(1);
+        let mut store = ShardedStore::new(Box::new(SqliteStore::open_in_memory().unwrap()));
+        store.add_pool_store(&[pool], Box::new(SqliteStore::open_in_memory().unwrap()));
+        store.init().await.unwrap();
+        assert_eq!(store.read_checkpoint().await.unwrap(), None);
+
+        store.insert_batch(&[swap_in(pool, 20, 0)]).await.unwrap();
+        assert_eq!(store.read_checkpoint().await.unwrap(), Some(20));
+    }
+
+    #[tokio::test]
+    async fn test_sharded_store_advances_idle_shards() {
+        let busy = Address::from_low_u64_be(1);
+        let idle = Address::from_low_u64_be(2);
+        let mut store = ShardedStore::new(Box::new(SqliteStore::open_in_memory().unwrap()));
+        store.add_pool_store(&[idle], Box::new(SqliteStore::open_in_memory().unwrap()));
+        store.init().await.unwrap();
+
+        store.insert_batch(&[swap_in(idle, 100, 0)]).await.unwrap();
+        // The idle pool sees no swaps for a long stretch of blocks
+        store
+            .insert_batch(&[swap_in(busy, 150, 1), swap_in(busy, 200, 2)])
+            .await
+            .unwrap();
+        store.insert_batch(&[swap_in(busy, 300, 3)]).await.unwrap();
+        assert_eq!(store.stores[1].read_checkpoint().await.unwrap(), Some(300));
+        assert_eq!(store.read_checkpoint().await.unwrap(), Some(300));
+
+        // Batches without a block, such as pending swaps, move no checkpoint
+        let mut pending = create_test_combined_log();
+        pending.block_number = None;
+        store.insert_batch(&[pending.into()]).await.unwrap();
+        store.insert_batch(&[]).await.unwrap();
+        assert_eq!(store.read_checkpoint().await.unwrap(), Some(300));
+
+        store.advance_checkpoint(310).await.unwrap();
+        assert_eq!(store.stores[0].read_checkpoint().await.unwrap(), Some(310));
+        assert_eq!(store.stores[1].read_checkpoint().await.unwrap(), Some(310));
+    }
+}
@@ -0,0 +1,1616 @@
+use super::{SwapStore, EVENT_TABLES, PRUNE_BATCH_SIZE};
+use crate::error::{MonitorError, Result};
+use crate::{
+    decode_swap_data, price_from_sqrt_price, price_from_tick, to_token_units, BurnLog, CombinedLog,
+    CustomLog, MintLog, PoolEvent, PoolMetadata, SwapDirection, TokenDecimals, TokenMetadata,
+    V2SwapLog,
+};
+use async_trait::async_trait;
+use ethers::core::types::Address;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Path that opens a private in-memory database instead of a file.
+pub const IN_MEMORY_PATH: &str = ":memory:";
+
+/// How a SQLite database trades crash safety for insert throughput, see
+/// `initialize_database`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DurabilityMode {
+    /// SQLite's defaults: every commit is synced to disk before it returns.
+    #[default]
+    Safe,
+    /// Write-ahead logging with `synchronous=NORMAL`, which syncs only at WAL
+    /// checkpoints.
+    Fast,
+}
+
+impl FromStr for DurabilityMode {
+    type Err = eyre::Report;
+
+    fn from_str(mode: &str) -> eyre::Result<Self> {
+        match mode {
+            "safe" => Ok(DurabilityMode::Safe),
+            "fast" => Ok(DurabilityMode::Fast),
+            _ => eyre::bail!(
+                "invalid durability mode '{}': expected 'safe' or 'fast'",
+                mode
+            ),
+        }
+    }
+}
+
+/// The table swaps are stored in unless another is chosen, see `TableName`.
+pub const DEFAULT_SWAP_TABLE: &str = "logs";
+
+/// The name of the table a `SqliteStore` stores swaps in. Names are spliced
+/// into the SQL, so only plain identifiers are accepted: ASCII letters, digits
+/// and underscores, not starting with a digit, at most 64 characters, and
+/// neither a table of another kind of data nor one reserved by SQLite.
+///
+/// The queries such as `get_swaps` and the `stats` subcommand read the
+/// default `logs` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableName(String);
+
+impl TableName {
+    pub fn new(name: &str) -> Result<Self> {
+        let valid = !name.is_empty()
+            && name.len() <= 64
+            && name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        let lowercase = name.to_ascii_lowercase();
+        if !valid {
+            return Err(MonitorError::InvalidConfig(format!(
+                "invalid table name '{}': expected ASCII letters, digits and underscores",
+                name
+            )));
+        }
+        let taken = EVENT_TABLES
+            .iter()
+            .chain(&["addresses", "pools", "meta", "checkpoints"])
+            .any(|&table| table != DEFAULT_SWAP_TABLE && table == lowercase);
+        if taken || lowercase.starts_with("sqlite_") {
+            return Err(MonitorError::InvalidConfig(format!(
+                "table name '{}' is reserved",
+                name
+            )));
+        }
+        Ok(TableName(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for TableName {
+    fn default() -> Self {
+        TableName(DEFAULT_SWAP_TABLE.to_string())
+    }
+}
+
+impl fmt::Display for TableName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for TableName {
+    type Err = MonitorError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        TableName::new(name)
+    }
+}
+
+/// Stores swaps in a SQLite database file, or in memory when opened with
+/// `IN_MEMORY_PATH`.
+pub struct SqliteStore {
+    conn: Connection,
+    durability: DurabilityMode,
+    swap_table: TableName,
+    /// Whether commits were made since the last `sync` in fast mode.
+    unsynced: bool,
+}
+
+impl SqliteStore {
+    /// Opens or creates the database at `db_path` and migrates its schema.
+    pub fn open(db_path: &str) -> Result<Self> {
+        Self::open_with_durability(db_path, DurabilityMode::Safe)
+    }
+
+    /// Like `open`, choosing how commits are synced to disk.
+    pub fn open_with_durability(db_path: &str, durability: DurabilityMode) -> Result<Self> {
+        Self::open_with_swap_table(db_path, durability, TableName::default())
+    }
+
+    /// Like `open_with_durability`, storing swaps in `swap_table` instead of
+    /// `logs`, so that several monitors can keep their swaps apart in one
+    /// file. The other event kinds keep their tables.
+    pub fn open_with_swap_table(
+        db_path: &str,
+        durability: DurabilityMode,
+        swap_table: TableName,
+    ) -> Result<Self> {
+        Ok(SqliteStore {
+            conn: initialize_database(db_path, durability, &swap_table)?,
+            durability,
+            swap_table,
+            unsynced: false,
+        })
+    }
+
+    /// Opens an empty in-memory database. Its swaps are lost when the store is
+    /// dropped.
+    pub fn open_in_memory() -> Result<Self> {
+        Ok(SqliteStore::from(initialize_in_memory_database()?))
+    }
+
+    fn committed(&mut self) {
+        self.unsynced = self.durability == DurabilityMode::Fast;
+    }
+
+    /// The underlying connection, for running queries against the stored swaps.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// The underlying connection, for maintenance such as `reprocess_all`.
+    pub fn connection_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+}
+
+impl From<Connection> for SqliteStore {
+    fn from(conn: Connection) -> Self {
+        SqliteStore {
+            conn,
+            durability: DurabilityMode::Safe,
+            swap_table: TableName::default(),
+            unsynced: false,
+        }
+    }
+}
+
+#[async_trait]
+impl SwapStore for SqliteStore {
+    async fn init(&mut self) -> Result<()> {
+        create_schema(&self.conn, &self.swap_table)
+    }
+
+    async fn insert(&mut self, event: &PoolEvent) -> Result<()> {
+        insert_event(&self.conn, &self.swap_table, event)?;
+        self.committed();
+        Ok(())
+    }
+
+    async fn insert_batch(&mut self, events: &[PoolEvent]) -> Result<()> {
+        insert_events_into(&mut self.conn, &self.swap_table, events)?;
+        self.committed();
+        Ok(())
+    }
+
+    async fn delete(&mut self, event: &PoolEvent) -> Result<usize> {
+        let deleted = delete_event(&self.conn, &self.swap_table, event)?;
+        self.committed();
+        Ok(deleted)
+    }
+
+    /// In fast mode, syncs the write-ahead log to disk and copies it into the
+    /// database file, so the commits so far survive a power loss.
+    async fn sync(&mut self) -> Result<()> {
+        if self.unsynced {
+            checkpoint_wal(&self.conn)?;
+            self.unsynced = false;
+        }
+        Ok(())
+    }
+
+    async fn read_checkpoint(&mut self) -> Result<Option<u64>> {
+        read_checkpoint(&self.conn)
+    }
+
+    async fn advance_checkpoint(&mut self, block_number: u64) -> Result<()> {
+        update_checkpoint(&self.conn, block_number)?;
+        self.committed();
+        Ok(())
+    }
+
+    async fn insert_pool(&mut self, metadata: &PoolMetadata) -> Result<()> {
+        insert_pool(&self.conn, metadata)
+    }
+
+    async fn read_pool(&mut self, pool: Address) -> Result<Option<PoolMetadata>> {
+        read_pool(&self.conn, pool)
+    }
+
+    async fn prune(&mut self, max_rows: u64) -> Result<usize> {
+        let pruned = prune_events(&self.conn, &self.swap_table, max_rows)?;
+        if pruned > 0 {
+            self.committed();
+        }
+        Ok(pruned)
+    }
+}
+
+/// Opens the database at `db_path` and creates or migrates its schema. A path
+/// of `IN_MEMORY_PATH` opens an in-memory database. Missing parent
+/// directories of `db_path` are created, so a first run can point at a path
+/// such as `./data/swaps.db`.
+///
+/// `DurabilityMode::Fast` switches the database to write-ahead logging with
+/// `synchronous=NORMAL`, which avoids a disk sync per committed batch. The
+/// database cannot be corrupted either way, and a crash of the monitor itself
+/// loses nothing. A power loss or OS crash in fast mode, however, can roll back
+/// the batches committed since the last `SwapStore::sync`. Their checkpoint is
+/// rolled back with them, so on restart the monitor backfills those blocks
+/// again. WAL mode is a property of the file and stays on when it is reopened
+/// in safe mode, which only restores `synchronous=FULL`.
+///
+/// Swaps are stored in `swap_table`, see `TableName`.
+pub(crate) fn initialize_database(
+    db_path: &str,
+    durability: DurabilityMode,
+    swap_table: &TableName,
+) -> Result<Connection> {
+    let conn = if db_path == IN_MEMORY_PATH {
+        Connection::open_in_memory()?
+    } else {
+        create_parent_dirs(db_path)?;
+        Connection::open(db_path)?
+    };
+    match durability {
+        DurabilityMode::Safe => conn.pragma_update(None, "synchronous", "FULL")?,
+        DurabilityMode::Fast => {
+            // In-memory databases keep their `memory` journal
+            conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+            conn.pragma_update(None, "synchronous", "NORMAL")?;
+        }
+    }
+    create_schema(&conn, swap_table)?;
+    Ok(conn)
+}
+
+fn create_parent_dirs(db_path: &str) -> Result<()> {
+    let Some(parent) = Path::new(db_path).parent() else {
+        return Ok(());
+    };
+    if parent.as_os_str().is_empty() || parent.is_dir() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(parent).map_err(|e| {
+        MonitorError::Database(format!(
+            "failed to create the database directory {}: {}",
+            parent.display(),
+            e
+        ))
+    })
+}
+
+/// Opens an in-memory database with the swap schema, without touching the disk.
+pub(crate) fn initialize_in_memory_database() -> Result<Connection> {
+    initialize_database(IN_MEMORY_PATH, DurabilityMode::Safe, &TableName::default())
+}
+
+fn create_schema(conn: &Connection, swap_table: &TableName) -> Result<()> {
+    // Swaps refer to their sender and receiver by id instead of repeating the
+    // hex address in every row
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS addresses (
+        id INTEGER PRIMARY KEY,
+        address TEXT UNIQUE
+      )",
+        [],
+    )?;
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {swap_table} (
+        tx_hash TEXT,
+        pool_address TEXT,
+        sender_id INTEGER REFERENCES addresses(id),
+        receiver_id INTEGER REFERENCES addresses(id),
+        amount0 TEXT,
+        amount1 TEXT,  
+        sqrt_price TEXT,
+        liquidity TEXT,
+        tick INTEGER,
+        block_number INTEGER,
+        block_hash TEXT,
+        timestamp INTEGER,
+        price REAL,
+        log_index INTEGER,
+        amount0_normalized REAL,
+        amount1_normalized REAL,
+        tick_price REAL,
+        protocol_fees_token0 TEXT,
+        protocol_fees_token1 TEXT,
+        usd_value REAL,
+        direction TEXT,
+        raw_data BLOB,
+        transaction_index INTEGER,
+        gas_used INTEGER,
+        effective_gas_price TEXT,
+        source TEXT
+      )"
+        ),
+        [],
+    )?;
+    add_missing_columns(conn, swap_table)?;
+    intern_stored_addresses(conn, swap_table)?;
+    // A unique index rather than a table constraint, so databases created before
+    // log_index existed get it too. Only those can hold duplicates, so the full
+    // scan that removes them runs once, before the index is created.
+    if !index_exists(conn, &format!("idx_{swap_table}_tx_log_index"))? {
+        conn.execute(
+            &format!(
+                "DELETE FROM {swap_table} WHERE log_index IS NOT NULL AND rowid NOT IN (
+        SELECT MIN(rowid) FROM {swap_table} WHERE log_index IS NOT NULL GROUP BY tx_hash, log_index
+      )"
+            ),
+            [],
+        )?;
+        conn.execute(
+            &format!("CREATE UNIQUE INDEX idx_{swap_table}_tx_log_index ON {swap_table}(tx_hash, log_index)"),
+            [],
+        )?;
+    }
+    // Keep the sender, block range and time lookups of `get_swaps_*` off full scans.
+    // The sender index used to be on the hex sender_address column.
+    conn.execute(&format!("DROP INDEX IF EXISTS idx_{swap_table}_sender"), [])?;
+    conn.execute(
+        &format!(
+            "CREATE INDEX IF NOT EXISTS idx_{swap_table}_sender_id ON {swap_table}(sender_id)"
+        ),
+        [],
+    )?;
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS idx_{swap_table}_block ON {swap_table}(block_number)"),
+        [],
+    )?;
+    conn.execute(
+        &format!(
+            "CREATE INDEX IF NOT EXISTS idx_{swap_table}_timestamp ON {swap_table}(timestamp)"
+        ),
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS mints (
+        tx_hash TEXT,
+        pool_address TEXT,
+        sender_address TEXT,
+        owner_address TEXT,
+        tick_lower INTEGER,
+        tick_upper INTEGER,
+        amount TEXT,
+        amount0 TEXT,
+        amount1 TEXT,
+        block_number INTEGER,
+        block_hash TEXT,
+        timestamp INTEGER,
+        log_index INTEGER
+      )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_mints_tx_log_index ON mints(tx_hash, log_index)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS burns (
+        tx_hash TEXT,
+        pool_address TEXT,
+        owner_address TEXT,
+        tick_lower INTEGER,
+        tick_upper INTEGER,
+        amount TEXT,
+        amount0 TEXT,
+        amount1 TEXT,
+        block_number INTEGER,
+        block_hash TEXT,
+        timestamp INTEGER,
+        log_index INTEGER
+      )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_burns_tx_log_index ON burns(tx_hash, log_index)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS v2_swaps (
+        tx_hash TEXT,
+        pool_address TEXT,
+        sender_address TEXT,
+        to_address TEXT,
+        amount0_in TEXT,
+        amount1_in TEXT,
+        amount0_out TEXT,
+        amount1_out TEXT,
+        block_number INTEGER,
+        block_hash TEXT,
+        timestamp INTEGER,
+        log_index INTEGER
+      )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_v2_swaps_tx_log_index ON v2_swaps(tx_hash, log_index)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS custom_events (
+        tx_hash TEXT,
+        contract_address TEXT,
+        event_name TEXT,
+        params TEXT,
+        block_number INTEGER,
+        block_hash TEXT,
+        timestamp INTEGER,
+        log_index INTEGER
+      )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_custom_events_tx_log_index ON custom_events(tx_hash, log_index)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS pools (
+        pool_address TEXT PRIMARY KEY,
+        token0_address TEXT,
+        token0_symbol TEXT,
+        token0_decimals INTEGER,
+        token1_address TEXT,
+        token1_symbol TEXT,
+        token1_decimals INTEGER
+      )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value INTEGER
+      )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS checkpoints (
+        block_number INTEGER PRIMARY KEY,
+        recorded_at INTEGER NOT NULL
+      )",
+        [],
+    )?;
+    // SQLite re-prepares cached statements whose tables changed, but drop them
+    // anyway so the inserts are never bound against a pre-migration schema
+    conn.flush_prepared_statement_cache();
+    Ok(())
+}
+
+/// Columns introduced after the original schema, added to existing databases on startup.
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("pool_address", "TEXT"),
+    ("block_number", "INTEGER"),
+    ("block_hash", "TEXT"),
+    ("timestamp", "INTEGER"),
+    ("price", "REAL"),
+    ("log_index", "INTEGER"),
+    ("amount0_normalized", "REAL"),
+    ("amount1_normalized", "REAL"),
+    ("tick_price", "REAL"),
+    ("protocol_fees_token0", "TEXT"),
+    ("protocol_fees_token1", "TEXT"),
+    ("usd_value", "REAL"),
+    ("direction", "TEXT"),
+    ("raw_data", "BLOB"),
+    ("sender_id", "INTEGER REFERENCES addresses(id)"),
+    ("receiver_id", "INTEGER REFERENCES addresses(id)"),
+    ("transaction_index", "INTEGER"),
+    ("gas_used", "INTEGER"),
+    ("effective_gas_price", "TEXT"),
+    ("source", "TEXT"),
+];
+
+fn index_exists(conn: &Connection, name: &str) -> Result<bool> {
+    let exists = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'index' AND name = ?1",
+        params![name],
+        |row| row.get(0),
+    )?;
+    Ok(exists)
+}
+
+fn add_missing_columns(conn: &Connection, swap_table: &TableName) -> Result<()> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT name FROM pragma_table_info('{swap_table}')"
+    ))?;
+    let existing = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+
+    for (name, column_type) in ADDED_COLUMNS {
+        if !existing.iter().any(|column| column == name) {
+            conn.execute(
+                &format!("ALTER TABLE {swap_table} ADD COLUMN {name} {column_type}"),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves the hex `sender_address` and `receiver_address` of rows stored before
+/// the `addresses` table existed into it, leaving only the ids in the swap
+/// table.
+fn intern_stored_addresses(conn: &Connection, swap_table: &TableName) -> Result<()> {
+    let has_hex_columns: bool = conn.query_row(
+        &format!(
+            "SELECT COUNT(*) = 2 FROM pragma_table_info('{swap_table}')
+         WHERE name IN ('sender_address', 'receiver_address')"
+        ),
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_hex_columns {
+        return Ok(());
+    }
+    conn.execute_batch(&format!(
+        "INSERT OR IGNORE INTO addresses (address)
+           SELECT sender_address FROM {swap_table} WHERE sender_address IS NOT NULL
+           UNION SELECT receiver_address FROM {swap_table} WHERE receiver_address IS NOT NULL;
+         UPDATE {swap_table}
+           SET sender_id = (SELECT id FROM addresses WHERE address = {swap_table}.sender_address),
+             sender_address = NULL
+           WHERE sender_address IS NOT NULL;
+         UPDATE {swap_table}
+           SET receiver_id = (SELECT id FROM addresses WHERE address = {swap_table}.receiver_address),
+             receiver_address = NULL
+           WHERE receiver_address IS NOT NULL;"
+    ))?;
+    Ok(())
+}
+
+/// Returns the id of `address` in the `addresses` table, adding it first if it
+/// is not stored yet.
+pub(crate) fn intern_address(conn: &Connection, address: Address) -> Result<i64> {
+    let address = format!("{:#x}", address);
+    conn.prepare_cached("INSERT OR IGNORE INTO addresses (address) VALUES (?1)")?
+        .execute(params![address])?;
+    let id = conn
+        .prepare_cached("SELECT id FROM addresses WHERE address = ?1")?
+        .query_row(params![address], |row| row.get(0))?;
+    Ok(id)
+}
+
+pub(super) fn insert_log(
+    conn: &Connection,
+    swap_table: &TableName,
+    combined_log: &CombinedLog,
+) -> Result<()> {
+    let sender_id = intern_address(conn, combined_log.sender)?;
+    let receiver_id = intern_address(conn, combined_log.receiver)?;
+    conn.prepare_cached(&format!(
+        "INSERT OR IGNORE INTO {swap_table} (tx_hash, pool_address, sender_id, receiver_id, amount0, amount1, sqrt_price, liquidity, tick, block_number, block_hash, timestamp, price, log_index, amount0_normalized, amount1_normalized, tick_price, protocol_fees_token0, protocol_fees_token1, usd_value, direction, raw_data, transaction_index, gas_used, effective_gas_price, source)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26)"
+    ))?
+    .execute(params![
+        format!("{:#x}", combined_log.tx_hash),
+        format!("{:#x}", combined_log.pool),
+        sender_id,
+        receiver_id,
+        combined_log.data.amount0.to_string(),
+        combined_log.data.amount1.to_string(),
+        combined_log.data.sqrt_price.to_string(),
+        combined_log.data.liquidity.to_string(),
+        combined_log.data.tick,
+        combined_log.block_number,
+        combined_log.block_hash.map(|hash| format!("{:#x}", hash)),
+        combined_log.timestamp,
+        combined_log.price,
+        combined_log.log_index,
+        combined_log.amount0_normalized,
+        combined_log.amount1_normalized,
+        combined_log.tick_price,
+        combined_log
+            .data
+            .protocol_fees_token0
+            .map(|fees| fees.to_string()),
+        combined_log
+            .data
+            .protocol_fees_token1
+            .map(|fees| fees.to_string()),
+        combined_log.usd_value,
+        combined_log.direction.map(SwapDirection::as_str),
+        combined_log.raw_data.as_deref(),
+        combined_log.transaction_index,
+        combined_log.gas_used,
+        combined_log
+            .effective_gas_price
+            .map(|price| price.to_string()),
+        combined_log.source,
+    ])?;
+    Ok(())
+}
+
+fn insert_mint(conn: &Connection, mint: &MintLog) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT OR IGNORE INTO mints (tx_hash, pool_address, sender_address, owner_address, tick_lower, tick_upper, amount, amount0, amount1, block_number, block_hash, timestamp, log_index)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+    )?
+    .execute(params![
+        format!("{:#x}", mint.tx_hash),
+        format!("{:#x}", mint.pool),
+        format!("{:#x}", mint.sender),
+        format!("{:#x}", mint.owner),
+        mint.tick_lower,
+        mint.tick_upper,
+        mint.amount.to_string(),
+        mint.amount0.to_string(),
+        mint.amount1.to_string(),
+        mint.block_number,
+        mint.block_hash.map(|hash| format!("{:#x}", hash)),
+        mint.timestamp,
+        mint.log_index,
+    ])?;
+    Ok(())
+}
+
+fn insert_burn(conn: &Connection, burn: &BurnLog) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT OR IGNORE INTO burns (tx_hash, pool_address, owner_address, tick_lower, tick_upper, amount, amount0, amount1, block_number, block_hash, timestamp, log_index)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+    )?
+    .execute(params![
+        format!("{:#x}", burn.tx_hash),
+        format!("{:#x}", burn.pool),
+        format!("{:#x}", burn.owner),
+        burn.tick_lower,
+        burn.tick_upper,
+        burn.amount.to_string(),
+        burn.amount0.to_string(),
+        burn.amount1.to_string(),
+        burn.block_number,
+        burn.block_hash.map(|hash| format!("{:#x}", hash)),
+        burn.timestamp,
+        burn.log_index,
+    ])?;
+    Ok(())
+}
+
+fn insert_v2_swap(conn: &Connection, swap: &V2SwapLog) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT OR IGNORE INTO v2_swaps (tx_hash, pool_address, sender_address, to_address, amount0_in, amount1_in, amount0_out, amount1_out, block_number, block_hash, timestamp, log_index)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+    )?
+    .execute(params![
+        format!("{:#x}", swap.tx_hash),
+        format!("{:#x}", swap.pool),
+        format!("{:#x}", swap.sender),
+        format!("{:#x}", swap.to),
+        swap.data.amount0_in.to_string(),
+        swap.data.amount1_in.to_string(),
+        swap.data.amount0_out.to_string(),
+        swap.data.amount1_out.to_string(),
+        swap.block_number,
+        swap.block_hash.map(|hash| format!("{:#x}", hash)),
+        swap.timestamp,
+        swap.log_index,
+    ])?;
+    Ok(())
+}
+
+fn insert_custom_event(conn: &Connection, log: &CustomLog) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT OR IGNORE INTO custom_events (tx_hash, contract_address, event_name, params, block_number, block_hash, timestamp, log_index)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?
+    .execute(params![
+        format!("{:#x}", log.tx_hash),
+        format!("{:#x}", log.contract),
+        log.name,
+        log.params_json().to_string(),
+        log.block_number,
+        log.block_hash.map(|hash| format!("{:#x}", hash)),
+        log.timestamp,
+        log.log_index,
+    ])?;
+    Ok(())
+}
+
+fn insert_event(conn: &Connection, swap_table: &TableName, event: &PoolEvent) -> Result<()> {
+    match event {
+        PoolEvent::Swap(combined_log) => insert_log(conn, swap_table, combined_log),
+        PoolEvent::Mint(mint) => insert_mint(conn, mint),
+        PoolEvent::Burn(burn) => insert_burn(conn, burn),
+        PoolEvent::V2Swap(swap) => insert_v2_swap(conn, swap),
+        PoolEvent::Custom(log) => insert_custom_event(conn, log),
+    }
+}
+
+fn delete_event(conn: &Connection, swap_table: &TableName, event: &PoolEvent) -> Result<usize> {
+    let table = match event {
+        PoolEvent::Swap(_) => swap_table.as_str(),
+        PoolEvent::Mint(_) => "mints",
+        PoolEvent::Burn(_) => "burns",
+        PoolEvent::V2Swap(_) => "v2_swaps",
+        PoolEvent::Custom(_) => "custom_events",
+    };
+    let deleted = conn.execute(
+        &format!("DELETE FROM {table} WHERE tx_hash = ?1 AND log_index IS ?2"),
+        params![format!("{:#x}", event.tx_hash()), event.log_index()],
+    )?;
+    Ok(deleted)
+}
+
+/// Writes the events, swaps to the default `logs` table, and advances the
+/// block checkpoint in one transaction, see `insert_events_into`.
+#[cfg(test)]
+pub(super) fn insert_logs_batch(conn: &mut Connection, events: &[PoolEvent]) -> Result<()> {
+    insert_events_into(conn, &TableName::default(), events)
+}
+
+/// Writes the events, swaps to `swap_table`, and advances the block checkpoint
+/// in one transaction, so the checkpoint never runs ahead of the stored rows.
+fn insert_events_into(
+    conn: &mut Connection,
+    swap_table: &TableName,
+    events: &[PoolEvent],
+) -> Result<()> {
+    let tx = conn.transaction()?;
+    for event in events {
+        insert_event(&tx, swap_table, event)?;
+    }
+    if let Some(block_number) = events.iter().filter_map(PoolEvent::block_number).max() {
+        update_checkpoint(&tx, block_number)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Deletes the oldest rows of each event table beyond `max_rows`, see
+/// `SwapStore::prune`. Rows without a block number sort first.
+fn prune_events(conn: &Connection, swap_table: &TableName, max_rows: u64) -> Result<usize> {
+    let max_rows = i64::try_from(max_rows).unwrap_or(i64::MAX);
+    let mut pruned = 0;
+    for table in event_tables(swap_table) {
+        let rows: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+            row.get(0)
+        })?;
+        let mut excess = rows.saturating_sub(max_rows);
+        while excess > 0 {
+            let deleted = conn.execute(
+                &format!(
+                    "DELETE FROM {table} WHERE rowid IN
+                     (SELECT rowid FROM {table} ORDER BY block_number, log_index LIMIT ?1)"
+                ),
+                [excess.min(PRUNE_BATCH_SIZE)],
+            )?;
+            if deleted == 0 {
+                break;
+            }
+            pruned += deleted;
+            excess -= deleted as i64;
+        }
+    }
+    if pruned > 0 {
+        prune_checkpoint_history(conn, swap_table)?;
+    }
+    Ok(pruned)
+}
+
+/// `EVENT_TABLES`, with swaps in `swap_table`.
+fn event_tables(swap_table: &TableName) -> impl Iterator<Item = &str> {
+    EVENT_TABLES.iter().map(|&table| match table {
+        DEFAULT_SWAP_TABLE => swap_table.as_str(),
+        table => table,
+    })
+}
+
+/// Deletes the checkpoint history below the oldest block still stored, which
+/// `find_block_gaps` no longer needs.
+fn prune_checkpoint_history(conn: &Connection, swap_table: &TableName) -> Result<()> {
+    let oldest = event_tables(swap_table)
+        .map(|table| format!("SELECT MIN(block_number) AS block_number FROM {table}"))
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+    conn.execute(
+        &format!("DELETE FROM checkpoints WHERE block_number < (SELECT MIN(block_number) FROM ({oldest}))"),
+        [],
+    )?;
+    Ok(())
+}
+
+/// Syncs the write-ahead log and moves its commits into the database file.
+/// With `synchronous=NORMAL` this is the only point where commits are synced.
+/// Truncating the log keeps it from growing while the monitor runs.
+fn checkpoint_wal(conn: &Connection) -> Result<()> {
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+    Ok(())
+}
+
+const CHECKPOINT_KEY: &str = "last_block";
+
+/// Records `block_number` as processed unless a later block already is.
+fn update_checkpoint(conn: &Connection, block_number: u64) -> Result<()> {
+    let advanced = conn.execute(
+        "INSERT INTO meta (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value
+           WHERE excluded.value > meta.value",
+        params![CHECKPOINT_KEY, block_number],
+    )?;
+    // The history of advances tells `find_block_gaps` when blocks were stored
+    if advanced > 0 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        conn.execute(
+            "INSERT OR IGNORE INTO checkpoints (block_number, recorded_at) VALUES (?1, ?2)",
+            params![block_number, now],
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the highest block number whose swaps have been committed.
+fn read_checkpoint(conn: &Connection) -> Result<Option<u64>> {
+    let checkpoint = conn
+        .query_row(
+            "SELECT value FROM meta WHERE key = ?1",
+            params![CHECKPOINT_KEY],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(checkpoint)
+}
+
+fn insert_pool(conn: &Connection, metadata: &PoolMetadata) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO pools (pool_address, token0_address, token0_symbol, token0_decimals, token1_address, token1_symbol, token1_decimals)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            format!("{:#x}", metadata.pool),
+            format!("{:#x}", metadata.token0.address),
+            metadata.token0.symbol,
+            metadata.token0.decimals,
+            format!("{:#x}", metadata.token1.address),
+            metadata.token1.symbol,
+            metadata.token1.decimals,
+        ],
+    )?;
+    Ok(())
+}
+
+fn read_pool(conn: &Connection, pool: Address) -> Result<Option<PoolMetadata>> {
+    let row: Option<(String, String, u8, String, String, u8)> = conn
+        .query_row(
+            "SELECT token0_address, token0_symbol, token0_decimals, token1_address, token1_symbol, token1_decimals
+             FROM pools WHERE pool_address = ?1",
+            params![format!("{:#x}", pool)],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            },
+        )
+        .optional()?;
+    row.map(
+        |(address0, symbol0, decimals0, address1, symbol1, decimals1)| {
+            Ok(PoolMetadata {
+                pool,
+                token0: TokenMetadata {
+                    address: parse_address(&address0)?,
+                    symbol: symbol0,
+                    decimals: decimals0,
+                },
+                token1: TokenMetadata {
+                    address: parse_address(&address1)?,
+                    symbol: symbol1,
+                    decimals: decimals1,
+                },
+            })
+        },
+    )
+    .transpose()
+}
+
+/// Decodes the `raw_data` of every stored swap again and rewrites the columns
+/// derived from it: the amounts, `sqrt_price`, `liquidity`, `tick`, protocol
+/// fees and `direction`, and for pools with stored token metadata the prices
+/// and normalized amounts too. This repairs the rows written by a faulty
+/// decoder, or fills columns added later, without fetching the logs again.
+///
+/// Swaps stored before `raw_data` existed are left as they are. All swaps are
+/// rewritten in one transaction, which a swap whose data fails to decode rolls
+/// back.
+pub fn reprocess_all(conn: &mut Connection) -> Result<ReprocessStats> {
+    let tx = conn.transaction()?;
+    let swaps = tx
+        .prepare(
+            "SELECT rowid, tx_hash, pool_address, raw_data FROM logs WHERE raw_data IS NOT NULL",
+        )?
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Vec<u8>>(3)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut pool_decimals: HashMap<String, Option<TokenDecimals>> = HashMap::new();
+    let mut updated = 0;
+    let mut skipped = 0;
+    for (rowid, tx_hash, pool, raw_data) in &swaps {
+        let data = match decode_swap_data(raw_data) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!(rowid, tx_hash, error = %e, "skipping a stored swap whose raw data cannot be decoded");
+                skipped += 1;
+                continue;
+            }
+        };
+        let decimals = match pool {
+            Some(pool) => match pool_decimals.get(pool) {
+                Some(decimals) => *decimals,
+                None => {
+                    let decimals = read_pool(&tx, parse_address(pool)?)?.map(|m| m.decimals());
+                    pool_decimals.insert(pool.clone(), decimals);
+                    decimals
+                }
+            },
+            None => None,
+        };
+        // Without token metadata the stored prices, which may come from
+        // configured decimals, are kept. Rows that already hold the decoded
+        // values are not counted as updated.
+        updated += tx
+            .prepare_cached(
+                "UPDATE logs SET amount0 = ?1, amount1 = ?2, sqrt_price = ?3, liquidity = ?4, tick = ?5, protocol_fees_token0 = ?6, protocol_fees_token1 = ?7, direction = ?8,
+               price = COALESCE(?9, price), tick_price = COALESCE(?10, tick_price), amount0_normalized = COALESCE(?11, amount0_normalized), amount1_normalized = COALESCE(?12, amount1_normalized)
+             WHERE rowid = ?13 AND (amount0 IS NOT ?1 OR amount1 IS NOT ?2 OR sqrt_price IS NOT ?3 OR liquidity IS NOT ?4 OR tick IS NOT ?5 OR protocol_fees_token0 IS NOT ?6 OR protocol_fees_token1 IS NOT ?7 OR direction IS NOT ?8
+               OR price IS NOT COALESCE(?9, price) OR tick_price IS NOT COALESCE(?10, tick_price) OR amount0_normalized IS NOT COALESCE(?11, amount0_normalized) OR amount1_normalized IS NOT COALESCE(?12, amount1_normalized))",
+            )?
+        .execute(params![
+            data.amount0.to_string(),
+            data.amount1.to_string(),
+            data.sqrt_price.to_string(),
+            data.liquidity.to_string(),
+            data.tick,
+            data.protocol_fees_token0.map(|fees| fees.to_string()),
+            data.protocol_fees_token1.map(|fees| fees.to_string()),
+            SwapDirection::from_amounts(data.amount0, data.amount1).map(SwapDirection::as_str),
+            decimals.map(|d| price_from_sqrt_price(data.sqrt_price, d.decimals0, d.decimals1)),
+            decimals.map(|d| price_from_tick(data.tick, d.decimals0, d.decimals1)),
+            decimals.map(|d| to_token_units(data.amount0, d.decimals0)),
+            decimals.map(|d| to_token_units(data.amount1, d.decimals1)),
+            rowid,
+        ])?;
+    }
+    tx.commit()?;
+    Ok(ReprocessStats {
+        swaps: swaps.len() - skipped,
+        updated,
+        skipped,
+    })
+}
+
+/// What `reprocess_all` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReprocessStats {
+    /// The stored swaps with raw data that were decoded again.
+    pub swaps: usize,
+    /// The swaps whose stored columns differed from the decoded values and
+    /// were rewritten.
+    pub updated: usize,
+    /// The swaps whose raw data is corrupted and cannot be decoded, which are
+    /// left as they are and logged with their rowid.
+    pub skipped: usize,
+}
+
+pub(super) fn parse_address(address: &str) -> Result<Address> {
+    Address::from_str(address).map_err(|e| {
+        MonitorError::InvalidAddress(format!("invalid stored address '{}': {}", address, e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{create_test_combined_log, create_test_pool_metadata};
+    use ethers::abi::{encode, Token};
+    use ethers::types::I256;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_initialize_database() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir
+            .path()
+            .join("test.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let result = initialize_database(&db_path, DurabilityMode::Safe, &TableName::default());
+        assert!(result.is_ok());
+        assert!(Path::new(&db_path).exists());
+    }
+
+    #[test]
+    fn test_initialize_database_removes_duplicates_once() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let conn = Connection::open(db_path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE logs (tx_hash TEXT, sender_address TEXT, log_index INTEGER);
+             INSERT INTO logs (tx_hash, log_index) VALUES ('0x01', 1), ('0x01', 1), ('0x01', 2);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let swap_table = TableName::default();
+        let conn = initialize_database(db_path, DurabilityMode::Safe, &swap_table).unwrap();
+        let rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM logs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 2);
+        assert!(index_exists(&conn, "idx_logs_tx_log_index").unwrap());
+
+        // Once the index exists the table is not scanned for duplicates again
+        conn.execute("DROP INDEX idx_logs_tx_log_index", [])
+            .unwrap();
+        conn.execute("CREATE INDEX idx_logs_tx_log_index ON logs(tx_hash)", [])
+            .unwrap();
+        conn.execute(
+            "INSERT INTO logs (tx_hash, log_index) VALUES ('0x01', 2)",
+            [],
+        )
+        .unwrap();
+        create_schema(&conn, &swap_table).unwrap();
+        let rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM logs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 3);
+    }
+
+    #[test]
+    fn test_initialize_database_creates_parent_dirs() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir.path().join("data").join("nested").join("swaps.db");
+        initialize_database(
+            db_path.to_str().unwrap(),
+            DurabilityMode::Safe,
+            &TableName::default(),
+        )
+        .unwrap();
+        assert!(db_path.exists());
+
+        // A file in the way of the directory is reported with its path
+        let blocker = temp_dir.path().join("file");
+        std::fs::write(&blocker, b"").unwrap();
+        let db_path = blocker.join("swaps.db");
+        let err = initialize_database(
+            db_path.to_str().unwrap(),
+            DurabilityMode::Safe,
+            &TableName::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, MonitorError::Database(_)));
+        assert!(err.to_string().contains(blocker.to_str().unwrap()), "{err}");
+    }
+
+    #[test]
+    fn test_initialize_database_durability() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let pragmas = |conn: &Connection| -> (String, u32) {
+            let journal_mode = conn
+                .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+                .unwrap();
+            let synchronous = conn
+                .query_row("PRAGMA synchronous", [], |row| row.get(0))
+                .unwrap();
+            (journal_mode, synchronous)
+        };
+
+        let conn =
+            initialize_database(db_path, DurabilityMode::Safe, &TableName::default()).unwrap();
+        // synchronous reads back as 2 for FULL and 1 for NORMAL
+        assert_eq!(pragmas(&conn), ("delete".to_string(), 2));
+        drop(conn);
+
+        let conn =
+            initialize_database(db_path, DurabilityMode::Fast, &TableName::default()).unwrap();
+        assert_eq!(pragmas(&conn), ("wal".to_string(), 1));
+        insert_log(&conn, &TableName::default(), &create_test_combined_log()).unwrap();
+
+        assert!(
+            initialize_database(IN_MEMORY_PATH, DurabilityMode::Fast, &TableName::default())
+                .is_ok()
+        );
+        assert_eq!(
+            "fast".parse::<DurabilityMode>().unwrap(),
+            DurabilityMode::Fast
+        );
+        assert!("wal".parse::<DurabilityMode>().is_err());
+    }
+
+    #[test]
+    fn test_initialize_in_memory_database() {
+        let conn = initialize_in_memory_database().unwrap();
+        insert_log(&conn, &TableName::default(), &create_test_combined_log()).unwrap();
+        let rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM logs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 1);
+
+        // Every in-memory connection starts out empty
+        let conn = initialize_database(IN_MEMORY_PATH, DurabilityMode::Safe, &TableName::default())
+            .unwrap();
+        let rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM logs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    #[test]
+    fn test_initialize_database_adds_missing_columns() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        Connection::open(&db_path)
+            .unwrap()
+            .execute("CREATE TABLE logs (tx_hash TEXT, sender_address TEXT)", [])
+            .unwrap();
+
+        let conn = initialize_database(
+            db_path.to_str().unwrap(),
+            DurabilityMode::Safe,
+            &TableName::default(),
+        )
+        .unwrap();
+        let has_pool_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('logs') WHERE name = 'pool_address'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(has_pool_column);
+    }
+
+    #[test]
+    fn test_initialize_database_indexes_sender_and_block() {
+        let conn = initialize_in_memory_database().unwrap();
+        let indexes: Vec<String> = conn
+            .prepare("SELECT name FROM pragma_index_list('logs') ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            indexes,
+            [
+                "idx_logs_block",
+                "idx_logs_sender_id",
+                "idx_logs_timestamp",
+                "idx_logs_tx_log_index"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cached_insert_survives_migration() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let conn = initialize_database(
+            db_path.to_str().unwrap(),
+            DurabilityMode::Safe,
+            &TableName::default(),
+        )
+        .unwrap();
+        insert_log(&conn, &TableName::default(), &create_test_combined_log()).unwrap();
+
+        // Another process, such as an older version of the monitor, rebuilds
+        // the table without the columns added since
+        let other = Connection::open(&db_path).unwrap();
+        other
+            .execute_batch(
+                "DROP TABLE logs;
+                 CREATE TABLE logs (tx_hash TEXT, sender_address TEXT, receiver_address TEXT,
+                   amount0 TEXT, amount1 TEXT, sqrt_price TEXT, liquidity TEXT, tick INTEGER)",
+            )
+            .unwrap();
+        // The cached statement no longer matches the table
+        assert!(insert_log(&conn, &TableName::default(), &create_test_combined_log()).is_err());
+
+        create_schema(&conn, &TableName::default()).unwrap();
+        insert_log(&conn, &TableName::default(), &create_test_combined_log()).unwrap();
+        let count: u32 = conn
+            .query_row("SELECT COUNT(*) FROM logs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_intern_address() {
+        let conn = initialize_in_memory_database().unwrap();
+        let swap = create_test_combined_log();
+        let sender_id = intern_address(&conn, swap.sender).unwrap();
+        assert_eq!(intern_address(&conn, swap.sender).unwrap(), sender_id);
+        assert_ne!(intern_address(&conn, swap.receiver).unwrap(), sender_id);
+
+        // Swaps with the same parties share the two rows
+        insert_log(&conn, &TableName::default(), &swap).unwrap();
+        let mut other = swap.clone();
+        other.log_index = Some(7);
+        insert_log(&conn, &TableName::default(), &other).unwrap();
+        let addresses: u32 = conn
+            .query_row("SELECT COUNT(*) FROM addresses", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(addresses, 2);
+    }
+
+    #[test]
+    fn test_initialize_database_interns_stored_addresses() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let swap = create_test_combined_log();
+        // A database written before the addresses table existed
+        Connection::open(&db_path)
+            .unwrap()
+            .execute_batch(&format!(
+                "CREATE TABLE logs (tx_hash TEXT, sender_address TEXT, receiver_address TEXT,
+                   amount0 TEXT, amount1 TEXT, sqrt_price TEXT, liquidity TEXT, tick INTEGER);
+                 CREATE INDEX idx_logs_sender ON logs(sender_address);
+                 INSERT INTO logs VALUES ('{:#x}', '{:#x}', '{:#x}', '{}', '{}', '{}', '{}', {});",
+                swap.tx_hash,
+                swap.sender,
+                swap.receiver,
+                swap.data.amount0,
+                swap.data.amount1,
+                swap.data.sqrt_price,
+                swap.data.liquidity,
+                swap.data.tick,
+            ))
+            .unwrap();
+
+        let conn = initialize_database(
+            db_path.to_str().unwrap(),
+            DurabilityMode::Safe,
+            &TableName::default(),
+        )
+        .unwrap();
+        let hex_addresses: u32 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM logs
+                 WHERE sender_address IS NOT NULL OR receiver_address IS NOT NULL",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(hex_addresses, 0);
+
+        let stored = crate::store::get_swaps_by_sender(&conn, swap.sender).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].receiver, swap.receiver);
+        // New swaps from the same sender reuse its id
+        let mut later = swap.clone();
+        later.log_index = Some(7);
+        insert_log(&conn, &TableName::default(), &later).unwrap();
+        assert_eq!(
+            crate::store::get_swaps_by_sender(&conn, swap.sender)
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_insert_log_is_idempotent() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let conn = initialize_database(
+            db_path.to_str().unwrap(),
+            DurabilityMode::Safe,
+            &TableName::default(),
+        )
+        .unwrap();
+        let combined_log = create_test_combined_log();
+
+        insert_log(&conn, &TableName::default(), &combined_log).unwrap();
+        insert_log(&conn, &TableName::default(), &combined_log).unwrap();
+
+        let rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM logs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 1);
+    }
+
+    #[test]
+    fn test_table_name_validation() {
+        assert_eq!(TableName::default().as_str(), "logs");
+        assert_eq!(TableName::new("logs").unwrap(), TableName::default());
+        assert_eq!(
+            "swaps_eth".parse::<TableName>().unwrap().as_str(),
+            "swaps_eth"
+        );
+        assert!(TableName::new("_swaps2").is_ok());
+        for name in [
+            "",
+            "1swaps",
+            "logs; DROP TABLE logs",
+            "swaps-eth",
+            "mints",
+            "Meta",
+            "checkpoints",
+            "sqlite_master",
+        ] {
+            let err = TableName::new(name).unwrap_err();
+            assert!(
+                matches!(err, MonitorError::InvalidConfig(_)),
+                "{name}: {err}"
+            );
+        }
+        assert!(TableName::new(&"a".repeat(65)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_store_with_custom_swap_table() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let swap_table = TableName::new("swaps_eth").unwrap();
+        let mut store = SqliteStore::open_with_swap_table(
+            db_path.to_str().unwrap(),
+            DurabilityMode::Safe,
+            swap_table.clone(),
+        )
+        .unwrap();
+        let swap = create_test_combined_log();
+        store.insert_batch(&[swap.clone().into()]).await.unwrap();
+        let count = |conn: &Connection, table: &str| -> i64 {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!(count(&store.conn, "swaps_eth"), 1);
+        assert_eq!(store.delete(&swap.clone().into()).await.unwrap(), 1);
+        assert_eq!(count(&store.conn, "swaps_eth"), 0);
+
+        // The default table in the same file is a store of its own
+        drop(store);
+        let mut store = SqliteStore::open(db_path.to_str().unwrap()).unwrap();
+        store.insert(&swap.clone().into()).await.unwrap();
+        assert_eq!(count(&store.conn, "logs"), 1);
+        assert_eq!(count(&store.conn, "swaps_eth"), 0);
+    }
+
+    #[test]
+    fn test_checkpoint_advances_with_committed_batches() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let mut conn = initialize_database(
+            db_path.to_str().unwrap(),
+            DurabilityMode::Safe,
+            &TableName::default(),
+        )
+        .unwrap();
+        assert_eq!(read_checkpoint(&conn).unwrap(), None);
+
+        let mut later = create_test_combined_log();
+        later.block_number = Some(200);
+        let mut earlier = create_test_combined_log();
+        earlier.block_number = Some(100);
+        let mut pending = create_test_combined_log();
+        pending.block_number = None;
+
+        insert_logs_batch(
+            &mut conn,
+            &[earlier.clone().into(), later.into(), pending.into()],
+        )
+        .unwrap();
+        assert_eq!(read_checkpoint(&conn).unwrap(), Some(200));
+
+        // An older batch never moves the checkpoint backwards
+        insert_logs_batch(&mut conn, &[earlier.into()]).unwrap();
+        assert_eq!(read_checkpoint(&conn).unwrap(), Some(200));
+        // Only advances are recorded in the history
+        let history: Vec<u64> = conn
+            .prepare("SELECT block_number FROM checkpoints")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(history, vec![200]);
+
+        // The checkpoint survives reopening the database
+        drop(conn);
+        let conn = initialize_database(
+            db_path.to_str().unwrap(),
+            DurabilityMode::Safe,
+            &TableName::default(),
+        )
+        .unwrap();
+        assert_eq!(read_checkpoint(&conn).unwrap(), Some(200));
+    }
+
+    #[tokio::test]
+    async fn test_prune_deletes_oldest_events() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let swaps: Vec<PoolEvent> = [103, 101, 104, 102]
+            .into_iter()
+            .enumerate()
+            .map(|(log_index, block_number)| {
+                let mut swap = create_test_combined_log();
+                swap.block_number = Some(block_number);
+                swap.log_index = Some(log_index as u64);
+                swap.into()
+            })
+            .collect();
+        store.insert_batch(&swaps).await.unwrap();
+
+        assert_eq!(store.prune(2).await.unwrap(), 2);
+        let blocks: Vec<u64> = store
+            .conn
+            .prepare("SELECT block_number FROM logs ORDER BY block_number")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(blocks, vec![103, 104]);
+        assert_eq!(store.read_checkpoint().await.unwrap(), Some(104));
+        // The checkpoint history below the oldest stored block goes as well
+        store
+            .conn
+            .execute_batch(
+                "INSERT INTO checkpoints (block_number, recorded_at)
+                 VALUES (101, 0), (102, 0), (103, 0)",
+            )
+            .unwrap();
+        store.prune(1).await.unwrap();
+        let history: Vec<u64> = store
+            .conn
+            .prepare("SELECT block_number FROM checkpoints ORDER BY block_number")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(history, vec![104]);
+        // Tables within the cap are left alone
+        assert_eq!(store.prune(2).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sync_checkpoints_fast_mode_commits() {
+        let temp_dir = TempDir::new("tmptest").unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let wal_path = temp_dir.path().join("test.db-wal");
+        let wal_len = || std::fs::metadata(&wal_path).unwrap().len();
+        let mut store =
+            SqliteStore::open_with_durability(db_path.to_str().unwrap(), DurabilityMode::Fast)
+                .unwrap();
+        store
+            .insert_batch(&[create_test_combined_log().into()])
+            .await
+            .unwrap();
+        assert!(store.unsynced);
+        assert!(wal_len() > 0);
+
+        store.sync().await.unwrap();
+        assert!(!store.unsynced);
+        assert_eq!(wal_len(), 0);
+        assert_eq!(
+            store.read_checkpoint().await.unwrap(),
+            create_test_combined_log().block_number
+        );
+
+        // Safe mode commits are synced already
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        store
+            .insert_batch(&[create_test_combined_log().into()])
+            .await
+            .unwrap();
+        assert!(!store.unsynced);
+    }
+
+    #[test]
+    fn test_reprocess_all_rewrites_decoded_columns() {
+        let mut conn = initialize_in_memory_database().unwrap();
+        let swap = create_test_combined_log();
+        let raw_data = encode(&[
+            Token::Int(swap.data.amount0.into_raw()),
+            Token::Int(swap.data.amount1.into_raw()),
+            Token::Uint(swap.data.sqrt_price),
+            Token::Uint(swap.data.liquidity.into()),
+            Token::Int(I256::from(swap.data.tick).into_raw()),
+        ]);
+        // A faulty decoder swapped the amounts of the first swap
+        let mut faulty = CombinedLog {
+            raw_data: Some(raw_data.into()),
+            ..swap.clone()
+        };
+        std::mem::swap(&mut faulty.data.amount0, &mut faulty.data.amount1);
+        let mut unrecorded = swap.clone();
+        unrecorded.log_index = Some(99);
+        insert_logs_batch(&mut conn, &[faulty.into(), unrecorded.into()]).unwrap();
+        insert_pool(&conn, &create_test_pool_metadata()).unwrap();
+
+        assert_eq!(
+            reprocess_all(&mut conn).unwrap(),
+            ReprocessStats {
+                swaps: 1,
+                updated: 1,
+                skipped: 0
+            }
+        );
+        let rows: Vec<(String, Option<String>, Option<f64>)> = conn
+            .prepare("SELECT amount0, direction, amount0_normalized FROM logs ORDER BY log_index")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(rows[0].0, swap.data.amount0.to_string());
+        assert_eq!(rows[0].1.as_deref(), Some("token1->token0"));
+        // USDC has 6 decimals
+        assert_eq!(rows[0].2, Some(-263.12));
+        // A swap stored without its data is left as it was
+        assert_eq!(rows[1], (swap.data.amount0.to_string(), None, None));
+
+        // Replaying again decodes the same values, so nothing is rewritten
+        assert_eq!(reprocess_all(&mut conn).unwrap().updated, 0);
+
+        // Truncated raw data is skipped instead of failing the whole replay
+        conn.execute(
+            "UPDATE logs SET raw_data = X'0102' WHERE log_index = 99",
+            [],
+        )
+        .unwrap();
+        assert_eq!(
+            reprocess_all(&mut conn).unwrap(),
+            ReprocessStats {
+                swaps: 1,
+                updated: 0,
+                skipped: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_pool_metadata_round_trip() {
+        let conn = initialize_in_memory_database().unwrap();
+        let mut metadata = create_test_pool_metadata();
+        assert_eq!(read_pool(&conn, metadata.pool).unwrap(), None);
+
+        insert_pool(&conn, &metadata).unwrap();
+        assert_eq!(
+            read_pool(&conn, metadata.pool).unwrap(),
+            Some(metadata.clone())
+        );
+
+        // Storing a pool again replaces its entry
+        metadata.token1.symbol = "ETH".to_string();
+        insert_pool(&conn, &metadata).unwrap();
+        assert_eq!(read_pool(&conn, metadata.pool).unwrap(), Some(metadata));
+        let rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM pools", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 1);
+    }
+}
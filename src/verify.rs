@@ -0,0 +1,275 @@
+use crate::error::MonitorError;
+use crate::events::topic;
+use crate::store::get_swaps_by_tx_hash;
+use crate::{decode_swap_data, with_provider, CombinedLog, LogData, ProviderTask, ReconnectPolicy};
+use ethers::core::types::{Address, Log, H256};
+use ethers::providers::Middleware;
+use rusqlite::Connection;
+use std::fmt;
+use std::sync::Arc;
+
+/// A difference between a stored swap and its log on chain, see `verify_swap`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapMismatch {
+    pub tx_hash: H256,
+    pub log_index: Option<u64>,
+    /// The column that differs, or `log` when the transaction has no log at
+    /// the stored log index, `data` when that log is not a swap and
+    /// `transaction` when the transaction is not on chain.
+    pub field: &'static str,
+    pub stored: String,
+    pub on_chain: String,
+}
+
+impl fmt::Display for SwapMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.tx_hash)?;
+        if let Some(log_index) = self.log_index {
+            write!(f, " log {}", log_index)?;
+        }
+        write!(
+            f,
+            ": {} is {} in the database but {} on chain",
+            self.field, self.stored, self.on_chain
+        )
+    }
+}
+
+/// Fetches the receipt of `tx_hash`, decodes the logs of its stored swaps
+/// again and returns the fields where the stored swaps differ from them, so
+/// decoding drift and corrupted rows show up. An empty list means every
+/// stored swap of the transaction matches the chain.
+///
+/// Only the columns read from the log are compared: the pool, parties, block,
+/// amounts, square root price, liquidity, tick and protocol fees. Values
+/// derived from them, such as `price`, can be rebuilt with `reprocess_all`.
+pub async fn verify_swap<M: Middleware>(
+    client: &M,
+    conn: &Connection,
+    tx_hash: H256,
+) -> Result<Vec<SwapMismatch>, MonitorError> {
+    let stored = get_swaps_by_tx_hash(conn, tx_hash)?;
+    if stored.is_empty() {
+        return Err(MonitorError::InvalidConfig(format!(
+            "no swap of transaction {:#x} is stored",
+            tx_hash
+        )));
+    }
+    let receipt = client.get_transaction_receipt(tx_hash).await.map_err(|e| {
+        MonitorError::ProviderConnect(format!(
+            "failed to fetch the receipt of {:#x}: {}",
+            tx_hash, e
+        ))
+    })?;
+    let Some(receipt) = receipt else {
+        return Ok(vec![SwapMismatch {
+            tx_hash,
+            log_index: None,
+            field: "transaction",
+            stored: format!("{} swaps", stored.len()),
+            on_chain: "missing".to_string(),
+        }]);
+    };
+
+    let mut mismatches = Vec::new();
+    for swap in &stored {
+        let log = receipt
+            .logs
+            .iter()
+            .find(|log| log.log_index.map(|i| i.as_u64()) == swap.log_index);
+        match log {
+            Some(log) => compare_swap(swap, log, &mut mismatches),
+            None => mismatches.push(SwapMismatch {
+                tx_hash,
+                log_index: swap.log_index,
+                field: "log",
+                stored: "a swap".to_string(),
+                on_chain: "missing".to_string(),
+            }),
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Appends a mismatch for each field of `swap` that differs from `log`.
+fn compare_swap(swap: &CombinedLog, log: &Log, mismatches: &mut Vec<SwapMismatch>) {
+    let mut compare = |field: &'static str, stored: String, on_chain: String| {
+        if stored != on_chain {
+            mismatches.push(SwapMismatch {
+                tx_hash: swap.tx_hash,
+                log_index: swap.log_index,
+                field,
+                stored,
+                on_chain,
+            });
+        }
+    };
+    let (sender, receiver, data) = match decode_swap_log(log) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            let stored = "a swap".to_string();
+            return compare("data", stored, format!("undecodable ({})", e));
+        }
+    };
+    let address = |address: Address| format!("{:#x}", address);
+    compare("pool_address", address(swap.pool), address(log.address));
+    compare(
+        "sender",
+        address(swap.sender),
+        address(Address::from(sender)),
+    );
+    compare(
+        "receiver",
+        address(swap.receiver),
+        address(Address::from(receiver)),
+    );
+    compare(
+        "block_number",
+        format!("{:?}", swap.block_number),
+        format!("{:?}", log.block_number.map(|n| n.as_u64())),
+    );
+    compare(
+        "block_hash",
+        format!("{:?}", swap.block_hash),
+        format!("{:?}", log.block_hash),
+    );
+    compare(
+        "amount0",
+        swap.data.amount0.to_string(),
+        data.amount0.to_string(),
+    );
+    compare(
+        "amount1",
+        swap.data.amount1.to_string(),
+        data.amount1.to_string(),
+    );
+    compare(
+        "sqrt_price",
+        swap.data.sqrt_price.to_string(),
+        data.sqrt_price.to_string(),
+    );
+    compare(
+        "liquidity",
+        swap.data.liquidity.to_string(),
+        data.liquidity.to_string(),
+    );
+    compare("tick", swap.data.tick.to_string(), data.tick.to_string());
+    compare(
+        "protocol_fees_token0",
+        format!("{:?}", swap.data.protocol_fees_token0),
+        format!("{:?}", data.protocol_fees_token0),
+    );
+    compare(
+        "protocol_fees_token1",
+        format!("{:?}", swap.data.protocol_fees_token1),
+        format!("{:?}", data.protocol_fees_token1),
+    );
+}
+
+/// The sender, recipient and data of a swap log.
+fn decode_swap_log(log: &Log) -> Result<(H256, H256, LogData), MonitorError> {
+    Ok((topic(log, 1)?, topic(log, 2)?, decode_swap_data(&log.data)?))
+}
+
+/// Runs `verify_swap` for each of `tx_hashes`, with a client as connected by
+/// `with_provider`, returning all mismatches.
+pub async fn verify_swaps(
+    provider_urls: &[String],
+    reconnect: &ReconnectPolicy,
+    conn: &Connection,
+    tx_hashes: &[H256],
+) -> eyre::Result<Vec<SwapMismatch>> {
+    struct VerifySwaps<'a> {
+        conn: &'a Connection,
+        tx_hashes: &'a [H256],
+    }
+
+    impl ProviderTask for VerifySwaps<'_> {
+        type Output = Vec<SwapMismatch>;
+
+        async fn run<M: Middleware + 'static>(
+            self,
+            client: Arc<M>,
+        ) -> eyre::Result<Vec<SwapMismatch>> {
+            let mut mismatches = Vec::new();
+            for &tx_hash in self.tx_hashes {
+                mismatches.extend(verify_swap(client.as_ref(), self.conn, tx_hash).await?);
+            }
+            Ok(mismatches)
+        }
+    }
+
+    with_provider(provider_urls, reconnect, VerifySwaps { conn, tx_hashes }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{SqliteStore, SwapStore};
+    use crate::tests::{create_test_combined_log, create_test_log};
+    use ethers::providers::Provider;
+    use ethers::types::{TransactionReceipt, U256};
+
+    fn test_receipt(logs: Vec<Log>) -> TransactionReceipt {
+        TransactionReceipt {
+            transaction_hash: create_test_combined_log().tx_hash,
+            logs,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_swap_reports_mismatches() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let swap = create_test_combined_log();
+        store.insert(&swap.clone().into()).await.unwrap();
+
+        let (client, mock) = Provider::mocked();
+        // Responses are popped from the back, so they are pushed in reverse order
+        mock.push::<Option<TransactionReceipt>, _>(None).unwrap();
+        mock.push(test_receipt(vec![])).unwrap();
+        mock.push(test_receipt(vec![create_test_log()])).unwrap();
+        mock.push(test_receipt(vec![create_test_log()])).unwrap();
+
+        let conn = store.connection();
+        assert_eq!(
+            verify_swap(&client, conn, swap.tx_hash).await.unwrap(),
+            vec![]
+        );
+
+        conn.execute("UPDATE logs SET amount0 = '1', tick = tick + 1", [])
+            .unwrap();
+        let mismatches = verify_swap(&client, conn, swap.tx_hash).await.unwrap();
+        let fields: Vec<&str> = mismatches.iter().map(|m| m.field).collect();
+        assert_eq!(fields, vec!["amount0", "tick"]);
+        assert_eq!(mismatches[0].stored, "1");
+        assert_eq!(mismatches[0].on_chain, swap.data.amount0.to_string());
+
+        let mismatches = verify_swap(&client, conn, swap.tx_hash).await.unwrap();
+        assert_eq!(mismatches[0].field, "log");
+        let mismatches = verify_swap(&client, conn, swap.tx_hash).await.unwrap();
+        assert_eq!(mismatches[0].field, "transaction");
+
+        // A transaction without stored swaps cannot be verified
+        let err = verify_swap(&client, conn, H256::zero()).await.unwrap_err();
+        assert!(matches!(err, MonitorError::InvalidConfig(_)));
+    }
+
+    #[test]
+    fn test_swap_mismatch_display() {
+        let mismatch = SwapMismatch {
+            tx_hash: H256::from_low_u64_be(1),
+            log_index: Some(7),
+            field: "amount0",
+            stored: "1".to_string(),
+            on_chain: U256::from(2).to_string(),
+        };
+        assert_eq!(
+            mismatch.to_string(),
+            format!(
+                "{:#x} log 7: amount0 is 1 in the database but 2 on chain",
+                H256::from_low_u64_be(1)
+            )
+        );
+    }
+}
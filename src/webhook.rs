@@ -0,0 +1,256 @@
+use crate::{CombinedLog, PoolMetadata, SwapFilter, SwapSink};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Symbols whose normalized amount is taken as the USD value of a swap.
+const USD_STABLECOINS: &[&str] = &["USDC", "USDT", "DAI"];
+
+/// Time allowed for delivering one notification.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings for posting large swaps to a Slack or Discord compatible webhook.
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Raw amount thresholds, see `SwapFilter`.
+    pub threshold: SwapFilter,
+    /// Notify swaps worth at least this many dollars. The value is only known
+    /// for swaps valued by a price feed, or of pools with a USD stablecoin and
+    /// known token metadata, see `usd_value`.
+    pub min_usd_value: Option<f64>,
+    /// Minimum time between two notifications. Large swaps arriving sooner are
+    /// not notified, so a volatile period does not flood the channel.
+    pub min_interval: Duration,
+}
+
+impl WebhookConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        WebhookConfig {
+            url: url.into(),
+            threshold: SwapFilter::default(),
+            min_usd_value: None,
+            min_interval: Duration::from_secs(10),
+        }
+    }
+
+    /// Whether the swap reaches a threshold. Without any threshold set, every
+    /// swap does.
+    fn is_large(&self, combined_log: &CombinedLog, metadata: Option<&PoolMetadata>) -> bool {
+        let has_amount_threshold =
+            self.threshold.min_abs_amount0.is_some() || self.threshold.min_abs_amount1.is_some();
+        let reaches_amount = has_amount_threshold && self.threshold.matches(&combined_log.data);
+        let reaches_usd = self
+            .min_usd_value
+            .is_some_and(|min| usd_value(combined_log, metadata).is_some_and(|value| value >= min));
+        reaches_amount || reaches_usd || (!has_amount_threshold && self.min_usd_value.is_none())
+    }
+}
+
+/// The dollar value of a swap: the value from its price feed when it has one,
+/// otherwise that of the stablecoin side, when one of the pool's tokens is a
+/// USD stablecoin and its amount was normalized.
+pub fn usd_value(combined_log: &CombinedLog, metadata: Option<&PoolMetadata>) -> Option<f64> {
+    if combined_log.usd_value.is_some() {
+        return combined_log.usd_value;
+    }
+    let metadata = metadata?;
+    let is_usd = |symbol: &str| USD_STABLECOINS.contains(&symbol);
+    if is_usd(&metadata.token0.symbol) {
+        combined_log.amount0_normalized.map(f64::abs)
+    } else if is_usd(&metadata.token1.symbol) {
+        combined_log.amount1_normalized.map(f64::abs)
+    } else {
+        None
+    }
+}
+
+/// Posts large swaps to a webhook in the background.
+///
+/// Delivery runs on a spawned task, so a slow or failing endpoint never holds
+/// up log processing; failures are only reported on the console.
+pub struct Webhook {
+    config: WebhookConfig,
+    client: reqwest::Client,
+    last_sent: Option<Instant>,
+}
+
+impl Webhook {
+    pub fn new(config: WebhookConfig) -> Self {
+        Webhook {
+            config,
+            client: reqwest::Client::new(),
+            last_sent: None,
+        }
+    }
+
+    /// Sends a notification for the swap if it is large enough and the rate
+    /// limit allows it. Returns whether a notification was sent.
+    pub fn notify(&mut self, combined_log: &CombinedLog, metadata: Option<&PoolMetadata>) -> bool {
+        if combined_log.removed || !self.config.is_large(combined_log, metadata) {
+            return false;
+        }
+        let now = Instant::now();
+        if self
+            .last_sent
+            .is_some_and(|last_sent| now.duration_since(last_sent) < self.config.min_interval)
+        {
+            info!(tx_hash = ?combined_log.tx_hash, "webhook rate limited, not notifying swap");
+            return false;
+        }
+        self.last_sent = Some(now);
+
+        let request = self
+            .client
+            .post(&self.config.url)
+            .timeout(WEBHOOK_TIMEOUT)
+            .json(&payload(combined_log, metadata));
+        tokio::spawn(async move {
+            let result = request
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+            if let Err(e) = result {
+                warn!(error = %e, "failed to deliver webhook");
+            }
+        });
+        true
+    }
+}
+
+impl SwapSink for Webhook {
+    fn handle(&mut self, swap: &CombinedLog, metadata: Option<&PoolMetadata>) -> eyre::Result<()> {
+        self.notify(swap, metadata);
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+}
+
+/// The notification body. `text` and `content` carry a readable summary for
+/// Slack and Discord respectively; the other fields are for custom receivers.
+fn payload(combined_log: &CombinedLog, metadata: Option<&PoolMetadata>) -> serde_json::Value {
+    let amounts = match metadata {
+        Some(metadata) => format!(
+            "{} {} / {} {}",
+            combined_log
+                .amount0_normalized
+                .map_or_else(|| combined_log.data.amount0.to_string(), |a| a.to_string()),
+            metadata.token0.symbol,
+            combined_log
+                .amount1_normalized
+                .map_or_else(|| combined_log.data.amount1.to_string(), |a| a.to_string()),
+            metadata.token1.symbol,
+        ),
+        None => format!(
+            "amount0 {} / amount1 {}",
+            combined_log.data.amount0, combined_log.data.amount1
+        ),
+    };
+    let summary = format!(
+        "Large swap in pool {:#x}: {} (tx {:#x})",
+        combined_log.pool, amounts, combined_log.tx_hash
+    );
+    json!({
+        "text": summary,
+        "content": summary,
+        "tx_hash": format!("{:#x}", combined_log.tx_hash),
+        "pool": format!("{:#x}", combined_log.pool),
+        "amount0": combined_log.data.amount0.to_string(),
+        "amount1": combined_log.data.amount1.to_string(),
+        "amount0_normalized": combined_log.amount0_normalized,
+        "amount1_normalized": combined_log.amount1_normalized,
+        "usd_value": usd_value(combined_log, metadata),
+        "block_number": combined_log.block_number,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{create_test_combined_log, create_test_pool_metadata};
+    use ethers::types::U256;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn normalized_swap() -> CombinedLog {
+        CombinedLog {
+            amount0_normalized: Some(-263.12),
+            amount1_normalized: Some(0.162),
+            ..create_test_combined_log()
+        }
+    }
+
+    #[test]
+    fn test_webhook_thresholds() {
+        let swap = normalized_swap();
+        let metadata = create_test_pool_metadata();
+        assert_eq!(usd_value(&swap, Some(&metadata)), Some(263.12));
+        assert_eq!(usd_value(&swap, None), None);
+        let priced = CombinedLog {
+            usd_value: Some(1_500.0),
+            ..normalized_swap()
+        };
+        assert_eq!(usd_value(&priced, Some(&metadata)), Some(1_500.0));
+        assert_eq!(usd_value(&priced, None), Some(1_500.0));
+
+        let mut config = WebhookConfig::new("http://localhost");
+        assert!(config.is_large(&swap, None));
+
+        config.min_usd_value = Some(250.0);
+        assert!(config.is_large(&swap, Some(&metadata)));
+        // Without metadata the USD value is unknown
+        assert!(!config.is_large(&swap, None));
+        config.min_usd_value = Some(1_000.0);
+        assert!(!config.is_large(&swap, Some(&metadata)));
+
+        // |amount0| is 263120000, reaching either threshold is enough
+        config.threshold.min_abs_amount0 = Some(U256::from(100_000_000u64));
+        assert!(config.is_large(&swap, Some(&metadata)));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_delivers_and_rate_limits() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let mut webhook = Webhook::new(WebhookConfig {
+            min_interval: Duration::from_secs(3600),
+            ..WebhookConfig::new(url)
+        });
+
+        let swap = normalized_swap();
+        let metadata = create_test_pool_metadata();
+        assert!(webhook.notify(&swap, Some(&metadata)));
+        // The second swap falls inside the rate limit window
+        assert!(!webhook.notify(&swap, Some(&metadata)));
+        let mut removed = swap.clone();
+        removed.removed = true;
+        assert!(!Webhook::new(WebhookConfig::new("http://localhost")).notify(&removed, None));
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        // Read until the JSON body has arrived in full
+        while !request.ends_with(b"}") {
+            let n = socket.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed before the body arrived");
+            request.extend_from_slice(&buf[..n]);
+        }
+        socket
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+
+        let request = String::from_utf8(request).unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1"));
+        let body: serde_json::Value =
+            serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+        assert_eq!(body["tx_hash"], format!("{:#x}", swap.tx_hash));
+        assert_eq!(body["pool"], format!("{:#x}", swap.pool));
+        assert_eq!(body["amount0"], "-263120000");
+        assert_eq!(body["usd_value"], 263.12);
+        assert!(body["text"].as_str().unwrap().contains("-263.12 USDC"));
+    }
+}